@@ -0,0 +1,49 @@
+//! 变量替换性能基准：`cargo bench --bench replace_bench`（加 `--features fast-replace` 测量 Aho-Corasick 实现）
+//! 10000行脚本、50个变量，内容由行号确定生成；逐个变量 String::replace 作为对照
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use net_shell::vars::VariableManager;
+use std::collections::HashMap;
+use std::hint::black_box;
+
+const LINES: usize = 10_000;
+const VARIABLES: usize = 50;
+
+fn variables() -> HashMap<String, String> {
+    (0..VARIABLES).map(|i| (format!("var_{}", i), format!("value-{}", i))).collect()
+}
+
+/// 每行引用两个变量，按行号轮换
+fn script() -> String {
+    (0..LINES)
+        .map(|line| format!(
+            "echo \"step {} uses {{{{ var_{} }}}} and {{{{ var_{} }}}}\" >> /var/log/deploy.log\n",
+            line, line % VARIABLES, (line * 7 + 3) % VARIABLES
+        ))
+        .collect()
+}
+
+/// 逐个变量调用 String::replace，复杂度为 变量数 × 文档长度
+fn sequential_replace(variables: &HashMap<String, String>, content: &str) -> String {
+    let mut content = content.to_string();
+    for (key, value) in variables {
+        content = content.replace(&format!("{{{{ {} }}}}", key), value);
+    }
+    content
+}
+
+fn replace_variables(c: &mut Criterion) {
+    let variables = variables();
+    let manager = VariableManager::new(Some(variables.clone()));
+    let script = script();
+    assert_eq!(manager.batch_replace_all(&script), sequential_replace(&variables, &script));
+
+    let mut group = c.benchmark_group("replace_variables");
+    group.throughput(Throughput::Bytes(script.len() as u64));
+    group.bench_function("sequential", |b| b.iter(|| black_box(sequential_replace(&variables, black_box(&script)))));
+    group.bench_function("batch_replace_all", |b| b.iter(|| black_box(manager.batch_replace_all(black_box(&script)))));
+    group.finish();
+}
+
+criterion_group!(benches, replace_variables);
+criterion_main!(benches);
@@ -0,0 +1,167 @@
+//! TemplateEngine 渲染性能基准：`cargo bench --bench template_bench`
+//! 引擎在 iter_batched 的准备阶段构建，只统计渲染耗时；吞吐量按渲染结果的字节数计算
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use net_shell::TemplateEngine;
+use serde_json::json;
+use std::hint::black_box;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// include 的嵌套层数
+const INCLUDE_DEPTH: usize = 10;
+
+/// 准备 include 用的模板目录：level0.tmpl 逐层包含到 level9.tmpl；
+/// 另有 mixed 模板使用的 header.tmpl 和 footer.tmpl
+fn template_dir() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for level in 0..INCLUDE_DEPTH {
+        let body = if level + 1 < INCLUDE_DEPTH {
+            format!("level {{{{ name }}}} {}\n{{% include \"level{}.tmpl\" %}}\n", level, level + 1)
+        } else {
+            format!("level {{{{ name }}}} {}\n", level)
+        };
+        std::fs::write(dir.path().join(format!("level{}.tmpl", level)), body).unwrap();
+    }
+    std::fs::write(dir.path().join("header.tmpl"), "# {{ app_name }} {{ version }} ({{ environment }})\n").unwrap();
+    std::fs::write(dir.path().join("footer.tmpl"), "# generated for {{ owner }} in {{ region }}\n").unwrap();
+    dir
+}
+
+fn engine(template_dir: &Path) -> TemplateEngine {
+    let mut engine = TemplateEngine::new();
+    engine.set_template_dir(template_dir);
+    engine
+}
+
+/// 渲染一次得到输出字节数，作为吞吐量
+fn bench_render(c: &mut Criterion, name: &str, template: &str, setup: impl Fn() -> TemplateEngine) {
+    let rendered = setup().render_string(template).unwrap();
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(rendered.len() as u64));
+    group.bench_function("render", |b| {
+        b.iter_batched(&setup, |engine| black_box(engine.render_string(black_box(template)).unwrap()), BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+/// 约100个字符，5处变量替换
+fn simple_variable(c: &mut Criterion) {
+    let dir = template_dir();
+    let template = "deploy {{ app }} version {{ version }} to {{ host }}:{{ port }} as {{ user }} and restart the service now";
+    bench_render(c, "simple_variable", template, || {
+        let mut engine = engine(dir.path());
+        engine.set_variable("app", "api").set_variable("version", "1.4.2").set_variable("host", "web1")
+            .set_variable("port", 8080).set_variable("user", "deploy");
+        engine
+    });
+}
+
+/// 遍历10000个元素的for循环
+fn large_loop(c: &mut Criterion) {
+    let dir = template_dir();
+    let items: Vec<_> = (0..10_000).map(|i| json!({"name": format!("item-{}", i), "value": i})).collect();
+    let items = json!(items);
+    let template = "{% for item in items %}\n{{ item.name }}={{ item.value }}\n{% endfor %}";
+    bench_render(c, "large_loop", template, || {
+        let mut engine = engine(dir.path());
+        engine.set_variable("items", items.clone());
+        engine
+    });
+}
+
+/// 10层嵌套的include
+fn nested_include(c: &mut Criterion) {
+    let dir = template_dir();
+    bench_render(c, "nested_include", "{% include \"level0.tmpl\" %}", || {
+        let mut engine = engine(dir.path());
+        engine.set_variable("name", "nested");
+        engine
+    });
+}
+
+/// 接近实际使用的YAML配置模板：20个变量、3个循环、2个include
+fn mixed_template(c: &mut Criterion) {
+    let dir = template_dir();
+    let template = r#"{% include "header.tmpl" %}
+app:
+  name: {{ app_name }}
+  version: {{ version }}
+  environment: {{ environment }}
+  owner: {{ owner }}
+  region: {{ region }}
+database:
+  host: {{ db_host }}
+  port: {{ db_port }}
+  name: {{ db_name }}
+  user: {{ db_user }}
+  pool: {{ db_pool }}
+cache:
+  host: {{ cache_host }}
+  port: {{ cache_port }}
+  ttl: {{ cache_ttl }}
+http:
+  listen: {{ listen }}
+  port: {{ http_port }}
+  workers: {{ workers }}
+  timeout: {{ timeout }}
+  log_level: {{ log_level }}
+  tls: {{ tls }}
+  domain: {{ domain }}
+servers:
+{% for server in servers %}
+  - name: {{ server.name }}
+    ip: {{ server.ip }}
+{% endfor %}
+features:
+{% for name, enabled in features %}
+  {{ name }}: {{ enabled }}
+{% endfor %}
+routes:
+{% for route in routes %}
+  - {{ route }}
+{% endfor %}
+{% include "footer.tmpl" %}
+"#;
+    let servers: Vec<_> = (1..=20).map(|i| json!({"name": format!("web{}", i), "ip": format!("10.0.0.{}", i)})).collect();
+    let features: serde_json::Map<_, _> = (1..=20).map(|i| (format!("feature_{}", i), json!(i % 2 == 0))).collect();
+    let routes: Vec<_> = (1..=20).map(|i| format!("/api/v1/resource{}", i)).collect();
+    bench_render(c, "mixed_template", template, || {
+        let mut engine = engine(dir.path());
+        for (name, value) in [
+            ("app_name", json!("api")), ("version", json!("1.4.2")), ("environment", json!("production")),
+            ("owner", json!("platform")), ("region", json!("eu-west-1")), ("db_host", json!("db1")),
+            ("db_port", json!(5432)), ("db_name", json!("app")), ("db_user", json!("app")),
+            ("db_pool", json!(20)), ("cache_host", json!("cache1")), ("cache_port", json!(6379)),
+            ("cache_ttl", json!(300)), ("listen", json!("0.0.0.0")), ("http_port", json!(8080)),
+            ("workers", json!(8)), ("timeout", json!(30)), ("log_level", json!("info")),
+            ("tls", json!(true)), ("domain", json!("api.example.com")),
+            ("servers", json!(servers)), ("features", json!(features)), ("routes", json!(routes)),
+        ] {
+            engine.set_variable(name, value);
+        }
+        engine
+    });
+}
+
+/// 编译渲染与逐次解析渲染的对比组，用于发现性能回退；
+/// 目前只有逐次解析的实现，编译渲染加入后在本组中增加对应的函数
+fn compiled_vs_interpreted(c: &mut Criterion) {
+    let dir = template_dir();
+    let template = "{% for item in items %}\n{{ item }} on {{ host }}\n{% endfor %}";
+    let setup = || {
+        let mut engine = engine(dir.path());
+        engine.set_variable("items", json!((0..1_000).collect::<Vec<_>>())).set_variable("host", "web1");
+        engine
+    };
+    let rendered = setup().render_string(template).unwrap();
+    let mut group = c.benchmark_group("compiled_vs_interpreted");
+    group.throughput(Throughput::Bytes(rendered.len() as u64));
+    group.bench_function("interpreted", |b| {
+        b.iter_batched(&setup, |engine| black_box(engine.render_string(black_box(template)).unwrap()), BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+criterion_group!(benches, simple_variable, large_loop, nested_include, mixed_template, compiled_vs_interpreted);
+criterion_main!(benches);
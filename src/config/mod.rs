@@ -1,10 +1,111 @@
 use anyhow::{Context, Result};
+use regex::Regex;
 use serde_yaml;
+use std::fmt;
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::models::RemoteExecutionConfig;
-use crate::vars::VariableManager;
+use crate::executor::dag::StepGraph;
+use crate::executor::schedule::CronSchedule;
+use crate::models::{ClientConfig, ExtractRule, Pipeline, RemoteExecutionConfig, Step};
+use crate::ssh::step_phases;
+use crate::template::TemplateEngine;
+use crate::vars::{arithmetic, placeholder_regex, split_placeholder, VariableManager, STEP_ELAPSED_VARIABLE};
+
+mod templates;
+use templates::expand_step_templates;
+
+/// 执行时自动设置的内置变量
+const BUILTIN_VARIABLES: [&str; 4] = ["pipeline_name", "step_name", "ssh_server_name", "ssh_server_ip"];
+
+/// 配置模板的分隔符：变量 #{ name }，控制语句 #{% ... %}，与执行时替换的 {{ name }} 区分
+/// 控制语句以 # 开头，未渲染的模板也能作为YAML解析出 variables
+const CONFIG_TEMPLATE_DELIMITERS: (&str, &str, &str, &str) = ("#{", "}", "#{%", "%}");
+
+/// 提取规则支持的输出来源
+const EXTRACT_SOURCES: [&str; 3] = ["stdout", "stderr", "exit_code"];
+
+/// 配置检查发现的问题，不影响执行，由调用方决定如何展示
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationWarning {
+    /// 提取规则的变量名与初始变量同名，提取后会覆盖初始值
+    ShadowedVariable { pipeline: String, step: String, rule: String },
+    /// 脚本中的占位符没有任何变量、提取规则或内置变量可以提供，执行时会原样保留
+    UnresolvedPlaceholder { script: String, name: String },
+    /// 客户端开启了 local_fallback，SSH连接失败时步骤会改为在本地执行
+    LocalFallback { client: String },
+    /// 步骤依赖列表中排在它后面、且属于另一分组的步骤，分组的书写顺序与实际执行顺序不一致
+    CrossGroupDependency { pipeline: String, step: String, dependency: String },
+}
+
+impl fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationWarning::ShadowedVariable { pipeline, step, rule } => write!(
+                f, "Extract rule '{}' in step '{}' of pipeline '{}' overwrites an initial variable",
+                rule, step, pipeline
+            ),
+            ValidationWarning::UnresolvedPlaceholder { script, name } => write!(
+                f, "Placeholder '{{{{ {} }}}}' in script '{}' is never defined", name, script
+            ),
+            ValidationWarning::LocalFallback { client } => write!(
+                f, "Client '{}' has local_fallback enabled; its steps run locally when SSH fails", client
+            ),
+            ValidationWarning::CrossGroupDependency { pipeline, step, dependency } => write!(
+                f, "Step '{}' in pipeline '{}' depends on '{}', a later step in another group",
+                step, pipeline, dependency
+            ),
+        }
+    }
+}
+
+impl ValidationWarning {
+    /// 问题所在位置，用于 ValidationIssue
+    fn location(&self) -> String {
+        match self {
+            ValidationWarning::ShadowedVariable { pipeline, step, .. } => format!("pipeline '{}' step '{}'", pipeline, step),
+            ValidationWarning::UnresolvedPlaceholder { script, .. } => format!("script '{}'", script),
+            ValidationWarning::LocalFallback { client } => format!("client '{}'", client),
+            ValidationWarning::CrossGroupDependency { pipeline, step, .. } => format!("pipeline '{}' step '{}'", pipeline, step),
+        }
+    }
+}
+
+/// 配置问题的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IssueSeverity {
+    /// 执行会失败
+    Error,
+    /// 可能不会按预期执行
+    Warning,
+    /// 最佳实践建议
+    Info,
+}
+
+/// validate_config_and_explain 报告的配置问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub severity: IssueSeverity,
+    pub location: String, // 如 "pipeline 'deploy' step 'migrate'"
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn new(severity: IssueSeverity, location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { severity, location: location.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            IssueSeverity::Error => "error",
+            IssueSeverity::Warning => "warning",
+            IssueSeverity::Info => "info",
+        };
+        write!(f, "[{}] {}: {}", severity, self.location, self.message)
+    }
+}
 
 /// 配置管理器
 pub struct ConfigManager;
@@ -20,10 +121,7 @@ impl ConfigManager {
 
     /// 从YAML字符串加载配置（不处理变量替换）
     pub fn from_yaml_str_raw(yaml_content: &str) -> Result<RemoteExecutionConfig> {
-        let config: RemoteExecutionConfig = serde_yaml::from_str(yaml_content)
-            .context("Failed to parse YAML configuration")?;
-        
-        Ok(config)
+        parse_config(yaml_content).context("Failed to parse YAML configuration")
     }
 
     /// 从YAML文件加载配置并应用变量替换
@@ -40,10 +138,7 @@ impl ConfigManager {
         let replaced_content = variable_manager.replace_variables(yaml_content);
         
         // 解析替换后的内容为最终配置
-        let config: RemoteExecutionConfig = serde_yaml::from_str(&replaced_content)
-            .context("Failed to parse YAML configuration after variable replacement")?;
-        
-        Ok(config)
+        parse_config(&replaced_content).context("Failed to parse YAML configuration after variable replacement")
     }
 
     /// 提取YAML中的初始变量
@@ -51,19 +146,61 @@ impl ConfigManager {
         let yaml_value: serde_yaml::Value = serde_yaml::from_str(yaml_content)
             .context("Failed to parse YAML for variable extraction")?;
         
-        let initial_variables = if let Some(vars) = yaml_value.get("variables") {
-            if let Ok(vars_map) = serde_yaml::from_value::<HashMap<String, String>>(vars.clone()) {
-                Some(vars_map)
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        let initial_variables = yaml_value
+            .get("variables")
+            .and_then(|vars| serde_yaml::from_value::<HashMap<String, String>>(vars.clone()).ok());
         
         Ok(initial_variables)
     }
 
+    /// 用 TemplateEngine 渲染配置模板（分隔符为 #{ } 与 #{% %}），渲染结果再按普通配置解析
+    /// 模板中 variables 的值与 data 都可以在模板中引用，同名时 data 优先；未定义的变量渲染为空字符串
+    /// template_dir 为 include 的相对路径基准
+    pub fn render_template(template: &str, data: HashMap<String, serde_json::Value>, template_dir: Option<&Path>) -> Result<String> {
+        Self::config_template_engine(template, data, template_dir)
+            .render_string(template)
+            .context("Failed to render configuration template")
+    }
+
+    /// 与 render_template 相同，但引用未定义的变量、缺失的数组或属性时返回错误
+    pub fn render_template_strict(template: &str, data: HashMap<String, serde_json::Value>, template_dir: Option<&Path>) -> Result<String> {
+        Self::config_template_engine(template, data, template_dir)
+            .render_string_strict(template)
+            .context("Failed to render configuration template")
+    }
+
+    fn config_template_engine(template: &str, data: HashMap<String, serde_json::Value>, template_dir: Option<&Path>) -> TemplateEngine {
+        let (left, right, block_left, block_right) = CONFIG_TEMPLATE_DELIMITERS;
+        let mut engine = TemplateEngine::with_all_delimiters(left, right, block_left, block_right);
+        if let Some(dir) = template_dir {
+            engine.set_template_dir(dir);
+        }
+        // 模板未渲染时可能还不是合法的配置，此时只使用 data
+        let initial = Self::extract_initial_variables(template).ok().flatten().unwrap_or_default();
+        for (k, v) in initial {
+            engine.set_variable(k, v);
+        }
+        engine.set_variables(data);
+        engine.set_preserve_loop_newlines(false);
+        engine
+    }
+
+    /// 读取模板数据文件：扩展名为 .json 时按JSON解析，否则按YAML解析；顶层必须是映射
+    pub fn load_template_data<P: AsRef<Path>>(path: P) -> Result<HashMap<String, serde_json::Value>> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template data file '{}'", path.display()))?;
+        let data: serde_json::Value = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&content).with_context(|| format!("Failed to parse JSON data file '{}'", path.display()))?
+        } else {
+            serde_yaml::from_str(&content).with_context(|| format!("Failed to parse YAML data file '{}'", path.display()))?
+        };
+        match data {
+            serde_json::Value::Object(map) => Ok(map.into_iter().collect()),
+            _ => Err(anyhow::anyhow!("Template data file '{}' must contain a mapping at the top level", path.display())),
+        }
+    }
+
     /// 从YAML文件加载配置（保持向后兼容）
     pub fn from_yaml_file<P: AsRef<Path>>(path: P) -> Result<RemoteExecutionConfig> {
         let content = std::fs::read_to_string(path)
@@ -84,8 +221,18 @@ impl ConfigManager {
         Self::from_yaml_str_with_variables(yaml_content, &variable_manager)
     }
 
-    /// 验证配置的有效性
-    pub fn validate_config(config: &RemoteExecutionConfig) -> Result<()> {
+    /// 验证配置的有效性，返回不影响执行的警告
+    pub fn validate_config(config: &RemoteExecutionConfig) -> Result<Vec<ValidationWarning>> {
+        Self::validate_config_with_variables(config, config.variables.as_ref().unwrap_or(&HashMap::new()))
+    }
+
+    /// 验证配置的有效性，variables 为执行开始时的初始变量（配置变量与调用方传入的变量）
+    pub fn validate_config_with_variables(
+        config: &RemoteExecutionConfig,
+        variables: &HashMap<String, String>,
+    ) -> Result<Vec<ValidationWarning>> {
+        let mut warnings = Vec::new();
+
         // 检查是否有客户端配置
         if config.clients.is_empty() {
             return Err(anyhow::anyhow!("No clients configured"));
@@ -96,26 +243,609 @@ impl ConfigManager {
             return Err(anyhow::anyhow!("No pipelines configured"));
         }
 
-        // 检查每个流水线的步骤
-        for pipeline in &config.pipelines {
-            if pipeline.steps.is_empty() {
-                return Err(anyhow::anyhow!("Pipeline '{}' has no steps", pipeline.name));
+        // 检查每个流水线的步骤，before_all / after_all 按同名流水线检查
+        for pipeline in config.pipelines.iter().chain(&hook_pipelines(config)) {
+            check_pipeline(pipeline)?;
+            for step in &pipeline.steps {
+                check_step(config, pipeline, step, variables, &mut warnings)?;
             }
+            warnings.extend(cross_group_dependencies(pipeline));
+        }
+
+        warnings.extend(unresolved_placeholders(config, variables));
+        warnings.extend(local_fallback_clients(config));
+        Ok(warnings)
+    }
 
+    /// 检查配置并列出所有问题，不在第一个错误处停止
+    /// 除 validate_config 的错误与警告外，还报告未被任何步骤使用的客户端、未被引用的变量，
+    /// 以及使用密码登录的SSH客户端（建议改用密钥）。
+    /// 加载时已替换的变量引用无法再看到，检查未引用变量时应使用 from_yaml_str_raw 加载的配置
+    pub fn validate_config_and_explain(config: &RemoteExecutionConfig) -> Vec<ValidationIssue> {
+        let empty = HashMap::new();
+        let variables = config.variables.as_ref().unwrap_or(&empty);
+        let mut issues = Vec::new();
+        let mut warnings = Vec::new();
+
+        if config.clients.is_empty() {
+            issues.push(ValidationIssue::new(IssueSeverity::Error, "clients", "No clients configured"));
+        }
+        if config.pipelines.is_empty() {
+            issues.push(ValidationIssue::new(IssueSeverity::Error, "pipelines", "No pipelines configured"));
+        }
+        for pipeline in config.pipelines.iter().chain(&hook_pipelines(config)) {
+            if let Err(e) = check_pipeline(pipeline) {
+                issues.push(ValidationIssue::new(IssueSeverity::Error, format!("pipeline '{}'", pipeline.name), e.to_string()));
+            }
             for step in &pipeline.steps {
-                // 允许空服务器列表用于本地执行
-                if !step.servers.is_empty() {
-                    // 检查步骤中引用的服务器是否存在
-                    for server in &step.servers {
-                        if !config.clients.contains_key(server) {
-                            return Err(anyhow::anyhow!("Server '{}' referenced in step '{}' not found in clients", 
-                                                      server, step.name));
-                        }
-                    }
+                if let Err(e) = check_step(config, pipeline, step, variables, &mut warnings) {
+                    let location = format!("pipeline '{}' step '{}'", pipeline.name, step.name);
+                    issues.push(ValidationIssue::new(IssueSeverity::Error, location, e.to_string()));
+                }
+            }
+            warnings.extend(cross_group_dependencies(pipeline));
+        }
+
+        warnings.extend(unresolved_placeholders(config, variables));
+        warnings.extend(local_fallback_clients(config));
+        issues.extend(warnings.iter().map(|w| ValidationIssue::new(IssueSeverity::Warning, w.location(), w.to_string())));
+        issues.extend(unused_clients(config));
+        issues.extend(unused_variables(config));
+
+        let mut names: Vec<&String> = config.clients.keys().collect();
+        names.sort();
+        for name in names {
+            let ssh = config.clients[name].ssh_config.as_ref();
+            if ssh.is_some_and(|ssh| ssh.password.is_some() && ssh.private_key_path.is_none()) {
+                issues.push(ValidationIssue::new(
+                    IssueSeverity::Info,
+                    format!("client '{}'", name),
+                    "Uses password authentication; consider private_key_path instead",
+                ));
+            }
+        }
+
+        issues.sort_by_key(|issue| issue.severity);
+        issues
+    }
+}
+
+/// 解析配置，先展开 step_templates 中的步骤模板
+fn parse_config(yaml_content: &str) -> Result<RemoteExecutionConfig> {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(yaml_content)?;
+    expand_step_templates(&mut value)?;
+    Ok(serde_yaml::from_value(value)?)
+}
+
+/// 检查流水线本身：步骤不能为空，步骤依赖存在且无环，schedule 可以解析
+fn check_pipeline(pipeline: &Pipeline) -> Result<()> {
+    if pipeline.steps.is_empty() {
+        return Err(anyhow::anyhow!("Pipeline '{}' has no steps", pipeline.name));
+    }
+
+    // 检查步骤依赖是否存在以及是否有环
+    StepGraph::new(pipeline)?;
+
+    if let Some(schedule) = &pipeline.schedule {
+        CronSchedule::parse(schedule)
+            .map_err(|e| anyhow::anyhow!("Invalid schedule in pipeline '{}': {}", pipeline.name, e))?;
+    }
+    Ok(())
+}
+
+/// 检查单个步骤：执行条件、提取规则、标签选择与引用的服务器
+fn check_step(
+    config: &RemoteExecutionConfig,
+    pipeline: &Pipeline,
+    step: &Step,
+    variables: &HashMap<String, String>,
+    warnings: &mut Vec<ValidationWarning>,
+) -> Result<()> {
+    // 检查执行条件的语法，条件在步骤执行前按当时的变量求值
+    if let Some(condition) = &step.condition {
+        crate::vars::condition::validate(condition)
+            .map_err(|e| anyhow::anyhow!("Invalid condition in step '{}': {}", step.name, e))?;
+    }
+
+    for rule in step.extract.iter().flatten() {
+        check_extract_rule(pipeline, step, rule, variables, warnings)?;
+    }
+
+    // 提示应答的正则在加载时检查
+    for response in step.responses.iter().flatten() {
+        Regex::new(&response.expect_regex).map_err(|e| anyhow::anyhow!(
+            "Invalid expect_regex '{}' in step '{}': {}", response.expect_regex, step.name, e
+        ))?;
+    }
+
+    // 断言中的变量在执行时替换，检查正则时用占位文本代替
+    if let Some(pattern) = step.assert.as_ref().and_then(|a| a.stdout_matches.as_ref()) {
+        Regex::new(&placeholder_regex().replace_all(pattern, "x")).map_err(|e| anyhow::anyhow!(
+            "Invalid stdout_matches '{}' in step '{}': {}", pattern, step.name, e
+        ))?;
+    }
+
+    // 标签选择在加载时解析，没有匹配的服务器时报错，除非允许为空
+    if let Some(selector) = &step.server_selector {
+        if select_clients(&config.clients, selector).is_empty() && !step.allow_empty_selection.unwrap_or(false) {
+            return Err(anyhow::anyhow!(
+                "server_selector {:?} in step '{}' matches no clients (set allow_empty_selection: true to skip the step instead)",
+                selector, step.name
+            ));
+        }
+    }
+
+    // 允许空服务器列表用于本地执行
+    // 检查步骤中引用的服务器是否存在，含变量占位符的项在运行时解析，逗号分隔的项逐个检查
+    for server in static_servers(step) {
+        if !config.clients.contains_key(server) {
+            return Err(anyhow::anyhow!("Server '{}' referenced in step '{}' not found in clients",
+                                      server, step.name));
+        }
+    }
+    for server in step.servers_failover.iter().flatten().map(|s| s.trim()).filter(|s| !s.is_empty() && !s.contains("{{")) {
+        if !config.clients.contains_key(server) {
+            return Err(anyhow::anyhow!("Failover server '{}' referenced in step '{}' not found in clients",
+                                      server, step.name));
+        }
+    }
+    Ok(())
+}
+
+/// 依赖排在自己后面的另一分组步骤的步骤
+fn cross_group_dependencies(pipeline: &Pipeline) -> Vec<ValidationWarning> {
+    let mut warnings = Vec::new();
+    for (index, step) in pipeline.steps.iter().enumerate() {
+        for name in step.depends_on.iter().flatten() {
+            let later = pipeline.steps.iter().skip(index + 1)
+                .any(|dep| &dep.name == name && dep.group_name() != step.group_name());
+            if later {
+                warnings.push(ValidationWarning::CrossGroupDependency {
+                    pipeline: pipeline.name.clone(),
+                    step: step.name.clone(),
+                    dependency: name.clone(),
+                });
+            }
+        }
+    }
+    warnings
+}
+
+/// before_all / after_all 对应的流水线
+fn hook_pipelines(config: &RemoteExecutionConfig) -> Vec<Pipeline> {
+    config.before_all_pipeline().into_iter().chain(config.after_all_pipeline()).collect()
+}
+
+/// 配置中的所有步骤：各流水线的步骤以及 before_all / after_all 的步骤
+fn all_steps(config: &RemoteExecutionConfig) -> impl Iterator<Item = &Step> {
+    config.pipelines.iter().flat_map(|p| p.steps.iter())
+        .chain(config.before_all.iter().flatten())
+        .chain(config.after_all.iter().flatten())
+}
+
+/// 步骤中不含变量占位符的服务器名（逗号分隔的项逐个拆开）
+fn static_servers(step: &Step) -> impl Iterator<Item = &str> {
+    step.servers.iter()
+        .filter(|s| !s.contains("{{"))
+        .flat_map(|entry| entry.split(',').map(str::trim).filter(|s| !s.is_empty()))
+}
+
+/// 标签与 selector 全部匹配的客户端名称，按名称排序
+pub fn select_clients(clients: &HashMap<String, ClientConfig>, selector: &HashMap<String, String>) -> Vec<String> {
+    let mut names: Vec<String> = clients
+        .iter()
+        .filter(|(_, client)| client.matches_labels(selector))
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// 检查提取规则：source 是否有效、正则能否编译、变量名是否覆盖初始变量
+fn check_extract_rule(
+    pipeline: &Pipeline,
+    step: &Step,
+    rule: &ExtractRule,
+    variables: &HashMap<String, String>,
+    warnings: &mut Vec<ValidationWarning>,
+) -> Result<()> {
+    if !EXTRACT_SOURCES.contains(&rule.source.as_str()) {
+        return Err(anyhow::anyhow!(
+            "Unknown extract source '{}' for rule '{}' in step '{}' (expected one of: {})",
+            rule.source, rule.name, step.name, EXTRACT_SOURCES.join(", ")
+        ));
+    }
+
+    if rule.patterns.is_empty() && rule.source != "exit_code" {
+        return Err(anyhow::anyhow!(
+            "Extract rule '{}' in step '{}' has no patterns (only source 'exit_code' may omit them)",
+            rule.name, step.name
+        ));
+    }
+
+    for (index, pattern) in rule.patterns.iter().enumerate() {
+        // 表达式中的变量在执行时才有值，检查时用普通文本代替
+        let pattern_text = placeholder_regex().replace_all(pattern, "x");
+        Regex::new(&rule.pattern_text(&pattern_text)).map_err(|e| anyhow::anyhow!(
+            "Invalid regex pattern {} for rule '{}' in step '{}': {}: {}",
+            index + 1, rule.name, step.name, pattern, e
+        ))?;
+    }
+
+    if variables.contains_key(&rule.name) {
+        warnings.push(ValidationWarning::ShadowedVariable {
+            pipeline: pipeline.name.clone(),
+            step: step.name.clone(),
+            rule: rule.name.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// 提取规则可以设置的变量名，包括保存的中间阶段
+fn extracted_names(rule: &ExtractRule) -> Vec<String> {
+    let mut names = vec![rule.name.clone()];
+    if rule.cascade && rule.capture_intermediates == Some(true) {
+        let stages = rule.patterns.len().saturating_sub(1);
+        names.extend((0..stages).map(|index| {
+            rule.named_stages.as_ref()
+                .and_then(|stage_names| stage_names.get(index))
+                .cloned()
+                .unwrap_or_else(|| format!("{}_stage_{}", rule.name, index + 1))
+        }));
+    }
+    names
+}
+
+/// 配置引用的所有脚本：全局脚本、流水线脚本以及各步骤的各阶段脚本
+fn referenced_scripts(config: &RemoteExecutionConfig) -> impl Iterator<Item = &str> {
+    config.global_scripts.iter().flatten().map(String::as_str)
+        .chain(config.pipelines.iter().filter_map(|p| p.script.as_deref()))
+        .chain(all_steps(config).flat_map(|step| {
+            step_phases(step).into_iter().chain(step.local_scripts.iter().map(String::as_str))
+        }))
+}
+
+/// 开启了 local_fallback 的客户端，按名称排序
+fn local_fallback_clients(config: &RemoteExecutionConfig) -> Vec<ValidationWarning> {
+    let mut clients: Vec<String> = config.clients.iter()
+        .filter(|(_, client)| client.local_fallback == Some(true))
+        .map(|(name, _)| name.clone())
+        .collect();
+    clients.sort();
+    clients.into_iter().map(|client| ValidationWarning::LocalFallback { client }).collect()
+}
+
+/// 没有被任何步骤的 servers 或 server_selector 使用的客户端
+/// 有步骤的服务器列表含变量占位符时无法确定，不做检查
+fn unused_clients(config: &RemoteExecutionConfig) -> Vec<ValidationIssue> {
+    let steps = || all_steps(config);
+    if steps().any(|step| step.servers.iter().any(|s| s.contains("{{"))) {
+        return Vec::new();
+    }
+    let mut used: HashSet<String> = steps().flat_map(static_servers).map(str::to_string).collect();
+    for selector in steps().filter_map(|step| step.server_selector.as_ref()) {
+        used.extend(select_clients(&config.clients, selector));
+    }
+
+    let mut unused: Vec<&String> = config.clients.keys().filter(|name| !used.contains(*name)).collect();
+    unused.sort();
+    unused.into_iter()
+        .map(|name| ValidationIssue::new(IssueSeverity::Warning, format!("client '{}'", name), "Client is not used by any step"))
+        .collect()
+}
+
+/// 定义后没有被引用的变量
+/// 引用包括配置中的占位符、执行条件中的变量名，以及脚本中的占位符或 `$name` 环境变量；
+/// 有脚本无法读取时无法确定，不做检查
+fn unused_variables(config: &RemoteExecutionConfig) -> Vec<ValidationIssue> {
+    let Some(variables) = &config.variables else {
+        return Vec::new();
+    };
+    let mut texts = vec![serde_yaml::to_string(config).unwrap_or_default()];
+    let mut seen = HashSet::new();
+    for script in referenced_scripts(config) {
+        if !seen.insert(script) {
+            continue;
+        }
+        match std::fs::read_to_string(script) {
+            Ok(content) => texts.push(content),
+            Err(_) => return Vec::new(),
+        }
+    }
+    let conditions: Vec<&str> = all_steps(config)
+        .filter_map(|step| step.condition.as_deref())
+        .collect();
+
+    // 算术表达式占位符（如 {{ count + 1 }}）引用其中的每个变量
+    let mut referenced: HashSet<String> = texts.iter()
+        .flat_map(|text| placeholder_regex().captures_iter(text))
+        .flat_map(|caps| {
+            let name = split_placeholder(&caps[1]).0;
+            std::iter::once(name.to_string()).chain(arithmetic::variable_names(name))
+        })
+        .collect();
+    let identifier = Regex::new(r"[\w.\-]+").unwrap();
+    referenced.extend(conditions.iter().flat_map(|c| identifier.find_iter(c)).map(|m| m.as_str().to_string()));
+
+    let mut names: Vec<&String> = variables.keys()
+        .filter(|name| !referenced.contains(*name))
+        .filter(|name| {
+            let env = Regex::new(&format!(r"\$\{{?{}\b", regex::escape(name))).unwrap();
+            !texts[1..].iter().any(|text| env.is_match(text))
+        })
+        .collect();
+    names.sort();
+    names.into_iter()
+        .map(|name| ValidationIssue::new(IssueSeverity::Warning, format!("variable '{}'", name), "Variable is defined but never referenced"))
+        .collect()
+}
+
+/// 扫描步骤引用的脚本，找出任何变量、提取规则和内置变量都无法提供的占位符
+/// 读取失败或路径中仍含变量的脚本在执行时才能确定，这里跳过
+fn unresolved_placeholders(config: &RemoteExecutionConfig, variables: &HashMap<String, String>) -> Vec<ValidationWarning> {
+    let steps = || all_steps(config);
+    let mut known: HashSet<String> = variables.keys().cloned().collect();
+    known.extend(BUILTIN_VARIABLES.iter().map(|name| name.to_string()));
+    known.extend(config.clients.values().flat_map(|client| client.variables.iter().flatten()).map(|(name, _)| name.clone()));
+    for step in steps() {
+        known.extend(step.variables.iter().flatten().map(|(name, _)| name.clone()));
+        known.extend(step.extract.iter().flatten().flat_map(extracted_names));
+        if step.extract.as_ref().is_some_and(|rules| !rules.is_empty()) {
+            known.insert(STEP_ELAPSED_VARIABLE.to_string());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut warnings = Vec::new();
+    for script in referenced_scripts(config) {
+        if !seen.insert(script) || script.contains("{{") {
+            continue;
+        }
+        let Ok(content) = std::fs::read_to_string(script) else {
+            continue;
+        };
+        let mut reported = HashSet::new();
+        for caps in placeholder_regex().captures_iter(&content) {
+            let (name, _) = split_placeholder(&caps[1]);
+            // 算术表达式只检查其中引用的变量
+            let names = if known.contains(name) || !arithmetic::is_expression(name) {
+                vec![name.to_string()]
+            } else {
+                arithmetic::variable_names(name)
+            };
+            for name in names {
+                if !known.contains(&name) && reported.insert(name.clone()) {
+                    warnings.push(ValidationWarning::UnresolvedPlaceholder {
+                        script: script.to_string(),
+                        name,
+                    });
                 }
             }
         }
+    }
+    warnings
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn config_with_step(step_yaml: &str) -> RemoteExecutionConfig {
+        let yaml = format!(r#"
+variables:
+  app_name: "myapp"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+{}
+"#, step_yaml);
+        ConfigManager::from_yaml_str_raw(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_invalid_extract_rules_are_rejected() {
+        let config = config_with_step(r#"
+      - name: "build"
+        script: "build.sh"
+        extract:
+          - name: "version"
+            patterns: ["v(\\d+)", "(unclosed"]
+            source: "stdout"
+"#);
+        let err = ConfigManager::validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("pattern 2 for rule 'version'") && err.contains("(unclosed"), "{}", err);
+
+        // 表达式中的变量在执行时替换，不影响检查
+        let config = config_with_step(r#"
+      - name: "build"
+        script: "build.sh"
+        extract:
+          - name: "version"
+            patterns: ["{{ app_name }}-v(\\d+)"]
+            source: "sdtout"
+"#);
+        let err = ConfigManager::validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("Unknown extract source 'sdtout' for rule 'version'"), "{}", err);
+
+        // 只有 exit_code 规则可以省略正则
+        let rules = |source: &str| config_with_step(&format!(r#"
+      - name: "build"
+        script: "build.sh"
+        extract:
+          - name: "status"
+            source: "{}"
+"#, source));
+        assert!(ConfigManager::validate_config(&rules("exit_code")).is_ok());
+        let err = ConfigManager::validate_config(&rules("stdout")).unwrap_err().to_string();
+        assert!(err.contains("Extract rule 'status' in step 'build' has no patterns"), "{}", err);
+    }
+
+    #[test]
+    fn test_local_clients_and_serverless_steps_are_valid() {
+        use crate::models::ExecutionMethod;
+        // 没有 servers 的步骤在执行器所在机器上执行
+        let config = config_with_step("      - name: \"build\"\n        script: \"build.sh\"\n");
+        ConfigManager::validate_config(&config).unwrap();
+
+        let yaml = r#"
+clients:
+  controller:
+    name: "controller"
+    execution_method: local
+    variables:
+      region: "ams"
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "build"
+        script: "build.sh"
+        servers: ["controller"]
+"#;
+        let config = ConfigManager::from_yaml_str_raw(yaml).unwrap();
+        assert_eq!(config.clients["controller"].execution_method, ExecutionMethod::Local);
+        assert!(config.clients["controller"].ssh_config.is_none());
+        ConfigManager::validate_config(&config).unwrap();
+    }
+
+    #[test]
+    fn test_validate_config_and_explain_reports_all_issues() {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "echo {{{{ app_name }}}} $REGION").unwrap();
+        let yaml = format!(r#"
+variables:
+  app_name: "myapp"
+  REGION: "ams"
+  stale: "unused"
+  env: "prod"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+    ssh_config:
+      host: "10.0.0.1"
+      port: 22
+      username: "deploy"
+      password: "secret"
+  spare:
+    name: "spare"
+    execution_method: local
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "step_a"
+        script: "{0}"
+        servers: ["web1"]
+        depends_on: ["step_b"]
+      - name: "step_b"
+        script: "{0}"
+        servers: ["web1"]
+        depends_on: ["step_a"]
+  - name: "migrate"
+    steps:
+      - name: "run"
+        script: "{0}"
+        servers: ["db9"]
+        condition: 'env == "prod"'
+"#, script.path().display());
+        let config = ConfigManager::from_yaml_str_raw(&yaml).unwrap();
+
+        let issues = ConfigManager::validate_config_and_explain(&config);
+        let summary: Vec<(IssueSeverity, &str)> = issues.iter().map(|i| (i.severity, i.location.as_str())).collect();
+        assert_eq!(summary, [
+            (IssueSeverity::Error, "pipeline 'deploy'"),
+            (IssueSeverity::Error, "pipeline 'migrate' step 'run'"),
+            (IssueSeverity::Warning, "client 'spare'"),
+            (IssueSeverity::Warning, "variable 'stale'"),
+            (IssueSeverity::Info, "client 'web1'"),
+        ]);
+        assert!(issues[0].message.contains("form a cycle: step_a -> step_b -> step_a"), "{}", issues[0]);
+        assert!(issues[1].message.contains("Server 'db9'"), "{}", issues[1]);
+        assert_eq!(issues[3].to_string(), "[warning] variable 'stale': Variable is defined but never referenced");
+    }
+
+    #[test]
+    fn test_rule_shadowing_initial_variable_warns() {
+        let config = config_with_step(r#"
+      - name: "build"
+        script: "build.sh"
+        extract:
+          - name: "app_name"
+            patterns: ["name: (\\S+)"]
+            source: "stdout"
+"#);
+        let warnings = ConfigManager::validate_config(&config).unwrap();
+        assert_eq!(warnings, vec![ValidationWarning::ShadowedVariable {
+            pipeline: "deploy".to_string(),
+            step: "build".to_string(),
+            rule: "app_name".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_dependency_on_later_step_in_other_group_warns() {
+        let config = config_with_step(r#"
+      - { name: "migrate", script: "migrate.sh", group: "db", depends_on: ["build"] }
+      - { name: "seed", script: "seed.sh", group: "db", depends_on: ["index"] }
+      - { name: "build", script: "build.sh", group: "app", depends_on: [] }
+      - { name: "index", script: "index.sh", group: "db", depends_on: [] }
+"#);
+        let warnings = ConfigManager::validate_config(&config).unwrap();
+        assert_eq!(warnings, vec![ValidationWarning::CrossGroupDependency {
+            pipeline: "deploy".to_string(),
+            step: "migrate".to_string(),
+            dependency: "build".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_unresolved_placeholders_in_scripts() {
+        let mut first = tempfile::NamedTempFile::new().unwrap();
+        writeln!(first, "echo {{{{ app_name }}}} {{{{ step_name }}}} {{{{ varible }}}} {{{{ varible }}}}").unwrap();
+        let mut second = tempfile::NamedTempFile::new().unwrap();
+        writeln!(second, "echo {{{{ version }}}} {{{{ release_line }}}} {{{{ version_stage_2 }}}} {{{{ port }}}}").unwrap();
+
+        let config = config_with_step(&format!(r#"
+      - name: "build"
+        script: "{}"
+        extract:
+          - name: "version"
+            patterns: ["release: (.+)", "v(\\S+)", "(\\d+)"]
+            source: "stdout"
+            capture_intermediates: true
+            named_stages: ["release_line"]
+      - name: "deploy"
+        script: "{}"
+        variables:
+          port: "8080"
+"#, first.path().display(), second.path().display()));
+
+        let warnings = ConfigManager::validate_config(&config).unwrap();
+        assert_eq!(warnings, vec![ValidationWarning::UnresolvedPlaceholder {
+            script: first.path().display().to_string(),
+            name: "varible".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_config_template_is_strict_only_on_request() {
+        let template = "clients: {}\nowner: \"#{ owner }\"\n#{% for host in hosts %}\n# #{ host }\n#{% endfor %}\n";
+
+        // 默认未定义的变量渲染为空字符串，缺失的数组不展开
+        let rendered = ConfigManager::render_template(template, HashMap::new(), None).unwrap();
+        assert_eq!(rendered.trim_end(), "clients: {}\nowner: \"\"");
 
-        Ok(())
+        let err = ConfigManager::render_template_strict(template, HashMap::new(), None).unwrap_err();
+        assert!(format!("{:#}", err).contains("not found in variables"), "{:#}", err);
+        let data = HashMap::from([
+            ("owner".to_string(), serde_json::json!("ops")),
+            ("hosts".to_string(), serde_json::json!(["web1"])),
+        ]);
+        let rendered = ConfigManager::render_template_strict(template, data, None).unwrap();
+        assert!(rendered.contains("owner: \"ops\"") && rendered.contains("# web1"), "{}", rendered);
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::models::Step;
+
+/// 展开步骤模板：pipelines、before_all、after_all 中写了 `template` 的步骤，
+/// 以 step_templates 中的同名定义为基础，步骤中显式写出的字段覆盖模板的字段；
+/// variables 合并（模板 < 步骤 < with），未写 name 时使用模板名
+pub(crate) fn expand_step_templates(config: &mut Value) -> Result<()> {
+    let templates = match config.get("step_templates") {
+        Some(Value::Mapping(templates)) => templates.clone(),
+        Some(Value::Null) | None => Mapping::new(),
+        Some(_) => bail!("step_templates must map template names to step definitions"),
+    };
+
+    if let Some(Value::Sequence(pipelines)) = config.get_mut("pipelines") {
+        for pipeline in pipelines {
+            let location = format!("pipeline '{}'", pipeline.get("name").and_then(Value::as_str).unwrap_or_default());
+            if let Some(Value::Sequence(steps)) = pipeline.get_mut("steps") {
+                expand_steps(steps, &templates, &location)?;
+            }
+        }
+    }
+    for hook in ["before_all", "after_all"] {
+        if let Some(Value::Sequence(steps)) = config.get_mut(hook) {
+            expand_steps(steps, &templates, hook)?;
+        }
+    }
+    Ok(())
+}
+
+fn expand_steps(steps: &mut [Value], templates: &Mapping, location: &str) -> Result<()> {
+    for (index, step) in steps.iter_mut().enumerate() {
+        let Value::Mapping(fields) = step else { continue };
+        let Some(template_name) = fields.remove("template") else { continue };
+        let location = match fields.get("name").and_then(Value::as_str) {
+            Some(name) => format!("{} step '{}'", location, name),
+            None => format!("{} step #{}", location, index + 1),
+        };
+        let template_name = template_name.as_str()
+            .ok_or_else(|| anyhow!("{}: template must be the name of a step template", location))?;
+        let mut expanded = match templates.get(template_name) {
+            Some(Value::Mapping(template)) => template.clone(),
+            Some(_) => bail!("{}: step template '{}' must be a mapping", location, template_name),
+            None => bail!("{}: unknown step template '{}'", location, template_name),
+        };
+
+        let mut variables = Mapping::new();
+        for layer in [expanded.remove("variables"), fields.remove("variables"), fields.remove("with")] {
+            match layer {
+                Some(Value::Mapping(layer)) => variables.extend(layer),
+                Some(Value::Null) | None => {}
+                Some(_) => bail!("{}: variables and with must be mappings", location),
+            }
+        }
+        expanded.extend(std::mem::take(fields));
+        if !variables.is_empty() {
+            expanded.insert("variables".into(), Value::Mapping(variables));
+        }
+        if !expanded.contains_key("name") {
+            expanded.insert("name".into(), template_name.into());
+        }
+
+        // 在这里解析一次，字段类型不符时报告步骤位置
+        let expanded = Value::Mapping(expanded);
+        serde_yaml::from_value::<Step>(expanded.clone())
+            .with_context(|| format!("{}: invalid step after applying template '{}'", location, template_name))?;
+        *step = expanded;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigManager;
+    use crate::executor::RemoteExecutor;
+    use std::io::Write;
+
+    #[test]
+    fn test_template_shared_by_pipelines_with_different_values() {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "systemctl restart {{{{ service }}}} --timeout {{{{ wait }}}}").unwrap();
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+step_templates:
+  restart_service:
+    script: "{}"
+    timeout_seconds: 30
+    variables:
+      wait: "10"
+      service: "app"
+pipelines:
+  - name: "web"
+    steps:
+      - template: restart_service
+        with: {{ service: nginx }}
+        servers: [web1]
+  - name: "db"
+    steps:
+      - name: "restart db"
+        template: restart_service
+        timeout_seconds: 60
+        with: {{ service: postgres, wait: "20" }}
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        assert_eq!(executor.render_step_script("web", "restart_service").unwrap(), "\nsystemctl restart nginx --timeout 10\n");
+        assert_eq!(executor.render_step_script("db", "restart db").unwrap(), "\nsystemctl restart postgres --timeout 20\n");
+        let pipelines = ConfigManager::from_yaml_str_raw(&yaml).unwrap().pipelines;
+        assert_eq!(pipelines[0].steps[0].servers, ["web1"]);
+        assert_eq!(pipelines[0].steps[0].timeout_seconds, Some(30));
+        assert_eq!(pipelines[1].steps[0].timeout_seconds, Some(60));
+    }
+
+    #[test]
+    fn test_template_errors_name_the_step() {
+        let base = r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+step_templates:
+  restart_service:
+    script: "restart.sh"
+pipelines:
+  - name: "deploy"
+    steps:
+"#;
+        let err = ConfigManager::from_yaml_str_raw(&format!("{}      - template: restart_servce\n", base)).unwrap_err();
+        assert!(format!("{:#}", err).contains("pipeline 'deploy' step #1: unknown step template 'restart_servce'"), "{:#}", err);
+
+        let err = ConfigManager::from_yaml_str_raw(&format!("{}      - {{ name: go, template: restart_service, timeout_seconds: soon }}\n", base)).unwrap_err();
+        assert!(format!("{:#}", err).contains("pipeline 'deploy' step 'go': invalid step after applying template 'restart_service'"), "{:#}", err);
+    }
+}
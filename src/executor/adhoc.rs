@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::Instrument;
+
+use crate::models::{ExtractRule, OutputCallback, Pipeline, Step, StepExecutionResult};
+use super::events::legacy_subscriptions;
+use super::{new_run_id, run_span, with_global_timeout, RemoteExecutor};
+
+/// 临时执行时事件与结果中的流水线名和步骤名
+pub const ADHOC_PIPELINE_NAME: &str = "adhoc";
+
+/// 临时执行的脚本来源
+#[derive(Debug, Clone)]
+pub enum ScriptSource {
+    Inline(String), // 脚本内容，执行前写入临时文件
+    Path(PathBuf),  // 本地脚本文件路径
+}
+
+/// 临时执行的选项，对应步骤的同名配置
+#[derive(Debug, Clone, Default)]
+pub struct AdhocOptions {
+    pub timeout_seconds: Option<u64>, // 执行超时，未设置时使用 default_timeout
+    pub env: HashMap<String, String>, // 作为步骤级变量：用于 {{ }} 替换，并以环境变量传给脚本
+    pub extract: Vec<ExtractRule>,    // 从输出中提取变量，结果见 extracted_variables
+}
+
+impl RemoteExecutor {
+    /// 不编写流水线，直接在指定的服务器上执行一段脚本
+    /// 按单个步骤执行，与流水线步骤使用相同的并发、变量替换和事件机制，事件的 pipeline_name 为 "adhoc"。
+    /// 服务器名必须是配置中的客户端，否则在执行前返回错误并列出可用的客户端
+    pub async fn execute_adhoc(
+        &self,
+        servers: &[String],
+        script: ScriptSource,
+        options: AdhocOptions,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<Vec<StepExecutionResult>> {
+        self.check_adhoc_servers(servers)?;
+
+        // 内联脚本写入临时文件，执行结束后删除
+        let (script_path, _inline_file) = match script {
+            ScriptSource::Path(path) => (path.to_string_lossy().into_owned(), None),
+            ScriptSource::Inline(content) => {
+                let mut file = tempfile::NamedTempFile::new().context("Failed to create adhoc script file")?;
+                std::io::Write::write_all(&mut file, content.as_bytes()).context("Failed to write adhoc script file")?;
+                (file.path().to_string_lossy().into_owned(), Some(file))
+            }
+        };
+
+        let step = Step {
+            name: ADHOC_PIPELINE_NAME.to_string(),
+            script: script_path,
+            servers: servers.to_vec(),
+            timeout_seconds: options.timeout_seconds,
+            extract: (!options.extract.is_empty()).then_some(options.extract),
+            variables: (!options.env.is_empty()).then_some(options.env),
+            export_variables: Some(true),
+            ..Default::default()
+        };
+        let pipeline = Pipeline {
+            name: ADHOC_PIPELINE_NAME.to_string(),
+            steps: vec![step.clone()],
+            ..Default::default()
+        };
+
+        let run_id = new_run_id();
+        let events = self.dispatcher(&run_id, legacy_subscriptions(output_callback, log_callback));
+        let mut variables = self.variable_manager.clone();
+        let run = self.run_step(&pipeline, &step, events.as_ref(), &mut variables);
+        let mut results = with_global_timeout(self.options.global_timeout, run).instrument(run_span(&run_id)).await?;
+        for result in &mut results {
+            result.run_id.clone_from(&run_id);
+        }
+        Ok(results)
+    }
+
+    /// 检查临时执行的服务器都是已配置的客户端
+    fn check_adhoc_servers(&self, servers: &[String]) -> Result<()> {
+        let mut available = self.get_available_clients();
+        available.sort();
+        if servers.is_empty() {
+            anyhow::bail!("No servers given for adhoc execution; available clients: {}", available.join(", "));
+        }
+        let unknown: Vec<&str> = servers.iter()
+            .filter(|server| !self.client_exists(server))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            anyhow::bail!("Unknown server(s) {}; available clients: {}", unknown.join(", "), available.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const YAML: &str = r#"
+clients:
+  local1:
+    name: "local1"
+    execution_method: local
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "noop"
+    steps:
+      - name: "noop"
+        script: "noop.sh"
+"#;
+
+    #[tokio::test]
+    async fn test_adhoc_runs_inline_script_on_local_client() {
+        let executor = RemoteExecutor::from_yaml_str(YAML, None).unwrap();
+        let pipelines = Arc::new(Mutex::new(Vec::new()));
+        let seen = pipelines.clone();
+        let callback: OutputCallback = Arc::new(move |event| seen.lock().unwrap().push(event.pipeline_name));
+
+        let options = AdhocOptions {
+            env: HashMap::from([("GREETING".to_string(), "hi".to_string())]),
+            extract: vec![ExtractRule {
+                name: "greeting".to_string(),
+                patterns: vec!["adhoc (\\w+)".to_string()],
+                source: "stdout".to_string(),
+                cascade: true,
+                capture_intermediates: None,
+                named_stages: None,
+                dotall: None,
+                transform: None,
+                find_all: None,
+            }],
+            ..Default::default()
+        };
+        let script = ScriptSource::Inline("echo \"adhoc $GREETING {{ GREETING }}\"\n".to_string());
+        let results = executor
+            .execute_adhoc(&["local1".to_string()], script, options, Some(callback), None)
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].server_name, "local1");
+        assert_eq!(results[0].execution_result.stdout.trim(), "adhoc hi hi");
+        assert_eq!(results[0].extracted_variables["greeting"], "hi");
+        let pipelines = pipelines.lock().unwrap();
+        assert!(!pipelines.is_empty());
+        assert!(pipelines.iter().all(|name| name == ADHOC_PIPELINE_NAME));
+    }
+
+    #[tokio::test]
+    async fn test_adhoc_rejects_unknown_servers() {
+        let executor = RemoteExecutor::from_yaml_str(YAML, None).unwrap();
+        let script = ScriptSource::Inline("echo hi\n".to_string());
+        let err = executor
+            .execute_adhoc(&["db9".to_string()], script, AdhocOptions::default(), None, None)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Unknown server(s) db9; available clients: local1, web1");
+    }
+}
@@ -0,0 +1,167 @@
+use regex::RegexBuilder;
+
+use crate::models::{ExecutionResult, ExitClass, StepAssert};
+use crate::vars::VariableManager;
+
+/// 错误信息中期望值与实际值各自保留的最大字符数
+const ASSERT_DIFF_MAX_CHARS: usize = 400;
+
+/// 按当前变量替换断言期望值中的占位符
+pub fn render_assert(assert: &mut StepAssert, variables: &VariableManager) {
+    for expected in [&mut assert.stdout_equals, &mut assert.stdout_contains, &mut assert.stdout_matches].into_iter().flatten() {
+        *expected = variables.replace_variables(expected);
+    }
+}
+
+/// 按断言检查有可信退出码的执行结果，返回新的退出码分类
+/// 不满足时步骤失败，error_message 列出每一项不满足的期望；全部满足且设置了 exit_code 时步骤成功，
+/// 否则沿用原来的分类（未设置 exit_code 时脚本本身失败仍然失败）
+pub fn apply_assert(assert: &StepAssert, result: &mut ExecutionResult, exit_class: ExitClass) -> ExitClass {
+    if !result.success && assert.exit_code.is_none() {
+        return exit_class;
+    }
+    let failures = check_assert(assert, result);
+    if !failures.is_empty() {
+        result.success = false;
+        result.error_message = Some(failures.join("\n"));
+        return ExitClass::Failure;
+    }
+    if assert.exit_code.is_some() {
+        result.success = true;
+        result.error_message = None;
+        return ExitClass::Success;
+    }
+    exit_class
+}
+
+/// 检查每一项期望，返回不满足的期望的说明
+pub fn check_assert(assert: &StepAssert, result: &ExecutionResult) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(expected) = assert.exit_code {
+        if result.exit_code != expected {
+            failures.push(format!("exit_code: expected {}, got {}", expected, result.exit_code));
+        }
+    }
+
+    let actual = normalize(assert, &result.stdout);
+    if let Some(expected) = &assert.stdout_equals {
+        let expected_normalized = normalize(assert, expected);
+        if actual != expected_normalized {
+            let line = expected_normalized.lines().zip(actual.lines())
+                .position(|(e, a)| e != a)
+                .unwrap_or_else(|| expected_normalized.lines().count().min(actual.lines().count()));
+            failures.push(format!(
+                "stdout_equals: first difference at line {}\n  expected: {}\n  actual:   {}",
+                line + 1, excerpt(expected), excerpt(&result.stdout)
+            ));
+        }
+    }
+    if let Some(expected) = &assert.stdout_contains {
+        if !actual.contains(&normalize(assert, expected)) {
+            failures.push(format!(
+                "stdout_contains: text not found\n  expected: {}\n  actual:   {}",
+                excerpt(expected), excerpt(&result.stdout)
+            ));
+        }
+    }
+    if let Some(pattern) = &assert.stdout_matches {
+        let regex = RegexBuilder::new(pattern).multi_line(true).case_insensitive(assert.ignore_case).build();
+        match regex {
+            Ok(regex) if regex.is_match(&actual) => {}
+            Ok(_) => failures.push(format!(
+                "stdout_matches: pattern did not match\n  expected: /{}/\n  actual:   {}",
+                pattern, excerpt(&result.stdout)
+            )),
+            Err(e) => failures.push(format!("stdout_matches: invalid regex '{}': {}", pattern, e)),
+        }
+    }
+    failures
+}
+
+/// 按 trim / ignore_case 规范化文本
+fn normalize(assert: &StepAssert, text: &str) -> String {
+    let text = if assert.trim { text.trim() } else { text };
+    if assert.ignore_case { text.to_lowercase() } else { text.to_string() }
+}
+
+/// 错误信息中的文本：转义换行等字符后加引号，过长时截断并注明省略的字符数
+fn excerpt(text: &str) -> String {
+    let total = text.chars().count();
+    if total <= ASSERT_DIFF_MAX_CHARS {
+        return format!("{:?}", text);
+    }
+    let kept: String = text.chars().take(ASSERT_DIFF_MAX_CHARS).collect();
+    format!("{:?} ... ({} more chars)", kept, total - ASSERT_DIFF_MAX_CHARS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(stdout: &str, exit_code: i32) -> ExecutionResult {
+        ExecutionResult {
+            success: exit_code == 0,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+            script: String::new(),
+            exit_code,
+            execution_time_ms: 0,
+            error_message: None,
+            had_invalid_utf8: false,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: 0,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        }
+    }
+
+    #[test]
+    fn test_contains_failure_shows_expected_and_actual() {
+        let assert = StepAssert {
+            stdout_contains: Some("Status: OK".to_string()),
+            exit_code: Some(0),
+            ..StepAssert::default()
+        };
+        let mut output = result("Status: DEGRADED\nretrying\n", 0);
+        assert_eq!(apply_assert(&assert, &mut output, ExitClass::Success), ExitClass::Failure);
+        assert!(!output.success);
+        assert_eq!(
+            output.error_message.unwrap(),
+            "stdout_contains: text not found\n  expected: \"Status: OK\"\n  actual:   \"Status: DEGRADED\\nretrying\\n\""
+        );
+
+        // 过长的实际输出被截断
+        let long = "x".repeat(ASSERT_DIFF_MAX_CHARS + 25);
+        let failures = check_assert(&assert, &result(&long, 0));
+        assert!(failures[0].ends_with("... (25 more chars)"), "{}", failures[0]);
+    }
+
+    #[test]
+    fn test_regex_and_normalization_on_multiline_output() {
+        let output = result("  service nginx\nstate: Active\nuptime 3d\n", 3);
+        let assert = StepAssert {
+            stdout_matches: Some(r"^state: active$".to_string()),
+            stdout_equals: Some("SERVICE NGINX\nSTATE: ACTIVE\nUPTIME 3D".to_string()),
+            exit_code: Some(3),
+            trim: true,
+            ignore_case: true,
+            ..StepAssert::default()
+        };
+        let mut passing = output.clone();
+        assert_eq!(apply_assert(&assert, &mut passing, ExitClass::Failure), ExitClass::Success);
+        assert!(passing.success);
+
+        let strict = StepAssert { trim: false, ignore_case: false, exit_code: Some(0), ..assert };
+        let failures = check_assert(&strict, &output);
+        assert_eq!(failures.len(), 3, "{:?}", failures);
+        assert_eq!(failures[0], "exit_code: expected 0, got 3");
+        assert!(failures[1].starts_with("stdout_equals: first difference at line 1\n"), "{}", failures[1]);
+        assert!(failures[2].starts_with("stdout_matches: pattern did not match\n  expected: /^state: active$/"), "{}", failures[2]);
+    }
+}
@@ -0,0 +1,399 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use super::control::CancellationHandle;
+use super::events::{EventBus, EventThrottle, OutputBatching};
+use super::journal::{EventBuffering, EventJournal};
+use super::outputs::{load_outputs, merge_outputs};
+use super::RemoteExecutor;
+use crate::config::ConfigManager;
+use crate::models::{LineFilter, PassphraseCallback};
+use crate::ssh::auth::{AuthProvider, StaticAuthProvider};
+use crate::template::AutoEscapeMode;
+use crate::vars::VariableManager;
+
+/// 执行器选项，由 RemoteExecutorBuilder 设置
+#[derive(Debug, Clone, Default)]
+pub struct ExecutorOptions {
+    pub checkpoint_dir: Option<PathBuf>,  // 检查点目录（预留，后续实现）
+    pub history_dir: Option<PathBuf>,     // 执行历史目录（预留，后续实现）
+    pub global_timeout: Option<Duration>, // 单次执行（一个或全部流水线）的总超时
+    pub connection_pool: bool,            // 是否复用SSH连接（预留，后续实现）
+    pub auto_escape: AutoEscapeMode,      // 替换脚本内容时对变量值的自动转义
+    pub secret_patterns: Vec<String>,     // 输出事件中需要脱敏的正则表达式
+    pub output_batching: Option<OutputBatching>, // 合并 stdout/stderr 行后再发送事件，None 时每行一个事件
+    pub event_throttle: Option<EventThrottle>, // 限制每次执行每秒发送的 stdout/stderr 事件数，None 时不限制
+    pub event_buffer: Option<EventBuffering>, // 按 run_id 在内存中缓冲事件，供 replay_events 回放，None 时不缓冲
+    pub outputs_file: Option<PathBuf>,    // 流水线导出变量的持久化文件，构建时作为初始变量加载
+    pub trace_events: bool,               // 把所有事件同时记录为 debug 级别的 tracing 事件
+}
+
+/// 配置来源
+enum ConfigSource {
+    Yaml(String),
+    File(PathBuf),
+}
+
+/// RemoteExecutor 构建器
+#[derive(Default)]
+pub struct RemoteExecutorBuilder {
+    source: Option<ConfigSource>,
+    variables: HashMap<String, String>,
+    options: ExecutorOptions,
+    line_filter: Option<LineFilter>,
+    auth_provider: Option<Arc<dyn AuthProvider>>,
+    passphrase_callback: Option<PassphraseCallback>,
+}
+
+impl RemoteExecutorBuilder {
+    /// 创建构建器
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用YAML字符串作为配置
+    pub fn yaml_str(mut self, content: &str) -> Self {
+        self.source = Some(ConfigSource::Yaml(content.to_string()));
+        self
+    }
+
+    /// 使用YAML文件作为配置
+    pub fn yaml_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.source = Some(ConfigSource::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// 追加初始变量，优先级高于配置文件中的 variables
+    pub fn variables(mut self, variables: HashMap<String, String>) -> Self {
+        self.variables.extend(variables);
+        self
+    }
+
+    /// 设置检查点目录
+    pub fn with_checkpoint_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.checkpoint_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// 设置执行历史目录
+    pub fn with_history_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.history_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// 设置单次执行的总超时
+    pub fn with_global_timeout(mut self, timeout: Duration) -> Self {
+        self.options.global_timeout = Some(timeout);
+        self
+    }
+
+    /// 启用SSH连接复用
+    pub fn with_connection_pool(mut self) -> Self {
+        self.options.connection_pool = true;
+        self
+    }
+
+    /// 设置替换脚本内容时的自动转义方式
+    pub fn with_auto_escape(mut self, mode: AutoEscapeMode) -> Self {
+        self.options.auto_escape = mode;
+        self
+    }
+
+    /// 设置输出事件中需要脱敏的正则表达式
+    pub fn with_secret_patterns<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.secret_patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 设置输出文件：构建时读取其中的变量作为初始变量（同名时覆盖其他初始变量），
+    /// 流水线结束时把 outputs 导出的变量合并写入该文件。扩展名为 .json 时使用 JSON，否则使用 YAML
+    pub fn with_outputs_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.outputs_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// 合并输出行后再发送事件：每个 stdout/stderr 事件最多包含 max_lines 行（以换行连接），
+    /// 第一行等待超过 flush_interval 时即使不满也发送。用于输出量很大的步骤，减少回调次数
+    pub fn with_output_batching(mut self, max_lines: usize, flush_interval: Duration) -> Self {
+        self.options.output_batching = Some(OutputBatching { max_lines: max_lines.max(1), flush_interval });
+        self
+    }
+
+    /// 限制输出事件的速率：每次执行每秒最多直接发送 max_events_per_second 个 stdout/stderr 事件，
+    /// 超出时 coalesce 为 true 则把同一步骤、服务器、输出流的连续行合并为最多 max_batch_lines 行的事件，
+    /// 否则丢弃超出的输出事件。步骤开始、完成等其他事件不受影响，执行结果中的输出始终完整
+    pub fn with_event_throttle(mut self, max_events_per_second: u32, coalesce: bool, max_batch_lines: usize) -> Self {
+        self.options.event_throttle = Some(EventThrottle { max_events_per_second, coalesce, max_batch_lines: max_batch_lines.max(1) });
+        self
+    }
+
+    /// 开启事件缓冲：每次执行的事件按 run_id 保存在内存中（最近16次执行），
+    /// 执行开始后才接入的消费者可以通过 replay_events 先收到之前的事件；超出上限时丢弃最早的事件
+    pub fn with_event_buffer(mut self, max_events: usize, max_bytes: usize) -> Self {
+        self.options.event_buffer = Some(EventBuffering { max_events, max_bytes });
+        self
+    }
+
+    /// 把所有事件（脱敏后）同时记录为 debug 级别、target 为 net_shell::events 的 tracing 事件，
+    /// 只使用 tracing 的调用方不注册回调也能得到完整输出
+    pub fn with_event_tracing(mut self, enabled: bool) -> Self {
+        self.options.trace_events = enabled;
+        self
+    }
+
+    /// 设置输出行过滤器：可改写每一行 stdout/stderr 输出，返回 None 丢弃该行
+    /// 过滤后的行才会写入执行结果、参与变量提取并发送给回调
+    pub fn with_line_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.line_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// 设置认证提供者，每次建立SSH连接前调用以获取凭据；未设置时使用 ssh_config 中的凭据
+    pub fn with_auth_provider<P: AuthProvider + 'static>(mut self, provider: P) -> Self {
+        self.auth_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// 设置私钥口令回调：客户端使用私钥认证且没有配置 private_key_passphrase 时，在每次建立连接前调用，
+    /// 可用于在运行时提示输入口令
+    pub fn with_passphrase_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.passphrase_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// 加载并校验配置，创建执行器
+    pub fn build(self) -> Result<RemoteExecutor> {
+        let yaml_content = match self.source {
+            Some(ConfigSource::Yaml(content)) => content,
+            Some(ConfigSource::File(path)) => std::fs::read_to_string(&path)
+                .context("Failed to read YAML configuration file")?,
+            None => return Err(anyhow::anyhow!("No configuration provided, call yaml_str or yaml_file first")),
+        };
+
+        // 合并变量：配置文件中的变量在前，构建器传入的变量覆盖同名变量
+        let mut all_variables = ConfigManager::extract_initial_variables(&yaml_content)?.unwrap_or_default();
+        all_variables.extend(self.variables);
+        if let Some(path) = &self.options.outputs_file {
+            merge_outputs(&mut all_variables, load_outputs(path)?);
+        }
+
+        // 创建变量管理器
+        let mut variable_manager = VariableManager::new(Some(all_variables));
+
+        // 应用变量替换解析配置
+        let config = ConfigManager::from_yaml_str_with_variables(&yaml_content, &variable_manager)?;
+        ConfigManager::validate_config_with_variables(&config, variable_manager.get_variables())?;
+
+        let mut secret_patterns = self.options.secret_patterns.iter()
+            .map(|p| Regex::new(p).context(format!("Invalid secret pattern: {}", p)))
+            .collect::<Result<Vec<_>>>()?;
+        // 步骤配置的 sudo 密码自动脱敏
+        let sudo_passwords = config.pipelines.iter()
+            .flat_map(|pipeline| &pipeline.steps)
+            .chain(config.before_all.iter().flatten())
+            .chain(config.after_all.iter().flatten())
+            .filter_map(|step| step.sudo.as_ref()?.password.as_deref())
+            .filter(|password| !password.is_empty());
+        for password in sudo_passwords {
+            secret_patterns.push(Regex::new(&regex::escape(password))?);
+        }
+        // 配置文件中的私钥口令同样脱敏，并提醒不要把口令写在配置文件中
+        let mut clients: Vec<_> = config.clients.iter().collect();
+        clients.sort_by_key(|(name, _)| *name);
+        for (name, client) in clients {
+            let passphrase = client.ssh_config.as_ref().and_then(|ssh| ssh.private_key_passphrase.as_deref());
+            if let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) {
+                warn!("Client '{}' stores private_key_passphrase in the config file, which is insecure; consider with_passphrase_callback or an AuthProvider", name);
+                secret_patterns.push(Regex::new(&regex::escape(passphrase))?);
+            }
+        }
+        variable_manager.set_script_escape(self.options.auto_escape.escape_mode());
+
+        #[cfg(feature = "ssh")]
+        let ssh_worker_threads = config.ssh_worker_threads.unwrap_or(super::DEFAULT_SSH_WORKER_THREADS).max(1);
+
+        Ok(RemoteExecutor {
+            config: Arc::new(config),
+            variable_manager,
+            event_bus: Arc::new(EventBus::default()),
+            journal: Arc::new(EventJournal::default()),
+            options: self.options,
+            secret_patterns,
+            line_filter: self.line_filter,
+            cancellation: CancellationHandle::default(),
+            run_deadline: None,
+            return_connect_errors: false,
+            auth_provider: self.auth_provider.unwrap_or_else(|| Arc::new(StaticAuthProvider)),
+            passphrase_callback: self.passphrase_callback,
+            #[cfg(feature = "ssh")]
+            ssh_workers: Arc::new(tokio::sync::Semaphore::new(ssh_worker_threads)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{OutputCallback, OutputEvent, OutputType};
+    use crate::executor::events::EventFilter;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    fn local_yaml(script: &str) -> String {
+        format!(r#"
+variables:
+  greeting: "hello"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "local"
+    steps:
+      - name: "run"
+        script: "{}"
+"#, script)
+    }
+
+    fn script_file(body: &str) -> tempfile::NamedTempFile {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        script.write_all(body.as_bytes()).unwrap();
+        script
+    }
+
+    #[test]
+    fn test_builder_sources_variables_and_options() {
+        let yaml = local_yaml("/bin/true");
+
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&yaml)
+            .variables(HashMap::from([("greeting".to_string(), "hi".to_string())]))
+            .with_checkpoint_dir("/tmp/checkpoints")
+            .with_history_dir("/tmp/history")
+            .with_global_timeout(Duration::from_secs(5))
+            .with_connection_pool()
+            .with_auto_escape(AutoEscapeMode::Shell)
+            .with_secret_patterns(["token=\\w+"])
+            .build()
+            .unwrap();
+        assert_eq!(executor.variable_manager.get_variable("greeting").unwrap(), "hi");
+        let options = executor.options();
+        assert_eq!(options.checkpoint_dir.as_deref(), Some(Path::new("/tmp/checkpoints")));
+        assert_eq!(options.history_dir.as_deref(), Some(Path::new("/tmp/history")));
+        assert_eq!(options.global_timeout, Some(Duration::from_secs(5)));
+        assert!(options.connection_pool);
+        assert_eq!(options.auto_escape, AutoEscapeMode::Shell);
+
+        let file = script_file(&yaml);
+        let executor = RemoteExecutorBuilder::default().yaml_file(file.path()).build().unwrap();
+        assert_eq!(executor.variable_manager.get_variable("greeting").unwrap(), "hello");
+        assert!(executor.options().global_timeout.is_none());
+        assert!(!executor.options().connection_pool);
+    }
+
+    #[test]
+    fn test_build_validates_config() {
+        assert!(RemoteExecutorBuilder::new().build().is_err());
+        assert!(RemoteExecutorBuilder::new().yaml_file("/nonexistent/config.yaml").build().is_err());
+
+        let no_clients = "global_scripts: []\nclients: {}\npipelines: []\n";
+        let err = RemoteExecutorBuilder::new().yaml_str(no_clients).build().err().unwrap();
+        assert!(err.to_string().contains("No clients configured"));
+
+        let yaml = local_yaml("/bin/true");
+        assert!(RemoteExecutorBuilder::new().yaml_str(&yaml).with_secret_patterns(["("]).build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_builder_options_apply_to_execution() {
+        let script = script_file("echo token=abc123 {{ greeting }}\n");
+        let yaml = local_yaml(&script.path().to_string_lossy());
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&yaml)
+            .variables(HashMap::from([("greeting".to_string(), "a; echo injected".to_string())]))
+            .with_auto_escape(AutoEscapeMode::Shell)
+            .with_secret_patterns(["abc\\d+"])
+            .build()
+            .unwrap();
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let sink = stdout.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content));
+        executor.subscribe(EventFilter::output_types([OutputType::Stdout]), callback);
+
+        let result = executor.execute_pipeline("local").await.unwrap();
+        assert!(result.overall_success);
+        assert_eq!(*stdout.lock().unwrap(), vec!["token=****** a; echo injected".to_string()]);
+        let rendered = result.step_results[0].execution_result.rendered_script.as_deref().unwrap();
+        assert!(rendered.contains("token=****** 'a; echo injected'"), "{}", rendered);
+
+        let slow = script_file("sleep 5\n");
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&local_yaml(&slow.path().to_string_lossy()))
+            .with_global_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let err = executor.execute_pipeline("local").await.unwrap_err();
+        assert!(err.to_string().contains("global timeout"));
+    }
+
+    #[tokio::test]
+    async fn test_line_filter_rewrites_and_drops_lines() {
+        let script = script_file("printf '\\033[32mok\\033[0m\\nprogress 50%%\\nresult=done\\n'\nprintf 'progress 90%%\\n' >&2\n");
+        let ansi = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&local_yaml(&script.path().to_string_lossy()))
+            .with_line_filter(move |line| {
+                (!line.starts_with("progress")).then(|| ansi.replace_all(line, "").into_owned())
+            })
+            .build()
+            .unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content));
+        executor.subscribe(EventFilter::output_types([OutputType::Stdout, OutputType::Stderr]), callback);
+
+        let result = executor.execute_pipeline("local").await.unwrap();
+        let output = &result.step_results[0].execution_result;
+        assert_eq!(output.stdout, "ok\nresult=done\n");
+        assert_eq!(output.stderr, "");
+        assert_eq!(*events.lock().unwrap(), vec!["ok".to_string(), "result=done".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_sudo_password_is_masked_in_events() {
+        let script = script_file("echo ready\n");
+        let yaml = local_yaml(&script.path().to_string_lossy())
+            .replace("  greeting: \"hello\"\n", "  greeting: \"hello\"\n  sudo_password: \"s3cr3t!\"\n")
+            + "        sudo:\n          password: \"{{ sudo_password }}\"\n";
+        let executor = RemoteExecutor::builder().yaml_str(&yaml).build().unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        executor.subscribe(EventFilter::all(), Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e)));
+        assert!(executor.execute_pipeline("local").await.unwrap().overall_success);
+
+        let events = events.lock().unwrap();
+        assert!(!events.is_empty());
+        for event in events.iter() {
+            assert!(!format!("{:?}", event).contains("s3cr3t!"), "{:?}", event);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+
+use crate::vars::VariableManager;
+
+/// 控制命令
+enum Command {
+    Next,
+    RunToEnd,
+    Abort,
+    SetVariable(String, String),
+}
+
+/// 单步执行的控制句柄
+/// 执行器在每个步骤开始前暂停，等待 next / run_to_end / abort；暂停期间可以查看和修改本次执行的变量
+/// 句柄被丢弃时执行器视为 run_to_end，不会一直等待
+pub struct StepController {
+    commands: mpsc::UnboundedSender<Command>,
+    pauses: mpsc::UnboundedReceiver<String>,
+    variables: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl StepController {
+    /// 等待执行器在下一个步骤前暂停，返回该步骤名称；流水线结束（或已 run_to_end）时返回 None
+    pub async fn paused(&mut self) -> Option<String> {
+        self.pauses.recv().await
+    }
+
+    /// 执行下一个步骤，完成后在之后的步骤前再次暂停
+    pub fn next(&self) {
+        let _ = self.commands.send(Command::Next);
+    }
+
+    /// 不再暂停，执行剩余的全部步骤
+    pub fn run_to_end(&self) {
+        let _ = self.commands.send(Command::RunToEnd);
+    }
+
+    /// 中止执行，剩余步骤不再执行，流水线返回错误
+    pub fn abort(&self) {
+        let _ = self.commands.send(Command::Abort);
+    }
+
+    /// 本次执行当前的变量（最近一次暂停时的变量加上之后 set_variable 设置的值）
+    pub fn current_variables(&self) -> HashMap<String, String> {
+        self.variables.lock().unwrap().clone()
+    }
+
+    /// 设置本次执行的变量，在执行器恢复执行之前生效
+    pub fn set_variable(&self, key: impl Into<String>, value: impl Into<String>) {
+        let (key, value) = (key.into(), value.into());
+        self.variables.lock().unwrap().insert(key.clone(), value.clone());
+        let _ = self.commands.send(Command::SetVariable(key, value));
+    }
+}
+
+/// 暂停后的执行决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resume {
+    Next,
+    RunToEnd,
+}
+
+/// 执行器一侧的暂停点
+pub(crate) struct StepGate {
+    commands: mpsc::UnboundedReceiver<Command>,
+    pauses: Option<mpsc::UnboundedSender<String>>,
+    variables: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl StepGate {
+    /// 创建一对控制句柄与暂停点
+    pub fn new() -> (StepController, StepGate) {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (pause_tx, pause_rx) = mpsc::unbounded_channel();
+        let variables = Arc::new(Mutex::new(HashMap::new()));
+        let controller = StepController {
+            commands: command_tx,
+            pauses: pause_rx,
+            variables: variables.clone(),
+        };
+        let gate = StepGate {
+            commands: command_rx,
+            pauses: Some(pause_tx),
+            variables,
+        };
+        (controller, gate)
+    }
+
+    /// 是否仍在单步执行（尚未 run_to_end）
+    pub fn is_stepping(&self) -> bool {
+        self.pauses.is_some()
+    }
+
+    /// 在步骤开始前暂停，处理控制命令直到收到继续执行的命令
+    /// set_variable 直接写入本次执行的变量；收到 abort 时返回错误
+    pub async fn pause(&mut self, step_name: &str, variables: &mut VariableManager) -> Result<Resume> {
+        *self.variables.lock().unwrap() = variables.get_variables().clone();
+        if let Some(pauses) = &self.pauses {
+            let _ = pauses.send(step_name.to_string());
+        }
+
+        loop {
+            match self.commands.recv().await {
+                Some(Command::SetVariable(key, value)) => variables.set_variable(key, value),
+                Some(Command::Next) => return Ok(Resume::Next),
+                Some(Command::RunToEnd) | None => {
+                    // 关闭暂停通知，等待中的 paused() 返回 None
+                    self.pauses = None;
+                    return Ok(Resume::RunToEnd);
+                }
+                Some(Command::Abort) => {
+                    return Err(anyhow::anyhow!("Execution aborted before step '{}'", step_name));
+                }
+            }
+        }
+    }
+}
+
+/// 取消执行的句柄，可在任意任务或信号处理中调用 cancel
+/// 取消后执行器不再开始新的步骤和流水线，中止正在执行的步骤（本地进程被结束），
+/// 返回已完成步骤的结果，ShellExecutionResult.outcome 为 Interrupted
+#[derive(Clone)]
+pub struct CancellationHandle {
+    cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancellationHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationHandle {
+    /// 创建一个尚未取消的句柄
+    pub fn new() -> Self {
+        let (cancelled, _) = watch::channel(false);
+        Self { cancelled: Arc::new(cancelled) }
+    }
+
+    /// 取消执行，重复调用无副作用
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    /// 是否已取消
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// 等待取消；已取消时立即返回
+    pub(crate) async fn cancelled(&self) {
+        let mut receiver = self.cancelled.subscribe();
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+}
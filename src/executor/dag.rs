@@ -0,0 +1,163 @@
+use anyhow::Result;
+
+use crate::models::Pipeline;
+
+/// 流水线内步骤的依赖图
+/// 声明了 depends_on 的步骤只依赖列出的步骤；未声明的步骤依赖列表中的上一个步骤，保持原有的串行语义
+#[derive(Debug, Clone)]
+pub struct StepGraph {
+    dependencies: Vec<Vec<usize>>,
+}
+
+impl StepGraph {
+    /// 根据流水线构建依赖图，依赖不存在、有歧义或存在环时返回错误
+    pub fn new(pipeline: &Pipeline) -> Result<Self> {
+        let mut dependencies = Vec::with_capacity(pipeline.steps.len());
+
+        for (index, step) in pipeline.steps.iter().enumerate() {
+            let deps = match &step.depends_on {
+                Some(names) => {
+                    let mut deps = Vec::new();
+                    for name in names {
+                        let mut matches = pipeline.steps.iter().enumerate().filter(|(_, s)| &s.name == name);
+                        let (dep, _) = matches.next().ok_or_else(|| anyhow::anyhow!(
+                            "Step '{}' in pipeline '{}' depends on unknown step '{}'",
+                            step.name, pipeline.name, name
+                        ))?;
+                        if matches.next().is_some() {
+                            return Err(anyhow::anyhow!(
+                                "Step '{}' in pipeline '{}' depends on '{}', which names more than one step",
+                                step.name, pipeline.name, name
+                            ));
+                        }
+                        if dep == index {
+                            return Err(anyhow::anyhow!(
+                                "Step '{}' in pipeline '{}' depends on itself",
+                                step.name, pipeline.name
+                            ));
+                        }
+                        if !deps.contains(&dep) {
+                            deps.push(dep);
+                        }
+                    }
+                    deps
+                }
+                None if index > 0 => vec![index - 1],
+                None => Vec::new(),
+            };
+            dependencies.push(deps);
+        }
+
+        let graph = Self { dependencies };
+        if let Some(cycle) = graph.find_cycle() {
+            let names: Vec<&str> = cycle.iter().map(|i| pipeline.steps[*i].name.as_str()).collect();
+            return Err(anyhow::anyhow!(
+                "Step dependencies in pipeline '{}' form a cycle: {}",
+                pipeline.name, names.join(" -> ")
+            ));
+        }
+
+        Ok(graph)
+    }
+
+    /// 步骤直接依赖的步骤下标
+    pub fn dependencies(&self, index: usize) -> &[usize] {
+        &self.dependencies[index]
+    }
+
+    /// 查找依赖环，返回环上的步骤下标（首尾相同）
+    fn find_cycle(&self) -> Option<Vec<usize>> {
+        // 0 = 未访问，1 = 访问中，2 = 已完成
+        let mut state = vec![0u8; self.dependencies.len()];
+        let mut path = Vec::new();
+
+        fn visit(graph: &StepGraph, node: usize, state: &mut [u8], path: &mut Vec<usize>) -> Option<Vec<usize>> {
+            state[node] = 1;
+            path.push(node);
+            for &dep in &graph.dependencies[node] {
+                if state[dep] == 1 {
+                    let start = path.iter().position(|n| *n == dep).unwrap();
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(dep);
+                    return Some(cycle);
+                }
+                if state[dep] == 0 {
+                    if let Some(cycle) = visit(graph, dep, state, path) {
+                        return Some(cycle);
+                    }
+                }
+            }
+            path.pop();
+            state[node] = 2;
+            None
+        }
+
+        for node in 0..self.dependencies.len() {
+            if state[node] == 0 {
+                if let Some(cycle) = visit(self, node, &mut state, &mut path) {
+                    return Some(cycle);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Step;
+
+    fn pipeline(steps: &[(&str, Option<&[&str]>)]) -> Pipeline {
+        Pipeline {
+            name: "p".to_string(),
+            title: None,
+            description: None,
+            tags: None,
+            script: None,
+            schedule: None,
+            gather_facts: None,
+            require_facts: None,
+            outputs: None,
+            timeout_seconds: None,
+            steps: steps
+                .iter()
+                .map(|(name, deps)| Step {
+                    name: name.to_string(),
+                    depends_on: deps.map(|d| d.iter().map(|s| s.to_string()).collect()),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_implicit_and_explicit_dependencies() {
+        let graph = StepGraph::new(&pipeline(&[
+            ("a", None),
+            ("b", None),
+            ("c", Some(&[])),
+            ("d", Some(&["b", "c", "b"])),
+        ]))
+        .unwrap();
+        assert!(graph.dependencies(0).is_empty());
+        assert_eq!(graph.dependencies(1), &[0]);
+        assert!(graph.dependencies(2).is_empty());
+        assert_eq!(graph.dependencies(3), &[1, 2]);
+    }
+
+    #[test]
+    fn test_invalid_dependencies_are_rejected() {
+        let err = StepGraph::new(&pipeline(&[("a", Some(&["c"])), ("b", Some(&["a"])), ("c", Some(&["b"]))]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cycle"), "{}", err);
+        assert!(err.contains("a -> c -> b -> a"), "{}", err);
+
+        let err = StepGraph::new(&pipeline(&[("a", Some(&["missing"]))])).unwrap_err().to_string();
+        assert!(err.contains("unknown step 'missing'"));
+
+        assert!(StepGraph::new(&pipeline(&[("a", Some(&["a"]))])).is_err());
+        assert!(StepGraph::new(&pipeline(&[("a", None), ("a", None), ("b", Some(&["a"]))])).is_err());
+    }
+}
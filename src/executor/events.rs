@@ -0,0 +1,867 @@
+use futures::FutureExt;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+use crate::models::{AsyncOutputCallback, OutputCallback, OutputEvent, OutputType};
+use crate::ssh::{call_guarded, guard_callback};
+
+/// 订阅ID，用于取消订阅
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// 事件过滤器，未设置的条件视为匹配全部
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub output_types: Option<Vec<OutputType>>,
+    pub pipeline_name: Option<String>,
+    pub server_name: Option<String>,
+}
+
+impl EventFilter {
+    /// 匹配所有事件
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// 只匹配指定类型的事件
+    pub fn output_types(types: impl IntoIterator<Item = OutputType>) -> Self {
+        Self {
+            output_types: Some(types.into_iter().collect()),
+            ..Self::default()
+        }
+    }
+
+    /// 限定流水线名称
+    pub fn with_pipeline(mut self, pipeline_name: impl Into<String>) -> Self {
+        self.pipeline_name = Some(pipeline_name.into());
+        self
+    }
+
+    /// 限定服务器名称
+    pub fn with_server(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// 判断事件是否满足过滤条件
+    pub fn matches(&self, event: &OutputEvent) -> bool {
+        if let Some(types) = &self.output_types {
+            if !types.contains(&event.output_type) {
+                return false;
+            }
+        }
+        if let Some(pipeline_name) = &self.pipeline_name {
+            if &event.pipeline_name != pipeline_name {
+                return false;
+            }
+        }
+        if let Some(server_name) = &self.server_name {
+            if &event.server_name != server_name {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+struct Subscriber {
+    id: SubscriptionId,
+    filter: EventFilter,
+    callback: OutputCallback,
+}
+
+/// 执行器级别的事件订阅表
+#[derive(Default)]
+pub(crate) struct EventBus {
+    next_id: AtomicU64,
+    subscribers: RwLock<Vec<Subscriber>>,
+}
+
+impl EventBus {
+    /// 注册订阅
+    pub(crate) fn subscribe(&self, filter: EventFilter, callback: OutputCallback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.subscribers
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Subscriber { id, filter, callback });
+        id
+    }
+
+    /// 注册异步回调的订阅：异步回调包装为同步回调，事件进入有界队列，由后台任务依次等待回调处理
+    pub(crate) fn subscribe_async(&self, filter: EventFilter, callback: AsyncOutputCallback, options: AsyncSinkOptions) -> AsyncSubscription {
+        let queue = Arc::new(AsyncQueue {
+            callback,
+            options: AsyncSinkOptions { capacity: options.capacity.max(1), ..options },
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+        });
+        let sink = queue.clone();
+        let id = self.subscribe(filter, Arc::new(move |event: OutputEvent| sink.push(event)));
+        AsyncSubscription { id, queue }
+    }
+
+    /// 取消订阅，返回订阅是否存在
+    pub(crate) fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribers = self.subscribers.write().unwrap_or_else(|e| e.into_inner());
+        let before = subscribers.len();
+        subscribers.retain(|s| s.id != id);
+        subscribers.len() != before
+    }
+
+    fn is_empty(&self) -> bool {
+        self.subscribers.read().unwrap_or_else(|e| e.into_inner()).is_empty()
+    }
+
+    /// 为一次执行创建事件分发回调
+    /// `scoped` 为仅本次执行有效的订阅（兼容旧的 output_callback/log_callback 参数），
+    /// 执行器上注册的订阅在分发时实时读取；两者都为空时返回 None，避免构造无人接收的事件。
+    /// 回调panic时记录一次错误，并在本次执行的剩余部分停用该回调
+    pub(crate) fn dispatcher(
+        self: &Arc<Self>,
+        scoped: Vec<(EventFilter, OutputCallback)>,
+    ) -> Option<OutputCallback> {
+        if scoped.is_empty() && self.is_empty() {
+            return None;
+        }
+
+        let scoped: Vec<_> = scoped.into_iter().map(|(filter, callback)| (filter, guard_callback(callback))).collect();
+        let bus = self.clone();
+        let disabled: Mutex<HashSet<SubscriptionId>> = Mutex::new(HashSet::new());
+        Some(Arc::new(move |event: OutputEvent| {
+            for (filter, callback) in &scoped {
+                if filter.matches(&event) {
+                    callback(event.clone());
+                }
+            }
+
+            let callbacks: Vec<(SubscriptionId, OutputCallback)> = {
+                let disabled = disabled.lock().unwrap_or_else(|e| e.into_inner());
+                bus.subscribers
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .iter()
+                    .filter(|s| !disabled.contains(&s.id) && s.filter.matches(&event))
+                    .map(|s| (s.id, s.callback.clone()))
+                    .collect()
+            };
+            for (id, callback) in &callbacks {
+                if !call_guarded(callback, event.clone()) {
+                    disabled.lock().unwrap_or_else(|e| e.into_inner()).insert(*id);
+                    error!(
+                        "Output callback panicked while handling {:?} event for pipeline '{}'; disabling it for the rest of the run",
+                        event.output_type, event.pipeline_name
+                    );
+                }
+            }
+        }))
+    }
+}
+
+/// 把旧的双回调参数转换为本次执行的订阅：log_callback 接收 Log 事件，output_callback 接收其余事件
+pub(crate) fn legacy_subscriptions(
+    output_callback: Option<OutputCallback>,
+    log_callback: Option<OutputCallback>,
+) -> Vec<(EventFilter, OutputCallback)> {
+    let mut scoped = Vec::new();
+    if let Some(callback) = output_callback {
+        let filter = EventFilter::output_types([
+            OutputType::Stdout,
+            OutputType::Stderr,
+            OutputType::StepStarted,
+            OutputType::StepCompleted,
+        ]);
+        scoped.push((filter, callback));
+    }
+    if let Some(callback) = log_callback {
+        scoped.push((EventFilter::output_types([OutputType::Log]), callback));
+    }
+    scoped
+}
+
+/// 把事件记录为 debug 级别的 tracing 事件的回调，事件在发送它的步骤、服务器的 span 中记录
+pub(crate) fn tracing_callback() -> OutputCallback {
+    Arc::new(|event: OutputEvent| {
+        debug!(
+            target: "net_shell::events",
+            run_id = %event.run_id,
+            pipeline = %event.pipeline_name,
+            step = %event.step.name,
+            server = %event.server_name,
+            output_type = ?event.output_type,
+            "{}",
+            event.content
+        );
+    })
+}
+
+/// 把 stdout/stderr 输出写入 dir 下 {流水线}/{步骤}/{服务器}.log 的回调，两路输出按产生顺序写入同一文件
+/// 同一个回调第一次写某个文件时清空该文件；写入失败时记录警告，并丢弃该文件之后的输出
+pub fn file_logging_callback<P: AsRef<Path>>(dir: P) -> OutputCallback {
+    let dir = dir.as_ref().to_path_buf();
+    let files: Mutex<HashMap<PathBuf, Option<File>>> = Mutex::new(HashMap::new());
+    Arc::new(move |event: OutputEvent| {
+        if !matches!(event.output_type, OutputType::Stdout | OutputType::Stderr) {
+            return;
+        }
+        let path = dir
+            .join(log_path_component(&event.pipeline_name))
+            .join(log_path_component(&event.step.name))
+            .join(format!("{}.log", log_path_component(&event.server_name)));
+        let mut files = files.lock().unwrap();
+        let file = files.entry(path).or_insert_with_key(|path| {
+            let file = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| File::create(path));
+            file.map_err(|e| warn!("Failed to create log file '{}': {}", path.display(), e)).ok()
+        });
+        if let Some(handle) = file {
+            if let Err(e) = writeln!(handle, "{}", event.content) {
+                warn!("Failed to write log file for step '{}' on '{}': {}", event.step.name, event.server_name, e);
+                *file = None;
+            }
+        }
+    })
+}
+
+/// 名称中的路径分隔符替换为下划线，避免写到日志目录之外
+fn log_path_component(name: &str) -> String {
+    match name.replace(['/', '\\'], "_") {
+        name if name.is_empty() || name == "." || name == ".." => "_".to_string(),
+        name => name,
+    }
+}
+
+/// 输出事件的批量发送设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBatching {
+    pub max_lines: usize,         // 每个事件最多合并的行数
+    pub flush_interval: Duration, // 批次的第一行最多等待的时间
+}
+
+/// 等待发送的批次：合并中的事件与已合并的行数
+struct Batches {
+    pending: Mutex<Vec<(OutputEvent, usize)>>,
+    dispatch: OutputCallback,
+    batching: OutputBatching,
+}
+
+impl Batches {
+    fn push(&self, event: OutputEvent) {
+        if !matches!(event.output_type, OutputType::Stdout | OutputType::Stderr) {
+            // 其他事件之前的输出先发送，保持先后顺序
+            self.send(self.take(|_| true));
+            (self.dispatch)(event);
+            return;
+        }
+
+        let full = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            let batch = pending.iter_mut().find(|(batch, _)| {
+                batch.output_type == event.output_type
+                    && batch.pipeline_name == event.pipeline_name
+                    && batch.server_name == event.server_name
+                    && batch.step.name == event.step.name
+                    && batch.script_path == event.script_path
+            });
+            match batch {
+                Some((batch, lines)) => {
+                    batch.content.push('\n');
+                    batch.content.push_str(&event.content);
+                    batch.variables = event.variables;
+                    batch.elapsed_ms = event.elapsed_ms;
+                    batch.line_count = event.line_count;
+                    *lines += 1;
+                    batch.coalesced_line_count = *lines as u64;
+                }
+                None => pending.push((event, 1)),
+            }
+            drop(pending);
+            let (max_lines, interval) = (self.batching.max_lines, self.batching.flush_interval);
+            self.take(|(batch, lines)| *lines >= max_lines || batch.timestamp.elapsed() >= interval)
+        };
+        self.send(full);
+    }
+
+    /// 取出满足条件的批次
+    fn take(&self, ready: impl Fn(&(OutputEvent, usize)) -> bool) -> Vec<OutputEvent> {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let (ready, rest): (Vec<_>, Vec<_>) = pending.drain(..).partition(|batch| ready(batch));
+        *pending = rest;
+        ready.into_iter().map(|(event, _)| event).collect()
+    }
+
+    fn send(&self, events: Vec<OutputEvent>) {
+        for event in events {
+            (self.dispatch)(event);
+        }
+    }
+}
+
+impl Drop for Batches {
+    fn drop(&mut self) {
+        self.send(self.take(|_| true));
+    }
+}
+
+/// 把同一流水线、步骤、服务器的连续 stdout（或 stderr）行合并为一个事件，内容以换行连接，时间为第一行的时间
+/// 合并达到 max_lines 行或第一行等待超过 flush_interval 时发送；其他事件发送前先发送所有未发送的批次，
+/// 回调释放时发送剩余的批次
+pub(crate) fn batching_sink(dispatch: OutputCallback, batching: OutputBatching) -> OutputCallback {
+    let batches = Arc::new(Batches { pending: Mutex::new(Vec::new()), dispatch, batching });
+
+    // 输出停顿时由定时任务发送等待超时的批次
+    if Handle::try_current().is_ok() && !batching.flush_interval.is_zero() {
+        let weak = Arc::downgrade(&batches);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(batching.flush_interval).await;
+                let Some(batches) = weak.upgrade() else { break };
+                batches.send(batches.take(|(batch, _)| batch.timestamp.elapsed() >= batching.flush_interval));
+            }
+        });
+    }
+
+    Arc::new(move |event: OutputEvent| batches.push(event))
+}
+
+/// 输出事件的限流设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventThrottle {
+    pub max_events_per_second: u32, // 每秒最多直接发送的 stdout/stderr 事件数
+    pub coalesce: bool,             // 超出速率时合并输出行；为 false 时丢弃超出的输出事件
+    pub max_batch_lines: usize,     // 合并时每个事件最多包含的行数
+}
+
+/// 限流状态：当前一秒窗口内已发送的输出事件数与合并中的事件
+struct Throttle {
+    state: Mutex<ThrottleState>,
+    dispatch: OutputCallback,
+    throttle: EventThrottle,
+}
+
+struct ThrottleState {
+    window_start: Instant,
+    sent: u32,
+    pending: Vec<OutputEvent>,
+}
+
+impl Throttle {
+    fn push(&self, mut event: OutputEvent) {
+        if !matches!(event.output_type, OutputType::Stdout | OutputType::Stderr) {
+            // 生命周期事件不受限流影响，发送前先发送合并中的输出，保持先后顺序
+            let pending = std::mem::take(&mut self.lock().pending);
+            self.send(pending);
+            (self.dispatch)(event);
+            return;
+        }
+
+        let ready = {
+            let mut state = self.lock();
+            let mut ready = Vec::new();
+            if state.window_start.elapsed() >= Duration::from_secs(1) {
+                state.window_start = Instant::now();
+                state.sent = 0;
+                ready = std::mem::take(&mut state.pending);
+            }
+            let index = state.pending.iter().position(|batch| same_stream(batch, &event));
+            match index {
+                // 同一输出流已有合并中的行时继续合并，避免行的顺序颠倒
+                None if state.sent < self.throttle.max_events_per_second => {
+                    state.sent += 1;
+                    ready.push(event);
+                }
+                None if self.throttle.coalesce => {
+                    event.coalesced_line_count = 1;
+                    state.pending.push(event);
+                }
+                None => {}
+                Some(index) => {
+                    let batch = &mut state.pending[index];
+                    batch.content.push('\n');
+                    batch.content.push_str(&event.content);
+                    batch.variables = event.variables;
+                    batch.elapsed_ms = event.elapsed_ms;
+                    batch.line_count = event.line_count;
+                    batch.coalesced_line_count += 1;
+                    if batch.coalesced_line_count >= self.throttle.max_batch_lines as u64 {
+                        ready.push(state.pending.remove(index));
+                    }
+                }
+            }
+            ready
+        };
+        self.send(ready);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send(&self, events: Vec<OutputEvent>) {
+        for event in events {
+            (self.dispatch)(event);
+        }
+    }
+}
+
+impl Drop for Throttle {
+    fn drop(&mut self) {
+        let pending = std::mem::take(&mut self.lock().pending);
+        self.send(pending);
+    }
+}
+
+/// 两个事件是否来自同一流水线、步骤、服务器的同一输出流
+fn same_stream(a: &OutputEvent, b: &OutputEvent) -> bool {
+    a.output_type == b.output_type
+        && a.pipeline_name == b.pipeline_name
+        && a.server_name == b.server_name
+        && a.step.name == b.step.name
+        && a.script_path == b.script_path
+}
+
+/// 限制 stdout/stderr 事件的发送速率：每秒前 max_events_per_second 个输出事件直接发送，
+/// 之后的输出按流合并（coalesce）为最多 max_batch_lines 行的事件，或者丢弃；其他事件总是发送。
+/// 合并中的事件在下一秒开始、其他事件发送前、输出停顿一秒或回调释放时发送
+pub(crate) fn throttling_sink(dispatch: OutputCallback, throttle: EventThrottle) -> OutputCallback {
+    let state = ThrottleState { window_start: Instant::now(), sent: 0, pending: Vec::new() };
+    let throttle = EventThrottle { max_batch_lines: throttle.max_batch_lines.max(1), ..throttle };
+    let sink = Arc::new(Throttle { state: Mutex::new(state), dispatch, throttle });
+
+    if Handle::try_current().is_ok() && throttle.coalesce {
+        let weak = Arc::downgrade(&sink);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let Some(sink) = weak.upgrade() else { break };
+                let pending = {
+                    let mut state = sink.lock();
+                    if state.window_start.elapsed() < Duration::from_secs(1) {
+                        continue;
+                    }
+                    state.window_start = Instant::now();
+                    state.sent = 0;
+                    std::mem::take(&mut state.pending)
+                };
+                sink.send(pending);
+            }
+        });
+    }
+
+    Arc::new(move |event: OutputEvent| sink.push(event))
+}
+
+/// 创建把事件发送到有界通道的回调
+/// 通道已满时阻塞发送事件的一方，直到接收方取走事件（背压）；接收方已关闭时丢弃事件。
+/// current_thread 运行时中阻塞会让接收方无法运行，此时事件按顺序进入无界队列，
+/// 由返回的转发任务等待通道空位后发送，事件全部转发后任务结束
+pub(crate) fn channel_sink(sender: mpsc::Sender<OutputEvent>) -> (OutputCallback, Option<JoinHandle<()>>) {
+    let current_thread = Handle::try_current()
+        .is_ok_and(|handle| handle.runtime_flavor() == RuntimeFlavor::CurrentThread);
+    if current_thread {
+        let (queue, mut queued) = mpsc::unbounded_channel();
+        let forwarder = tokio::spawn(async move {
+            while let Some(event) = queued.recv().await {
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            let _ = queue.send(event);
+        });
+        return (callback, Some(forwarder));
+    }
+
+    let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+        if let Err(mpsc::error::TrySendError::Full(event)) = sender.try_send(event) {
+            let send = || {
+                let _ = futures::executor::block_on(sender.send(event));
+            };
+            if Handle::try_current().is_ok() {
+                tokio::task::block_in_place(send);
+            } else {
+                send();
+            }
+        }
+    });
+    (callback, None)
+}
+
+/// 异步回调的事件队列已满时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePolicy {
+    #[default]
+    Wait,       // 发送事件的一方等待队列有空位，回调较慢时执行随之变慢（背压）
+    DropOldest, // 丢弃队列中最早的 stdout/stderr 事件，执行不等待
+}
+
+/// 异步回调的事件队列设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsyncSinkOptions {
+    pub capacity: usize,     // 队列中最多等待的事件数
+    pub policy: QueuePolicy, // 队列已满时的处理方式
+}
+
+impl Default for AsyncSinkOptions {
+    fn default() -> Self {
+        Self { capacity: 256, policy: QueuePolicy::Wait }
+    }
+}
+
+/// 异步订阅：订阅ID与事件队列的状态
+#[derive(Clone)]
+pub struct AsyncSubscription {
+    pub id: SubscriptionId,
+    queue: Arc<AsyncQueue>,
+}
+
+impl AsyncSubscription {
+    /// 队列中等待回调处理的事件数
+    pub fn queue_len(&self) -> usize {
+        self.queue.lock().events.len()
+    }
+
+    /// 队列曾经达到的最大长度
+    pub fn max_queue_len(&self) -> usize {
+        self.queue.lock().max_len
+    }
+
+    /// 按 DropOldest 丢弃的事件数
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+/// 异步回调的事件队列，由按需启动的任务依次等待回调处理
+struct AsyncQueue {
+    callback: AsyncOutputCallback,
+    options: AsyncSinkOptions,
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    events: VecDeque<(u64, OutputEvent)>, // (序号, 事件)
+    next_sequence: u64,
+    handled: u64,         // 回调已处理完的最后一个事件的序号
+    draining: bool,       // 是否有任务在处理队列
+    max_len: usize,
+    dropped: u64,
+}
+
+impl AsyncQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 事件入队，队列已满时按 policy 等待或丢弃最早的 stdout/stderr 事件；
+    /// 生命周期事件（stdout/stderr 以外的事件）不会被丢弃，入队后等待回调处理完
+    fn push(self: &Arc<Self>, event: OutputEvent) {
+        let Ok(handle) = Handle::try_current() else {
+            // 不在 tokio 运行时中时直接等待回调
+            futures::executor::block_on(self.handle(event));
+            return;
+        };
+        // current_thread 运行时中等待会让处理队列的任务无法运行，此时不等待
+        let can_wait = handle.runtime_flavor() != RuntimeFlavor::CurrentThread;
+        let is_output = matches!(event.output_type, OutputType::Stdout | OutputType::Stderr);
+
+        let mut state = self.lock();
+        if state.events.len() >= self.options.capacity {
+            match self.options.policy {
+                QueuePolicy::DropOldest => {
+                    let oldest = state.events.iter()
+                        .position(|(_, e)| matches!(e.output_type, OutputType::Stdout | OutputType::Stderr));
+                    if let Some(index) = oldest {
+                        state.events.remove(index);
+                        state.dropped += 1;
+                    }
+                }
+                QueuePolicy::Wait if can_wait => {
+                    let capacity = self.options.capacity;
+                    state = tokio::task::block_in_place(|| {
+                        self.changed.wait_while(state, |s| s.events.len() >= capacity).unwrap_or_else(|e| e.into_inner())
+                    });
+                }
+                QueuePolicy::Wait => {}
+            }
+        }
+        state.next_sequence += 1;
+        let sequence = state.next_sequence;
+        state.events.push_back((sequence, event));
+        state.max_len = state.max_len.max(state.events.len());
+        if !state.draining {
+            state.draining = true;
+            handle.spawn(self.clone().drain());
+        }
+        if !is_output && can_wait {
+            drop(tokio::task::block_in_place(|| {
+                self.changed.wait_while(state, |s| s.handled < sequence).unwrap_or_else(|e| e.into_inner())
+            }));
+        }
+    }
+
+    /// 依次处理队列中的事件，队列为空时结束
+    async fn drain(self: Arc<Self>) {
+        loop {
+            let next = {
+                let mut state = self.lock();
+                let next = state.events.pop_front();
+                if next.is_none() {
+                    state.draining = false;
+                }
+                next
+            };
+            let Some((sequence, event)) = next else {
+                return;
+            };
+            self.changed.notify_all();
+            self.handle(event).await;
+            self.lock().handled = sequence;
+            self.changed.notify_all();
+        }
+    }
+
+    /// 等待回调处理事件，回调panic时记录错误并继续处理之后的事件
+    async fn handle(&self, event: OutputEvent) {
+        let output_type = event.output_type.clone();
+        if AssertUnwindSafe((self.callback)(event)).catch_unwind().await.is_err() {
+            error!("Async output callback panicked while handling {:?} event", output_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Step;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn event(output_type: OutputType, pipeline_name: &str, server_name: &str) -> OutputEvent {
+        OutputEvent::new(
+            pipeline_name.to_string(),
+            server_name.to_string(),
+            Step::default(),
+            output_type,
+            String::new(),
+            HashMap::new().into(),
+        )
+    }
+
+    fn recorder() -> (OutputCallback, Arc<Mutex<Vec<OutputEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let callback: OutputCallback = Arc::new(move |e| sink.lock().unwrap().push(e));
+        (callback, events)
+    }
+
+    #[test]
+    fn test_file_logging_callback_writes_one_file_per_step_and_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let line = |output_type: OutputType, step: &str, server: &str, content: &str| OutputEvent {
+            step: Step { name: step.to_string(), ..Step::default() },
+            content: content.to_string(),
+            ..event(output_type, "deploy", server)
+        };
+        std::fs::create_dir_all(dir.path().join("deploy/build")).unwrap();
+        std::fs::write(dir.path().join("deploy/build/web1.log"), "previous run\n").unwrap();
+
+        let callback = file_logging_callback(dir.path());
+        callback(line(OutputType::StepStarted, "build", "web1", "starting"));
+        callback(line(OutputType::Stdout, "build", "web1", "compiling"));
+        callback(line(OutputType::Stderr, "build", "web1", "warning: unused"));
+        callback(line(OutputType::Stdout, "build", "web2", "a\nb"));
+        callback(line(OutputType::Stdout, "../etc", "web1", "contained"));
+
+        let read = |path: &str| std::fs::read_to_string(dir.path().join(path)).unwrap();
+        assert_eq!(read("deploy/build/web1.log"), "compiling\nwarning: unused\n");
+        assert_eq!(read("deploy/build/web2.log"), "a\nb\n");
+        assert_eq!(read("deploy/.._etc/web1.log"), "contained\n");
+    }
+
+    #[test]
+    fn test_subscribers_receive_disjoint_event_sets() {
+        let bus = Arc::new(EventBus::default());
+        let (stdout_cb, stdout_events) = recorder();
+        let (completed_cb, completed_events) = recorder();
+        let (web2_cb, web2_events) = recorder();
+
+        bus.subscribe(EventFilter::output_types([OutputType::Stdout]).with_server("web1"), stdout_cb);
+        bus.subscribe(EventFilter::output_types([OutputType::StepCompleted]), completed_cb);
+        bus.subscribe(EventFilter::all().with_server("web2").with_pipeline("deploy"), web2_cb);
+
+        let dispatch = bus.dispatcher(Vec::new()).unwrap();
+        dispatch(event(OutputType::Stdout, "deploy", "web1"));
+        dispatch(event(OutputType::StepCompleted, "deploy", "system"));
+        dispatch(event(OutputType::Stderr, "deploy", "web2"));
+        dispatch(event(OutputType::Stderr, "other", "web2"));
+
+        let stdout_events = stdout_events.lock().unwrap();
+        assert_eq!(stdout_events.len(), 1);
+        assert_eq!(stdout_events[0].server_name, "web1");
+
+        let completed_events = completed_events.lock().unwrap();
+        assert_eq!(completed_events.len(), 1);
+        assert_eq!(completed_events[0].output_type, OutputType::StepCompleted);
+
+        let web2_events = web2_events.lock().unwrap();
+        assert_eq!(web2_events.len(), 1);
+        assert_eq!(web2_events[0].pipeline_name, "deploy");
+    }
+
+    #[tokio::test]
+    async fn test_batching_merges_lines_until_full_or_flushed() {
+        let (callback, events) = recorder();
+        let batching = OutputBatching { max_lines: 2, flush_interval: Duration::from_millis(50) };
+        let dispatch = batching_sink(callback, batching);
+        let line = |output_type, server_name: &str, content: &str| {
+            let mut event = event(output_type, "deploy", server_name);
+            event.content = content.to_string();
+            event
+        };
+
+        for content in ["a", "b", "c"] {
+            dispatch(line(OutputType::Stdout, "web1", content));
+        }
+        dispatch(line(OutputType::Stdout, "web2", "x"));
+        dispatch(line(OutputType::Stderr, "web1", "err"));
+        // 其他事件之前先发送未满的批次
+        dispatch(line(OutputType::StepCompleted, "web1", "done"));
+        let contents: Vec<_> = events.lock().unwrap().drain(..).map(|e| e.content).collect();
+        assert_eq!(contents, ["a\nb", "c", "x", "err", "done"]);
+
+        // 输出停顿时按间隔发送
+        dispatch(line(OutputType::Stdout, "web1", "slow"));
+        assert!(events.lock().unwrap().is_empty());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(events.lock().unwrap()[0].content, "slow");
+
+        // 回调释放时发送剩余的批次
+        dispatch(line(OutputType::Stdout, "web1", "last"));
+        drop(dispatch);
+        assert_eq!(events.lock().unwrap().last().unwrap().content, "last");
+    }
+
+    #[test]
+    fn test_throttle_drops_excess_output_but_not_lifecycle_events() {
+        let (callback, events) = recorder();
+        let throttle = EventThrottle { max_events_per_second: 2, coalesce: false, max_batch_lines: 10 };
+        let dispatch = throttling_sink(callback, throttle);
+        for (output_type, content) in [
+            (OutputType::Stdout, "a"), (OutputType::Stdout, "b"), (OutputType::Stdout, "c"),
+            (OutputType::StepCompleted, "done"), (OutputType::Stderr, "d"),
+        ] {
+            let mut event = event(output_type, "deploy", "web1");
+            event.content = content.to_string();
+            dispatch(event);
+        }
+        let contents: Vec<_> = events.lock().unwrap().drain(..).map(|e| e.content).collect();
+        assert_eq!(contents, ["a", "b", "done"]);
+    }
+
+    #[test]
+    fn test_throttle_coalesces_excess_output() {
+        let (callback, events) = recorder();
+        let throttle = EventThrottle { max_events_per_second: 1, coalesce: true, max_batch_lines: 2 };
+        let dispatch = throttling_sink(callback, throttle);
+        for content in ["a", "b", "c", "d"] {
+            let mut event = event(OutputType::Stdout, "deploy", "web1");
+            event.content = content.to_string();
+            dispatch(event);
+        }
+        dispatch(event(OutputType::StepCompleted, "deploy", "web1"));
+        let events: Vec<_> = events.lock().unwrap().drain(..).map(|e| (e.content, e.coalesced_line_count)).collect();
+        // 未合并的事件保持 OutputEvent::new 的默认值 0
+        assert_eq!(events, [("a".to_string(), 0), ("b\nc".to_string(), 2), ("d".to_string(), 1), (String::new(), 0)]);
+    }
+
+    #[test]
+    fn test_unsubscribe_and_panicking_subscriber() {
+        let bus = Arc::new(EventBus::default());
+        let (callback, events) = recorder();
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        let panicking: OutputCallback = Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            panic!("subscriber failure")
+        });
+
+        bus.subscribe(EventFilter::all(), panicking);
+        let id = bus.subscribe(EventFilter::all(), callback);
+
+        let dispatch = bus.dispatcher(Vec::new()).unwrap();
+        dispatch(event(OutputType::Log, "p", "system"));
+        dispatch(event(OutputType::Log, "p", "system"));
+        assert_eq!(events.lock().unwrap().len(), 2);
+        // panic 过的订阅在本次执行中不再被调用，新的执行重新启用
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        bus.dispatcher(Vec::new()).unwrap()(event(OutputType::Log, "p", "system"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        events.lock().unwrap().clear();
+
+        assert!(bus.unsubscribe(id));
+        assert!(!bus.unsubscribe(id));
+        dispatch(event(OutputType::Log, "p", "system"));
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_async_subscriber_applies_backpressure() {
+        use crate::executor::RemoteExecutor;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("lines.sh");
+        std::fs::write(&script, "seq 1 30\n").unwrap();
+        let yaml = format!(
+            "clients:\n  web1:\n    name: \"web1\"\n    execution_method: ssh\npipelines:\n  - name: \"p\"\n    steps:\n      - name: \"lines\"\n        script: \"{}\"\n",
+            script.display()
+        );
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        // 每个事件处理50ms
+        let slow_sink = |handled: Arc<AtomicU64>| -> AsyncOutputCallback {
+            Arc::new(move |_event| {
+                let handled = handled.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    handled.fetch_add(1, Ordering::SeqCst);
+                }
+                .boxed()
+            })
+        };
+        let filter = EventFilter::output_types([OutputType::Stdout, OutputType::StepCompleted]);
+
+        // Wait：队列不超过10个事件，执行等到步骤完成事件处理完才返回
+        let handled = Arc::new(AtomicU64::new(0));
+        let options = AsyncSinkOptions { capacity: 10, policy: QueuePolicy::Wait };
+        let subscription = executor.subscribe_async(filter.clone(), slow_sink(handled.clone()), options);
+        let start = Instant::now();
+        assert!(executor.execute_pipeline("p").await.unwrap().overall_success);
+        assert!(start.elapsed() >= Duration::from_millis(31 * 50), "{:?}", start.elapsed());
+        assert_eq!(handled.load(Ordering::SeqCst), 31);
+        assert_eq!(subscription.queue_len(), 0);
+        assert!((2..=10).contains(&subscription.max_queue_len()), "{}", subscription.max_queue_len());
+        assert_eq!(subscription.dropped(), 0);
+        assert!(executor.unsubscribe(subscription.id));
+
+        // DropOldest：队列满时丢弃最早的输出，不等待
+        let handled = Arc::new(AtomicU64::new(0));
+        let options = AsyncSinkOptions { capacity: 10, policy: QueuePolicy::DropOldest };
+        let subscription = executor.subscribe_async(filter, slow_sink(handled.clone()), options);
+        assert!(executor.execute_pipeline("p").await.unwrap().overall_success);
+        assert!(subscription.dropped() > 0);
+        assert!(subscription.max_queue_len() <= 10, "{}", subscription.max_queue_len());
+        assert_eq!(handled.load(Ordering::SeqCst) + subscription.dropped(), 31);
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{anyhow, Context, Result};
+use futures::future::join_all;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::models::{OutputCallback, OutputEvent, OutputType, Pipeline, Step};
+use crate::ssh::local::LocalExecutor;
+use crate::vars::VariableManager;
+use super::RemoteExecutor;
+
+/// 内置的主机信息探测脚本，可通过配置的 facts_script 替换
+pub const DEFAULT_FACTS_SCRIPT: &str = include_str!("facts.sh");
+
+/// 本地执行的步骤收集主机信息时使用的服务器名
+const LOCAL_SERVER: &str = "localhost";
+
+/// 解析探测脚本的输出：每行一个 `key=value`，忽略空行、`#` 注释和不合法的行
+pub fn parse_facts(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .filter(|(key, _)| !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// 主机信息对应的变量名：facts.<服务器>.<key>
+pub fn fact_variable(server_name: &str, key: &str) -> String {
+    format!("facts.{}.{}", server_name, key)
+}
+
+impl RemoteExecutor {
+    /// 在流水线引用的每台服务器上执行探测脚本，把结果写入 facts.<服务器>.<key> 变量
+    /// 本地执行的步骤对应 localhost。收集失败时默认只发送日志事件（变量不存在），
+    /// 流水线设置 require_facts 时返回错误
+    pub(super) async fn gather_facts(
+        &self,
+        pipeline: &Pipeline,
+        events: Option<&OutputCallback>,
+        variables: &mut VariableManager,
+    ) -> Result<()> {
+        let mut servers = BTreeSet::new();
+        for step in &pipeline.steps {
+            if step.servers.is_empty() && step.server_selector.is_none() {
+                servers.insert(LOCAL_SERVER.to_string());
+                continue;
+            }
+            // 服务器列表依赖后续步骤提取的变量时无法提前解析，跳过这些服务器
+            match self.resolve_step_servers(step, variables) {
+                Ok(names) => servers.extend(names),
+                Err(e) => info!("Skipping facts for step '{}': {}", step.name, e),
+            }
+        }
+
+        let script = match &self.config.facts_script {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read facts_script '{}'", path))?,
+            None => DEFAULT_FACTS_SCRIPT.to_string(),
+        };
+        let mut probe = tempfile::Builder::new().prefix("net-shell-facts-").suffix(".sh").tempfile()?;
+        probe.write_all(script.as_bytes())?;
+        let step = Step {
+            name: "gather_facts".to_string(),
+            script: probe.path().to_string_lossy().to_string(),
+            ..Step::default()
+        };
+
+        let probes = servers.iter().map(|server_name| {
+            let step = &step;
+            async move {
+                let result = if server_name == LOCAL_SERVER && !self.config.clients.contains_key(LOCAL_SERVER) {
+                    LocalExecutor::execute_script_with_realtime_output(
+                        None, Vec::new(), step, &pipeline.name, &step.name, None, VariableManager::new(None), None,
+                    ).await
+                } else {
+                    self.execute_script_with_realtime_output(
+                        None, Arc::new(Vec::new()), server_name, step.clone(), &pipeline.name, None,
+                    ).await
+                };
+                let facts = result.and_then(|result| {
+                    if result.success {
+                        Ok(parse_facts(&result.stdout))
+                    } else {
+                        let reason = result.error_message.unwrap_or(result.stderr);
+                        Err(anyhow!("exit code {}: {}", result.exit_code, reason.trim()))
+                    }
+                });
+                (server_name, facts)
+            }
+        });
+
+        for (server_name, facts) in join_all(probes).await {
+            match facts {
+                Ok(facts) => {
+                    for (key, value) in facts {
+                        variables.set_variable(fact_variable(server_name, &key), value);
+                    }
+                }
+                Err(e) if pipeline.require_facts.unwrap_or(false) => {
+                    return Err(anyhow!("Failed to gather facts on '{}': {}", server_name, e));
+                }
+                Err(e) => {
+                    warn!("Failed to gather facts on '{}': {}", server_name, e);
+                    if let Some(callback) = events {
+                        callback(OutputEvent::new(
+                            pipeline.name.clone(),
+                            server_name.clone(),
+                            Step::default(),
+                            OutputType::Log,
+                            format!("收集主机信息失败: {}", e),
+                            variables.snapshot(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_facts() {
+        let facts = parse_facts("# probe\nos=debian\n\nkernel = 6.1.0-18-amd64\nmotd=a=b\nnot a fact\nbad key=1\n");
+        assert_eq!(facts.len(), 3);
+        assert_eq!(facts["os"], "debian");
+        assert_eq!(facts["kernel"], "6.1.0-18-amd64");
+        assert_eq!(facts["motd"], "a=b");
+    }
+
+    #[tokio::test]
+    async fn test_gather_facts_on_localhost() {
+        let check = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(check.path(), "echo \"kernel={{ facts.localhost.kernel }}\"\n").unwrap();
+        let failing = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(failing.path(), "echo broken >&2\nexit 3\n").unwrap();
+        let yaml = |facts_script: &str, require: bool| format!(r#"
+{}
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "probe"
+    gather_facts: true
+    require_facts: {}
+    steps:
+      - name: "check"
+        script: "{}"
+"#, facts_script, require, check.path().display());
+
+        let executor = RemoteExecutor::from_yaml_str(&yaml("", false), None).unwrap();
+        let result = executor.execute_pipeline("probe").await.unwrap();
+        let kernel = String::from_utf8(std::process::Command::new("uname").arg("-r").output().unwrap().stdout).unwrap();
+        assert_eq!(result.step_results[0].execution_result.stdout.trim(), format!("kernel={}", kernel.trim()));
+
+        // 自定义探测脚本失败时默认只记录日志，变量不存在
+        let facts_script = format!("facts_script: \"{}\"", failing.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml(&facts_script, false), None).unwrap();
+        let logs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if event.output_type == OutputType::Log {
+                sink.lock().unwrap().push(event.content);
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("probe", None, Some(callback)).await.unwrap();
+        assert_eq!(result.step_results[0].execution_result.stdout.trim(), "kernel={{ facts.localhost.kernel }}");
+        assert!(logs.lock().unwrap().iter().any(|log| log.contains("收集主机信息失败") && log.contains("exit code 3")));
+
+        // require_facts 时失败中止流水线
+        let executor = RemoteExecutor::from_yaml_str(&yaml(&facts_script, true), None).unwrap();
+        let err = executor.execute_pipeline("probe").await.unwrap_err().to_string();
+        assert!(err.contains("Failed to gather facts on 'localhost'"), "{}", err);
+    }
+}
@@ -0,0 +1,22 @@
+# 默认的主机信息探测脚本，每行输出一个 key=value
+if [ -r /etc/os-release ]; then
+    . /etc/os-release
+    echo "os=${ID:-unknown}"
+    echo "os_version=${VERSION_ID:-}"
+    echo "os_family=$(echo "${ID_LIKE:-${ID:-unknown}}" | cut -d' ' -f1)"
+else
+    echo "os=$(uname -s | tr '[:upper:]' '[:lower:]')"
+    echo "os_version="
+    echo "os_family=$(uname -s | tr '[:upper:]' '[:lower:]')"
+fi
+echo "kernel=$(uname -r)"
+echo "arch=$(uname -m)"
+echo "hostname=$(uname -n)"
+echo "disk_free_root_mb=$(df -Pm / 2>/dev/null | awk 'NR==2 {print $4}')"
+if [ -d /run/systemd/system ]; then
+    echo "init_system=systemd"
+elif command -v openrc >/dev/null 2>&1 || [ -x /sbin/openrc-run ]; then
+    echo "init_system=openrc"
+else
+    echo "init_system=unknown"
+fi
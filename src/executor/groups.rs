@@ -0,0 +1,119 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use tracing::Instrument;
+
+use crate::models::{GroupSummary, OutputCallback, PipelineExecutionResult, Step, StepExecutionResult};
+use super::events::legacy_subscriptions;
+use super::{new_run_id, run_span, with_global_timeout, RemoteExecutor};
+
+impl RemoteExecutor {
+    /// 只执行流水线中属于指定分组的步骤（未设置 group 的步骤属于 "default"），用于单独执行一部分步骤
+    /// 步骤对组外步骤的依赖视为已满足，组内步骤之间的依赖与顺序不变；分组中没有步骤时返回错误
+    pub async fn execute_pipeline_steps_in_group(
+        &self,
+        pipeline_name: &str,
+        group: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<PipelineExecutionResult> {
+        let mut pipeline = self.config.pipelines.iter()
+            .find(|p| p.name == pipeline_name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Pipeline '{}' not found", pipeline_name))?;
+        pipeline.steps.retain(|step| step.group_name() == group);
+        if pipeline.steps.is_empty() {
+            anyhow::bail!("Pipeline '{}' has no steps in group '{}'", pipeline_name, group);
+        }
+        let names: Vec<String> = pipeline.steps.iter().map(|step| step.name.clone()).collect();
+        for step in &mut pipeline.steps {
+            if let Some(depends_on) = &mut step.depends_on {
+                depends_on.retain(|name| names.contains(name));
+            }
+        }
+
+        let run_id = new_run_id();
+        let events = self.dispatcher(&run_id, legacy_subscriptions(output_callback, log_callback));
+        let mut variables = self.variable_manager.clone();
+        let run = self.run_pipeline_steps(pipeline, &run_id, events, &mut variables, None);
+        with_global_timeout(self.options.global_timeout, run).instrument(run_span(&run_id)).await
+    }
+}
+
+/// 按步骤分组汇总执行结果：步骤的所有服务器都成功才算成功，步骤耗时取最长的一台服务器
+pub(crate) fn group_summaries(steps: &[Step], results: &[StepExecutionResult]) -> HashMap<String, GroupSummary> {
+    let mut summaries: HashMap<String, GroupSummary> = HashMap::new();
+    for step in steps {
+        let step_results = results.iter().filter(|r| r.step_name == step.name);
+        let success = step_results.clone().all(|r| r.execution_result.success);
+        let elapsed = step_results.map(|r| r.execution_time_ms).max().unwrap_or(0);
+
+        let summary = summaries.entry(step.group_name().to_string()).or_default();
+        summary.total_steps += 1;
+        summary.successful_steps += usize::from(success);
+        summary.total_execution_time_ms += elapsed;
+    }
+    for summary in summaries.values_mut() {
+        summary.success = summary.successful_steps == summary.total_steps;
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn script(body: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(body.as_bytes()).unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn test_group_summaries_and_group_execution() {
+        let ok = script("echo ok\n");
+        let fail = script("exit 3\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+      - {{ name: "migrate", script: "{ok}", group: "db" }}
+      - {{ name: "seed", script: "{ok}", group: "db" }}
+      - {{ name: "announce", script: "{ok}" }}
+      - {{ name: "restart", script: "{fail}", group: "app" }}
+      - {{ name: "verify", script: "{ok}", group: "app", depends_on: ["seed"] }}
+"#, ok = ok.path().display(), fail = fail.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("deploy").await.unwrap();
+        let summaries = &result.group_summaries;
+        assert_eq!(summaries.len(), 3);
+        assert!(summaries["db"].success);
+        assert_eq!((summaries["db"].total_steps, summaries["db"].successful_steps), (2, 2));
+        assert_eq!((summaries["default"].total_steps, summaries["default"].successful_steps), (1, 1));
+        // verify 依赖的 seed 已成功，与失败的 restart 并发执行
+        assert!(!summaries["app"].success);
+        assert_eq!((summaries["app"].total_steps, summaries["app"].successful_steps), (2, 1));
+        let db_time: u64 = result.step_results.iter()
+            .filter(|r| r.step_name == "migrate" || r.step_name == "seed")
+            .map(|r| r.execution_time_ms)
+            .sum();
+        assert_eq!(summaries["db"].total_execution_time_ms, db_time);
+
+        let result = executor.execute_pipeline_steps_in_group("deploy", "db", None, None).await.unwrap();
+        let steps: Vec<&str> = result.step_results.iter().map(|r| r.step_name.as_str()).collect();
+        assert_eq!(steps, ["migrate", "seed"]);
+        assert_eq!(result.group_summaries.keys().collect::<Vec<_>>(), ["db"]);
+
+        // 对组外步骤的依赖视为已满足
+        let result = executor.execute_pipeline_steps_in_group("deploy", "app", None, None).await.unwrap();
+        assert!(result.step_results.iter().any(|r| r.step_name == "verify" && r.execution_result.success));
+
+        let err = executor.execute_pipeline_steps_in_group("deploy", "cache", None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "Pipeline 'deploy' has no steps in group 'cache'");
+    }
+}
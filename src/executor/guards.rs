@@ -0,0 +1,150 @@
+use std::io::Write;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::models::{ExecutionResult, OutputCallback, OutputEvent, OutputType, Step};
+use crate::ssh::local::LocalExecutor;
+use crate::vars::VariableManager;
+use super::RemoteExecutor;
+
+/// 步骤是否设置了幂等守卫（creates / removes / unless）
+pub fn has_guards(step: &Step) -> bool {
+    step.creates.is_some() || step.removes.is_some() || step.unless.is_some()
+}
+
+/// 生成守卫检查脚本：任一守卫命中时输出跳过原因并以0退出，否则以1退出
+/// 路径与命令中的变量在执行检查脚本时与步骤脚本一样替换
+pub fn guard_script(step: &Step) -> String {
+    let mut script = String::new();
+    if let Some(path) = &step.creates {
+        script.push_str(&format!(
+            "if [ -e {} ]; then echo {}; exit 0; fi\n",
+            shell_quote(path), shell_quote(&format!("creates: {} exists", path)),
+        ));
+    }
+    if let Some(path) = &step.removes {
+        script.push_str(&format!(
+            "if [ ! -e {} ]; then echo {}; exit 0; fi\n",
+            shell_quote(path), shell_quote(&format!("removes: {} does not exist", path)),
+        ));
+    }
+    if let Some(check) = &step.unless {
+        script.push_str(&format!(
+            "if ( {}\n) >/dev/null 2>&1; then echo {}; exit 0; fi\n",
+            check, shell_quote("unless: check succeeded"),
+        ));
+    }
+    script.push_str("exit 1\n");
+    script
+}
+
+/// 单引号包裹，值中的单引号转义为 '\''
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// 守卫命中时的执行结果：视为成功，不执行脚本
+fn guarded_result(step: &Step, reason: String) -> ExecutionResult {
+    ExecutionResult {
+        success: true,
+        stdout: String::new(),
+        stderr: String::new(),
+        script: step.script.clone(),
+        exit_code: 0,
+        execution_time_ms: 0,
+        error_message: None,
+        had_invalid_utf8: false,
+        artifacts: Vec::new(),
+        connect_time_ms: 0,
+        command_time_ms: 0,
+        fallback_reason: None,
+        rendered_script: None,
+        script_sha256: None,
+        connection_info: None,
+        answered_prompts: Vec::new(),
+        ssh_trace: None,
+        skip_reason: Some(reason),
+    }
+}
+
+impl RemoteExecutor {
+    /// 在目标服务器（server_name 为 None 时为本机）上检查步骤的幂等守卫
+    /// 命中时发送一条日志事件并返回跳过的执行结果；未设置守卫、守卫未命中或检查失败时返回 None，照常执行步骤
+    pub(super) async fn check_guards(
+        &self,
+        step: &Step,
+        server_name: Option<&str>,
+        pipeline_name: &str,
+        variables: &VariableManager,
+        output_callback: Option<&OutputCallback>,
+    ) -> Option<ExecutionResult> {
+        if !has_guards(step) {
+            return None;
+        }
+        let probe = tempfile::Builder::new().prefix("net-shell-guard-").suffix(".sh").tempfile()
+            .and_then(|mut probe| probe.write_all(guard_script(step).as_bytes()).map(|_| probe));
+        let probe = match probe {
+            Ok(probe) => probe,
+            Err(e) => {
+                warn!("Failed to write guard script for step '{}': {}", step.name, e);
+                return None;
+            }
+        };
+        let check = Step {
+            name: step.name.clone(),
+            script: probe.path().to_string_lossy().to_string(),
+            ..Step::default()
+        };
+
+        let result = match server_name {
+            Some(server_name) => {
+                self.execute_script_with_realtime_output(None, Arc::new(Vec::new()), server_name, check, pipeline_name, None).await
+            }
+            None => {
+                LocalExecutor::execute_script_with_realtime_output(
+                    None, Vec::new(), &check, pipeline_name, &check.name, None, variables.clone(), None,
+                ).await
+            }
+        };
+        let reason = match result {
+            Ok(result) if result.success && result.exit_code == 0 => result.stdout.trim().to_string(),
+            Ok(_) => return None,
+            Err(e) => {
+                warn!("Guard check for step '{}' failed, running the step: {}", step.name, e);
+                return None;
+            }
+        };
+
+        let server_name = server_name.unwrap_or("localhost");
+        info!("Step '{}' skipped on '{}': {}", step.name, server_name, reason);
+        if let Some(callback) = output_callback {
+            callback(OutputEvent::new(
+                pipeline_name.to_string(),
+                server_name.to_string(),
+                step.clone(),
+                OutputType::Log,
+                format!("Step skipped: {}", reason),
+                variables.snapshot(),
+            ));
+        }
+        Some(guarded_result(step, reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_script_quotes_paths() {
+        let step = Step {
+            creates: Some("/opt/it's/{{ app }}".to_string()),
+            unless: Some("systemctl is-active nginx".to_string()),
+            ..Step::default()
+        };
+        let script = guard_script(&step);
+        assert!(script.starts_with(r"if [ -e '/opt/it'\''s/{{ app }}' ]; then echo 'creates: /opt/it'\''s/{{ app }} exists'; exit 0; fi"), "{}", script);
+        assert!(script.contains("if ( systemctl is-active nginx\n) >/dev/null 2>&1;"), "{}", script);
+        assert!(script.ends_with("exit 1\n"));
+    }
+}
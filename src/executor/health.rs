@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::models::{ClientConfig, ExecutionMethod};
+#[cfg(any(feature = "ssh", feature = "russh"))]
+use crate::models::{SshBackend, SshConfig};
+#[cfg(any(feature = "ssh", feature = "russh"))]
+use crate::ssh::auth::resolve_passphrase;
+use super::RemoteExecutor;
+
+impl RemoteExecutor {
+    /// 检查所有客户端的连通性（类似 `ansible -m ping`）：对SSH客户端建立TCP连接、完成握手和认证，不执行任何脚本。
+    /// 所有客户端并发检查，返回客户端名称 -> 检查结果；local 客户端总是可用
+    pub async fn healthcheck(&self) -> HashMap<String, Result<()>> {
+        let checks = self.config.clients.iter().map(|(name, client)| async move {
+            (name.clone(), self.check_client(client).await)
+        });
+        futures::future::join_all(checks).await.into_iter().collect()
+    }
+
+    /// 检查单个客户端，凭据与执行步骤时一样来自认证提供者和口令回调
+    #[cfg_attr(not(any(feature = "ssh", feature = "russh")), allow(unreachable_code, unused_variables))]
+    async fn check_client(&self, client: &ClientConfig) -> Result<()> {
+        match client.execution_method {
+            ExecutionMethod::Local => return Ok(()),
+            ExecutionMethod::WebSocket => return Err(anyhow::anyhow!("WebSocket execution not implemented yet")),
+            ExecutionMethod::SSH => {}
+        }
+        let ssh_config = client.ssh_config.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SSH configuration not found for client '{}'", client.name))?;
+        let credentials = self.auth_provider.credentials(client)?;
+
+        #[cfg(any(feature = "ssh", feature = "russh"))]
+        {
+            let ssh_config = credentials.apply(SshConfig {
+                timeout_seconds: ssh_config.timeout_seconds.or(self.config.default_timeout),
+                ..ssh_config.clone()
+            });
+            let passphrase_callback = self.passphrase_callback.clone();
+            match ssh_config.backend.unwrap_or_default() {
+                #[cfg(feature = "ssh")]
+                SshBackend::Libssh2 => {
+                    return super::run_blocking_ssh(&self.ssh_workers, move || {
+                        let ssh_config = resolve_passphrase(ssh_config, passphrase_callback.as_ref());
+                        crate::ssh::SshExecutor::connect_and_auth(&ssh_config)
+                    }).await?.map(|_| ());
+                }
+                #[cfg(feature = "russh")]
+                SshBackend::Russh => {
+                    let ssh_config = tokio::task::spawn_blocking(move || resolve_passphrase(ssh_config, passphrase_callback.as_ref())).await?;
+                    return crate::ssh::RusshExecutor::connect_and_auth(&ssh_config).await.map(|_| ());
+                }
+                #[allow(unreachable_patterns)]
+                _ => {}
+            }
+        }
+
+        let feature = if ssh_config.backend.unwrap_or_default() == crate::models::SshBackend::Russh { "russh" } else { "ssh" };
+        Err(anyhow::anyhow!(
+            "Client '{}' uses SSH execution, but net-shell was built without the `{}` feature",
+            client.name, feature
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_healthcheck_reports_each_client() {
+        let yaml = r#"
+clients:
+  controller:
+    name: "controller"
+    execution_method: local
+  web1:
+    name: "web1"
+    execution_method: ssh
+    ssh_config:
+      host: "127.0.0.1"
+      port: 1
+      username: "user"
+      password: "password"
+      timeout_seconds: 1
+pipelines:
+  - name: "noop"
+    steps:
+      - name: "noop"
+        script: "noop.sh"
+"#;
+        let executor = RemoteExecutor::from_yaml_str(yaml, None).unwrap();
+        let results = executor.healthcheck().await;
+        assert_eq!(results.len(), 2);
+        assert!(results["controller"].is_ok());
+        assert!(results["web1"].is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::models::{OutputCallback, OutputEvent, OutputType, Step};
+use crate::ssh::call_guarded;
+use super::RemoteExecutor;
+
+/// 最多保留缓冲事件的执行数，超出时丢弃最早开始的执行
+const BUFFERED_RUNS: usize = 16;
+
+/// 事件缓冲的上限，每次执行分别计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventBuffering {
+    pub max_events: usize, // 最多保留的事件数
+    pub max_bytes: usize,  // 保留事件的 content 总字节数上限
+}
+
+/// 一次执行缓冲的事件
+#[derive(Default)]
+struct RunJournal {
+    events: VecDeque<OutputEvent>,
+    bytes: usize,                   // events 的 content 总字节数
+    dropped: u64,                   // 超出上限丢弃的最早事件数
+    next_sequence: u64,
+    finished: bool,
+    consumers: Vec<OutputCallback>, // 回放后接入的消费者，继续实时接收之后的事件
+}
+
+/// 执行器的事件缓冲：按 run_id 保存最近几次执行的事件
+#[derive(Default)]
+pub(crate) struct EventJournal {
+    runs: Mutex<VecDeque<(String, Arc<Mutex<RunJournal>>)>>,
+}
+
+impl EventJournal {
+    /// 开始缓冲一次执行的事件，同一 run_id 已有的缓冲被替换
+    pub(crate) fn start(&self, run_id: &str, limits: EventBuffering) -> JournalWriter {
+        let run = Arc::new(Mutex::new(RunJournal::default()));
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        runs.retain(|(id, _)| id != run_id);
+        if runs.len() >= BUFFERED_RUNS {
+            runs.pop_front();
+        }
+        runs.push_back((run_id.to_string(), run.clone()));
+        JournalWriter { run, limits }
+    }
+
+    /// 按顺序把缓冲的事件发送给回调，执行未结束时回调继续接收之后的事件；
+    /// 有事件被丢弃时先发送 EventsDropped 事件。没有该 run_id 的缓冲时返回 false
+    pub(crate) fn replay(&self, run_id: &str, callback: OutputCallback) -> bool {
+        let run = {
+            let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+            match runs.iter().find(|(id, _)| id == run_id) {
+                Some((_, run)) => run.clone(),
+                None => return false,
+            }
+        };
+        // 持有锁期间发送历史事件并登记回调，新事件只能在之后发送，不会重复或遗漏
+        let mut run = run.lock().unwrap_or_else(|e| e.into_inner());
+        if run.dropped > 0 && !call_guarded(&callback, dropped_marker(run_id, &run)) {
+            return true;
+        }
+        for event in &run.events {
+            if !call_guarded(&callback, event.clone()) {
+                return true;
+            }
+        }
+        if !run.finished {
+            run.consumers.push(callback);
+        }
+        true
+    }
+}
+
+/// 一次执行的事件写入端，执行结束（分发回调被释放）时释放接入的消费者
+pub(crate) struct JournalWriter {
+    run: Arc<Mutex<RunJournal>>,
+    limits: EventBuffering,
+}
+
+impl JournalWriter {
+    /// 为事件分配序号并缓冲，超出上限时从最早的事件开始丢弃；同时发送给回放后接入的消费者
+    pub(crate) fn record(&self, event: &mut OutputEvent) {
+        let mut run = self.run.lock().unwrap_or_else(|e| e.into_inner());
+        run.next_sequence += 1;
+        event.sequence = run.next_sequence;
+        run.bytes += event.content.len();
+        run.events.push_back(event.clone());
+        while run.events.len() > self.limits.max_events || run.bytes > self.limits.max_bytes {
+            let Some(oldest) = run.events.pop_front() else { break };
+            run.bytes -= oldest.content.len();
+            run.dropped += 1;
+        }
+        // panic 的消费者不再接收事件
+        run.consumers.retain(|callback| call_guarded(callback, event.clone()));
+    }
+}
+
+impl Drop for JournalWriter {
+    fn drop(&mut self) {
+        let mut run = self.run.lock().unwrap_or_else(|e| e.into_inner());
+        run.finished = true;
+        run.consumers.clear();
+    }
+}
+
+/// 表示最早的事件已被丢弃的事件，序号为最后一个被丢弃事件的序号
+fn dropped_marker(run_id: &str, run: &RunJournal) -> OutputEvent {
+    let first = run.events.front();
+    OutputEvent {
+        run_id: run_id.to_string(),
+        pipeline_index: first.map_or(0, |e| e.pipeline_index),
+        total_steps: first.map_or(0, |e| e.total_steps),
+        sequence: run.dropped,
+        ..OutputEvent::new(
+            first.map(|e| e.pipeline_name.clone()).unwrap_or_default(),
+            "system",
+            Step::default(),
+            OutputType::EventsDropped,
+            run.dropped.to_string(),
+            Default::default(),
+        )
+    }
+}
+
+impl RemoteExecutor {
+    /// 把执行器缓冲的某次执行的事件按顺序发送给回调，用于执行开始后才接入的消费者（如网页界面）
+    /// 需要通过 `with_event_buffer` 开启事件缓冲。执行未结束时回调随后继续实时接收新事件，不会重复或遗漏；
+    /// 缓冲超出上限丢弃过事件时先收到 EventsDropped 事件。没有该 run_id 的缓冲时返回 false
+    pub fn replay_events(&self, run_id: &str, callback: OutputCallback) -> bool {
+        self.journal.replay(run_id, callback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(content: &str) -> OutputEvent {
+        OutputEvent {
+            run_id: "run-1".to_string(),
+            ..OutputEvent::new(
+                "deploy",
+                "web1",
+                Step::default(),
+                OutputType::Stdout,
+                content.to_string(),
+                Default::default(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_oldest_events_are_dropped_with_marker() {
+        let journal = EventJournal::default();
+        let writer = journal.start("run-1", EventBuffering { max_events: 3, max_bytes: 10 });
+        for content in ["one", "two", "three", "four", "five"] {
+            writer.record(&mut event(content));
+        }
+        drop(writer);
+
+        let replayed = Arc::new(Mutex::new(Vec::new()));
+        let sink = replayed.clone();
+        assert!(journal.replay("run-1", Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e))));
+        assert!(!journal.replay("run-2", Arc::new(|_| {})));
+
+        // 字节数上限只容得下最后两个事件
+        let replayed = replayed.lock().unwrap();
+        let summary: Vec<(OutputType, String, u64)> = replayed.iter()
+            .map(|e| (e.output_type.clone(), e.content.clone(), e.sequence))
+            .collect();
+        assert_eq!(summary, [
+            (OutputType::EventsDropped, "3".to_string(), 3),
+            (OutputType::Stdout, "four".to_string(), 4),
+            (OutputType::Stdout, "five".to_string(), 5),
+        ]);
+    }
+}
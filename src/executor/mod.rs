@@ -1,435 +1,1263 @@
+pub mod adhoc;
+pub mod assertions;
+pub mod builder;
+pub mod control;
+pub mod dag;
+pub mod events;
+pub mod facts;
+pub mod guards;
+pub mod groups;
+pub mod health;
+pub mod journal;
+pub mod outputs;
+pub mod plan;
+pub mod recording;
+pub mod schedule;
+pub mod stream;
+
 use anyhow::{Context, Result};
-use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
 use std::collections::HashMap;
 use std::path::Path;
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use tracing::{error, info};
+use tokio::sync::mpsc;
+#[cfg(feature = "ssh")]
+use tokio::sync::Semaphore;
+use tracing::{error, info, Instrument};
 
-use crate::config::ConfigManager;
 use crate::models::{
-    ClientConfig, ExecutionMethod, ExecutionResult, PipelineExecutionResult, 
-    RemoteExecutionConfig, Step, StepExecutionResult, OutputCallback, OutputEvent
+    AggregateStrategy, ClientConfig, ExecutionMethod, ExecutionOutcome, ExecutionResult, ExitClass, LineFilter, PassphraseCallback, Pipeline, PipelineExecutionResult, 
+    RemoteExecutionConfig, SshBackend, SshConfig, Step, StepExecutionResult, StepStatus, AsyncOutputCallback, OutputCallback, OutputEvent
 };
+#[cfg(feature = "ssh")]
 use crate::ssh::SshExecutor;
+#[cfg(feature = "russh")]
+use crate::ssh::RusshExecutor;
+use crate::config::{select_clients, ConfigManager, ValidationWarning};
+use crate::ssh::auth::AuthProvider;
+#[cfg(any(feature = "ssh", feature = "russh"))]
+use crate::ssh::auth::resolve_passphrase;
 use crate::ssh::local::LocalExecutor;
-use crate::vars::VariableManager;
+use crate::ssh::{build_script_preamble, check_script_file, render_phase_script, script_error_result, strip_ansi};
+#[cfg(any(feature = "ssh", feature = "russh"))]
+use crate::ssh::ConnectError;
+use crate::template::EscapeMode;
+use crate::vars::{VariableManager, STEP_ELAPSED_VARIABLE};
 use crate::ShellExecutionResult;
+use builder::{ExecutorOptions, RemoteExecutorBuilder};
+use control::{CancellationHandle, Resume, StepController, StepGate};
+use dag::StepGraph;
+use groups::group_summaries;
+use journal::EventJournal;
+use events::{batching_sink, channel_sink, file_logging_callback, legacy_subscriptions, throttling_sink, tracing_callback, AsyncSinkOptions, AsyncSubscription, EventBus, EventFilter, SubscriptionId};
+
+/// 本地并发执行脚本的默认并发数
+const DEFAULT_LOCAL_PARALLELISM: usize = 4;
+
+/// 默认同时执行的 libssh2 会话数，远小于 tokio 阻塞线程池的512个线程
+#[cfg(feature = "ssh")]
+pub(crate) const DEFAULT_SSH_WORKER_THREADS: usize = 64;
+
+/// 流水线提前结束时，没有执行完的步骤的完成事件内容与错误信息
+const NOT_RUN_REASON: &str = "not run (pipeline aborted)";
+
+/// 时间预算用完时流水线的失败原因
+const PIPELINE_TIMEOUT_REASON: &str = "pipeline timeout exceeded";
+
+/// 结果中 rendered_script 默认保留的最大字节数
+const DEFAULT_RENDERED_SCRIPT_MAX_BYTES: usize = 64 * 1024;
+
+/// 流水线中步骤的调度状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
 
 /// 远程执行器
+/// 执行方法只需要 `&self`：每次执行从基础变量复制一份独立的变量上下文，配置只读共享，
+/// 因此同一个执行器可以在多个任务中并发执行流水线
+#[derive(Clone)]
 pub struct RemoteExecutor {
-    config: RemoteExecutionConfig,
+    config: Arc<RemoteExecutionConfig>,
     variable_manager: VariableManager,
+    event_bus: Arc<EventBus>,
+    journal: Arc<EventJournal>, // 开启 event_buffer 时按 run_id 缓冲的事件，供 replay_events 回放
+    options: ExecutorOptions,
+    secret_patterns: Vec<Regex>,
+    line_filter: Option<LineFilter>, // 输出行过滤器，在行写入结果和事件之前调用
+    cancellation: CancellationHandle, // 本次执行的取消句柄，默认不会被取消
+    run_deadline: Option<std::time::Instant>, // 本次执行按 total_timeout_seconds 计算的截止时间，执行多个流水线时设置
+    return_connect_errors: bool, // SSH连接失败时返回错误而不是失败结果，由调用方换下一台服务器或备用服务器
+    auth_provider: Arc<dyn AuthProvider>, // 建立SSH连接前获取凭据，默认使用 ssh_config 中的凭据
+    #[cfg_attr(not(any(feature = "ssh", feature = "russh")), allow(dead_code))]
+    passphrase_callback: Option<PassphraseCallback>, // 私钥没有配置口令时获取口令
+    #[cfg(feature = "ssh")]
+    ssh_workers: Arc<Semaphore>, // libssh2 会话占用的阻塞线程许可，执行器的所有副本共用
 }
 
 impl RemoteExecutor {
-
+    /// 创建执行器构建器
+    pub fn builder() -> RemoteExecutorBuilder {
+        RemoteExecutorBuilder::new()
+    }
 
     /// 从YAML文件创建执行器
     pub fn from_yaml_file<P: AsRef<Path>>(path: P, variables: Option<HashMap<String, String>>) -> Result<Self> {
-        let content = std::fs::read_to_string(path)
-            .context("Failed to read YAML configuration file")?;
-        
-        Self::from_yaml_str(&content, variables)
+        RemoteExecutorBuilder::new()
+            .yaml_file(path)
+            .variables(variables.unwrap_or_default())
+            .build()
     }
 
     /// 从YAML字符串创建执行器
     pub fn from_yaml_str(yaml_content: &str, variables: Option<HashMap<String, String>>) -> Result<Self> {
-        // 提取初始变量
-        let initial_variables = ConfigManager::extract_initial_variables(yaml_content)?;
+        RemoteExecutorBuilder::new()
+            .yaml_str(yaml_content)
+            .variables(variables.unwrap_or_default())
+            .build()
+    }
 
-        // 合并变量
-        let mut all_variables = HashMap::new();
+    /// 从配置模板和数据文件（JSON或YAML）创建执行器
+    /// 模板按 ConfigManager::render_template_strict 渲染（#{ name } 引用数据，#{% for %} 展开清单），渲染结果按YAML配置加载；
+    /// variables 与 from_yaml_file 相同，是执行时的变量
+    pub fn from_template<P: AsRef<Path>, Q: AsRef<Path>>(
+        template_path: P,
+        data_path: Q,
+        variables: Option<HashMap<String, String>>,
+    ) -> Result<Self> {
+        let template_path = template_path.as_ref();
+        let template = std::fs::read_to_string(template_path)
+            .with_context(|| format!("Failed to read configuration template '{}'", template_path.display()))?;
+        let data = ConfigManager::load_template_data(data_path)?;
+        let yaml = ConfigManager::render_template_strict(&template, data, template_path.parent())?;
+        Self::from_yaml_str(&yaml, variables)
+    }
 
-        if let Some(v) = initial_variables {
-            all_variables.extend(v);
-        }
+    /// 构建时设置的执行器选项
+    pub fn options(&self) -> &ExecutorOptions {
+        &self.options
+    }
+
+    /// 订阅输出事件，所有满足过滤条件的订阅者都会收到事件
+    pub fn subscribe(&self, filter: EventFilter, callback: OutputCallback) -> SubscriptionId {
+        self.event_bus.subscribe(filter, callback)
+    }
+
+    /// 以异步回调订阅输出事件，回调返回的 future 依次执行
+    /// 事件进入容量为 options.capacity 的队列，队列已满时按 options.policy 等待或丢弃最早的 stdout/stderr 事件；
+    /// 其他事件（步骤开始、完成等）等回调处理完才继续执行
+    pub fn subscribe_async(&self, filter: EventFilter, callback: AsyncOutputCallback, options: AsyncSinkOptions) -> AsyncSubscription {
+        self.event_bus.subscribe_async(filter, callback, options)
+    }
+
+    /// 取消订阅，返回订阅是否存在
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.event_bus.unsubscribe(id)
+    }
+
+    /// 检查配置中的提取规则和脚本占位符，返回可能的问题（无效的正则或 source 返回错误）
+    pub fn lint(&self) -> Result<Vec<ValidationWarning>> {
+        ConfigManager::validate_config_with_variables(&self.config, self.variable_manager.get_variables())
+    }
+
+    /// 渲染步骤脚本但不执行，返回实际会运行的脚本内容（全局脚本、流水线脚本与步骤脚本拼接后替换变量）
+    /// 使用执行器的基础变量与步骤级 variables；执行中才会提取的变量尚不存在，其占位符保持原样
+    pub fn render_step_script(&self, pipeline_name: &str, step_name: &str) -> Result<String> {
+        let pipeline = self.config.pipelines.iter()
+            .find(|p| p.name == pipeline_name)
+            .ok_or_else(|| anyhow::anyhow!("Pipeline '{}' not found", pipeline_name))?;
+        let step = pipeline.steps.iter()
+            .find(|s| s.name == step_name)
+            .ok_or_else(|| anyhow::anyhow!("Step '{}' not found in pipeline '{}'", step_name, pipeline_name))?;
 
-        if let Some(v) = variables {
-            all_variables.extend(v);
+        // 与 run_step 相同的变量上下文
+        let mut variables = self.variable_manager.clone();
+        for (k, v) in step.variables.iter().flatten() {
+            variables.set_variable(k.clone(), v.clone());
         }
-        
-        // 创建变量管理器
-        let variable_manager = VariableManager::new(Some(all_variables));
-        
-        // 应用变量替换解析配置
-        let config = ConfigManager::from_yaml_str_with_variables(yaml_content, &variable_manager)?;
-        ConfigManager::validate_config(&config)?;
-        
-        Ok(Self { config, variable_manager})
+        if let Some(quote) = step.quote_variables {
+            variables.set_script_escape(quote.then_some(EscapeMode::Shell));
+        }
+        variables.set_variable("pipeline_name".to_string(), pipeline_name.to_string());
+        variables.set_variable("step_name".to_string(), step_name.to_string());
+
+        let script_path = variables.replace_variables(&step.script);
+        let preamble = build_script_preamble(
+            self.config.global_scripts.as_deref().unwrap_or_default(),
+            pipeline.script.as_deref(),
+        )?;
+        render_phase_script(&preamble, &script_path, &variables)
     }
 
     /// 执行指定的流水线（支持实时输出）
+    /// output_callback 接收步骤与输出事件，log_callback 接收日志事件，
+    /// 两者仅对本次执行有效，执行器上的订阅者同样会收到事件
+    /// 基础变量在开始执行时复制为本次执行的变量上下文，执行中提取的变量不会写回执行器
     pub async fn execute_pipeline_with_realtime_output(
-        &mut self, // 需要可变引用
+        &self,
         pipeline_name: &str,
         output_callback: Option<OutputCallback>,
         log_callback: Option<OutputCallback>
+    ) -> Result<PipelineExecutionResult> {
+        self.execute_pipeline_with_run_id(pipeline_name, &new_run_id(), output_callback, log_callback).await
+    }
+
+    /// 使用调用方指定的 run_id 执行流水线，便于与外部系统关联
+    /// 本次执行的事件、结果与 tracing 日志都带有该 run_id；未指定时各 execute_* 方法自动生成UUID
+    pub async fn execute_pipeline_with_run_id(
+        &self,
+        pipeline_name: &str,
+        run_id: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>
+    ) -> Result<PipelineExecutionResult> {
+        let events = self.dispatcher(run_id, legacy_subscriptions(output_callback, log_callback));
+        let mut variables = self.variable_manager.clone();
+        let run = self.run_pipeline(pipeline_name, run_id, events, &mut variables, None);
+        with_global_timeout(self.options.global_timeout, run).instrument(run_span(run_id)).await
+    }
+
+    /// 在后台任务中执行指定的流水线，适合不等待结果的调用方
+    pub fn execute_pipeline_detached(
+        &self,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> tokio::task::JoinHandle<Result<PipelineExecutionResult>> {
+        let executor = self.clone();
+        let pipeline_name = pipeline_name.to_string();
+        tokio::spawn(async move {
+            executor.execute_pipeline_with_realtime_output(&pipeline_name, output_callback, log_callback).await
+        })
+    }
+
+    /// 执行指定的流水线，事件发送到容量为 buffer 的通道
+    /// 返回的 future 被轮询时才开始执行；接收方处理较慢、通道已满时，输出的发送会等待（背压），
+    /// 因此需要与 future 同时接收事件（如 `tokio::join!` / `select!`）。执行结束后通道关闭
+    pub fn execute_pipeline_with_progress_channel(
+        &self,
+        pipeline_name: &str,
+        buffer: usize,
+    ) -> (impl std::future::Future<Output = Result<PipelineExecutionResult>> + Send + 'static, mpsc::Receiver<OutputEvent>) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        let executor = self.clone();
+        let pipeline_name = pipeline_name.to_string();
+        let run_id = new_run_id();
+        let span = run_span(&run_id);
+        let future = async move {
+            let (sink, forwarder) = channel_sink(sender);
+            let events = executor.dispatcher(&run_id, vec![(EventFilter::all(), sink)]);
+            let mut variables = executor.variable_manager.clone();
+            let result = with_global_timeout(
+                executor.options.global_timeout,
+                executor.run_pipeline(&pipeline_name, &run_id, events, &mut variables, None),
+            ).await;
+            if let Some(forwarder) = forwarder {
+                let _ = forwarder.await;
+            }
+            result
+        }.instrument(span);
+        (future, receiver)
+    }
+
+    /// 以单步模式在后台执行流水线，返回控制句柄与执行任务
+    /// 每个步骤开始前暂停，等待 StepController 的 next / run_to_end / abort，单步期间步骤逐个执行；
+    /// SSH连接在步骤执行时才建立，暂停期间不占用连接。暂停等待的时间不受 global_timeout 限制
+    pub fn execute_pipeline_controlled(
+        &self,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> (StepController, tokio::task::JoinHandle<Result<PipelineExecutionResult>>) {
+        let (controller, mut gate) = StepGate::new();
+        let executor = self.clone();
+        let pipeline_name = pipeline_name.to_string();
+        let run_id = new_run_id();
+        let span = run_span(&run_id);
+        let handle = tokio::spawn(async move {
+            let events = executor.dispatcher(&run_id, legacy_subscriptions(output_callback, log_callback));
+            let mut variables = executor.variable_manager.clone();
+            executor.run_pipeline(&pipeline_name, &run_id, events, &mut variables, Some(&mut gate)).await
+        }.instrument(span));
+        (controller, handle)
+    }
+
+    /// 创建本次执行的事件分发回调，为事件填入 run_id
+    /// 配置了 secret_patterns（包括自动加入的 sudo 密码）时先对事件内容和变量值脱敏，步骤中的 sudo 密码总是隐藏；
+    /// 事件按分发顺序编号，开启 event_buffer 时同时写入事件缓冲
+    fn dispatcher(&self, run_id: &str, mut scoped: Vec<(EventFilter, OutputCallback)>) -> Option<OutputCallback> {
+        // 配置了 log_dir 时每次执行把输出写入该目录
+        if let Some(dir) = &self.config.log_dir {
+            let filter = EventFilter::output_types([crate::models::OutputType::Stdout, crate::models::OutputType::Stderr]);
+            scoped.push((filter, file_logging_callback(dir)));
+        }
+        if self.options.trace_events {
+            scoped.push((EventFilter::all(), tracing_callback()));
+        }
+        // 开启事件缓冲时即使没有订阅者也要产生事件，供之后接入的消费者回放
+        let journal = self.options.event_buffer.map(|limits| self.journal.start(run_id, limits));
+        let dispatch = match self.event_bus.dispatcher(scoped) {
+            Some(dispatch) => dispatch,
+            None if journal.is_some() => Arc::new(|_| {}),
+            None => return None,
+        };
+        let dispatch = match self.options.output_batching {
+            Some(batching) => batching_sink(dispatch, batching),
+            None => dispatch,
+        };
+        let dispatch = match self.options.event_throttle {
+            Some(throttle) => throttling_sink(dispatch, throttle),
+            None => dispatch,
+        };
+        let run_id = run_id.to_string();
+        let patterns = self.secret_patterns.clone();
+        // 流水线名 -> (流水线位置, 步骤名 -> 步骤位置, 步骤总数)
+        let mut positions: HashMap<String, (usize, HashMap<String, usize>, usize)> = HashMap::new();
+        for (pipeline_index, pipeline) in self.config.pipelines.iter().enumerate() {
+            let mut steps = HashMap::new();
+            for (step_index, step) in pipeline.steps.iter().enumerate() {
+                steps.entry(step.name.clone()).or_insert(step_index);
+            }
+            positions.entry(pipeline.name.clone()).or_insert((pipeline_index, steps, pipeline.steps.len()));
+        }
+        // 同一份变量副本只脱敏一次：(原副本, 脱敏后的副本)
+        let masked = std::sync::Mutex::new(None);
+        let sequence = std::sync::atomic::AtomicU64::new(0);
+        Some(Arc::new(move |mut event: OutputEvent| {
+            event.run_id.clone_from(&run_id);
+            if let Some((pipeline_index, steps, total_steps)) = positions.get(&event.pipeline_name) {
+                event.pipeline_index = *pipeline_index;
+                event.step_index = steps.get(&event.step.name).copied();
+                event.total_steps = *total_steps;
+            }
+            if let Some(password) = event.step.sudo.as_mut().and_then(|sudo| sudo.password.as_mut()) {
+                *password = "******".to_string();
+            }
+            for pattern in &patterns {
+                event.content = pattern.replace_all(&event.content, "******").into_owned();
+            }
+            if !patterns.is_empty() {
+                let mut masked = masked.lock().unwrap();
+                match &*masked {
+                    Some((original, variables)) if Arc::ptr_eq(original, &event.variables) => {
+                        event.variables = Arc::clone(variables);
+                    }
+                    _ => {
+                        let mut variables = (*event.variables).clone();
+                        for value in variables.values_mut() {
+                            for pattern in &patterns {
+                                if pattern.is_match(value) {
+                                    *value = pattern.replace_all(value, "******").into_owned();
+                                }
+                            }
+                        }
+                        let variables = Arc::new(variables);
+                        *masked = Some((std::mem::replace(&mut event.variables, variables.clone()), variables));
+                    }
+                }
+            }
+            // 缓冲事件时由缓冲区分配序号，保证缓冲的顺序与序号一致
+            match &journal {
+                Some(journal) => journal.record(&mut event),
+                None => event.sequence = sequence.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1,
+            }
+            dispatch(event)
+        }))
+    }
+
+    /// 执行指定的流水线，所有事件发送到同一个分发回调
+    /// 传入 gate 时按单步模式执行，每个步骤开始前在 gate 上暂停
+    async fn run_pipeline(
+        &self,
+        pipeline_name: &str,
+        run_id: &str,
+        events: Option<OutputCallback>,
+        variables: &mut VariableManager,
+        gate: Option<&mut StepGate>,
     ) -> Result<PipelineExecutionResult> {
         let pipeline = self.config.pipelines.iter()
             .find(|p| p.name == pipeline_name)
             .cloned()
             .ok_or_else(|| anyhow::anyhow!("Pipeline '{}' not found", pipeline_name))?;
+        self.run_pipeline_steps(pipeline, run_id, events, variables, gate).await
+    }
+
+    /// 执行给定的流水线（包括不在 pipelines 中的 before_all / after_all），期间的日志都在该流水线的 span 中
+    async fn run_pipeline_steps(
+        &self,
+        pipeline: Pipeline,
+        run_id: &str,
+        events: Option<OutputCallback>,
+        variables: &mut VariableManager,
+        gate: Option<&mut StepGate>,
+    ) -> Result<PipelineExecutionResult> {
+        let span = tracing::info_span!("pipeline", run_id = %run_id, pipeline = %pipeline.name);
+        self.run_pipeline_steps_in_span(pipeline, run_id, events, variables, gate).instrument(span).await
+    }
 
+    async fn run_pipeline_steps_in_span(
+        &self,
+        pipeline: Pipeline,
+        run_id: &str,
+        events: Option<OutputCallback>,
+        variables: &mut VariableManager,
+        mut gate: Option<&mut StepGate>,
+    ) -> Result<PipelineExecutionResult> {
         let pipeline_name = pipeline.name.clone();
-        let steps: Vec<Step> = pipeline.steps.clone();
+        let graph = StepGraph::new(&pipeline)?;
         let start_time = std::time::Instant::now();
+        let deadline = self.pipeline_deadline(&pipeline, start_time);
         let mut all_step_results = Vec::new();
 
         // 发送开始执行流水线的日志
-        if let Some(callback) = &log_callback {
-            let event = OutputEvent {
-                pipeline_name: pipeline_name.clone(),
-                server_name: "system".to_string(),
-                script_path:"".to_string(),
-                step: Step::default(), // 流水线开始事件没有具体的步骤
-                output_type: crate::models::OutputType::Log,
-                content: format!("开始执行流水线: {}", pipeline_name),
-                timestamp: std::time::Instant::now(),
-                variables: self.variable_manager.get_variables().clone(),
-            };
+        if let Some(callback) = &events {
+            let event = OutputEvent::new(
+                pipeline_name.clone(),
+                "system",
+                Step::default(),
+                crate::models::OutputType::Log,
+                format!("开始执行流水线: {}", pipeline_name),
+                variables.snapshot(),
+            );
             callback(event);
         }
 
         info!("Starting pipeline: {}", pipeline_name);
+        if let (Some(deadline), Some(callback)) = (deadline, &events) {
+            let budget = deadline.saturating_duration_since(start_time).as_secs();
+            callback(pipeline_log_event(&pipeline_name, format!("流水线时间预算: {}s", budget), variables));
+        }
 
-        // 按顺序执行每个步骤（串行）
-        for step in steps {
-            // 合并 step 级变量到全局变量（优先级高）
-            let mut step_var_keys = Vec::new();
-            if let Some(vars) = &step.variables {
-                for (k, v) in vars {
-                    self.variable_manager.set_variable(k.clone(), v.clone());
-                    step_var_keys.push(k.clone());
+        if pipeline.gather_facts.unwrap_or(false) {
+            self.gather_facts(&pipeline, events.as_ref(), variables).await?;
+        }
+
+        // 导出变量在流水线开始前的值，用于发现被覆盖的变量
+        let outputs_before: HashMap<String, String> = pipeline.outputs.iter().flatten()
+            .filter_map(|name| Some((name.clone(), variables.get_variable(name)?.clone())))
+            .collect();
+
+        // 按依赖关系调度步骤：依赖全部成功的步骤立即开始，互不依赖的步骤并发执行
+        // 每个运行中的步骤使用独立的变量副本，完成后把它改动的变量合并回来
+        let mut states = vec![StepState::Pending; pipeline.steps.len()];
+        let mut running = FuturesUnordered::new();
+        let mut failed = false;
+        let mut timed_out = false;
+        loop {
+            // 剩余时间不足1秒时无法再给步骤设置超时，视为预算已用完
+            if !timed_out && deadline.is_some_and(|d| remaining_seconds(d) == 0) {
+                timed_out = true;
+                info!("Pipeline '{}' exceeded its time budget, stopping", pipeline_name);
+                if let Some(callback) = &events {
+                    callback(pipeline_log_event(&pipeline_name, PIPELINE_TIMEOUT_REASON.to_string(), variables));
                 }
             }
-            // 对当前步骤应用变量替换
-            let mut step_with_variables = step.clone();
-            step_with_variables.script = self.variable_manager.replace_variables(&step.script);
-            
-            // 发送步骤开始事件
-            if let Some(callback) = &output_callback {
-                let event = OutputEvent {
-                    pipeline_name: pipeline_name.clone(),
-                    server_name: "system".to_string(),
-                    step: step.clone(), // 传递完整的Step对象
-                    output_type: crate::models::OutputType::StepStarted,
-                    script_path:step.script.clone(),
-                    content: format!("开始执行步骤: {} ({} 个服务器)", step.name, step.servers.len()),
-                    timestamp: std::time::Instant::now(),
-                    variables: self.variable_manager.get_variables().clone(),
-                };
-                callback(event);
+            if !failed && !timed_out && !self.cancellation.is_cancelled() {
+                for index in 0..pipeline.steps.len() {
+                    let ready = states[index] == StepState::Pending
+                        && graph.dependencies(index).iter().all(|d| states[*d] == StepState::Succeeded);
+                    if !ready {
+                        continue;
+                    }
+                    // 单步模式下一次只运行一个步骤，前一个步骤结束后才暂停等待下一个
+                    let mut stepping = false;
+                    if let Some(gate) = gate.as_mut().filter(|g| g.is_stepping()) {
+                        if !running.is_empty() {
+                            break;
+                        }
+                        stepping = gate.pause(&pipeline.steps[index].name, variables).await? == Resume::Next;
+                    }
+                    states[index] = StepState::Running;
+                    let mut step_variables = variables.clone();
+                    let (pipeline, events) = (&pipeline, &events);
+                    let step = self.with_remaining_budget(&pipeline.steps[index], deadline);
+                    running.push(async move {
+                        let before = step_variables.get_variables().clone();
+                        let result = self.run_step(pipeline, &step, events.as_ref(), &mut step_variables).await;
+                        let changed: Vec<(String, String)> = step_variables.get_variables().iter()
+                            .filter(|(k, v)| before.get(*k) != Some(*v))
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                            .collect();
+                        (index, changed, result)
+                    });
+                    if stepping {
+                        break;
+                    }
+                }
+            }
+
+            // 取消或时间预算用完时丢弃正在执行的步骤（中止其任务），只保留已完成步骤的结果
+            let budget = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let next = tokio::select! {
+                next = running.next() => next,
+                _ = self.cancellation.cancelled() => None,
+                _ = budget => None,
+            };
+            let Some((index, changed, result)) = next else {
+                break;
+            };
+            for (k, v) in changed {
+                variables.set_variable(k, v);
+            }
+
+            let step_results = result?;
+            let step = &pipeline.steps[index];
+            // 步骤在变量副本上执行，把它的变量差异记入流水线变量的 history
+            if let Some(diff) = step_results.first().and_then(|r| r.variable_diff.clone()) {
+                variables.record_history(step.name.clone(), diff);
             }
-            
-            // 发送开始执行步骤的日志
-            if let Some(callback) = &log_callback {
-                let event = OutputEvent {
-                    pipeline_name: pipeline_name.clone(),
-                    server_name: "system".to_string(),
-                    step: step.clone(), // 传递完整的Step对象
-                    script_path:step.script.clone(),
-                    output_type: crate::models::OutputType::Log,
-                    content: format!("开始执行步骤: {} ({} 个服务器)", step.name, step.servers.len()),
-                    timestamp: std::time::Instant::now(),
-                    variables: self.variable_manager.get_variables().clone(),
-                };
-                callback(event);
-            }
-
-            info!("Starting step: {} on {} servers", step.name, step.servers.len());
-            
-            // 同一步骤内的所有服务器并发执行
-            let step_results = self.execute_step_with_realtime_output(pipeline.script.clone(),&step_with_variables, pipeline_name.as_str(), output_callback.as_ref()).await?;
-            
             // 检查步骤是否成功（所有服务器都成功才算成功）
             let step_success = step_results.iter().all(|r| r.execution_result.success);
-            
-            // 添加步骤结果
             all_step_results.extend(step_results);
 
-            // 发送步骤完成事件
-            if let Some(callback) = &output_callback {
-                let status = if step_success { "成功" } else { "失败" };
-                let event = OutputEvent {
-                    pipeline_name: pipeline_name.clone(),
-                    script_path:step.script.clone(),
-                    server_name: "system".to_string(),
-                    step: step.clone(), // 传递完整的Step对象
-                    output_type: crate::models::OutputType::StepCompleted,
-                    content: format!("步骤完成: {} ({})", step.name, status),
-                    timestamp: std::time::Instant::now(),
-                    variables: self.variable_manager.get_variables().clone(),
-                };
-                callback(event);
-            }
-
-            // 如果步骤失败，可以选择是否继续执行后续步骤
-            if !step_success {
+            if step_success {
+                states[index] = StepState::Succeeded;
+                info!("Step '{}' completed successfully", step.name);
+            } else {
+                // 步骤失败后不再启动新的步骤，等待已在运行的步骤结束
+                states[index] = StepState::Failed;
+                failed = true;
                 info!("Step '{}' failed, stopping pipeline", step.name);
-                break;
             }
-            
-            info!("Step '{}' completed successfully", step.name);
         }
 
+        // 先结束被取消的步骤，再发送流水线完成事件
+        drop(running);
         let total_time = start_time.elapsed().as_millis() as u64;
-        let overall_success = all_step_results.iter().all(|r| r.execution_result.success);
+        if !timed_out && deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+            timed_out = true;
+            info!("Pipeline '{}' exceeded its time budget, aborting running steps", pipeline_name);
+            if let Some(callback) = &events {
+                callback(pipeline_log_event(&pipeline_name, PIPELINE_TIMEOUT_REASON.to_string(), variables));
+            }
+        }
+        let overall_success = !self.cancellation.is_cancelled()
+            && !timed_out
+            && all_step_results.iter().all(|r| r.execution_result.success);
+
+        // 前面的步骤失败、被取消或时间预算用完时，没有开始或被中止的步骤记为未执行
+        for (step, state) in pipeline.steps.iter().zip(&states) {
+            if matches!(state, StepState::Pending | StepState::Running) {
+                all_step_results.push(not_run_step(step, &pipeline_name, events.as_ref(), variables));
+            }
+        }
 
         // 发送流水线完成日志
-        if let Some(callback) = &log_callback {
+        if let Some(callback) = &events {
             let status = if overall_success { "成功" } else { "失败" };
-            let event = OutputEvent {
-                pipeline_name: pipeline_name.clone(),
-                script_path:"".to_string(),
-                server_name: "system".to_string(),
-                step: Step::default(), // 流水线完成事件没有具体的步骤
-                output_type: crate::models::OutputType::Log,
-                content: format!("流水线完成: {} ({}) - 总耗时: {}ms", pipeline_name, status, total_time),
-                timestamp: std::time::Instant::now(),
-                variables: self.variable_manager.get_variables().clone(),
-            };
+            let event = OutputEvent::new(
+                pipeline_name.clone(),
+                "system",
+                Step::default(),
+                crate::models::OutputType::Log,
+                format!("流水线完成: {} ({}) - 总耗时: {}ms", pipeline_name, status, total_time),
+                variables.snapshot(),
+            );
             callback(event);
         }
 
+        for step_result in &mut all_step_results {
+            step_result.run_id = run_id.to_string();
+        }
+        let outputs = self.export_outputs(&pipeline, &outputs_before, events.as_ref(), variables);
+        let group_summaries = group_summaries(&pipeline.steps, &all_step_results);
+
         Ok(PipelineExecutionResult {
             title: pipeline.title.clone().unwrap_or(pipeline_name.clone()),
             pipeline_name: pipeline_name.clone(),
+            description: pipeline.description.clone(),
+            tags: pipeline.tags.clone().unwrap_or_default(),
             step_results: all_step_results,
             overall_success,
             total_execution_time_ms: total_time,
+            run_id: run_id.to_string(),
+            final_variables: variables.get_variables().clone(),
+            outputs,
+            group_summaries,
+            failure_reason: timed_out.then(|| PIPELINE_TIMEOUT_REASON.to_string()),
         })
     }
 
+    /// 执行流水线中的单个步骤，发送步骤开始与完成事件
+    /// 期间的日志都在该步骤的 span 中，步骤结束时把耗时记入 span 的 duration_ms
+    async fn run_step(
+        &self,
+        pipeline: &Pipeline,
+        step: &Step,
+        events: Option<&OutputCallback>,
+        variables: &mut VariableManager,
+    ) -> Result<Vec<StepExecutionResult>> {
+        let index = pipeline.steps.iter().position(|s| s.name == step.name);
+        let span = tracing::info_span!("step", step = %step.name, index = ?index, duration_ms = tracing::field::Empty);
+        let start_time = std::time::Instant::now();
+        let result = self.run_step_in_span(pipeline, step, events, variables).instrument(span.clone()).await;
+        span.record("duration_ms", start_time.elapsed().as_millis() as u64);
+        result
+    }
+
+    async fn run_step_in_span(
+        &self,
+        pipeline: &Pipeline,
+        step: &Step,
+        events: Option<&OutputCallback>,
+        variables: &mut VariableManager,
+    ) -> Result<Vec<StepExecutionResult>> {
+        let pipeline_name = pipeline.name.clone();
+        variables.begin_tracking(&step.name);
+        // 合并 step 级变量到全局变量（优先级高）
+        if let Some(vars) = &step.variables {
+            for (k, v) in vars {
+                variables.set_variable(k.clone(), v.clone());
+            }
+        }
+        // quote_variables 覆盖执行器的自动转义设置，只对本步骤生效（variables 是本步骤的副本）
+        if let Some(quote) = step.quote_variables {
+            variables.set_script_escape(quote.then_some(EscapeMode::Shell));
+        }
+        // 对脚本路径应用变量替换，脚本内容在执行时由 render_phase_script 替换一次
+        let mut step_with_variables = step.clone();
+        step_with_variables.script = variables.replace_variables(&step.script);
+        step_with_variables.before_script = step.before_script.as_ref().map(|p| variables.replace_variables(p));
+        step_with_variables.after_script = step.after_script.as_ref().map(|p| variables.replace_variables(p));
+        step_with_variables.local_scripts = step.local_scripts.iter().map(|p| variables.replace_variables(p)).collect();
+        
+        // 发送步骤开始事件
+        if let Some(callback) = events {
+            let event = OutputEvent::new(
+                pipeline_name.clone(),
+                "system",
+                step.clone(),
+                crate::models::OutputType::StepStarted,
+                format!("开始执行步骤: {} ({} 个服务器)", step.name, step.servers.len()),
+                variables.snapshot(),
+            );
+            callback(event);
+        }
+        
+        // 发送开始执行步骤的日志
+        if let Some(callback) = events {
+            let event = OutputEvent::new(
+                pipeline_name.clone(),
+                "system",
+                step.clone(),
+                crate::models::OutputType::Log,
+                format!("开始执行步骤: {} ({} 个服务器)", step.name, step.servers.len()),
+                variables.snapshot(),
+            );
+            callback(event);
+        }
+
+        info!("Starting step: {} on {} servers", step.name, step.servers.len());
+        
+        // 同一步骤内的所有服务器并发执行
+        let before = variables.get_variables().clone();
+        let mut step_results = self.execute_step_with_realtime_output(pipeline.script.clone(),&step_with_variables, pipeline_name.as_str(), events, variables).await?;
+
+        // 汇总所有服务器的输出，供后续步骤使用（条件为假跳过的步骤不汇总）
+        if let Some(aggregate) = &step.aggregate_output {
+            if !step_results.iter().any(|r| r.skipped) {
+                variables.set_variable(aggregate.variable_name.clone(), aggregate_output(&aggregate.strategy, &step_results));
+            }
+        }
+
+        // 记录本步骤新设置或修改的变量（不含 step 级 variables 和执行耗时）
+        let extracted: HashMap<String, String> = variables.get_variables().iter()
+            .filter(|(k, v)| before.get(*k) != Some(*v) && *k != STEP_ELAPSED_VARIABLE)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        // 执行耗时每个步骤都会变化，不计入差异
+        let mut diff = variables.end_tracking();
+        diff.added.remove(STEP_ELAPSED_VARIABLE);
+        diff.modified.remove(STEP_ELAPSED_VARIABLE);
+        for step_result in &mut step_results {
+            step_result.extracted_variables.clone_from(&extracted);
+            step_result.variable_diff = Some(diff.clone());
+        }
+        
+        // 检查步骤是否成功（所有服务器都成功才算成功）
+        let step_success = step_results.iter().all(|r| r.execution_result.success);
+        let step_class = step_exit_class(&step_results);
+
+        // 发送步骤完成事件
+        if let Some(callback) = events {
+            let status = if step_success { "成功" } else { "失败" };
+            let event = OutputEvent::new(
+                pipeline_name.clone(),
+                "system",
+                step.clone(),
+                crate::models::OutputType::StepCompleted,
+                format!("步骤完成: {} ({}, {})", step.name, status, step_class.as_str()),
+                variables.snapshot(),
+            );
+            callback(event);
+        }
+
+        Ok(step_results)
+    }
+
     /// 执行所有流水线（支持实时输出）
     pub async fn execute_all_pipelines_with_realtime_output(
-        &mut self, // 需要可变引用
+        &self,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>
+    ) -> Result<ShellExecutionResult> {
+        self.execute_all_pipelines_with_run_id(&new_run_id(), output_callback, log_callback).await
+    }
+
+    /// 按配置顺序执行所有流水线，cancel 被取消时停止执行并返回已完成步骤的结果
+    /// 取消后不再开始新的步骤和流水线（包括 after_all），正在执行的步骤被中止，
+    /// 结果的 outcome 为 Interrupted、success 为 false
+    pub async fn execute_all_pipelines_with_cancellation(
+        &self,
+        cancel: &CancellationHandle,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>
+    ) -> Result<ShellExecutionResult> {
+        let mut executor = self.clone();
+        executor.cancellation = cancel.clone();
+        executor.execute_all_pipelines_with_realtime_output(output_callback, log_callback).await
+    }
+
+    /// 使用调用方指定的 run_id 执行所有流水线，所有流水线的事件与结果共用该 run_id
+    pub async fn execute_all_pipelines_with_run_id(
+        &self,
+        run_id: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>
+    ) -> Result<ShellExecutionResult> {
+        let pipeline_names = self.config.pipelines.iter().map(|p| p.name.clone()).collect();
+        self.run_pipelines_with_callbacks(pipeline_names, run_id, output_callback, log_callback).await
+    }
+
+    /// 按配置顺序执行所有流水线，事件发送到容量为 buffer 的通道
+    /// 背压与通道关闭的行为与 execute_pipeline_with_progress_channel 相同
+    pub fn execute_all_pipelines_with_progress_channel(
+        &self,
+        buffer: usize,
+    ) -> (impl std::future::Future<Output = Result<ShellExecutionResult>> + Send + 'static, mpsc::Receiver<OutputEvent>) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        let executor = self.clone();
+        let run_id = new_run_id();
+        let span = run_span(&run_id);
+        let future = async move {
+            let (sink, forwarder) = channel_sink(sender);
+            let events = executor.dispatcher(&run_id, vec![(EventFilter::all(), sink)]);
+            let pipeline_names = executor.config.pipelines.iter().map(|p| p.name.clone()).collect();
+            let run = executor.run_pipelines(pipeline_names, &run_id, events);
+            let result = with_global_timeout(executor.options.global_timeout, run).await;
+            if let Some(forwarder) = forwarder {
+                let _ = forwarder.await;
+            }
+            result
+        }.instrument(span);
+        (future, receiver)
+    }
+
+    /// 按配置顺序执行带有指定标签的流水线（支持实时输出）
+    pub async fn execute_pipelines_by_tag(
+        &self,
+        tag: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>
+    ) -> Result<ShellExecutionResult> {
+        let pipeline_names: Vec<String> = self.get_pipelines_by_tag(tag).iter().map(|p| p.name.clone()).collect();
+        if pipeline_names.is_empty() {
+            return Err(anyhow::anyhow!("No pipelines tagged '{}'", tag));
+        }
+        self.run_pipelines_with_callbacks(pipeline_names, &new_run_id(), output_callback, log_callback).await
+    }
+
+    /// 按给出的顺序执行指定的流水线（支持实时输出），与执行所有流水线一样执行 before_all / after_all
+    /// 执行前检查所有名称，有不存在的流水线或名称列表为空时不执行任何流水线，错误中列出可用的流水线
+    pub async fn execute_pipelines(
+        &self,
+        names: &[String],
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>
+    ) -> Result<ShellExecutionResult> {
+        let available = || self.config.pipelines.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+        if names.is_empty() {
+            return Err(anyhow::anyhow!("No pipelines given; available pipelines: {}", available()));
+        }
+        let unknown: Vec<&str> = names.iter()
+            .filter(|name| !self.config.pipelines.iter().any(|p| &p.name == *name))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(anyhow::anyhow!("Unknown pipeline(s) {}; available pipelines: {}", unknown.join(", "), available()));
+        }
+        self.run_pipelines_with_callbacks(names.to_vec(), &new_run_id(), output_callback, log_callback).await
+    }
+
+    /// 以旧的双回调参数依次执行多个流水线，受 global_timeout 限制
+    async fn run_pipelines_with_callbacks(
+        &self,
+        pipeline_names: Vec<String>,
+        run_id: &str,
         output_callback: Option<OutputCallback>,
         log_callback: Option<OutputCallback>
     ) -> Result<ShellExecutionResult> {
+        let events = self.dispatcher(run_id, legacy_subscriptions(output_callback, log_callback));
+        let run = self.run_pipelines(pipeline_names, run_id, events);
+        with_global_timeout(self.options.global_timeout, run).instrument(run_span(run_id)).await
+    }
+
+    /// 依次执行多个流水线，所有事件发送到同一个分发回调
+    /// 配置了 total_timeout_seconds 时，所有流水线（含 before_all / after_all）共用从此刻开始计算的时间预算
+    async fn run_pipelines(&self, pipeline_names: Vec<String>, run_id: &str, events: Option<OutputCallback>) -> Result<ShellExecutionResult> {
+        let mut executor = self.clone();
+        executor.run_deadline = self.config.total_timeout_seconds
+            .map(|seconds| std::time::Instant::now() + std::time::Duration::from_secs(seconds));
+        executor.run_pipelines_within_budget(pipeline_names, run_id, events).await
+    }
+
+    /// 所有流水线共用同一个变量上下文，前面流水线提取的变量对后面的流水线可见
+    async fn run_pipelines_within_budget(&self, pipeline_names: Vec<String>, run_id: &str, events: Option<OutputCallback>) -> Result<ShellExecutionResult> {
         let mut results = Vec::new();
+        let mut variables = self.variable_manager.clone();
         
         // 发送开始执行所有流水线的日志
-        if let Some(callback) = &log_callback {
-            let event = OutputEvent {
-                pipeline_name: "system".to_string(),
-                server_name: "system".to_string(),
-                script_path :"".to_string(),
-                step: Step::default(), // 系统级别事件没有具体步骤
-                output_type: crate::models::OutputType::Log,
-                content: format!("=== 远程脚本执行器 ==="),
-                timestamp: std::time::Instant::now(),
-                variables: self.variable_manager.get_variables().clone(),
-            };
+        if let Some(callback) = &events {
+            let event = OutputEvent::new(
+                "system",
+                "system",
+                Step::default(),
+                crate::models::OutputType::Log,
+                "=== 远程脚本执行器 ===",
+                variables.snapshot(),
+            );
             callback(event);
             
-            let event = OutputEvent {
-                pipeline_name: "system".to_string(),
-                server_name: "system".to_string(),
-                script_path :"".to_string(),
-                step: Step::default(), // 系统级别事件没有具体步骤
-                output_type: crate::models::OutputType::Log,
-                content: format!("配置加载成功，发现 {} 个流水线", self.config.pipelines.len()),
-                timestamp: std::time::Instant::now(),
-                variables: self.variable_manager.get_variables().clone(),
-            };
+            let event = OutputEvent::new(
+                "system",
+                "system",
+                Step::default(),
+                crate::models::OutputType::Log,
+                format!("配置加载成功，将执行 {} 个流水线", pipeline_names.len()),
+                variables.snapshot(),
+            );
             callback(event);
             
-            let event = OutputEvent {
-                pipeline_name: "system".to_string(),
-                script_path :"".to_string(),
-                server_name: "system".to_string(),
-                step: Step::default(), // 系统级别事件没有具体步骤
-                output_type: crate::models::OutputType::Log,
-                content: format!("执行模式: 步骤串行执行，同一步骤内服务器并发执行"),
-                timestamp: std::time::Instant::now(),
-                variables: self.variable_manager.get_variables().clone(),
-            };
+            let event = OutputEvent::new(
+                "system",
+                "system",
+                Step::default(),
+                crate::models::OutputType::Log,
+                "执行模式: 步骤串行执行，同一步骤内服务器并发执行",
+                variables.snapshot(),
+            );
             callback(event);
         }
         
-        // 按顺序执行每个流水线（串行）
-        let pipeline_names: Vec<String> = self.config.pipelines.iter().map(|p| p.name.clone()).collect();
-        for pipeline_name in pipeline_names {
-            // 发送开始执行流水线的日志
-            if let Some(callback) = &log_callback {
-                let event = OutputEvent {
-                    pipeline_name: pipeline_name.clone(),
-                    server_name: "system".to_string(),
-                    script_path :"".to_string(),
-                    step: Step::default(), // 流水线开始事件没有具体的步骤
-                    output_type: crate::models::OutputType::Log,
-                    content: format!("开始执行流水线: {}", pipeline_name),
-                    timestamp: std::time::Instant::now(),
-                    variables: self.variable_manager.get_variables().clone(),
-                };
-                callback(event);
-            }
-            info!("Starting pipeline: {}", pipeline_name);
-
-            let result = self.execute_pipeline_with_realtime_output(&pipeline_name, output_callback.as_ref().cloned(), log_callback.as_ref().cloned()).await?;
-            let success = result.overall_success;
-            results.push(result);
-            if !success {
-                info!("Pipeline '{}' failed, stopping execution", pipeline_name);
-                break;
+        let outcome: Result<()> = async {
+            // before_all 失败时不再执行流水线
+            if let Some(before_all) = self.config.before_all_pipeline() {
+                let result = self.run_pipeline_steps(before_all, run_id, events.clone(), &mut variables, None).await?;
+                let success = result.overall_success;
+                results.push(result);
+                if !success {
+                    info!("before_all failed, skipping pipelines");
+                    return Ok(());
+                }
+            }
+
+            // 按顺序执行每个流水线（串行）
+            for pipeline_name in pipeline_names {
+                if self.cancellation.is_cancelled() {
+                    break;
+                }
+                // 流水线开始/完成事件由 run_pipeline 统一发送
+                let result = self.run_pipeline(&pipeline_name, run_id, events.clone(), &mut variables, None).await?;
+                let success = result.overall_success;
+                results.push(result);
+                if !success {
+                    info!("Pipeline '{}' failed, stopping execution", pipeline_name);
+                    break;
+                }
+                info!("Pipeline '{}' completed successfully", pipeline_name);
+            }
+            Ok(())
+        }.await;
+
+        // after_all 总是执行，前面的流水线失败或出错时也不例外；出错时返回前面的错误
+        // 执行被取消时不再执行
+        let after_all = match self.config.after_all_pipeline() {
+            Some(after_all) if !self.cancellation.is_cancelled() => {
+                Some(self.run_pipeline_steps(after_all, run_id, events.clone(), &mut variables, None).await)
             }
-            info!("Pipeline '{}' completed successfully", pipeline_name);
+            _ => None,
+        };
+        outcome?;
+        if let Some(result) = after_all {
+            results.push(result?);
         }
-        
+
+        if self.cancellation.is_cancelled() {
+            info!("Execution interrupted, returning partial results");
+            return Ok(ShellExecutionResult {
+                success: false,
+                reason: "interrupted".to_string(),
+                pipeline_results: results,
+                run_id: run_id.to_string(),
+                outcome: ExecutionOutcome::Interrupted,
+            });
+        }
+
         Ok(ShellExecutionResult{
             success: true,
             reason: "ok".to_string(),
             pipeline_results: results,
+            run_id: run_id.to_string(),
+            outcome: ExecutionOutcome::Completed,
         })
     }
 
     /// 执行指定的流水线（原有方法，保持兼容性）
-    pub async fn execute_pipeline(&mut self, pipeline_name: &str) -> Result<PipelineExecutionResult> {
+    pub async fn execute_pipeline(&self, pipeline_name: &str) -> Result<PipelineExecutionResult> {
         self.execute_pipeline_with_realtime_output(pipeline_name, None, None).await
     }
 
     /// 执行单个步骤（支持实时输出）
     async fn execute_step_with_realtime_output(
-        &mut self,
+        &self,
         script: Option<String>,
         step: &Step,
         pipeline_name: &str,
-        output_callback: Option<&OutputCallback>
+        output_callback: Option<&OutputCallback>,
+        variable_manager: &mut VariableManager,
     ) -> Result<Vec<StepExecutionResult>> {
         let start_time = std::time::Instant::now();
-        // Clone config at the start to avoid &self borrow conflicts
-        let config = self.config.clone();
-        let variable_manager = &mut self.variable_manager;
+        let mut step = self.with_config_defaults(step);
+        // 断言的期望值可以引用变量，执行前按当前变量替换
+        if let Some(assert) = step.assert.as_mut() {
+            assertions::render_assert(assert, variable_manager);
+        }
+        let step = &step;
+        // 条件为假时跳过步骤，记为成功的 skipped 结果
+        if let Some(condition) = &step.condition {
+            if !variable_manager.evaluate_condition(condition)? {
+                info!("Step '{}' skipped: condition '{}' is false", step.name, condition);
+                let reason = format!("Step skipped: condition false ({})", condition);
+                return Ok(skip_step(step, pipeline_name, &reason, output_callback, variable_manager, start_time));
+            }
+        }
+
+        // 解析服务器列表中的变量（支持 {{ hosts }} 展开为逗号分隔的多个服务器）
+        let server_names = self.resolve_step_servers(step, variable_manager)?;
+        // 标签选择没有匹配的服务器且允许为空时跳过步骤
+        if server_names.is_empty() && step.server_selector.is_some() {
+            info!("Step '{}' skipped: server_selector matched no clients", step.name);
+            let reason = "Step skipped: server_selector matched no clients";
+            return Ok(skip_step(step, pipeline_name, reason, output_callback, variable_manager, start_time));
+        }
+        let config = &self.config;
+        let exit_code_meanings = self.exit_code_meanings(step);
+        let line_filter = self.line_filter(step);
         
         // 检查是否有服务器配置
-        if step.servers.is_empty() {
-            // 本地执行
+        if step.servers.is_empty() && step.server_selector.is_none() {
+            // 本地执行：script 与 local_scripts 以有限并发执行，before_script/after_script 只包裹 script
             info!("Executing step: {} locally (no servers specified)", step.name);
-            let output_callback = output_callback.cloned();
-            let step_clone = step.clone();
-            let pipeline_name = pipeline_name.to_string();
-            let step_name = step.name.clone();
-            let mut variables = variable_manager.get_variables().clone();
-            variables.insert("pipeline_name".to_string(), pipeline_name.clone());
-            variables.insert("step_name".to_string(), step_name.clone());
-            let execution_result = LocalExecutor::execute_script_with_realtime_output(
-                script.clone(),
-                self.config.global_scripts.clone(),
-                &step_clone,
-                &pipeline_name,
-                &step_name,
-                output_callback,
-                variables,
-            ).await?;
-            let success = execution_result.success;
-            // 提取变量（如果有extract规则）
-            if let Some(extract_rules) = step.extract.clone() {
-                if let Err(e) = variable_manager.extract_variables(&extract_rules, &execution_result) {
-                    info!("Failed to extract variables from step '{}': {}", step.name, e);
+            let mut variables = variable_manager.clone();
+            variables.set_variable("pipeline_name".to_string(), pipeline_name.to_string());
+            variables.set_variable("step_name".to_string(), step.name.clone());
+            let local_steps: Vec<Step> = std::iter::once(step.clone())
+                .chain(step.local_scripts.iter().map(|path| Step {
+                    script: path.clone(),
+                    before_script: None,
+                    after_script: None,
+                    artifacts: None,
+                    ..step.clone()
+                }))
+                .collect();
+            let parallelism = step.local_parallelism.unwrap_or(DEFAULT_LOCAL_PARALLELISM).max(1);
+
+            // 按下标构造 future，避免闭包参数带生命周期导致 spawn 时无法满足 Send 约束
+            let runs = (0..local_steps.len()).map(|index| {
+                let local_step = &local_steps[index];
+                let (script, variables, exit_code_meanings) = (&script, &variables, &exit_code_meanings);
+                let global_scripts = config.global_scripts.as_deref().unwrap_or_default();
+                let line_filter = &line_filter;
+                async move {
+                    let result = run_classified(local_step, exit_code_meanings, || {
+                        LocalExecutor::execute_script_with_realtime_output(
+                            script.clone(),
+                            global_scripts.to_vec(),
+                            local_step,
+                            pipeline_name,
+                            &local_step.name,
+                            output_callback.cloned(),
+                            variables.clone(),
+                            line_filter.clone(),
+                        )
+                    }).await;
+                    (local_step, result, start_time.elapsed().as_millis() as u64)
+                }.instrument(server_span("localhost", "localhost"))
+            });
+            // 幂等守卫命中时不执行 script 与 local_scripts
+            let results: Vec<_> = match self.check_guards(step, None, pipeline_name, &variables, output_callback).await {
+                Some(result) => vec![(&local_steps[0], Ok((result, ExitClass::Skipped, 0)), start_time.elapsed().as_millis() as u64)],
+                None => futures::stream::iter(runs).buffered(parallelism).collect().await,
+            };
+
+            let mut step_results = Vec::new();
+            // 每个脚本各自从步骤开始时的变量提取，结果按脚本顺序合并后统一写回
+            let mut extracted_vars: HashMap<String, String> = HashMap::new();
+            for (local_step, result, execution_time_ms) in results {
+                let (mut execution_result, exit_class, attempts) = result?;
+                self.audit_rendered_script(&mut execution_result);
+                let success = execution_result.success;
+                // 提取变量（如果有extract规则），跳过的步骤没有输出可提取
+                if let Some(extract_rules) = step.extract.clone().filter(|_| execution_result.skip_reason.is_none()) {
+                    let mut script_variables = variable_manager.clone();
+                    script_variables.set_variable(STEP_ELAPSED_VARIABLE.to_string(), execution_result.execution_time_ms.to_string());
+                    if let Err(e) = script_variables.extract_variables(&extract_rules, &execution_result) {
+                        info!("Failed to extract variables from step '{}': {}", step.name, e);
+                    }
+                    extracted_vars.extend(script_variables.get_variables().iter()
+                        .filter(|(k, v)| variable_manager.get_variable(k) != Some(*v))
+                        .map(|(k, v)| (k.clone(), v.clone())));
                 }
+                let (stdout_line_count, stderr_line_count, output_rate_lps) = output_stats(&execution_result);
+                step_results.push(StepExecutionResult {
+                    title: step.title.clone().unwrap_or(step.name.clone()),
+                    step_name: step.name.clone(),
+                    scritp_path: local_step.script.clone(),
+                    server_name: "localhost".to_string(),
+                    exit_code: execution_result.exit_code,
+                    artifacts: execution_result.artifacts.clone(),
+                    execution_result,
+                    overall_success: success,
+                    status: StepStatus::of(success, exit_class),
+                    execution_time_ms,
+                    exit_class,
+                    attempts,
+                    skipped: false,
+                    run_id: String::new(),
+                    extracted_variables: HashMap::new(),
+                    variable_diff: None,
+                    executed_locally: false,
+                    stdout_line_count,
+                    stderr_line_count,
+                    output_rate_lps,
+                    ssh_trace: None,
+                    failover_used: false,
+                    primary_server: "localhost".to_string(),
+                });
             }
-            let step_result = StepExecutionResult {
-                title: step.title.clone().unwrap_or(step.name.clone()),
-                step_name: step.name.clone(),
-                scritp_path:step.script.clone(),
-                server_name: "localhost".to_string(),
-                execution_result,
-                overall_success: success,
-                execution_time_ms: start_time.elapsed().as_millis() as u64,
-            };
-            return Ok(vec![step_result]);
+            for (k, v) in extracted_vars {
+                variable_manager.set_variable(k, v);
+            }
+            return Ok(step_results);
         }
         
         // 远程执行（原有逻辑）
-        info!("Executing step: {} on {} servers", step.name, step.servers.len());
+        info!("Executing step: {} on {} servers", step.name, server_names.len());
 
         let mut step_results = Vec::new();
-        let mut futures = Vec::new();
+        let fail_fast = step.fail_fast.unwrap_or(true);
         // 用于收集所有服务器提取到的变量 (变量名, 变量值)
         let mut extracted_vars: Vec<(String, String)> = Vec::new();
         let clone_variable_manager = variable_manager.clone();
 
-        // 为每个服务器创建执行任务
-        let server_names: Vec<String> = step.servers.clone();
-        let  global_script= Arc::new(self.config.global_scripts.clone());
+        // 为服务器创建执行任务
+        let  global_script= Arc::new(self.config.global_scripts.clone().unwrap_or_default());
         let clone_global_script = global_script.clone();
-        for server_name in server_names {
-            if !config.clients.contains_key(&server_name) {
-                return Err(anyhow::anyhow!("Server '{}' not found in configuration", server_name));
-            }
-
+        if let Some(server_name) = server_names.iter().find(|name| !config.clients.contains_key(*name)) {
+            return Err(anyhow::anyhow!("Server '{}' not found in configuration", server_name));
+        }
+        let failover_servers = self.resolve_failover_servers(step, &server_names, variable_manager)?;
+        let spawn_task = |server_name: String| {
             // 克隆必要的数据以避免生命周期问题
-            let config = config.clone();
             let step_name = step.name.clone();
             let output_callback = output_callback.cloned();
-            let clone_step = step.clone();
             let pipeline_name = pipeline_name.to_string();
+            let clone_step = step.clone();
             let mut clone_variable_manager = clone_variable_manager.clone();
             clone_variable_manager.set_variable("pipeline_name".to_string(), pipeline_name.clone());
             clone_variable_manager.set_variable("step_name".to_string(), step_name.clone());
+            // 备用服务器使用自己的客户端变量
+            let mut failover = failover_servers.get(&server_name).map(|failover_name| {
+                let mut variables = clone_variable_manager.clone();
+                for (k, v) in config.clients[failover_name].variables.iter().flatten() {
+                    variables.set_variable(k.clone(), v.clone());
+                }
+                (failover_name.clone(), variables)
+            });
+            for (k, v) in config.clients[&server_name].variables.iter().flatten() {
+                clone_variable_manager.set_variable(k.clone(), v.clone());
+            }
             let script = script.clone();
 
             let clone_global_script = clone_global_script.clone();
+            let exit_code_meanings = exit_code_meanings.clone();
+            // 任务内的执行器副本使用本次执行的变量上下文
+            let mut executor = self.clone();
+            executor.variable_manager = clone_variable_manager;
+            // run_once 步骤和有备用服务器的步骤需要区分连接失败；没有备用服务器时连接失败照常记为失败结果
+            let run_once = clone_step.run_once.unwrap_or(false);
+            executor.return_connect_errors = run_once || failover.is_some();
+            let client = &config.clients[&server_name];
+            let span = match (&client.execution_method, &client.ssh_config) {
+                (ExecutionMethod::SSH, Some(ssh_config)) => server_span(&server_name, &ssh_config.host),
+                _ => server_span(&server_name, "localhost"),
+            };
 
-            let future = tokio::spawn(async move {
-                // 创建新的执行器实例
-                let executor = RemoteExecutor { 
-                    config,
-                    variable_manager:clone_variable_manager,
-                };
-
-                match executor.execute_script_with_realtime_output(script,clone_global_script,&server_name, clone_step, &pipeline_name, output_callback).await {
-                    Ok(result) => {
-                        info!("Step '{}' on server '{}' completed with exit code: {}", 
-                              step_name, server_name, result.exit_code);
-                        Ok((server_name, result))
+            AbortOnDrop(tokio::spawn(async move {
+                // 主服务器连接失败时换到备用服务器，返回实际执行的备用服务器
+                let mut target = server_name.clone();
+                let mut failover_server = None;
+                loop {
+                    if let Some(result) = executor.check_guards(&clone_step, Some(&target), &pipeline_name, &executor.variable_manager, output_callback.as_ref()).await {
+                        return Ok((result, ExitClass::Skipped, 0, failover_server));
                     }
-                    Err(e) => {
-                        error!("Step '{}' on server '{}' failed: {}", step_name, server_name, e);
-                        Err(e)
+
+                    let run = || executor.execute_script_with_realtime_output(
+                        script.clone(),
+                        clone_global_script.clone(),
+                        &target,
+                        clone_step.clone(),
+                        &pipeline_name,
+                        output_callback.clone(),
+                    );
+                    match run_classified(&clone_step, &exit_code_meanings, run).await {
+                        Ok((result, exit_class, attempts)) => {
+                            info!("Step '{}' on server '{}' completed with exit code: {} ({})", 
+                                  step_name, target, result.exit_code, exit_class.as_str());
+                            return Ok((result, exit_class, attempts, failover_server));
+                        }
+                        Err(e) => match failover.take().filter(|_| is_connect_error(&e)) {
+                            Some((failover_name, variables)) => {
+                                info!("SSH connection to server '{}' failed, running step '{}' on failover server '{}': {:#}", target, step_name, failover_name, e);
+                                if let Some(callback) = &output_callback {
+                                    callback(OutputEvent::new(
+                                        pipeline_name.clone(),
+                                        target.clone(),
+                                        clone_step.clone(),
+                                        crate::models::OutputType::Log,
+                                        format!("SSH connection to server {} failed, failing over to {}", target, failover_name),
+                                        executor.variable_manager.snapshot(),
+                                    ));
+                                }
+                                executor.variable_manager = variables;
+                                executor.return_connect_errors = run_once;
+                                failover_server = Some(failover_name.clone());
+                                target = failover_name;
+                            }
+                            None => {
+                                error!("Step '{}' on server '{}' failed: {}", step_name, target, e);
+                                return Err(e);
+                            }
+                        },
                     }
                 }
+            }.instrument(span)))
+        };
 
+        let (task_servers, results) = if step.run_once.unwrap_or(false) {
+            // 只在一台服务器上执行：依次尝试，第一台执行成功（脚本已运行，不论退出码）即停止，
+            // 只有连接等错误才换下一台，避免重复的副作用
+            let mut attempt = None;
+            for server_name in server_names {
+                let result = spawn_task(server_name.clone()).await;
+                let ran = matches!(result, Ok(Ok(_)));
+                if !ran {
+                    info!("Step '{}' could not run on server '{}', trying the next one", step.name, server_name);
+                }
+                attempt = Some((server_name, result));
+                if ran {
+                    break;
+                }
+            }
+            // 所有服务器都无法执行时记为一个失败结果，带上最后一台服务器的错误
+            let attempt = attempt.map(|(server_name, result)| match result {
+                Ok(Err(e)) => (server_name, Ok(Ok((errored_result(&step.script, &anyhow::anyhow!("{:#}", e)), ExitClass::Failure, 1, None)))),
+                result => (server_name, result),
             });
-
-            futures.push(future);
-        }
-
-        // 等待所有执行完成
-        let results = join_all(futures).await;
+            attempt.map(|(server, result)| (vec![server], vec![result])).unwrap_or_default()
+        } else {
+            // 设置 max_parallel 时最多同时执行这么多台服务器，其余服务器在有空位时才开始
+            let parallelism = step.max_parallel.unwrap_or(server_names.len()).max(1);
+            let tasks = futures::stream::iter(server_names.iter().cloned().map(&spawn_task));
+            // 等待所有执行完成，结果保持服务器顺序
+            let results: Vec<_> = tasks.buffered(parallelism).collect().await;
+            (server_names, results)
+        };
         
-        for result in results {
+        for (server_name, result) in task_servers.into_iter().zip(results) {
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => Err(anyhow::anyhow!("Task execution failed: {}", e)),
+            };
+            // fail_fast 关闭时把出错的服务器记为失败结果，继续收集其他服务器的结果
+            let result = match result {
+                Err(e) if !fail_fast => Ok((errored_result(&step.script, &e), ExitClass::Failure, 1, None)),
+                result => result,
+            };
             match result {
-                Ok(Ok((server_name, execution_result))) => {
+                Ok((execution_result, exit_class, attempts, failover_server)) => {
                     let success = execution_result.success;
-                    // 提取变量（如果有extract规则）
-                    if let Some(extract_rules) = step.extract.clone() {
-                        // 提取变量到本地 map
-                        let mut temp_vm = VariableManager::new(None);
+                    // 提取变量（如果有extract规则），跳过的步骤没有输出可提取
+                    if let Some(extract_rules) = step.extract.clone().filter(|_| execution_result.skip_reason.is_none()) {
+                        // 提取变量到临时副本（正则中可引用当前变量），只收集规则提取到的变量
+                        let mut temp_vm = variable_manager.clone();
+                        for rule in &extract_rules {
+                            temp_vm.remove_variable(&rule.name);
+                        }
+                        // 执行耗时可在提取规则和后续步骤中引用
+                        let elapsed = execution_result.execution_time_ms.to_string();
+                        temp_vm.set_variable(STEP_ELAPSED_VARIABLE.to_string(), elapsed.clone());
+                        extracted_vars.push((STEP_ELAPSED_VARIABLE.to_string(), elapsed));
                         if let Err(e) = temp_vm.extract_variables(&extract_rules, &execution_result) {
                             info!("Failed to extract variables from step '{}': {}", step.name, e);
                         } else {
-                            for (k, v) in temp_vm.get_variables() {
-                                extracted_vars.push((k.clone(), v.clone()));
+                            for rule in &extract_rules {
+                                if let Some(v) = temp_vm.get_variable(&rule.name) {
+                                    extracted_vars.push((rule.name.clone(), v.clone()));
+                                }
                             }
                         }
                     }
+                    let (stdout_line_count, stderr_line_count, output_rate_lps) = output_stats(&execution_result);
                     
                     step_results.push(StepExecutionResult {
                         title: step.title.clone().unwrap_or(step.name.clone()),
                         step_name: step.name.clone(),
-                        server_name,
+                        server_name: failover_server.clone().unwrap_or_else(|| server_name.clone()),
+                        failover_used: failover_server.is_some(),
+                        primary_server: server_name,
                         scritp_path:step.script.clone(),
+                        exit_code: execution_result.exit_code,
+                        artifacts: execution_result.artifacts.clone(),
+                        executed_locally: execution_result.fallback_reason.is_some(),
+                        ssh_trace: execution_result.ssh_trace,
                         execution_result,
                         overall_success: success,
+                        status: StepStatus::of(success, exit_class),
                         execution_time_ms: start_time.elapsed().as_millis() as u64,
+                        exit_class,
+                        attempts,
+                        skipped: false,
+                        run_id: String::new(),
+                        extracted_variables: HashMap::new(),
+                        variable_diff: None,
+                        stdout_line_count,
+                        stderr_line_count,
+                        output_rate_lps,
                     });
                 }
-                Ok(Err(e)) => {
-                    return Err(e);
-                }
                 Err(e) => {
-                    return Err(anyhow::anyhow!("Task execution failed: {}", e));
+                    return Err(e);
                 }
             }
         }
@@ -441,38 +1269,222 @@ impl RemoteExecutor {
         Ok(step_results)
     }
 
-    /// 在指定客户端执行shell脚本（支持实时输出）
-    pub async fn execute_script_with_realtime_output(
-        &self, 
-        script: Option<String>,
-        global_scripts:Arc<Vec<String>>,
-        client_name: &str, 
-        step: Step,
-        pipeline_name: &str,
-        output_callback: Option<OutputCallback>
-    ) -> Result<ExecutionResult> {
-        // 检查脚本文件是否存在
-        let script_path = Path::new(step.script.as_str());
-        if !script_path.exists() {
-            return Err(anyhow::anyhow!("Script '{}' not found", step.script));
+    /// 合并全局与步骤级的退出码分类，步骤级优先
+    fn exit_code_meanings(&self, step: &Step) -> HashMap<i32, ExitClass> {
+        let mut meanings = self.config.exit_code_meanings.clone().unwrap_or_default();
+        if let Some(step_meanings) = &step.exit_code_meanings {
+            meanings.extend(step_meanings.iter().map(|(code, class)| (*code, *class)));
         }
+        meanings
+    }
 
-        let client_config = self.config
+    /// 流水线的截止时间：流水线 timeout_seconds 与本次执行的 total_timeout_seconds 中较早的一个
+    /// 单独执行一个流水线时，total_timeout_seconds 从该流水线开始计算
+    fn pipeline_deadline(&self, pipeline: &Pipeline, start: std::time::Instant) -> Option<std::time::Instant> {
+        let budget = |seconds: u64| start + std::time::Duration::from_secs(seconds);
+        let run_deadline = self.run_deadline.or_else(|| self.config.total_timeout_seconds.map(budget));
+        pipeline.timeout_seconds.map(budget).into_iter().chain(run_deadline).min()
+    }
+
+    /// 步骤的超时不超过流水线剩余的时间预算；未设置超时的步骤使用执行方式的默认超时，到截止时间时被中止
+    fn with_remaining_budget(&self, step: &Step, deadline: Option<std::time::Instant>) -> Step {
+        let (Some(deadline), Some(timeout)) = (deadline, step.timeout_seconds.or(self.config.default_timeout)) else {
+            return step.clone();
+        };
+        Step {
+            timeout_seconds: Some(timeout.min(remaining_seconds(deadline))),
+            ..step.clone()
+        }
+    }
+
+    /// 步骤未设置时使用全局配置：timeout_seconds 使用 default_timeout，
+    /// include_rendered_script_in_events 使用同名的全局设置
+    fn with_config_defaults(&self, step: &Step) -> Step {
+        Step {
+            timeout_seconds: step.timeout_seconds.or(self.config.default_timeout),
+            include_rendered_script_in_events: step.include_rendered_script_in_events
+                .or(self.config.include_rendered_script_in_events),
+            ..step.clone()
+        }
+    }
+
+    /// 为执行结果中的渲染后脚本计算SHA-256，再按 secret_patterns 脱敏并截断到 rendered_script_max_bytes
+    fn audit_rendered_script(&self, result: &mut ExecutionResult) {
+        let Some(script) = result.rendered_script.take() else {
+            return;
+        };
+        result.script_sha256 = Some(format!("{:x}", Sha256::digest(script.as_bytes())));
+
+        let mut script = script;
+        for pattern in &self.secret_patterns {
+            script = pattern.replace_all(&script, "******").into_owned();
+        }
+        let limit = self.config.rendered_script_max_bytes.unwrap_or(DEFAULT_RENDERED_SCRIPT_MAX_BYTES);
+        if script.len() > limit {
+            let mut end = limit;
+            while !script.is_char_boundary(end) {
+                end -= 1;
+            }
+            let truncated = script.len() - end;
+            script.truncate(end);
+            script.push_str(&format!("\n... [truncated {} bytes]", truncated));
+        }
+        result.rendered_script = Some(script);
+    }
+
+    /// 步骤使用的输出行过滤器：开启 strip_ansi 时先去除ANSI序列，再交给自定义过滤器
+    fn line_filter(&self, step: &Step) -> Option<LineFilter> {
+        let strip = step.strip_ansi.or(self.config.strip_ansi).unwrap_or(false);
+        if !strip {
+            return self.line_filter.clone();
+        }
+        let custom = self.line_filter.clone();
+        Some(Arc::new(move |line: &str| {
+            let line = strip_ansi(line);
+            match &custom {
+                Some(filter) => filter(&line),
+                None => Some(line.into_owned()),
+            }
+        }))
+    }
+
+    /// 解析步骤的备用服务器：servers_failover 的每一项变量替换后对应解析后服务器列表中同一下标的服务器
+    /// 空项或超出服务器列表的项表示没有备用服务器，返回 主服务器 -> 备用服务器
+    fn resolve_failover_servers(&self, step: &Step, server_names: &[String], variables: &VariableManager) -> Result<HashMap<String, String>> {
+        let mut failover_servers = HashMap::new();
+        for (server_name, entry) in server_names.iter().zip(step.servers_failover.iter().flatten()) {
+            let failover_name = variables.replace_variables(entry).trim().to_string();
+            if failover_name.is_empty() {
+                continue;
+            }
+            if !self.config.clients.contains_key(&failover_name) {
+                return Err(anyhow::anyhow!(
+                    "Failover server '{}' (resolved from '{}') in step '{}' not found in clients",
+                    failover_name, entry, step.name
+                ));
+            }
+            failover_servers.insert(server_name.clone(), failover_name);
+        }
+        Ok(failover_servers)
+    }
+
+    /// 解析步骤的服务器列表
+    /// 每一项先进行变量替换，替换结果包含逗号时展开为多个服务器名（去重并保持顺序），
+    /// 之后追加 server_selector 匹配的客户端（按名称排序）
+    fn resolve_step_servers(&self, step: &Step, variables: &VariableManager) -> Result<Vec<String>> {
+        let mut servers: Vec<String> = Vec::new();
+
+        for entry in &step.servers {
+            let resolved = variables.replace_variables(entry);
+            for name in resolved.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                if !self.config.clients.contains_key(name) {
+                    return Err(anyhow::anyhow!(
+                        "Server '{}' (resolved from '{}') in step '{}' not found in clients",
+                        name, entry, step.name
+                    ));
+                }
+                if !servers.iter().any(|s| s == name) {
+                    servers.push(name.to_string());
+                }
+            }
+        }
+
+        if let Some(selector) = &step.server_selector {
+            for name in self.get_clients_by_label(selector) {
+                if !servers.contains(&name) {
+                    servers.push(name);
+                }
+            }
+            if servers.is_empty() && !step.allow_empty_selection.unwrap_or(false) {
+                return Err(anyhow::anyhow!(
+                    "server_selector {:?} in step '{}' matched no clients", selector, step.name
+                ));
+            }
+            return Ok(servers);
+        }
+
+        if servers.is_empty() && !step.servers.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Servers {:?} in step '{}' resolved to an empty server list",
+                step.servers, step.name
+            ));
+        }
+
+        Ok(servers)
+    }
+
+    /// 在指定客户端执行shell脚本（支持实时输出）
+    pub async fn execute_script_with_realtime_output(
+        &self, 
+        script: Option<String>,
+        global_scripts:Arc<Vec<String>>,
+        client_name: &str, 
+        step: Step,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>
+    ) -> Result<ExecutionResult> {
+        let step = self.with_config_defaults(&step);
+        // 检查脚本文件：不存在、是目录或不可读时返回失败结果
+        if let Err(e) = check_script_file(&step.script) {
+            return Ok(script_error_result(&step.script, &e));
+        }
+
+        let client_config = self.config
             .clients
             .get(client_name)
             .ok_or_else(|| anyhow::anyhow!("Client '{}' not found in configuration", client_name))?;
 
-        match client_config.execution_method {
+        let mut result = match client_config.execution_method {
             ExecutionMethod::SSH => {
                 self.execute_script_via_ssh_with_realtime_output(script,global_scripts,client_config, step, client_name, pipeline_name, output_callback).await
             }
             ExecutionMethod::WebSocket => {
                 Err(anyhow::anyhow!("WebSocket execution not implemented yet"))
             }
-        }
+            ExecutionMethod::Local => {
+                self.execute_script_locally(script, global_scripts, client_name, &step, pipeline_name, output_callback).await
+            }
+        }?;
+        self.audit_rendered_script(&mut result);
+        Ok(result)
+    }
+
+    /// 以客户端身份在本地执行脚本（local 客户端与 local_fallback 共用）
+    async fn execute_script_locally(
+        &self,
+        script: Option<String>,
+        global_scripts: Arc<Vec<String>>,
+        client_name: &str,
+        step: &Step,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+    ) -> Result<ExecutionResult> {
+        // LocalExecutor 的事件使用 localhost，改为客户端名称以区分多个本地客户端
+        let output_callback = output_callback.map(|callback| {
+            let client_name = client_name.to_string();
+            Arc::new(move |mut event: OutputEvent| {
+                event.server_name.clone_from(&client_name);
+                callback(event)
+            }) as OutputCallback
+        });
+        let mut variable_manager = self.variable_manager.clone();
+        variable_manager.set_variable("server_name".to_string(), client_name.to_string());
+        LocalExecutor::execute_script_with_realtime_output(
+            script,
+            global_scripts.to_vec(),
+            step,
+            pipeline_name,
+            &step.name,
+            output_callback,
+            variable_manager,
+            self.line_filter(step),
+        ).await
     }
 
     /// 通过SSH执行脚本（支持实时输出）
+    /// 根据 ssh_config.backend 选择 libssh2 或 russh 实现，连接或执行错误转换为失败的执行结果
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(not(any(feature = "ssh", feature = "russh")), allow(unreachable_code, unused_variables))]
     async fn execute_script_via_ssh_with_realtime_output(
         &self, 
         script: Option<String>,
@@ -488,31 +1500,114 @@ impl RemoteExecutor {
 
         let start_time = std::time::Instant::now();
 
-        // 克隆数据以避免生命周期问题
-        let ssh_config = ssh_config.clone();
+        // 克隆数据以避免生命周期问题；未设置连接超时时使用全局 default_timeout
+        let ssh_config = SshConfig {
+            timeout_seconds: ssh_config.timeout_seconds.or(self.config.default_timeout),
+            ..ssh_config.clone()
+        };
         let script_content = step.script.to_string();
         let server_name = server_name.to_string();
         let pipeline_name = pipeline_name.to_string();
         let step_name = step.name.clone();
         let extract_rules = step.extract.clone();
         let variable_manager = self.variable_manager.clone();
+        let line_filter = self.line_filter(&step);
+        // 开启 local_fallback 时保留本地执行所需的数据
+        let fallback = (client_config.local_fallback == Some(true))
+            .then(|| (script.clone(), global_scripts.clone(), step.clone(), output_callback.clone(), server_name.clone(), pipeline_name.clone()));
 
-        // 在tokio的阻塞线程池中执行SSH操作
-        let result = match tokio::task::spawn_blocking(move || {
-            SshExecutor::execute_script_with_realtime_output(
-                script.clone(),
-                global_scripts.clone(),
-                &server_name, 
-                &ssh_config, 
-                &step,
-                &pipeline_name,
-                &step_name,
-                output_callback,
-                variable_manager,
-                extract_rules
-            )
-        }).await?.context(format!("run script faield")) {
+        // 连接前向认证提供者获取凭据，获取失败按连接失败处理
+        let credentials = self.auth_provider.credentials(client_config);
+        #[cfg(any(feature = "ssh", feature = "russh"))]
+        let credentials = credentials.context(ConnectError);
+
+        let result: Result<ExecutionResult> = match (credentials, ssh_config.backend.unwrap_or_default()) {
+            (Err(e), _) => Err(e),
+            // 在tokio的阻塞线程池中执行SSH操作
+            #[cfg(feature = "ssh")]
+            (Ok(credentials), SshBackend::Libssh2) => {
+                let ssh_config = credentials.apply(ssh_config);
+                let passphrase_callback = self.passphrase_callback.clone();
+                // 阻塞线程不继承当前 span，显式带入以保留 run_id
+                let span = tracing::Span::current();
+                run_blocking_ssh(&self.ssh_workers, move || span.in_scope(|| {
+                    // 口令回调可能阻塞等待输入，在阻塞线程中调用
+                    let ssh_config = resolve_passphrase(ssh_config, passphrase_callback.as_ref());
+                    SshExecutor::execute_script_with_realtime_output(
+                        script,
+                        global_scripts,
+                        &server_name,
+                        &ssh_config,
+                        &step,
+                        &pipeline_name,
+                        &step_name,
+                        output_callback,
+                        variable_manager,
+                        extract_rules,
+                        line_filter,
+                    )
+                })).await?
+            }
+            #[cfg(feature = "russh")]
+            (Ok(credentials), SshBackend::Russh) => {
+                let ssh_config = credentials.apply(ssh_config);
+                let passphrase_callback = self.passphrase_callback.clone();
+                let ssh_config = tokio::task::spawn_blocking(move || resolve_passphrase(ssh_config, passphrase_callback.as_ref())).await?;
+                RusshExecutor::execute_script_with_realtime_output(
+                    script,
+                    global_scripts,
+                    &server_name,
+                    &ssh_config,
+                    &step,
+                    &pipeline_name,
+                    &step_name,
+                    output_callback,
+                    variable_manager,
+                    extract_rules,
+                    line_filter,
+                ).await
+            }
+            #[allow(unreachable_patterns)]
+            (_, backend) => {
+                let feature = if backend == SshBackend::Russh { "russh" } else { "ssh" };
+                return Err(anyhow::anyhow!(
+                    "Client '{}' uses SSH execution, but net-shell was built without the `{}` feature",
+                    client_config.name, feature
+                ));
+            }
+        };
+
+        // 连接失败且客户端开启 local_fallback 时改为在本地执行
+        #[cfg(any(feature = "ssh", feature = "russh"))]
+        let result = match (result, fallback) {
+            (Err(e), Some((script, global_scripts, step, output_callback, server_name, pipeline_name))) if e.downcast_ref::<ConnectError>().is_some() => {
+                info!("SSH failed for server '{}', running step '{}' locally: {:#}", server_name, step.name, e);
+                if let Some(callback) = &output_callback {
+                    callback(OutputEvent::new(
+                        pipeline_name.clone(),
+                        server_name.clone(),
+                        step.clone(),
+                        crate::models::OutputType::Log,
+                        format!("SSH failed for server {}, running locally", server_name),
+                        self.variable_manager.snapshot(),
+                    ));
+                }
+                let mut result = self.execute_script_locally(
+                    script, global_scripts, &server_name, &step, &pipeline_name, output_callback,
+                ).await?;
+                result.fallback_reason = Some(format!("{:#}", e));
+                return Ok(result);
+            }
+            (result, _) => result,
+        };
+
+        let result = match result.context("run script faield") {
             Ok(v) => v,
+            // 调用方要求区分连接失败时返回错误，由调用方换下一台服务器或备用服务器
+            #[cfg(any(feature = "ssh", feature = "russh"))]
+            Err(e) if self.return_connect_errors && e.downcast_ref::<ConnectError>().is_some() => {
+                return Err(e);
+            }
             Err(e) => {
 
                 let execution_time = start_time.elapsed().as_millis() as u64;
@@ -524,6 +1619,17 @@ impl RemoteExecutor {
                     exit_code: 0,
                     execution_time_ms: execution_time,
                     error_message: Some(format!("{:?}", e)),
+                    had_invalid_utf8: false,
+                    artifacts: Vec::new(),
+                    connect_time_ms: 0,
+                    command_time_ms: 0,
+                    fallback_reason: None,
+                    rendered_script: None,
+                    script_sha256: None,
+                    connection_info: None,
+                    answered_prompts: Vec::new(),
+                    ssh_trace: None,
+                    skip_reason: None,
                 });
             }
         };
@@ -531,21 +1637,44 @@ impl RemoteExecutor {
         let execution_time = start_time.elapsed().as_millis() as u64;
 
         Ok(ExecutionResult {
-            success: result.exit_code == 0,
+            success: result.success,
             stdout: result.stdout,
             stderr: result.stderr,
             script: script_content,
             exit_code: result.exit_code,
             execution_time_ms: execution_time,
             error_message: result.error_message,
+            had_invalid_utf8: result.had_invalid_utf8,
+            artifacts: result.artifacts,
+            connect_time_ms: result.connect_time_ms,
+            command_time_ms: result.command_time_ms,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: result.connection_info,
+            answered_prompts: result.answered_prompts,
+            ssh_trace: result.ssh_trace,
+            skip_reason: result.skip_reason,
         })
     }
 
+    /// 获取带有指定标签的流水线
+    pub fn get_pipelines_by_tag(&self, tag: &str) -> Vec<&Pipeline> {
+        self.config.pipelines.iter()
+            .filter(|p| p.tags.as_ref().is_some_and(|tags| tags.iter().any(|t| t == tag)))
+            .collect()
+    }
+
     /// 获取所有可用的客户端名称
     pub fn get_available_clients(&self) -> Vec<String> {
         self.config.clients.keys().cloned().collect()
     }
 
+    /// 获取标签与 selector 全部匹配的客户端名称（按名称排序）
+    pub fn get_clients_by_label(&self, selector: &HashMap<String, String>) -> Vec<String> {
+        select_clients(&self.config.clients, selector)
+    }
+
     /// 检查客户端是否存在
     pub fn client_exists(&self, client_name: &str) -> bool {
         self.config.clients.contains_key(client_name)
@@ -560,4 +1689,2619 @@ impl RemoteExecutor {
     pub fn pipeline_exists(&self, pipeline_name: &str) -> bool {
         self.config.pipelines.iter().any(|p| p.name == pipeline_name)
     }
-} 
\ No newline at end of file
+} 
+
+/// 在 tokio 阻塞线程池中执行同步的SSH操作，同时占用的线程数不超过 workers 的许可数，
+/// 避免大量服务器耗尽共享的阻塞线程池；没有空闲许可时排队等待
+#[cfg(feature = "ssh")]
+async fn run_blocking_ssh<T: Send + 'static>(workers: &Arc<Semaphore>, f: impl FnOnce() -> T + Send + 'static) -> Result<T> {
+    let permit = workers.clone().acquire_owned().await?;
+    Ok(tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        f()
+    }).await?)
+}
+
+/// 服务器任务的句柄，被丢弃时（执行取消或超时）中止任务，任务中的本地进程随之结束
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> std::future::Future for AbortOnDrop<T> {
+    type Output = std::result::Result<T, tokio::task::JoinError>;
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        std::pin::Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// 执行结果的 stdout 行数、stderr 行数与平均每秒输出行数
+fn output_stats(result: &ExecutionResult) -> (usize, usize, f64) {
+    let stdout_lines = result.stdout.lines().count();
+    let stderr_lines = result.stderr.lines().count();
+    let rate = if result.execution_time_ms == 0 {
+        0.0
+    } else {
+        (stdout_lines + stderr_lines) as f64 * 1000.0 / result.execution_time_ms as f64
+    };
+    (stdout_lines, stderr_lines, rate)
+}
+
+/// 生成新的执行ID
+fn new_run_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// 一次执行的 tracing span，执行期间（包括各服务器任务）的日志都带有 run_id
+fn run_span(run_id: &str) -> tracing::Span {
+    tracing::info_span!("run", run_id = %run_id)
+}
+
+/// 在一台服务器上执行步骤的 tracing span，SSH与本地执行期间的日志都带有服务器名和主机
+fn server_span(server: &str, host: &str) -> tracing::Span {
+    tracing::info_span!("server", server = %server, host = %host)
+}
+
+/// 距截止时间的剩余整秒数
+fn remaining_seconds(deadline: std::time::Instant) -> u64 {
+    deadline.saturating_duration_since(std::time::Instant::now()).as_secs()
+}
+
+/// 流水线级别的日志事件（不属于任何步骤）
+fn pipeline_log_event(pipeline_name: &str, content: String, variables: &VariableManager) -> OutputEvent {
+    OutputEvent::new(
+        pipeline_name.to_string(),
+        "system",
+        Step::default(),
+        crate::models::OutputType::Log,
+        content,
+        variables.snapshot(),
+    )
+}
+
+/// 在总超时内执行，未设置超时时直接执行
+async fn with_global_timeout<T>(
+    timeout: Option<std::time::Duration>,
+    future: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| anyhow::anyhow!("Execution exceeded global timeout of {} ms", timeout.as_millis()))?,
+        None => future.await,
+    }
+}
+
+/// 执行步骤并按退出码分类，分类为 retryable 且未超过 step.retries 时重新执行
+/// 返回最后一次的执行结果、分类和总执行次数；执行结果的 success 按分类改写
+async fn run_classified<F, Fut>(
+    step: &Step,
+    meanings: &HashMap<i32, ExitClass>,
+    mut run: F,
+) -> Result<(ExecutionResult, ExitClass, u32)>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<ExecutionResult>>,
+{
+    let max_attempts = step.retries.unwrap_or(0) + 1;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut result = run().await?;
+        // 退出码为0却失败说明是连接或执行错误，没有可信的退出码，直接视为失败
+        let trusted_exit_code = result.success || result.exit_code != 0;
+        let mut exit_class = if trusted_exit_code {
+            ExitClass::resolve(result.exit_code, meanings)
+        } else {
+            ExitClass::Failure
+        };
+        result.success = exit_class.is_success();
+        if result.success {
+            result.error_message = None;
+        }
+        if let (Some(assert), true) = (&step.assert, trusted_exit_code) {
+            exit_class = assertions::apply_assert(assert, &mut result, exit_class);
+        }
+
+        if exit_class == ExitClass::Retryable && attempts < max_attempts {
+            info!(
+                "Step '{}' exited with retryable code {}, retrying ({}/{})",
+                step.name, result.exit_code, attempts, max_attempts - 1
+            );
+            continue;
+        }
+        return Ok((result, exit_class, attempts));
+    }
+}
+
+/// 跳过的步骤：发送日志事件，记为成功的 skipped 结果
+fn skip_step(
+    step: &Step,
+    pipeline_name: &str,
+    reason: &str,
+    output_callback: Option<&OutputCallback>,
+    variable_manager: &VariableManager,
+    start_time: std::time::Instant,
+) -> Vec<StepExecutionResult> {
+    if let Some(callback) = output_callback {
+        callback(placeholder_event(step, pipeline_name, crate::models::OutputType::Log, reason, variable_manager));
+    }
+    let mut result = placeholder_result(step, StepStatus::Skipped, None, start_time.elapsed().as_millis() as u64);
+    result.execution_result.skip_reason = Some(reason.to_string());
+    vec![result]
+}
+
+/// 流水线提前结束时没有执行完的步骤：发送步骤完成事件并返回一个标记为未执行的结果
+fn not_run_step(
+    step: &Step,
+    pipeline_name: &str,
+    output_callback: Option<&OutputCallback>,
+    variable_manager: &VariableManager,
+) -> StepExecutionResult {
+    if let Some(callback) = output_callback {
+        let event = placeholder_event(step, pipeline_name, crate::models::OutputType::StepCompleted, NOT_RUN_REASON, variable_manager);
+        callback(event);
+    }
+    placeholder_result(step, StepStatus::NotRun, Some(NOT_RUN_REASON.to_string()), 0)
+}
+
+fn placeholder_event(
+    step: &Step,
+    pipeline_name: &str,
+    output_type: crate::models::OutputType,
+    content: &str,
+    variable_manager: &VariableManager,
+) -> OutputEvent {
+    OutputEvent::new(
+        pipeline_name.to_string(),
+        "system",
+        step.clone(),
+        output_type,
+        content.to_string(),
+        variable_manager.snapshot(),
+    )
+}
+
+/// 没有在服务器上执行的步骤（跳过、未执行）的结果，执行结果为空的占位
+fn placeholder_result(step: &Step, status: StepStatus, error_message: Option<String>, execution_time_ms: u64) -> StepExecutionResult {
+    let success = status != StepStatus::NotRun;
+    StepExecutionResult {
+        title: step.title.clone().unwrap_or(step.name.clone()),
+        step_name: step.name.clone(),
+        server_name: "system".to_string(),
+        scritp_path: step.script.clone(),
+        exit_code: 0,
+        execution_result: ExecutionResult {
+            success,
+            stdout: String::new(),
+            stderr: String::new(),
+            script: step.script.clone(),
+            exit_code: 0,
+            execution_time_ms: 0,
+            error_message,
+            had_invalid_utf8: false,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: 0,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        },
+        overall_success: success,
+        status,
+        execution_time_ms,
+        exit_class: if success { ExitClass::Skipped } else { ExitClass::Failure },
+        attempts: 0,
+        skipped: status == StepStatus::Skipped,
+        artifacts: Vec::new(),
+        run_id: String::new(),
+        extracted_variables: HashMap::new(),
+        variable_diff: None,
+        executed_locally: false,
+        stdout_line_count: 0,
+        stderr_line_count: 0,
+        output_rate_lps: 0.0,
+        ssh_trace: None,
+        failover_used: false,
+        primary_server: "system".to_string(),
+    }
+}
+
+/// 服务器执行出错（而不是脚本返回失败）时记录的执行结果
+fn errored_result(script: &str, error: &anyhow::Error) -> ExecutionResult {
+    ExecutionResult {
+        success: false,
+        stdout: String::new(),
+        stderr: format!("{}\n", error),
+        script: script.to_string(),
+        exit_code: -1,
+        execution_time_ms: 0,
+        error_message: Some(error.to_string()),
+        had_invalid_utf8: false,
+        artifacts: Vec::new(),
+        connect_time_ms: 0,
+        command_time_ms: 0,
+        fallback_reason: None,
+        rendered_script: None,
+        script_sha256: None,
+        connection_info: None,
+        answered_prompts: Vec::new(),
+        ssh_trace: None,
+        skip_reason: None,
+    }
+}
+
+/// 错误是否为SSH连接失败（含获取凭据失败）
+#[cfg(any(feature = "ssh", feature = "russh"))]
+fn is_connect_error(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ConnectError>().is_some()
+}
+
+/// 没有SSH支持时不会出现连接失败
+#[cfg(not(any(feature = "ssh", feature = "russh")))]
+fn is_connect_error(_error: &anyhow::Error) -> bool {
+    false
+}
+
+/// 按汇总方式把各服务器的结果合并为一个变量值，stdout 去掉末尾的换行符
+fn aggregate_output(strategy: &AggregateStrategy, step_results: &[StepExecutionResult]) -> String {
+    let stdout = |r: &StepExecutionResult| r.execution_result.stdout.trim_end_matches(['\r', '\n']).to_string();
+    match strategy {
+        AggregateStrategy::Concat(separator) => step_results.iter().map(stdout).collect::<Vec<_>>().join(separator),
+        AggregateStrategy::CountSuccess => step_results.iter().filter(|r| r.execution_result.success).count().to_string(),
+        AggregateStrategy::CountFailure => step_results.iter().filter(|r| !r.execution_result.success).count().to_string(),
+        AggregateStrategy::FirstSuccess => step_results.iter()
+            .find(|r| r.execution_result.success)
+            .map(stdout)
+            .unwrap_or_default(),
+        AggregateStrategy::Json => serde_json::Value::Array(step_results.iter()
+            .map(|r| serde_json::json!({
+                "server": r.server_name,
+                "stdout": stdout(r),
+                "exit_code": r.exit_code,
+            }))
+            .collect())
+            .to_string(),
+    }
+}
+
+/// 汇总步骤在所有服务器上的分类：任一服务器失败则取其分类，全部跳过时为 skipped
+fn step_exit_class(step_results: &[StepExecutionResult]) -> ExitClass {
+    if let Some(failed) = step_results.iter().find(|r| !r.exit_class.is_success()) {
+        return failed.exit_class;
+    }
+    if !step_results.is_empty() && step_results.iter().all(|r| r.exit_class == ExitClass::Skipped) {
+        return ExitClass::Skipped;
+    }
+    ExitClass::Success
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 两个指向不可达端口的SSH客户端，连接会立即失败，便于验证目标服务器
+    fn unreachable_clients_yaml(script: &str) -> String {
+        format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+    ssh_config:
+      host: "127.0.0.1"
+      port: 1
+      username: "user"
+      password: "password"
+      timeout_seconds: 1
+  web2:
+    name: "web2"
+    execution_method: ssh
+    ssh_config:
+      host: "127.0.0.1"
+      port: 1
+      username: "user"
+      password: "password"
+      timeout_seconds: 1
+pipelines:
+  - name: "patch"
+    steps:
+      - name: "patch_step"
+        script: "{}"
+        servers:
+          - "{{{{ hosts }}}}"
+"#, script)
+    }
+
+    #[test]
+    fn test_resolve_step_servers_expands_comma_list() {
+        let mut executor = RemoteExecutor::from_yaml_str(&unreachable_clients_yaml("noop.sh"), None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1, web2,web1".to_string());
+
+        let step = executor.config.pipelines[0].steps[0].clone();
+        assert_eq!(executor.resolve_step_servers(&step, &executor.variable_manager).unwrap(), vec!["web1", "web2"]);
+    }
+
+    #[test]
+    fn test_resolve_step_servers_unknown_server() {
+        let mut executor = RemoteExecutor::from_yaml_str(&unreachable_clients_yaml("noop.sh"), None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1,db9".to_string());
+
+        let step = executor.config.pipelines[0].steps[0].clone();
+        let err = executor.resolve_step_servers(&step, &executor.variable_manager).unwrap_err().to_string();
+        assert!(err.contains("'db9'"));
+        assert!(err.contains("{{ hosts }}"));
+    }
+
+    fn labelled_clients_yaml(selector: &str) -> String {
+        format!(r#"
+clients:
+  web-fra:
+    name: "web-fra"
+    execution_method: ssh
+    labels: {{ role: web, dc: fra }}
+  web-ams:
+    name: "web-ams"
+    execution_method: ssh
+    labels: {{ role: web, dc: ams }}
+  db-fra:
+    name: "db-fra"
+    execution_method: ssh
+    labels: {{ role: db, dc: fra }}
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "rollout"
+        script: "noop.sh"
+        {}
+"#, selector)
+    }
+
+    #[test]
+    fn test_server_selector_expands_to_matching_clients() {
+        let executor = RemoteExecutor::from_yaml_str(&labelled_clients_yaml("server_selector: { role: web }"), None).unwrap();
+        let step = executor.config.pipelines[0].steps[0].clone();
+        assert_eq!(executor.resolve_step_servers(&step, &executor.variable_manager).unwrap(), vec!["web-ams", "web-fra"]);
+
+        // 多个标签需全部匹配，与 servers 合并去重
+        let yaml = labelled_clients_yaml("servers: [\"web-ams\"]\n        server_selector: { dc: fra, role: web }");
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let step = executor.config.pipelines[0].steps[0].clone();
+        assert_eq!(executor.resolve_step_servers(&step, &executor.variable_manager).unwrap(), vec!["web-ams", "web-fra"]);
+
+        let selector = HashMap::from([("dc".to_string(), "fra".to_string())]);
+        assert_eq!(executor.get_clients_by_label(&selector), vec!["db-fra", "web-fra"]);
+    }
+
+    #[tokio::test]
+    async fn test_server_selector_without_matches() {
+        let err = RemoteExecutor::from_yaml_str(&labelled_clients_yaml("server_selector: { role: cache }"), None)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("matches no clients"), "{}", err);
+
+        let yaml = labelled_clients_yaml("server_selector: { role: cache }\n        allow_empty_selection: true");
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let result = executor.execute_pipeline("deploy").await.unwrap();
+        assert!(result.overall_success);
+        assert!(result.step_results[0].skipped);
+    }
+
+    #[cfg(feature = "ssh")]
+    #[tokio::test]
+    async fn test_step_runs_on_all_interpolated_servers() {
+        let script = tempfile::NamedTempFile::new().unwrap();
+        let yaml = unreachable_clients_yaml(&script.path().to_string_lossy());
+        let mut executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1,web2".to_string());
+
+        let result = executor.execute_pipeline("patch").await.unwrap();
+        let mut servers: Vec<&str> = result.step_results.iter().map(|r| r.server_name.as_str()).collect();
+        servers.sort();
+        assert_eq!(servers, vec!["web1", "web2"]);
+    }
+
+    #[cfg(any(feature = "ssh", feature = "russh"))]
+    #[tokio::test]
+    async fn test_run_once_tries_servers_in_order_until_one_connects() {
+        let script = tempfile::NamedTempFile::new().unwrap();
+        let yaml = unreachable_clients_yaml(&script.path().to_string_lossy())
+            .replace("          - \"{{ hosts }}\"\n", "          - \"{{ hosts }}\"\n        run_once: true\n");
+        let mut executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1,web2".to_string());
+
+        // 两台都连接失败时，不论 fail_fast 都只记录最后尝试的服务器的一个失败结果，带上它的连接错误
+        for yaml in [yaml.clone(), yaml.replace("run_once: true", "run_once: true\n        fail_fast: false")] {
+            let mut executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+            executor.variable_manager.set_variable("hosts".to_string(), "web1,web2".to_string());
+            let result = executor.execute_pipeline("patch").await.unwrap();
+            assert!(!result.overall_success);
+            assert_eq!(result.step_results.len(), 1);
+            let step = &result.step_results[0];
+            assert_eq!(step.server_name, "web2");
+            assert!(!step.overall_success);
+            let error = step.execution_result.error_message.as_deref().unwrap();
+            assert!(error.contains("SSH connection failed"), "{}", error);
+        }
+    }
+
+    #[cfg(any(feature = "ssh", feature = "russh"))]
+    #[tokio::test]
+    async fn test_failover_server_used_when_primary_unreachable() {
+        use std::sync::Mutex;
+
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut script, b"echo ran on $role\n").unwrap();
+        let yaml = unreachable_clients_yaml(&script.path().to_string_lossy())
+            .replace("pipelines:\n", "  standby:\n    name: \"standby\"\n    execution_method: local\n    variables:\n      role: \"standby\"\npipelines:\n")
+            .replace("          - \"{{ hosts }}\"\n", "          - \"{{ hosts }}\"\n        servers_failover: [\"standby\"]\n        fail_fast: false\n");
+        let mut executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1,web2".to_string());
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let step_sink = steps.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if !event.step.name.is_empty() {
+                step_sink.lock().unwrap().push(event.step.servers_failover.clone());
+            }
+            if event.output_type == crate::models::OutputType::Log {
+                sink.lock().unwrap().push(event.content);
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("patch", None, Some(callback)).await.unwrap();
+        // 是否返回连接错误不通过改写步骤的 servers_failover 传递，事件中的步骤保持原样
+        let standby = Some(vec!["standby".to_string()]);
+        let steps = steps.lock().unwrap();
+        assert!(!steps.is_empty() && steps.iter().all(|failover| *failover == standby), "{:?}", steps);
+
+        // web1 连接超时后在备用服务器上执行
+        let primary = &result.step_results[0];
+        assert!(primary.overall_success, "{:?}", primary.execution_result.error_message);
+        assert!(primary.failover_used);
+        assert_eq!((primary.server_name.as_str(), primary.primary_server.as_str()), ("standby", "web1"));
+        assert_eq!(primary.execution_result.stdout.trim(), "ran on standby");
+        assert!(logs.lock().unwrap().iter().any(|log| log == "SSH connection to server web1 failed, failing over to standby"));
+
+        // web2 没有对应的备用服务器，照常记为失败
+        let second = &result.step_results[1];
+        assert!(!second.overall_success);
+        assert!(!second.failover_used);
+        assert_eq!((second.server_name.as_str(), second.primary_server.as_str()), ("web2", "web2"));
+    }
+
+    #[cfg(any(feature = "ssh", feature = "russh"))]
+    #[tokio::test]
+    async fn test_local_fallback_when_ssh_unreachable() {
+        use std::sync::Mutex;
+
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut script, b"echo ran locally on $server_name\n").unwrap();
+        let yaml = unreachable_clients_yaml(&script.path().to_string_lossy())
+            .replacen("      timeout_seconds: 1\n", "      timeout_seconds: 1\n    local_fallback: true\n", 1);
+        let mut executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1".to_string());
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if event.output_type == crate::models::OutputType::Log {
+                sink.lock().unwrap().push(event.content);
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("patch", None, Some(callback)).await.unwrap();
+        let step = &result.step_results[0];
+        assert!(result.overall_success);
+        assert!(step.executed_locally);
+        assert_eq!(step.execution_result.stdout.trim(), "ran locally on web1");
+        assert!(step.execution_result.fallback_reason.as_deref().unwrap().contains("SSH connection failed"));
+        assert!(logs.lock().unwrap().iter().any(|log| log == "SSH failed for server web1, running locally"));
+
+        // 未开启 local_fallback 的客户端仍然失败
+        executor.variable_manager.set_variable("hosts".to_string(), "web2".to_string());
+        let result = executor.execute_pipeline("patch").await.unwrap();
+        assert!(!result.overall_success);
+        assert!(!result.step_results[0].executed_locally);
+    }
+
+    #[cfg(any(feature = "ssh", feature = "russh"))]
+    #[tokio::test]
+    async fn test_auth_provider_is_asked_before_connecting() {
+        use crate::ssh::auth::SshCredentials;
+        use std::sync::Mutex;
+
+        /// 只为 web1 提供凭据的模拟密钥管理服务
+        struct Vault(Arc<Mutex<Vec<String>>>);
+        impl AuthProvider for Vault {
+            fn credentials(&self, client: &ClientConfig) -> Result<SshCredentials> {
+                self.0.lock().unwrap().push(client.name.clone());
+                match client.name.as_str() {
+                    "web1" => Ok(SshCredentials { username: "deploy".to_string(), password: Some("vaulted".to_string()), ..Default::default() }),
+                    name => Err(anyhow::anyhow!("no secret for '{}'", name)),
+                }
+            }
+        }
+
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut script, b"echo ran locally\n").unwrap();
+        let yaml = unreachable_clients_yaml(&script.path().to_string_lossy())
+            .replace("      timeout_seconds: 1\n", "      timeout_seconds: 1\n    local_fallback: true\n");
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&yaml)
+            .variables(HashMap::from([("hosts".to_string(), "web1,web2".to_string())]))
+            .with_auth_provider(Vault(asked.clone()))
+            .build()
+            .unwrap();
+
+        let result = executor.execute_pipeline("patch").await.unwrap();
+        assert_eq!(*asked.lock().unwrap(), ["web1", "web2"]);
+        let reasons: Vec<_> = result.step_results.iter()
+            .map(|r| r.execution_result.fallback_reason.clone().unwrap())
+            .collect();
+        // web1 拿到凭据后连接失败，web2 获取凭据失败，都改为本地执行
+        assert!(!reasons[0].contains("no secret"), "{}", reasons[0]);
+        assert!(reasons[1].contains("SSH connection failed") && reasons[1].contains("no secret for 'web2'"), "{}", reasons[1]);
+    }
+
+    #[cfg(not(any(feature = "ssh", feature = "russh")))]
+    #[tokio::test]
+    async fn test_ssh_client_errors_without_ssh_feature() {
+        let script = tempfile::NamedTempFile::new().unwrap();
+        let yaml = unreachable_clients_yaml(&script.path().to_string_lossy());
+        let mut executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        executor.variable_manager.set_variable("hosts".to_string(), "web1".to_string());
+
+        let err = executor.execute_pipeline("patch").await.unwrap_err().to_string();
+        assert!(err.contains("without the `ssh` feature"));
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_pipeline_events() {
+        use std::sync::Mutex;
+        use crate::models::OutputType;
+
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut script, b"echo hello\n").unwrap();
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "local"
+    steps:
+      - name: "say_hello"
+        script: "{}"
+"#, script.path().to_string_lossy());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let completed = Arc::new(Mutex::new(0));
+        let sink = stdout.clone();
+        executor.subscribe(
+            EventFilter::output_types([OutputType::Stdout]),
+            Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content)),
+        );
+        let counter = completed.clone();
+        let id = executor.subscribe(
+            EventFilter::output_types([OutputType::StepCompleted]).with_pipeline("local"),
+            Arc::new(move |_| *counter.lock().unwrap() += 1),
+        );
+
+        executor.execute_pipeline("local").await.unwrap();
+        assert_eq!(*stdout.lock().unwrap(), vec!["hello".to_string()]);
+        assert_eq!(*completed.lock().unwrap(), 1);
+
+        executor.unsubscribe(id);
+        executor.execute_pipeline("local").await.unwrap();
+        assert_eq!(stdout.lock().unwrap().len(), 2);
+        assert_eq!(*completed.lock().unwrap(), 1);
+    }
+
+    /// 创建内容为 `body` 的临时脚本
+    fn script_file(body: &str) -> tempfile::NamedTempFile {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut script, body.as_bytes()).unwrap();
+        script
+    }
+
+    #[test]
+    fn test_from_template_renders_inventory() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("config.tpl.yaml");
+        std::fs::write(&template, r##"variables:
+  app: "#{ app }"
+clients:
+#{% for host in hosts %}
+  #{ host.name }:
+    name: "#{ host.name }"
+    execution_method: ssh
+    ssh_config: { host: "#{ host.ip }", port: 22, username: "deploy", password: "x" }
+#{% endfor %}
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "ship"
+        script: "ship.sh"
+        servers: ["web1", "web2"]
+"##).unwrap();
+        let data = dir.path().join("inventory.json");
+        std::fs::write(&data, r#"{"app": "shop", "hosts": [{"name": "web1", "ip": "10.0.0.1"}, {"name": "web2", "ip": "10.0.0.2"}]}"#).unwrap();
+
+        let executor = RemoteExecutor::from_template(&template, &data, None).unwrap();
+        assert_eq!(executor.config.clients["web2"].ssh_config.as_ref().unwrap().host, "10.0.0.2");
+        assert_eq!(executor.variable_manager.get_variable("app").map(String::as_str), Some("shop"));
+
+        // YAML数据文件缺少模板引用的 hosts 时报错
+        let data = dir.path().join("inventory.yaml");
+        std::fs::write(&data, "app: shop\n").unwrap();
+        let err = RemoteExecutor::from_template(&template, &data, None).err().unwrap();
+        assert!(format!("{:#}", err).contains("Failed to render configuration template"), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn test_assert_step_checks_output() {
+        let script = script_file("echo 'version {{ version }}'\n");
+        let yaml = format!(r#"
+variables:
+  version: "1.4.2"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "smoke"
+    steps:
+      - name: "version"
+        script: "{0}"
+        assert:
+          stdout_equals: "version {{{{ version }}}}"
+          trim: true
+      - name: "wrong_version"
+        script: "{0}"
+        assert:
+          stdout_contains: "version 2."
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let result = executor.execute_pipeline("smoke").await.unwrap();
+
+        let passed = &result.step_results[0];
+        assert_eq!(passed.status, StepStatus::Succeeded);
+        assert_eq!(passed.execution_result.stdout, "version 1.4.2\n");
+        let failed = &result.step_results[1];
+        assert_eq!(failed.status, StepStatus::Failed);
+        assert_eq!(
+            failed.execution_result.error_message.as_deref(),
+            Some("stdout_contains: text not found\n  expected: \"version 2.\"\n  actual:   \"version 1.4.2\\n\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_creates_guard_skips_step_once_path_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("installed");
+        let install = script_file("touch {{ marker }}\necho installing\n");
+        let yaml = format!(r#"
+global_scripts: []
+variables:
+  marker: "{}"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "install"
+    steps:
+      - name: "install"
+        script: "{}"
+        creates: "{{{{ marker }}}}"
+"#, marker.display(), install.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let first = executor.execute_pipeline("install").await.unwrap();
+        let step = &first.step_results[0];
+        assert_eq!(step.status, StepStatus::Succeeded);
+        assert!(step.execution_result.stdout.contains("installing"));
+        assert!(marker.exists());
+
+        let second = executor.execute_pipeline("install").await.unwrap();
+        assert!(second.overall_success);
+        let step = &second.step_results[0];
+        assert_eq!(step.status, StepStatus::Skipped);
+        assert_eq!(step.execution_result.stdout, "");
+        assert_eq!(step.execution_result.skip_reason.as_deref(), Some(format!("creates: {} exists", marker.display()).as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_exit_code_meanings_classify_steps() {
+        use std::sync::Mutex;
+        use crate::models::OutputType;
+
+        let deployed = script_file("exit 3\n");
+        let broken = script_file("exit 1\n");
+        let never = script_file("echo unreachable\n");
+        let yaml = format!(r#"
+global_scripts: []
+exit_code_meanings:
+  1: failure
+  3: success
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "already_deployed"
+        script: "{}"
+        exit_code_meanings:
+          3: skipped
+      - name: "broken"
+        script: "{}"
+      - name: "never"
+        script: "{}"
+"#, deployed.path().display(), broken.path().display(), never.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let sink = completed.clone();
+        executor.subscribe(
+            EventFilter::output_types([OutputType::StepCompleted]),
+            Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content)),
+        );
+
+        let result = executor.execute_pipeline("deploy").await.unwrap();
+        assert!(!result.overall_success);
+        assert_eq!(result.step_results.len(), 3);
+        assert_eq!(result.step_results[2].status, StepStatus::NotRun);
+
+        let skipped = &result.step_results[0];
+        assert_eq!(skipped.exit_class, ExitClass::Skipped);
+        assert_eq!(skipped.status, StepStatus::Skipped);
+        assert_eq!(skipped.exit_code, 3);
+        assert!(skipped.execution_result.success);
+
+        let failed = &result.step_results[1];
+        assert_eq!(failed.exit_class, ExitClass::Failure);
+        assert_eq!(failed.exit_code, 1);
+        assert!(!failed.execution_result.success);
+
+        let completed = completed.lock().unwrap();
+        assert!(completed[0].contains("skipped"));
+        assert!(completed[1].contains("failure"));
+    }
+
+    #[tokio::test]
+    async fn test_retryable_exit_code_retries_until_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        // 前两次以 75 退出，第三次成功
+        let flaky = script_file(&format!(
+            "echo x >> {0}\n[ $(wc -l < {0}) -ge 3 ] || exit 75\n",
+            counter.display()
+        ));
+        let always = script_file("exit 75\n");
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "flaky"
+    steps:
+      - name: "flaky"
+        script: "{}"
+        retries: 5
+        exit_code_meanings:
+          75: retryable
+  - name: "exhausted"
+    steps:
+      - name: "always"
+        script: "{}"
+        retries: 1
+        exit_code_meanings:
+          75: retryable
+  - name: "no_retries"
+    steps:
+      - name: "always"
+        script: "{}"
+        exit_code_meanings:
+          75: retryable
+"#, flaky.path().display(), always.path().display(), always.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("flaky").await.unwrap();
+        assert!(result.overall_success);
+        assert_eq!(result.step_results[0].attempts, 3);
+        assert_eq!(result.step_results[0].exit_class, ExitClass::Success);
+
+        let result = executor.execute_pipeline("exhausted").await.unwrap();
+        assert!(!result.overall_success);
+        assert_eq!(result.step_results[0].attempts, 2);
+        assert_eq!(result.step_results[0].exit_class, ExitClass::Retryable);
+        assert_eq!(result.step_results[0].exit_code, 75);
+
+        let result = executor.execute_pipeline("no_retries").await.unwrap();
+        assert_eq!(result.step_results[0].attempts, 1);
+        assert!(!result.step_results[0].execution_result.success);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_start_logged_once_and_values_not_reexpanded() {
+        use std::sync::Mutex;
+
+        let script = script_file("echo '{{ payload }}'\n");
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "literal"
+    steps:
+      - name: "echo_payload"
+        script: "{}"
+"#, script.path().display());
+        let variables = HashMap::from([
+            ("payload".to_string(), "{{ secret }}".to_string()),
+            ("secret".to_string(), "leaked".to_string()),
+        ]);
+        let executor = RemoteExecutor::from_yaml_str(&yaml, Some(variables)).unwrap();
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let log_callback: OutputCallback = Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content));
+        let result = executor
+            .execute_all_pipelines_with_realtime_output(None, Some(log_callback))
+            .await
+            .unwrap();
+
+        let stdout = &result.pipeline_results[0].step_results[0].execution_result.stdout;
+        assert_eq!(stdout.trim(), "{{ secret }}");
+        let starts = logs.lock().unwrap().iter().filter(|c| c.starts_with("开始执行流水线")).count();
+        assert_eq!(starts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_local_clients_run_with_their_own_variables_alongside_ssh() {
+        let script = script_file("echo \"$region {{ region }} {{ server_name }}\"\n");
+        let yaml = format!(r#"
+variables:
+  region: "none"
+clients:
+  controller-a:
+    name: "controller-a"
+    execution_method: local
+    variables:
+      region: "ams"
+  controller-b:
+    name: "controller-b"
+    execution_method: local
+    variables:
+      region: "fra"
+  web1:
+    name: "web1"
+    execution_method: ssh
+    ssh_config:
+      host: "127.0.0.1"
+      port: 1
+      username: "user"
+      password: "password"
+      timeout_seconds: 1
+pipelines:
+  - name: "mixed"
+    steps:
+      - name: "report"
+        script: "{}"
+        servers: ["controller-a", "controller-b", "web1"]
+        fail_fast: false
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| {
+            if e.output_type == crate::models::OutputType::Stdout {
+                sink.lock().unwrap().push((e.server_name, e.content));
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("mixed", Some(callback), None).await.unwrap();
+        let outputs: Vec<_> = result.step_results.iter()
+            .map(|r| (r.server_name.as_str(), r.execution_result.stdout.trim()))
+            .collect();
+        assert_eq!(outputs[..2], [("controller-a", "ams ams controller-a"), ("controller-b", "fra fra controller-b")]);
+        assert_eq!(outputs[2].0, "web1");
+        assert!(!result.step_results[2].execution_result.success);
+
+        let mut events = events.lock().unwrap().clone();
+        events.sort();
+        assert_eq!(events, [
+            ("controller-a".to_string(), "ams ams controller-a".to_string()),
+            ("controller-b".to_string(), "fra fra controller-b".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_before_all_and_after_all_wrap_pipelines() {
+        let ok = script_file("echo ok\n");
+        let fail = script_file("exit 1\n");
+        let yaml = |before: &std::path::Path, pipeline: &std::path::Path| format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+before_all:
+  - name: "lock"
+    script: "{}"
+after_all:
+  - name: "unlock"
+    script: "{}"
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "rollout"
+        script: "{}"
+  - name: "verify"
+    steps:
+      - name: "check"
+        script: "{}"
+"#, before.display(), ok.path().display(), pipeline.display(), ok.path().display());
+        let names = |result: &ShellExecutionResult| -> Vec<String> {
+            result.pipeline_results.iter().map(|r| r.pipeline_name.clone()).collect()
+        };
+
+        let executor = RemoteExecutor::from_yaml_str(&yaml(ok.path(), ok.path()), None).unwrap();
+        let result = executor.execute_all_pipelines_with_realtime_output(None, None).await.unwrap();
+        assert_eq!(names(&result), ["before_all", "deploy", "verify", "after_all"]);
+
+        // 流水线失败时 after_all 仍然执行
+        let executor = RemoteExecutor::from_yaml_str(&yaml(ok.path(), fail.path()), None).unwrap();
+        let result = executor.execute_all_pipelines_with_realtime_output(None, None).await.unwrap();
+        assert_eq!(names(&result), ["before_all", "deploy", "after_all"]);
+        assert!(result.pipeline_results[2].overall_success);
+
+        // before_all 失败时跳过所有流水线
+        let executor = RemoteExecutor::from_yaml_str(&yaml(fail.path(), ok.path()), None).unwrap();
+        let result = executor.execute_all_pipelines_with_realtime_output(None, None).await.unwrap();
+        assert_eq!(names(&result), ["before_all", "after_all"]);
+
+        // 单独执行一个流水线时不运行
+        let result = executor.execute_pipeline("verify").await.unwrap();
+        assert_eq!(result.pipeline_name, "verify");
+    }
+
+    #[tokio::test]
+    async fn test_rendered_script_is_recorded_for_audit() {
+        use sha2::{Digest, Sha256};
+
+        let helpers = script_file("log() { echo \"[deploy] $*\"; }\n");
+        let script = script_file("log releasing {{ version }}\n");
+        let yaml = |extra: &str| format!(r#"
+global_scripts: ["{}"]
+variables:
+  version: "1.4.2"
+{}
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "release"
+    steps:
+      - name: "announce"
+        script: "{}"
+"#, helpers.path().display(), extra, script.path().display());
+
+        let executor = RemoteExecutor::from_yaml_str(&yaml("include_rendered_script_in_events: true"), None).unwrap();
+        let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = started.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| {
+            if e.output_type == crate::models::OutputType::StepStarted && e.server_name != "system" {
+                sink.lock().unwrap().push(e.content);
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("release", Some(callback), None).await.unwrap();
+        let execution = &result.step_results[0].execution_result;
+        assert_eq!(execution.stdout.trim(), "[deploy] releasing 1.4.2");
+        let rendered = execution.rendered_script.as_deref().unwrap();
+        assert!(rendered.contains("log() { echo \"[deploy] $*\"; }"));
+        assert!(rendered.contains("log releasing 1.4.2"));
+        assert_eq!(execution.script_sha256.as_deref().unwrap(), format!("{:x}", Sha256::digest(rendered.as_bytes())));
+        assert_eq!(*started.lock().unwrap(), [rendered.to_string()]);
+
+        // 结果中的脚本截断后哈希仍对应完整内容，未开启时不发送脚本事件
+        let executor = RemoteExecutor::from_yaml_str(&yaml("rendered_script_max_bytes: 8"), None).unwrap();
+        let started = Arc::new(std::sync::Mutex::new(0));
+        let sink = started.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| {
+            if e.output_type == crate::models::OutputType::StepStarted {
+                *sink.lock().unwrap() += 1;
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("release", Some(callback), None).await.unwrap();
+        let truncated = &result.step_results[0].execution_result;
+        assert!(truncated.rendered_script.as_deref().unwrap().contains("... [truncated"));
+        assert_eq!(truncated.script_sha256, execution.script_sha256);
+        assert_eq!(*started.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_variables_override_per_server() {
+        let script = script_file("echo \"mkdir -p {{ data_dir }}/app\"\n");
+        let yaml = format!(r#"
+clients:
+  metal1:
+    name: "metal1"
+    execution_method: local
+    variables:
+      data_dir: "/data"
+  cloud1:
+    name: "cloud1"
+    execution_method: local
+    variables:
+      data_dir: "/mnt/vol1"
+pipelines:
+  - name: "prepare"
+    steps:
+      - name: "mkdir"
+        script: "{}"
+        servers: ["metal1", "cloud1"]
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        // 只在客户端上定义的占位符不算未定义
+        assert!(executor.lint().unwrap().is_empty());
+
+        let snapshots = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let sink = snapshots.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| {
+            if e.output_type == crate::models::OutputType::Stdout {
+                sink.lock().unwrap().insert(e.server_name, e.variables["data_dir"].clone());
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("prepare", Some(callback), None).await.unwrap();
+        let outputs: HashMap<_, _> = result.step_results.iter()
+            .map(|r| (r.server_name.as_str(), r.execution_result.stdout.trim()))
+            .collect();
+        assert_eq!(outputs["metal1"], "mkdir -p /data/app");
+        assert_eq!(outputs["cloud1"], "mkdir -p /mnt/vol1/app");
+
+        let snapshots = snapshots.lock().unwrap();
+        assert_eq!(snapshots["metal1"], "/data");
+        assert_eq!(snapshots["cloud1"], "/mnt/vol1");
+        // 客户端变量不写回共享的变量上下文
+        assert!(!result.final_variables.contains_key("data_dir"));
+    }
+
+    #[tokio::test]
+    async fn test_extracted_variables_are_reported_per_step_and_pipeline() {
+        let build = script_file("echo version=1.0\n");
+        let deploy = script_file("echo deploying {{ version }}\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "release"
+    steps:
+      - name: "build"
+        script: "{}"
+        extract:
+          - name: "version"
+            patterns: ["version=(\\S+)"]
+            source: "stdout"
+      - name: "deploy"
+        script: "{}"
+        variables:
+          target: "prod"
+"#, build.path().display(), deploy.path().display());
+        let variables = HashMap::from([("app".to_string(), "shop".to_string())]);
+        let executor = RemoteExecutor::from_yaml_str(&yaml, Some(variables)).unwrap();
+
+        let result = executor.execute_all_pipelines_with_realtime_output(None, None).await.unwrap();
+        let pipeline = &result.pipeline_results[0];
+        assert_eq!(pipeline.step_results[1].execution_result.stdout.trim(), "deploying 1.0");
+        assert_eq!(pipeline.step_results[0].extracted_variables, HashMap::from([("version".to_string(), "1.0".to_string())]));
+        assert!(pipeline.step_results[1].extracted_variables.is_empty());
+        assert_eq!(pipeline.final_variables["version"], "1.0");
+        assert_eq!(pipeline.final_variables["app"], "shop");
+        assert_eq!(result.all_extracted_variables(), HashMap::from([("version".to_string(), "1.0".to_string())]));
+        // 变量差异包含提取的变量和 step 级 variables
+        let build_diff = pipeline.step_results[0].variable_diff.as_ref().unwrap();
+        assert_eq!(build_diff.added, HashMap::from([("version".to_string(), "1.0".to_string())]));
+        let deploy_diff = pipeline.step_results[1].variable_diff.as_ref().unwrap();
+        assert_eq!(deploy_diff.added, HashMap::from([("target".to_string(), "prod".to_string())]));
+        assert!(deploy_diff.modified.is_empty() && deploy_diff.removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_late_consumer_replays_buffered_events_then_live_ones() {
+        use std::sync::Mutex;
+
+        let script = script_file("echo one\nsleep 0.5\necho two\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "slow"
+    steps:
+      - name: "print"
+        script: "{}"
+"#, script.path().display());
+        let executor = RemoteExecutor::builder().yaml_str(&yaml).with_event_buffer(1000, 1 << 20).build().unwrap();
+        let recorder = || {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let sink = events.clone();
+            let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+                sink.lock().unwrap().push((event.sequence, event.content));
+            });
+            (callback, events)
+        };
+        let (all_callback, all) = recorder();
+        executor.subscribe(EventFilter::all(), all_callback);
+
+        let run = executor.clone();
+        let handle = tokio::spawn(async move {
+            run.execute_pipeline_with_run_id("slow", "late-run", None, None).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        let (late_callback, late) = recorder();
+        assert!(!handle.is_finished());
+        assert!(executor.replay_events("late-run", late_callback));
+        handle.await.unwrap().unwrap();
+
+        let all = all.lock().unwrap().clone();
+        let sequences: Vec<u64> = all.iter().map(|(sequence, _)| *sequence).collect();
+        assert_eq!(sequences, (1..=all.len() as u64).collect::<Vec<_>>());
+        assert!(all.iter().any(|(_, content)| content == "two"));
+        assert_eq!(*late.lock().unwrap(), all);
+        assert!(!executor.replay_events("unknown-run", Arc::new(|_| {})));
+    }
+
+    #[tokio::test]
+    async fn test_event_throttle_coalesces_high_volume_output() {
+        use std::sync::Mutex;
+        use crate::models::OutputType;
+
+        let script = script_file("seq 1 100000\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "build"
+    steps:
+      - name: "compile"
+        script: "{}"
+"#, script.path().display());
+        let executor = RemoteExecutor::builder().yaml_str(&yaml).with_event_throttle(100, true, 1000).build().unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| sink.lock().unwrap().push(event));
+
+        let result = executor.execute_pipeline_with_realtime_output("build", Some(callback), None).await.unwrap();
+        let stdout = &result.step_results[0].execution_result.stdout;
+        assert_eq!(stdout.lines().count(), 100_000);
+
+        let events = events.lock().unwrap();
+        let output: Vec<&OutputEvent> = events.iter().filter(|e| e.output_type == OutputType::Stdout).collect();
+        // 每秒最多100个单行事件，其余按1000行合并
+        assert!(output.len() < 500, "{} output events", output.len());
+        assert!(output.iter().any(|e| e.coalesced_line_count > 1));
+        let lines: u64 = output.iter().map(|e| e.coalesced_line_count.max(1)).sum();
+        assert_eq!(lines, 100_000);
+        let content: Vec<&str> = output.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(content.join("\n"), stdout.trim_end());
+        assert!(events.iter().any(|e| e.output_type == OutputType::StepCompleted));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_outputs_hand_off_in_process_and_through_file() {
+        use std::sync::Mutex;
+        use crate::models::OutputType;
+
+        let build = script_file("echo version=2.0\n");
+        let deploy = script_file("echo deploying {{ version }}\n");
+        let yaml = |pipelines: &str| format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+{}
+"#, pipelines);
+        let build_pipeline = format!(r#"
+  - name: "build"
+    outputs: ["version", "missing"]
+    steps:
+      - name: "build"
+        script: "{}"
+        extract:
+          - name: "version"
+            patterns: ["version=(\\S+)"]
+            source: "stdout"
+"#, build.path().display());
+        let deploy_pipeline = format!(r#"
+  - name: "deploy"
+    steps:
+      - name: "deploy"
+        script: "{}"
+"#, deploy.path().display());
+
+        // 同一次执行中后面的流水线使用导出的值，覆盖已有值时发送警告
+        let executor = RemoteExecutor::from_yaml_str(
+            &yaml(&format!("{}{}", build_pipeline, deploy_pipeline)),
+            Some(HashMap::from([("version".to_string(), "1.0".to_string())])),
+        ).unwrap();
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if event.output_type == OutputType::Log {
+                sink.lock().unwrap().push(event.content);
+            }
+        });
+        let result = executor.execute_all_pipelines_with_realtime_output(None, Some(callback)).await.unwrap();
+        assert_eq!(result.pipeline_results[0].outputs, HashMap::from([("version".to_string(), "2.0".to_string())]));
+        assert!(result.pipeline_results[1].outputs.is_empty());
+        assert_eq!(result.pipeline_results[1].step_results[0].execution_result.stdout.trim(), "deploying 2.0");
+        assert!(logs.lock().unwrap().iter().any(|log| log.contains("输出变量 'version' 覆盖了已有的值: '1.0' -> '2.0'")));
+
+        // 通过输出文件传给下一次执行
+        let dir = tempfile::tempdir().unwrap();
+        let outputs_file = dir.path().join("outputs.yaml");
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&yaml(&build_pipeline))
+            .with_outputs_file(&outputs_file)
+            .build()
+            .unwrap();
+        executor.execute_pipeline("build").await.unwrap();
+        let executor = RemoteExecutor::builder()
+            .yaml_str(&yaml(&deploy_pipeline))
+            .variables(HashMap::from([("version".to_string(), "1.0".to_string())]))
+            .with_outputs_file(&outputs_file)
+            .build()
+            .unwrap();
+        let result = executor.execute_pipeline("deploy").await.unwrap();
+        assert_eq!(result.step_results[0].execution_result.stdout.trim(), "deploying 2.0");
+    }
+
+    #[tokio::test]
+    async fn test_output_progress_and_line_statistics() {
+        use std::sync::Mutex;
+        use crate::models::OutputType;
+
+        let chatty = script_file("for i in 1 2 3; do echo out$i; echo err$i >&2; sleep 0.1; done\necho done\n");
+        let report = script_file("echo took {{ __step_elapsed_ms }}\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "progress"
+    steps:
+      - name: "chatty"
+        script: "{}"
+        extract:
+          - name: "status"
+            source: "exit_code"
+      - name: "report"
+        script: "{}"
+"#, chatty.path().display(), report.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if event.step.name == "chatty" && matches!(event.output_type, OutputType::Stdout | OutputType::Stderr) {
+                sink.lock().unwrap().push((event.elapsed_ms, event.line_count));
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("progress", Some(callback), None).await.unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 7);
+        assert!(events.windows(2).all(|pair| pair[0].0 <= pair[1].0), "{:?}", events);
+        assert!(events.last().unwrap().0 >= 300, "{:?}", events);
+        let mut counts: Vec<u64> = events.iter().map(|(_, count)| *count).collect();
+        counts.sort();
+        assert_eq!(counts, (1..=7).collect::<Vec<u64>>());
+
+        let chatty = &result.step_results[0];
+        assert_eq!((chatty.stdout_line_count, chatty.stderr_line_count), (4, 3));
+        let expected_rate = 7.0 * 1000.0 / chatty.execution_result.execution_time_ms as f64;
+        assert!((chatty.output_rate_lps - expected_rate).abs() < 1e-9);
+        assert!(!chatty.extracted_variables.contains_key(STEP_ELAPSED_VARIABLE));
+        let took: u64 = result.step_results[1].execution_result.stdout.trim().strip_prefix("took ").unwrap().parse().unwrap();
+        assert!(took >= 300, "{}", took);
+    }
+
+    #[tokio::test]
+    async fn test_independent_steps_run_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let flag = dir.path().join("flag");
+        // wait 只有在 signal 并发执行时才能等到标记文件
+        let wait = script_file(&format!(
+            "for i in $(seq 50); do [ -f {0} ] && echo token=ready && exit 0; sleep 0.1; done; exit 1\n",
+            flag.display()
+        ));
+        let signal = script_file(&format!("touch {}\n", flag.display()));
+        let join = script_file("echo joined {{ token }}\n");
+        let broken = script_file("exit 1\n");
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "dag"
+    steps:
+      - name: "wait"
+        script: "{}"
+        depends_on: []
+        extract:
+          - name: "token"
+            patterns: ["token=(\\w+)"]
+            source: "stdout"
+      - name: "signal"
+        script: "{}"
+        depends_on: []
+      - name: "join"
+        script: "{}"
+        depends_on: ["wait", "signal"]
+  - name: "broken"
+    steps:
+      - name: "broken"
+        script: "{}"
+      - name: "after_broken"
+        script: "{}"
+"#, wait.path().display(), signal.path().display(), join.path().display(), broken.path().display(), join.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("dag").await.unwrap();
+        assert!(result.overall_success);
+        let names: Vec<&str> = result.step_results.iter().map(|r| r.step_name.as_str()).collect();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[2], "join");
+        assert_eq!(result.step_results[2].execution_result.stdout.trim(), "joined ready");
+
+        let result = executor.execute_pipeline("broken").await.unwrap();
+        assert!(!result.overall_success);
+        let statuses: Vec<_> = result.step_results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [StepStatus::Failed, StepStatus::NotRun]);
+    }
+
+    #[test]
+    fn test_dependency_cycle_rejected_at_load() {
+        let yaml = r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "cycle"
+    steps:
+      - name: "a"
+        script: "a.sh"
+        depends_on: ["b"]
+      - name: "b"
+        script: "b.sh"
+        depends_on: ["a"]
+"#;
+        let err = RemoteExecutor::from_yaml_str(yaml, None).err().unwrap();
+        assert!(err.to_string().contains("cycle"));
+    }
+
+    #[tokio::test]
+    async fn test_false_condition_skips_step() {
+        use std::sync::Mutex;
+
+        let detect = script_file("echo env=staging\n");
+        let run = script_file("echo ran\n");
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "conditional"
+    steps:
+      - name: "detect"
+        script: "{0}"
+        extract:
+          - name: "env"
+            patterns: ["env=(\\w+)"]
+            source: "stdout"
+      - name: "deploy"
+        script: "{1}"
+        condition: 'env == "production"'
+      - name: "stage"
+        script: "{1}"
+        condition: 'env == "staging" || deploy_count > 0'
+"#, detect.path().display(), run.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content));
+        let result = executor.execute_pipeline_with_realtime_output("conditional", None, Some(callback)).await.unwrap();
+
+        assert!(result.overall_success);
+        assert_eq!(result.step_results.len(), 3);
+        let deploy = &result.step_results[1];
+        assert!(deploy.skipped);
+        assert_eq!(deploy.exit_class, ExitClass::Skipped);
+        assert!(deploy.execution_result.stdout.is_empty());
+        let stage = &result.step_results[2];
+        assert!(!stage.skipped);
+        assert_eq!(stage.execution_result.stdout.trim(), "ran");
+        assert!(logs.lock().unwrap().iter().any(|c| c.starts_with("Step skipped: condition false")));
+
+        let invalid = yaml.replace(r#"'env == "production"'"#, r#"'env =='"#);
+        let err = RemoteExecutor::from_yaml_str(&invalid, None).err().unwrap();
+        assert!(err.to_string().contains("Invalid condition in step 'deploy'"));
+    }
+
+    #[tokio::test]
+    async fn test_global_scripts_prepend_helper_functions() {
+        let helpers = script_file("greet() { echo \"hello $1\"; }\n");
+        let script = script_file("greet world\n");
+        let pipelines = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "local"
+    steps:
+      - name: "greet"
+        script: "{}"
+"#, script.path().display());
+
+        let yaml = format!("global_scripts:\n  - \"{}\"\n{}", helpers.path().display(), pipelines);
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let result = executor.execute_pipeline("local").await.unwrap();
+        assert!(result.overall_success);
+        assert_eq!(result.step_results[0].execution_result.stdout.trim(), "hello world");
+
+        // 未配置 global_scripts 时脚本照常执行，辅助函数不可用
+        let executor = RemoteExecutor::from_yaml_str(&pipelines, None).unwrap();
+        assert!(executor.config.global_scripts.is_none());
+        let result = executor.execute_pipeline("local").await.unwrap();
+        assert!(!result.overall_success);
+    }
+
+    #[test]
+    fn test_render_step_script_matches_what_would_run() {
+        let helpers = script_file("greet() { echo \"hello $1\"; }\n");
+        let script = script_file("greet {{ target }} {{ step_name }} {{ extracted_later }}\n");
+        let yaml = format!(r#"
+global_scripts: ["{}"]
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "local"
+    steps:
+      - name: "greet"
+        script: "{}"
+        quote_variables: true
+        variables:
+          target: "big world"
+"#, helpers.path().display(), script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let rendered = executor.render_step_script("local", "greet").unwrap();
+        assert_eq!(rendered, "\ngreet() { echo \"hello $1\"; }\n\ngreet 'big world' greet {{ extracted_later }}\n");
+
+        let err = executor.render_step_script("local", "missing").unwrap_err().to_string();
+        assert_eq!(err, "Step 'missing' not found in pipeline 'local'");
+    }
+
+    #[tokio::test]
+    async fn test_strip_ansi_cleans_output_before_extraction() {
+        let colored = script_file("printf '\\033[32mversion: 1.2\\033[0m\\n'\n");
+        let echo = script_file("printf '\\033[1mextracted: {{ version }}\\033[0m\\n'\n");
+        let yaml = format!(r#"
+strip_ansi: true
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "colored"
+    steps:
+      - name: "clean"
+        script: "{0}"
+        extract:
+          - name: "version"
+            patterns: ["version: (\\S+)"]
+            source: "stdout"
+      - name: "raw"
+        script: "{1}"
+        strip_ansi: false
+"#, colored.path().display(), echo.path().display());
+
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let result = executor.execute_pipeline("colored").await.unwrap();
+        assert!(result.overall_success);
+        assert_eq!(result.step_results[0].execution_result.stdout, "version: 1.2\n");
+        // 步骤级 strip_ansi: false 覆盖全局配置，保留原始输出
+        assert_eq!(result.step_results[1].execution_result.stdout, "\x1b[1mextracted: 1.2\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_lint_reports_rules_shadowing_caller_variables() {
+        let yaml = r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "build"
+    steps:
+      - name: "version"
+        script: "build.sh"
+        extract:
+          - name: "release"
+            patterns: ["release: (\\S+)"]
+            source: "stdout"
+"#;
+        let executor = RemoteExecutor::from_yaml_str(yaml, None).unwrap();
+        assert!(executor.lint().unwrap().is_empty());
+
+        let variables = HashMap::from([("release".to_string(), "1.0".to_string())]);
+        let executor = RemoteExecutor::from_yaml_str(yaml, Some(variables)).unwrap();
+        let warnings = executor.lint().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Extract rule 'release' in step 'version' of pipeline 'build' overwrites an initial variable"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_controlled_pipeline_steps_and_injects_variables() {
+        let first = script_file("echo \"build: 7\"\n");
+        let second = script_file("echo \"{{ build }}-{{ target }}\"\n");
+        let third = script_file("echo done\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "debug"
+    steps:
+      - name: "first"
+        script: "{}"
+        extract:
+          - name: "build"
+            patterns: ["build: (\\d+)"]
+            source: "stdout"
+      - name: "second"
+        script: "{}"
+      - name: "third"
+        script: "{}"
+"#, first.path().display(), second.path().display(), third.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let (mut controller, handle) = executor.execute_pipeline_controlled("debug", None, None);
+        assert_eq!(controller.paused().await.as_deref(), Some("first"));
+        assert!(!controller.current_variables().contains_key("build"));
+        controller.next();
+
+        assert_eq!(controller.paused().await.as_deref(), Some("second"));
+        assert_eq!(controller.current_variables()["build"], "7");
+        controller.set_variable("target", "staging");
+        assert_eq!(controller.current_variables()["target"], "staging");
+        controller.next();
+
+        assert_eq!(controller.paused().await.as_deref(), Some("third"));
+        controller.run_to_end();
+        assert_eq!(controller.paused().await, None);
+
+        let result = handle.await.unwrap().unwrap();
+        assert!(result.overall_success);
+        assert_eq!(result.step_results.len(), 3);
+        assert_eq!(result.step_results[1].execution_result.stdout.trim(), "7-staging");
+        // 注入的变量只属于本次执行
+        assert!(executor.variable_manager.get_variable("target").is_none());
+
+        // abort 后剩余步骤不再执行
+        let (mut controller, handle) = executor.execute_pipeline_controlled("debug", None, None);
+        assert_eq!(controller.paused().await.as_deref(), Some("first"));
+        controller.next();
+        assert_eq!(controller.paused().await.as_deref(), Some("second"));
+        controller.abort();
+        let err = handle.await.unwrap().unwrap_err().to_string();
+        assert!(err.contains("aborted before step 'second'"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_false_records_server_errors() {
+        let script = script_file("echo hi\n");
+        let config = |fail_fast: &str| format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+  ws1:
+    name: "ws1"
+    execution_method: websocket
+pipelines:
+  - name: "fleet"
+    steps:
+      - name: "check"
+        script: "{}"
+        servers: ["web1", "ws1"]
+        {}
+"#, script.path().display(), fail_fast);
+
+        // 默认第一个出错的服务器中止整个步骤
+        let executor = RemoteExecutor::from_yaml_str(&config(""), None).unwrap();
+        assert!(executor.execute_pipeline("fleet").await.is_err());
+
+        let executor = RemoteExecutor::from_yaml_str(&config("fail_fast: false"), None).unwrap();
+        let result = executor.execute_pipeline("fleet").await.unwrap();
+        assert!(!result.overall_success);
+        let servers: Vec<_> = result.step_results.iter().map(|r| r.server_name.as_str()).collect();
+        assert_eq!(servers, ["web1", "ws1"]);
+        for step_result in &result.step_results {
+            assert!(!step_result.execution_result.success);
+            assert_eq!(step_result.exit_class, ExitClass::Failure);
+        }
+        let error = result.step_results[1].execution_result.error_message.as_deref().unwrap();
+        assert!(error.contains("WebSocket execution not implemented"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_events_carry_pipeline_and_step_positions() {
+        let script = script_file("echo hi\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "build"
+    steps:
+      - name: "compile"
+        script: "{0}"
+  - name: "release"
+    steps:
+      - name: "package"
+        script: "{0}"
+      - name: "upload"
+        script: "{0}"
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| {
+            sink.lock().unwrap().push((e.output_type, e.pipeline_name, e.step.name, e.pipeline_index, e.step_index, e.total_steps));
+        });
+        executor.execute_all_pipelines_with_realtime_output(Some(callback.clone()), Some(callback)).await.unwrap();
+
+        let events = events.lock().unwrap();
+        let started: Vec<_> = events.iter()
+            .filter(|e| e.0 == crate::models::OutputType::StepStarted)
+            .map(|e| (e.2.as_str(), e.3, e.4, e.5))
+            .collect();
+        assert_eq!(started, [("compile", 0, Some(0), 1), ("package", 1, Some(0), 2), ("upload", 1, Some(1), 2)]);
+        let stdout = events.iter().find(|e| e.0 == crate::models::OutputType::Stdout && e.2 == "upload").unwrap();
+        assert_eq!((stdout.3, stdout.4, stdout.5), (1, Some(1), 2));
+        // 流水线级事件没有步骤位置
+        let pipeline_start = events.iter().find(|e| e.1 == "release" && e.2.is_empty()).unwrap();
+        assert_eq!((pipeline_start.3, pipeline_start.4, pipeline_start.5), (1, None, 2));
+    }
+
+    fn progress_executor() -> (RemoteExecutor, tempfile::NamedTempFile) {
+        let script = script_file("for i in 1 2 3 4 5; do echo line$i; done\necho oops >&2\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "first"
+    steps:
+      - name: "print"
+        script: "{0}"
+        servers: []
+  - name: "second"
+    steps:
+      - name: "print"
+        script: "{0}"
+        servers: []
+"#, script.path().display());
+        (RemoteExecutor::from_yaml_str(&yaml, None).unwrap(), script)
+    }
+
+    /// 事件的类型与内容（去掉耗时），stdout 与 stderr 分别来自不同的读取任务，之间的先后顺序不固定，单独比较
+    fn event_summary(events: &[OutputEvent]) -> (Vec<String>, Vec<String>) {
+        let describe = |e: &OutputEvent| {
+            let content = e.content.split(" - ").next().unwrap_or_default();
+            format!("{:?} {} {}", e.output_type, e.pipeline_name, content)
+        };
+        let (stderr, rest): (Vec<_>, Vec<_>) = events.iter()
+            .partition(|e| e.output_type == crate::models::OutputType::Stderr);
+        (rest.into_iter().map(describe).collect(), stderr.into_iter().map(describe).collect())
+    }
+
+    async fn reference_events(executor: &RemoteExecutor, pipeline: Option<&str>) -> Vec<OutputEvent> {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback: OutputCallback = {
+            let events = events.clone();
+            Arc::new(move |event| events.lock().unwrap().push(event))
+        };
+        match pipeline {
+            Some(name) => executor.execute_pipeline_with_realtime_output(name, Some(callback.clone()), Some(callback)).await.map(|_| ()),
+            None => executor.execute_all_pipelines_with_realtime_output(Some(callback.clone()), Some(callback)).await.map(|_| ()),
+        }.unwrap();
+        let events = events.lock().unwrap().clone();
+        events
+    }
+
+    async fn receive_slowly(mut receiver: mpsc::Receiver<OutputEvent>) -> Vec<OutputEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_progress_channel_matches_callback_events() {
+        let (executor, _script) = progress_executor();
+        let expected = reference_events(&executor, Some("first")).await;
+
+        let (run, receiver) = executor.execute_pipeline_with_progress_channel("first", 1);
+        let (result, events) = tokio::join!(run, receive_slowly(receiver));
+        assert!(result.unwrap().overall_success);
+        assert_eq!(event_summary(&events), event_summary(&expected));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_progress_channel_applies_back_pressure_on_multi_thread_runtime() {
+        let (executor, _script) = progress_executor();
+        let expected = reference_events(&executor, None).await;
+
+        let (run, receiver) = executor.execute_all_pipelines_with_progress_channel(1);
+        let (result, events) = tokio::join!(tokio::spawn(run), receive_slowly(receiver));
+        assert!(result.unwrap().unwrap().success);
+        assert_eq!(event_summary(&events), event_summary(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_log_dir_keeps_step_output_per_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = script_file("echo built\necho careful >&2\n");
+        let yaml = format!(r#"
+log_dir: "{}"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "build"
+        script: "{}"
+"#, dir.path().display(), script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("deploy").await.unwrap();
+        assert!(result.overall_success);
+        let log = std::fs::read_to_string(dir.path().join("deploy/build/localhost.log")).unwrap();
+        let mut lines: Vec<&str> = log.lines().collect();
+        lines.sort();
+        assert_eq!(lines, ["built", "careful"]);
+    }
+
+    #[tokio::test]
+    async fn test_steps_after_failure_are_reported_as_not_run() {
+        let ok = script_file("echo ok\n");
+        let broken = script_file("exit 1\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "build"
+        script: "{0}"
+      - name: "migrate"
+        script: "{1}"
+      - name: "release"
+        script: "{0}"
+"#, ok.path().display(), broken.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let completed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = completed.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if event.output_type == crate::models::OutputType::StepCompleted {
+                sink.lock().unwrap().push((event.step.name.clone(), event.content.clone()));
+            }
+        });
+        let result = executor.execute_pipeline_with_realtime_output("deploy", Some(callback), None).await.unwrap();
+        assert!(!result.overall_success);
+        let statuses: Vec<_> = result.step_results.iter().map(|r| (r.step_name.as_str(), r.status)).collect();
+        assert_eq!(statuses, [
+            ("build", StepStatus::Succeeded),
+            ("migrate", StepStatus::Failed),
+            ("release", StepStatus::NotRun),
+        ]);
+        let not_run = &result.step_results[2];
+        assert!(!not_run.overall_success);
+        assert_eq!(not_run.execution_time_ms, 0);
+        assert_eq!(not_run.execution_result.error_message.as_deref(), Some(NOT_RUN_REASON));
+        assert!(completed.lock().unwrap().contains(&("release".to_string(), NOT_RUN_REASON.to_string())));
+    }
+
+    /// (span 名称, 父 span 名称, 字段)
+    type RecordedSpan = (String, Option<String>, HashMap<String, String>);
+
+    /// 记录 span 的名称、父 span 名称和字段，以及事件的 target 与所在 span 的测试 Layer
+    #[derive(Clone, Default)]
+    struct SpanRecorder {
+        spans: Arc<std::sync::Mutex<Vec<RecordedSpan>>>,
+        events: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for SpanRecorder
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            let parent = ctx.span(id).and_then(|span| span.parent()).map(|parent| parent.name().to_string());
+            self.spans.lock().unwrap().push((attrs.metadata().name().to_string(), parent, fields));
+        }
+
+        fn on_record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let name = ctx.span(id).map(|span| span.name().to_string());
+            let mut spans = self.spans.lock().unwrap();
+            if let Some((_, _, fields)) = spans.iter_mut().rev().find(|(n, _, _)| Some(n) == name.as_ref()) {
+                values.record(&mut FieldVisitor(fields));
+            }
+        }
+
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let span = ctx.event_span(event).map(|span| span.name().to_string()).unwrap_or_default();
+            self.events.lock().unwrap().push((event.metadata().target().to_string(), span));
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tracing_spans_nest_run_pipeline_step_server() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let script = script_file("echo traced\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "build"
+        script: "{}"
+"#, script.path().display());
+        let executor = RemoteExecutorBuilder::new().yaml_str(&yaml).with_event_tracing(true).build().unwrap();
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+        let result = executor.execute_pipeline_with_realtime_output("deploy", None, None).await.unwrap();
+        assert!(result.overall_success);
+
+        let spans = recorder.spans.lock().unwrap();
+        let span = |name: &str| spans.iter().find(|(n, _, _)| n == name).unwrap_or_else(|| panic!("no {} span", name));
+        let (_, run_parent, run) = span("run");
+        assert!(run_parent.is_none());
+        let (_, parent, pipeline) = span("pipeline");
+        assert_eq!((parent.as_deref(), pipeline["pipeline"].as_str()), (Some("run"), "deploy"));
+        assert_eq!(pipeline["run_id"], run["run_id"]);
+        let (_, parent, step) = span("step");
+        assert_eq!((parent.as_deref(), step["step"].as_str(), step["index"].as_str()), (Some("pipeline"), "build", "Some(0)"));
+        assert!(step["duration_ms"].parse::<u64>().is_ok(), "{:?}", step);
+        let (_, parent, server) = span("server");
+        assert_eq!((parent.as_deref(), server["server"].as_str(), server["host"].as_str()), (Some("step"), "localhost", "localhost"));
+
+        // 开启 with_event_tracing 后事件在服务器的 span 中记录为 tracing 事件
+        let events = recorder.events.lock().unwrap();
+        assert!(events.iter().any(|(target, span)| target == "net_shell::events" && span == "server"), "{:?}", events);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_timeout_stops_scheduling_steps() {
+        let marker = tempfile::tempdir().unwrap();
+        let first = script_file("sleep 2.5\necho first\n");
+        let second = script_file(&format!("touch {}/second\nsleep 2\n", marker.path().display()));
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    timeout_seconds: 3
+    steps:
+      - name: "first"
+        script: "{}"
+      - name: "second"
+        script: "{}"
+"#, first.path().display(), second.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("deploy").await.unwrap();
+        assert!(!result.overall_success);
+        assert_eq!(result.failure_reason.as_deref(), Some("pipeline timeout exceeded"));
+        let statuses: Vec<_> = result.step_results.iter().map(|r| (r.step_name.as_str(), r.status)).collect();
+        assert_eq!(statuses, [("first", StepStatus::Succeeded), ("second", StepStatus::NotRun)]);
+        assert!(!marker.path().join("second").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_returns_partial_results() {
+        let marker = tempfile::tempdir().unwrap();
+        let quick = script_file("echo quick\n");
+        let slow = script_file(&format!("echo $$ > {}/slow.pid\nsleep 30\n", marker.path().display()));
+        let never = script_file(&format!("touch {}/never\n", marker.path().display()));
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "deploy"
+    steps:
+      - name: "quick"
+        script: "{}"
+      - name: "slow"
+        script: "{}"
+      - name: "never"
+        script: "{}"
+  - name: "later"
+    steps:
+      - name: "never"
+        script: "{}"
+"#, quick.path().display(), slow.path().display(), never.path().display(), never.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let cancel = CancellationHandle::new();
+        let timer = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            timer.cancel();
+        });
+        let start = std::time::Instant::now();
+        let result = executor.execute_all_pipelines_with_cancellation(&cancel, None, None).await.unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        assert_eq!(result.outcome, ExecutionOutcome::Interrupted);
+        assert!(!result.success);
+        assert_eq!(result.reason, "interrupted");
+        assert_eq!(result.pipeline_results.len(), 1);
+        let pipeline = &result.pipeline_results[0];
+        assert!(!pipeline.overall_success);
+        let statuses: Vec<_> = pipeline.step_results.iter().map(|r| (r.step_name.as_str(), r.status)).collect();
+        assert_eq!(statuses, [("quick", StepStatus::Succeeded), ("slow", StepStatus::NotRun), ("never", StepStatus::NotRun)]);
+        assert!(pipeline.step_results[0].execution_result.success);
+        assert!(!marker.path().join("never").exists());
+
+        // 中止的步骤的本地进程已被结束
+        let pid = std::fs::read_to_string(marker.path().join("slow.pid")).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let alive = std::process::Command::new("kill").args(["-0", pid.trim()])
+            .stderr(std::process::Stdio::null()).status().unwrap().success();
+        assert!(!alive, "slow step process {} still running", pid.trim());
+    }
+
+    #[tokio::test]
+    async fn test_default_timeout_applies_when_step_sets_none() {
+        let script = script_file("sleep 1.5\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+default_timeout: 1
+pipelines:
+  - name: "inherits"
+    steps:
+      - name: "slow"
+        script: "{0}"
+        servers: []
+  - name: "overrides"
+    steps:
+      - name: "slow"
+        script: "{0}"
+        servers: []
+        timeout_seconds: 5
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("inherits").await.unwrap();
+        let execution = &result.step_results[0].execution_result;
+        assert!(!result.overall_success);
+        assert_eq!(execution.error_message.as_deref(), Some("Timed out after 1s"));
+        assert!(executor.execute_pipeline("overrides").await.unwrap().overall_success);
+    }
+
+    #[tokio::test]
+    async fn test_local_step_reports_command_time_without_connect_time() {
+        let script = script_file("sleep 0.2\necho done\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "timed"
+    steps:
+      - name: "sleepy"
+        script: "{}"
+        servers: []
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("timed").await.unwrap();
+        let execution = &result.step_results[0].execution_result;
+        assert!(result.overall_success);
+        // 本地执行没有连接阶段，耗时全部计入命令执行
+        assert_eq!(execution.connect_time_ms, 0);
+        assert!(execution.command_time_ms >= 200, "{:?}", execution);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_runs_are_separable_by_run_id() {
+        let (executor, _script) = progress_executor();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        {
+            let events = events.clone();
+            executor.subscribe(EventFilter::all(), Arc::new(move |event: OutputEvent| {
+                events.lock().unwrap().push((event.run_id, event.output_type, event.content));
+            }));
+        }
+
+        let (a, b) = tokio::join!(
+            executor.execute_pipeline("first"),
+            executor.execute_pipeline_with_run_id("first", "deploy-42", None, None),
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(b.run_id, "deploy-42");
+        assert_ne!(a.run_id, b.run_id);
+        assert!(a.step_results.iter().all(|r| r.run_id == a.run_id));
+
+        let events = events.lock().unwrap();
+        for run_id in [&a.run_id, &b.run_id] {
+            let stdout: Vec<_> = events.iter()
+                .filter(|(id, output_type, _)| id == run_id && *output_type == crate::models::OutputType::Stdout)
+                .map(|(_, _, content)| content.as_str())
+                .collect();
+            assert_eq!(stdout, ["line1", "line2", "line3", "line4", "line5"]);
+        }
+        assert!(events.iter().all(|(id, _, _)| id == &a.run_id || id == &b.run_id));
+    }
+
+    #[tokio::test]
+    async fn test_quoted_variables_are_echoed_verbatim() {
+        let values = [
+            ("single", "don't stop"),
+            ("double", "say \"hi\""),
+            ("newline", "line one\nline two"),
+            ("backtick", "`touch /tmp/net-shell-pwned`"),
+            ("subshell", "$(echo pwned); rm -rf /nonexistent"),
+        ];
+        let filtered: String = values.iter()
+            .map(|(name, _)| format!("printf '%s|' {{{{ {} | sh_quote }}}}\n", name))
+            .collect();
+        let plain: String = values.iter()
+            .map(|(name, _)| format!("printf '%s|' {{{{ {} }}}}\n", name))
+            .collect();
+        let (filtered, plain) = (script_file(&filtered), script_file(&plain));
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "quoting"
+    steps:
+      - name: "filter"
+        script: "{}"
+      - name: "step_option"
+        script: "{}"
+        quote_variables: true
+"#, filtered.path().display(), plain.path().display());
+        let variables = values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let executor = RemoteExecutor::from_yaml_str(&yaml, Some(variables)).unwrap();
+
+        let result = executor.execute_pipeline("quoting").await.unwrap();
+        assert!(result.overall_success);
+        let expected: String = values.iter().map(|(_, v)| format!("{}|", v)).collect();
+        // 本地执行按行读取输出，最后一行补上换行符
+        assert_eq!(result.step_results[0].execution_result.stdout, format!("{}\n", expected));
+        assert_eq!(result.step_results[1].execution_result.stdout, format!("{}\n", expected));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_output_concat() {
+        let scripts: Vec<_> = ["echo alpha\n", "echo beta\n", "echo gamma\n"].iter().map(|b| script_file(b)).collect();
+        let report = script_file("echo \"all: {{ outputs }}\"\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "aggregate"
+    steps:
+      - name: "collect"
+        script: "{}"
+        local_scripts: ["{}", "{}"]
+        aggregate_output:
+          variable_name: "outputs"
+          strategy: !concat ", "
+      - name: "report"
+        script: "{}"
+"#, scripts[0].path().display(), scripts[1].path().display(), scripts[2].path().display(), report.path().display());
+
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let result = executor.execute_pipeline("aggregate").await.unwrap();
+        assert!(result.overall_success);
+        assert_eq!(result.step_results[3].execution_result.stdout.trim(), "all: alpha, beta, gamma");
+    }
+
+    #[test]
+    fn test_aggregate_strategies() {
+        let result = |server: &str, stdout: &str, exit_code: i32| StepExecutionResult {
+            title: "check".to_string(),
+            step_name: "check".to_string(),
+            server_name: server.to_string(),
+            execution_result: ExecutionResult {
+                success: exit_code == 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+                script: "check.sh".to_string(),
+                exit_code,
+                execution_time_ms: 0,
+                error_message: None,
+                had_invalid_utf8: false,
+                artifacts: Vec::new(),
+                connect_time_ms: 0,
+                command_time_ms: 0,
+                fallback_reason: None,
+                rendered_script: None,
+                script_sha256: None,
+                connection_info: None,
+                answered_prompts: Vec::new(),
+                ssh_trace: None,
+                skip_reason: None,
+            },
+            overall_success: exit_code == 0,
+            status: StepStatus::of(exit_code == 0, ExitClass::Success),
+            scritp_path: "check.sh".to_string(),
+            execution_time_ms: 0,
+            exit_code,
+            exit_class: ExitClass::Success,
+            attempts: 1,
+            skipped: false,
+            artifacts: Vec::new(),
+            run_id: String::new(),
+            extracted_variables: HashMap::new(),
+            variable_diff: None,
+            executed_locally: false,
+            stdout_line_count: 1,
+            stderr_line_count: 0,
+            output_rate_lps: 0.0,
+            ssh_trace: None,
+            failover_used: false,
+            primary_server: server.to_string(),
+        };
+        let results = [result("s1", "down\n", 1), result("s2", "up\n", 0), result("s3", "up\n", 0)];
+
+        assert_eq!(aggregate_output(&AggregateStrategy::CountSuccess, &results), "2");
+        assert_eq!(aggregate_output(&AggregateStrategy::CountFailure, &results), "1");
+        assert_eq!(aggregate_output(&AggregateStrategy::FirstSuccess, &results[..1]), "");
+        assert_eq!(aggregate_output(&AggregateStrategy::FirstSuccess, &results), "up");
+        assert_eq!(aggregate_output(&AggregateStrategy::Concat("|".to_string()), &results), "down|up|up");
+        let json: serde_json::Value = serde_json::from_str(&aggregate_output(&AggregateStrategy::Json, &results)).unwrap();
+        assert_eq!(json[0], serde_json::json!({"server": "s1", "stdout": "down", "exit_code": 1}));
+        assert_eq!(json.as_array().unwrap().len(), 3);
+
+        let strategy: AggregateStrategy = serde_yaml::from_str("count_success").unwrap();
+        assert_eq!(strategy, AggregateStrategy::CountSuccess);
+    }
+
+    #[tokio::test]
+    async fn test_local_step_collects_artifacts() {
+        let work = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let script = script_file(&format!("echo diag > {}/diag-1.tar.gz\n", work.path().display()));
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "collect"
+    steps:
+      - name: "diag"
+        script: "{0}"
+        artifacts:
+          - remote_path: "{1}/diag-*.tar.gz"
+            local_dir: "{2}"
+            required: true
+  - name: "missing"
+    steps:
+      - name: "diag"
+        script: "{0}"
+        artifacts:
+          - remote_path: "{1}/core-*"
+            local_dir: "{2}"
+            required: true
+"#, script.path().display(), work.path().display(), out.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("collect").await.unwrap();
+        assert!(result.overall_success);
+        let artifacts = &result.step_results[0].artifacts;
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].size, 5);
+        assert!(out.path().join("localhost").join("diag-1.tar.gz").exists());
+
+        let result = executor.execute_pipeline("missing").await.unwrap();
+        assert!(!result.overall_success);
+        let error = result.step_results[0].execution_result.error_message.clone().unwrap();
+        assert!(error.contains("no matching files"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_pipelines_selected_by_tag() {
+        let script = script_file("echo ok\n");
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "build"
+    description: "Build the app"
+    tags: ["ci", "build"]
+    steps:
+      - name: "compile"
+        description: "Compile sources"
+        script: "{0}"
+  - name: "deploy"
+    tags: ["cd"]
+    steps:
+      - name: "ship"
+        script: "{0}"
+  - name: "test"
+    tags: ["ci"]
+    steps:
+      - name: "unit"
+        script: "{0}"
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let names = |tag: &str| -> Vec<String> {
+            executor.get_pipelines_by_tag(tag).iter().map(|p| p.name.clone()).collect()
+        };
+        assert_eq!(names("ci"), vec!["build", "test"]);
+        assert_eq!(names("cd"), vec!["deploy"]);
+        assert!(names("missing").is_empty());
+
+        let result = executor.execute_pipelines_by_tag("ci", None, None).await.unwrap();
+        let ran: Vec<&str> = result.pipeline_results.iter().map(|r| r.pipeline_name.as_str()).collect();
+        assert_eq!(ran, vec!["build", "test"]);
+        assert_eq!(result.pipeline_results[0].description.as_deref(), Some("Build the app"));
+        assert_eq!(result.pipeline_results[0].tags, vec!["ci", "build"]);
+        assert!(result.pipeline_results[1].description.is_none());
+
+        assert!(executor.execute_pipelines_by_tag("missing", None, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_pipelines_runs_named_pipelines_in_given_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("ran.log");
+        let script = |name: &str| script_file(&format!("echo {} >> {}\necho name={}\n", name, log.display(), name));
+        let (build, test, deploy) = (script("build"), script("test"), script("deploy"));
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "build"
+    steps:
+      - name: "compile"
+        script: "{}"
+  - name: "test"
+    steps:
+      - name: "unit"
+        script: "{}"
+        extract:
+          - name: "first"
+            patterns: ["name=(\\w+)"]
+            source: "stdout"
+  - name: "deploy"
+    steps:
+      - name: "ship"
+        script: "{}"
+"#, build.path().display(), test.path().display(), deploy.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let names = |list: &[&str]| -> Vec<String> { list.iter().map(|s| s.to_string()).collect() };
+        let ran = || std::fs::read_to_string(&log).unwrap_or_default();
+
+        // 按给出的顺序执行，未列出的流水线不执行
+        let result = executor.execute_pipelines(&names(&["test", "build"]), None, None).await.unwrap();
+        assert!(result.success);
+        let pipelines: Vec<&str> = result.pipeline_results.iter().map(|r| r.pipeline_name.as_str()).collect();
+        assert_eq!(pipelines, ["test", "build"]);
+        assert_eq!(ran(), "test\nbuild\n");
+        assert_eq!(result.all_extracted_variables()["first"], "test");
+
+        // 有不存在的名称时不执行任何流水线，错误中列出可用的流水线
+        std::fs::remove_file(&log).unwrap();
+        let err = executor.execute_pipelines(&names(&["deploy", "lint", "docs"]), None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "Unknown pipeline(s) lint, docs; available pipelines: build, test, deploy");
+        assert_eq!(ran(), "");
+
+        // 空列表不执行任何流水线，同样返回错误
+        let err = executor.execute_pipelines(&[], None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "No pipelines given; available pipelines: build, test, deploy");
+        assert_eq!(ran(), "");
+    }
+
+    #[tokio::test]
+    async fn test_local_scripts_run_concurrently() {
+        use std::sync::Mutex;
+        use crate::models::OutputType;
+
+        let dir = tempfile::tempdir().unwrap();
+        let flag = dir.path().join("flag");
+        let wait = script_file(&format!(
+            "for i in $(seq 20); do [ -f {0} ] && echo waited && exit 0; sleep 0.1; done; exit 1\n",
+            flag.display()
+        ));
+        let signal = script_file(&format!("touch {}\necho signalled\n", flag.display()));
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "parallel"
+    steps:
+      - name: "local_batch"
+        script: "{0}"
+        local_scripts: ["{1}"]
+        local_parallelism: 2
+  - name: "serial"
+    steps:
+      - name: "local_batch"
+        script: "{0}"
+        local_scripts: ["{1}"]
+        local_parallelism: 1
+"#, wait.path().display(), signal.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        executor.subscribe(
+            EventFilter::output_types([OutputType::Stdout]),
+            Arc::new(move |e: OutputEvent| sink.lock().unwrap().push((e.script_path, e.content))),
+        );
+
+        let result = executor.execute_pipeline("parallel").await.unwrap();
+        assert!(result.overall_success);
+        let paths: Vec<&str> = result.step_results.iter().map(|r| r.scritp_path.as_str()).collect();
+        assert_eq!(paths, vec![wait.path().to_str().unwrap(), signal.path().to_str().unwrap()]);
+        let mut events = events.lock().unwrap().clone();
+        events.sort();
+        let mut expected = vec![
+            (wait.path().display().to_string(), "waited".to_string()),
+            (signal.path().display().to_string(), "signalled".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(events, expected);
+
+        // 并发数为1时 wait 先执行，等不到标记文件
+        std::fs::remove_file(&flag).unwrap();
+        let result = executor.execute_pipeline("serial").await.unwrap();
+        assert!(!result.overall_success);
+    }
+
+    #[tokio::test]
+    async fn test_local_scripts_extractions_are_merged() {
+        let version = script_file("echo version=1.0\n");
+        let build = script_file("echo build=42\n");
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "extract"
+    steps:
+      - name: "local_batch"
+        script: "{}"
+        local_scripts: ["{}"]
+        local_parallelism: 2
+        extract:
+          - name: "version"
+            patterns: ["version=(\\S+)"]
+            source: "stdout"
+          - name: "build"
+            patterns: ["build=(\\S+)"]
+            source: "stdout"
+"#, version.path().display(), build.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let result = executor.execute_pipeline("extract").await.unwrap();
+        assert!(result.overall_success);
+        let expected = HashMap::from([
+            ("version".to_string(), "1.0".to_string()),
+            ("build".to_string(), "42".to_string()),
+        ]);
+        assert_eq!(result.step_results[0].extracted_variables, expected);
+    }
+
+    #[tokio::test]
+    async fn test_max_parallel_limits_concurrent_servers() {
+        let dir = tempfile::tempdir().unwrap();
+        let running = dir.path().join("running");
+        std::fs::create_dir(&running).unwrap();
+        // 记录开始时正在执行的服务器数
+        let script = script_file(&format!(
+            "touch {0}/$$\nls {0} | wc -l >> {1}\nsleep 0.3\nrm {0}/$$\n",
+            running.display(), dir.path().join("observed").display()
+        ));
+        let clients: String = (1..=4)
+            .map(|i| format!("  local{0}:\n    name: \"local{0}\"\n    execution_method: local\n", i))
+            .collect();
+        let yaml = format!(r#"
+clients:
+{}
+pipelines:
+  - name: "rollout"
+    steps:
+      - name: "restart"
+        script: "{}"
+        servers: ["local1", "local2", "local3", "local4"]
+        max_parallel: 2
+"#, clients, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let start = std::time::Instant::now();
+        let result = executor.execute_pipeline("rollout").await.unwrap();
+        assert!(result.overall_success);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(600));
+        let servers: Vec<&str> = result.step_results.iter().map(|r| r.server_name.as_str()).collect();
+        assert_eq!(servers, ["local1", "local2", "local3", "local4"]);
+        let observed = std::fs::read_to_string(dir.path().join("observed")).unwrap();
+        assert!(observed.lines().all(|n| n.trim().parse::<usize>().unwrap() <= 2), "{}", observed);
+    }
+
+    #[cfg(feature = "ssh")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_blocking_ssh_work_is_bounded() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // 模拟的阻塞SSH会话：记录同时占用的线程数
+        let workers = Arc::new(Semaphore::new(8));
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let sessions = (0..100).map(|i| {
+            let (workers, active, peak) = (workers.clone(), active.clone(), peak.clone());
+            tokio::spawn(async move {
+                run_blocking_ssh(&workers, move || {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    i
+                }).await
+            })
+        });
+        let results = tokio::time::timeout(std::time::Duration::from_secs(30), futures::future::join_all(sessions))
+            .await
+            .expect("blocking SSH work deadlocked");
+        let mut finished: Vec<usize> = results.into_iter().map(|r| r.unwrap().unwrap()).collect();
+        finished.sort();
+        assert_eq!(finished, (0..100).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= 8);
+        assert_eq!(workers.available_permits(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_pipelines_run_concurrently_from_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let flag = dir.path().join("flag");
+        let wait = script_file(&format!(
+            "for i in $(seq 50); do [ -f {0} ] && echo result=waited && exit 0; sleep 0.1; done; exit 1\n",
+            flag.display()
+        ));
+        let signal = script_file(&format!("touch {}\necho result=signalled\n", flag.display()));
+        let yaml = format!(r#"
+global_scripts: []
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "wait"
+    steps:
+      - name: "wait"
+        script: "{}"
+        extract:
+          - name: "result"
+            patterns: ["result=(\\w+)"]
+            source: "stdout"
+  - name: "signal"
+    steps:
+      - name: "signal"
+        script: "{}"
+        extract:
+          - name: "result"
+            patterns: ["result=(\\w+)"]
+            source: "stdout"
+"#, wait.path().display(), signal.path().display());
+        let executor = Arc::new(RemoteExecutor::from_yaml_str(&yaml, None).unwrap());
+
+        // wait 只有在 signal 同时执行时才能成功
+        let waiting = {
+            let executor = executor.clone();
+            tokio::spawn(async move { executor.execute_pipeline("wait").await })
+        };
+        let signalling = executor.execute_pipeline_detached("signal", None, None);
+
+        let waited = waiting.await.unwrap().unwrap();
+        let signalled = signalling.await.unwrap().unwrap();
+        assert!(waited.overall_success);
+        assert!(signalled.overall_success);
+
+        // 每次执行使用独立的变量上下文，提取的变量不写回执行器
+        assert!(executor.variable_manager.get_variable("result").is_none());
+    }
+}
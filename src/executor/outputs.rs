@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::warn;
+
+use crate::models::{OutputCallback, OutputEvent, OutputType, Pipeline, Step};
+use crate::vars::VariableManager;
+use super::RemoteExecutor;
+
+/// 读取输出文件（YAML 或 JSON，JSON 也按 YAML 解析），文件不存在时返回空
+pub fn load_outputs(path: &Path) -> Result<HashMap<String, String>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read outputs file '{}'", path.display()))?;
+    if content.trim().is_empty() {
+        return Ok(HashMap::new());
+    }
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse outputs file '{}'", path.display()))
+}
+
+/// 把输出合并进输出文件，扩展名为 .json 时写 JSON，否则写 YAML；按变量名排序
+pub fn save_outputs(path: &Path, outputs: &HashMap<String, String>) -> Result<()> {
+    let mut all: BTreeMap<String, String> = load_outputs(path)?.into_iter().collect();
+    all.extend(outputs.iter().map(|(k, v)| (k.clone(), v.clone())));
+    let content = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_string_pretty(&all)?
+    } else {
+        serde_yaml::to_string(&all)?
+    };
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write outputs file '{}'", path.display()))
+}
+
+/// 把输出文件中的值合并进初始变量，同名变量使用输出文件中的值（较新）并记录警告
+pub(crate) fn merge_outputs(variables: &mut HashMap<String, String>, outputs: HashMap<String, String>) {
+    for (name, value) in outputs {
+        if let Some(old) = variables.get(&name).filter(|old| **old != value) {
+            warn!("Output '{}' from the outputs file replaces the value '{}' with '{}'", name, old, value);
+        }
+        variables.insert(name, value);
+    }
+}
+
+impl RemoteExecutor {
+    /// 流水线结束时按 outputs 收集导出的变量；变量不存在时跳过
+    /// 导出的值改写了流水线开始前已有的不同值时发送警告日志事件，后续流水线使用新值。
+    /// 配置了输出文件时把导出的变量写入文件
+    pub(super) fn export_outputs(
+        &self,
+        pipeline: &Pipeline,
+        before: &HashMap<String, String>,
+        events: Option<&OutputCallback>,
+        variables: &VariableManager,
+    ) -> HashMap<String, String> {
+        let mut outputs = HashMap::new();
+        for name in pipeline.outputs.iter().flatten() {
+            let Some(value) = variables.get_variable(name) else {
+                warn!("Output '{}' of pipeline '{}' is not set", name, pipeline.name);
+                continue;
+            };
+            if let Some(old) = before.get(name).filter(|old| *old != value) {
+                self.output_warning(pipeline, events, variables, format!(
+                    "输出变量 '{}' 覆盖了已有的值: '{}' -> '{}'", name, old, value
+                ));
+            }
+            outputs.insert(name.clone(), value.clone());
+        }
+
+        if let Some(path) = &self.options.outputs_file {
+            if !outputs.is_empty() {
+                if let Err(e) = save_outputs(path, &outputs) {
+                    warn!("{:#}", e);
+                    self.output_warning(pipeline, events, variables, format!("保存输出变量失败: {:#}", e));
+                }
+            }
+        }
+        outputs
+    }
+
+    fn output_warning(&self, pipeline: &Pipeline, events: Option<&OutputCallback>, variables: &VariableManager, content: String) {
+        if let Some(callback) = events {
+            callback(OutputEvent::new(
+                pipeline.name.clone(),
+                "system",
+                Step::default(),
+                OutputType::Log,
+                content,
+                variables.snapshot(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outputs_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["outputs.yaml", "outputs.json"] {
+            let path = dir.path().join(file);
+            assert!(load_outputs(&path).unwrap().is_empty());
+            save_outputs(&path, &HashMap::from([("version".to_string(), "1.2.3".to_string())])).unwrap();
+            save_outputs(&path, &HashMap::from([("image".to_string(), "app:1.2.3".to_string())])).unwrap();
+            let outputs = load_outputs(&path).unwrap();
+            assert_eq!(outputs.len(), 2);
+            assert_eq!(outputs["version"], "1.2.3");
+            assert_eq!(outputs["image"], "app:1.2.3");
+        }
+        let json = std::fs::read_to_string(dir.path().join("outputs.json")).unwrap();
+        assert!(json.trim_start().starts_with('{'), "{}", json);
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::models::{ExecutionMethod, Pipeline};
+use crate::vars::{placeholder_regex, split_placeholder, VariableManager};
+use super::dag::StepGraph;
+use super::RemoteExecutor;
+
+/// 执行计划中每个步骤显示的脚本行数
+const PLAN_SCRIPT_LINES: usize = 3;
+
+impl RemoteExecutor {
+    /// 以文本图的形式输出执行计划，不执行任何脚本，也不建立任何网络连接
+    /// 依次列出 before_all、各流水线与 after_all；流水线内的步骤按依赖顺序列出，包括脚本的前3行、
+    /// 解析后的服务器（host:port 与用户）以及脚本引用的变量的当前值。条件按当前变量求值，为假的步骤标记为跳过
+    pub fn print_execution_plan(&self, writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer, "=== 执行计划 ===")?;
+        let pipelines = self.config.before_all_pipeline().into_iter()
+            .chain(self.config.pipelines.iter().cloned())
+            .chain(self.config.after_all_pipeline());
+        for pipeline in pipelines {
+            self.print_pipeline_plan(&pipeline, writer)?;
+        }
+        Ok(())
+    }
+
+    fn print_pipeline_plan(&self, pipeline: &Pipeline, writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer)?;
+        writeln!(writer, "[pipeline] {}", pipeline.name)?;
+        if let Some(title) = &pipeline.title {
+            writeln!(writer, "|  title: {}", title)?;
+        }
+        if let Some(description) = &pipeline.description {
+            writeln!(writer, "|  description: {}", description)?;
+        }
+        let graph = StepGraph::new(pipeline)?;
+        for index in dependency_order(&graph, pipeline.steps.len()) {
+            writeln!(writer, "|")?;
+            self.print_step_plan(pipeline, &graph, index, writer)?;
+        }
+        writeln!(writer, "+-- end")?;
+        Ok(())
+    }
+
+    fn print_step_plan(&self, pipeline: &Pipeline, graph: &StepGraph, index: usize, writer: &mut dyn Write) -> Result<()> {
+        let step = &self.with_config_defaults(&pipeline.steps[index]);
+        let mut variables = self.variable_manager.clone();
+        for (k, v) in step.variables.iter().flatten() {
+            variables.set_variable(k.clone(), v.clone());
+        }
+
+        // 条件中的变量可能由前面的步骤提取，此时只能在执行时求值
+        let condition = step.condition.as_ref().map(|condition| match variables.evaluate_condition(condition) {
+            Ok(true) => format!("runs, condition true: {}", condition),
+            Ok(false) => format!("SKIP, condition false: {}", condition),
+            Err(_) => format!("condition checked at run time: {}", condition),
+        });
+        match &condition {
+            Some(condition) => writeln!(writer, "+-- [{}] {}  ({})", index + 1, step.name, condition)?,
+            None => writeln!(writer, "+-- [{}] {}", index + 1, step.name)?,
+        }
+
+        let dependencies: Vec<&str> = graph.dependencies(index).iter().map(|d| pipeline.steps[*d].name.as_str()).collect();
+        if !dependencies.is_empty() {
+            writeln!(writer, "|     after: {}", dependencies.join(", "))?;
+        }
+
+        let script_path = variables.replace_variables(&step.script);
+        writeln!(writer, "|     script: {}", script_path)?;
+        let content = std::fs::read_to_string(&script_path).ok();
+        match &content {
+            Some(content) => {
+                for line in content.lines().take(PLAN_SCRIPT_LINES) {
+                    writeln!(writer, "|       | {}", line)?;
+                }
+                if content.lines().count() > PLAN_SCRIPT_LINES {
+                    writeln!(writer, "|       | ...")?;
+                }
+            }
+            None => writeln!(writer, "|       (script file not found)")?,
+        }
+
+        if step.servers.is_empty() && step.server_selector.is_none() {
+            writeln!(writer, "|     servers: local")?;
+        } else {
+            match self.resolve_step_servers(step, &variables) {
+                Ok(servers) => {
+                    writeln!(writer, "|     servers:")?;
+                    for server in servers {
+                        writeln!(writer, "|       - {}", self.server_address(&server))?;
+                    }
+                }
+                Err(e) => writeln!(writer, "|     servers: {}", e)?,
+            }
+        }
+
+        let referenced = referenced_variables(&variables, content.as_deref().unwrap_or_default());
+        if !referenced.is_empty() {
+            writeln!(writer, "|     variables:")?;
+            for (name, value) in referenced {
+                match value {
+                    Some(value) => writeln!(writer, "|       {} = {}", name, self.mask_secrets(&value))?,
+                    None => writeln!(writer, "|       {} (set at run time)", name)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 服务器的连接地址：SSH客户端为 名称 (user@host:port)，本地客户端为 名称 (local)
+    fn server_address(&self, server: &str) -> String {
+        let client = &self.config.clients[server];
+        match (&client.execution_method, &client.ssh_config) {
+            (ExecutionMethod::SSH, Some(ssh)) => format!("{} ({}@{}:{})", server, ssh.username, ssh.host, ssh.port),
+            (ExecutionMethod::SSH, None) => format!("{} (no ssh_config)", server),
+            (ExecutionMethod::WebSocket, _) => format!("{} (websocket)", server),
+            (ExecutionMethod::Local, _) => format!("{} (local)", server),
+        }
+    }
+
+    fn mask_secrets(&self, value: &str) -> String {
+        self.secret_patterns.iter()
+            .fold(value.to_string(), |value, pattern| pattern.replace_all(&value, "******").into_owned())
+    }
+}
+
+/// 按依赖顺序排列步骤：每次取下标最小的、依赖都已列出的步骤
+fn dependency_order(graph: &StepGraph, len: usize) -> Vec<usize> {
+    let mut order = Vec::with_capacity(len);
+    while order.len() < len {
+        let next = (0..len).find(|index| {
+            !order.contains(index) && graph.dependencies(*index).iter().all(|d| order.contains(d))
+        });
+        // 依赖图在构建时已检查过环，总能找到下一个步骤
+        let Some(next) = next else { break };
+        order.push(next);
+    }
+    order
+}
+
+/// 脚本中引用的变量及其当前值，按变量名排序；尚未定义的变量值为 None
+fn referenced_variables(variables: &VariableManager, content: &str) -> BTreeMap<String, Option<String>> {
+    placeholder_regex()
+        .captures_iter(content)
+        .map(|captures| {
+            let name = split_placeholder(&captures[1]).0.to_string();
+            let value = variables.get_variable(&name).cloned();
+            (name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_lists_steps_servers_and_skipped_conditions() {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        write!(script, "echo deploying {{{{ app }}}}\necho {{{{ build_id }}}}\nline 3\nline 4\n").unwrap();
+        let yaml = format!(r#"
+variables:
+  app: "shop"
+  env: "staging"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+    labels: {{ role: web }}
+    ssh_config: {{ host: "10.0.0.5", port: 2222, username: "deploy", password: "secret" }}
+pipelines:
+  - name: "deploy"
+    title: "Deploy the shop"
+    steps:
+      - name: "notify"
+        script: "{0}"
+        depends_on: ["ship"]
+      - name: "ship"
+        script: "{0}"
+        server_selector: {{ role: web }}
+        depends_on: []
+      - name: "prod_only"
+        script: "{0}"
+        condition: "env == 'production'"
+"#, script.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let mut output = Vec::new();
+        executor.print_execution_plan(&mut output).unwrap();
+        let plan = String::from_utf8(output).unwrap();
+
+        assert!(plan.contains("[pipeline] deploy\n|  title: Deploy the shop"), "{}", plan);
+        // 按依赖顺序：ship 在 notify 之前
+        assert!(plan.find("+-- [2] ship").unwrap() < plan.find("+-- [1] notify").unwrap(), "{}", plan);
+        assert!(plan.contains("|       - web1 (deploy@10.0.0.5:2222)"), "{}", plan);
+        assert!(plan.contains("|       | echo deploying {{ app }}\n|       | echo {{ build_id }}\n|       | line 3\n|       | ...\n"), "{}", plan);
+        assert!(plan.contains("|       app = shop\n|       build_id (set at run time)"), "{}", plan);
+        assert!(plan.contains("+-- [3] prod_only  (SKIP, condition false: env == 'production')"), "{}", plan);
+        assert!(!plan.contains("secret"));
+    }
+}
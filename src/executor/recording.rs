@@ -0,0 +1,373 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::{
+    ExecutionOutcome, OutputCallback, OutputEvent, OutputType, PipelineExecutionResult, ShellExecutionResult, Step,
+};
+use super::RemoteExecutor;
+
+/// 录制文件的格式标识
+pub const RECORDING_FORMAT: &str = "net-shell-recording";
+
+/// 当前录制文件的版本，读取其他版本的文件时返回错误
+pub const RECORDING_VERSION: u32 = 1;
+
+/// 流水线执行器的公共接口，RemoteExecutor 与 RecordingExecutor 都实现该接口
+pub trait PipelineExecutor {
+    /// 执行指定的流水线（支持实时输出）
+    fn execute_pipeline_with_realtime_output(
+        &self,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> impl Future<Output = Result<PipelineExecutionResult>> + Send;
+
+    /// 按配置顺序执行所有流水线（支持实时输出）
+    fn execute_all_pipelines_with_realtime_output(
+        &self,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> impl Future<Output = Result<ShellExecutionResult>> + Send;
+
+    /// 按配置顺序执行带有指定标签的流水线（支持实时输出）
+    fn execute_pipelines_by_tag(
+        &self,
+        tag: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> impl Future<Output = Result<ShellExecutionResult>> + Send;
+
+    /// 执行指定的流水线，不接收事件
+    fn execute_pipeline(&self, pipeline_name: &str) -> impl Future<Output = Result<PipelineExecutionResult>> + Send {
+        self.execute_pipeline_with_realtime_output(pipeline_name, None, None)
+    }
+}
+
+impl PipelineExecutor for RemoteExecutor {
+    async fn execute_pipeline_with_realtime_output(
+        &self,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<PipelineExecutionResult> {
+        RemoteExecutor::execute_pipeline_with_realtime_output(self, pipeline_name, output_callback, log_callback).await
+    }
+
+    async fn execute_all_pipelines_with_realtime_output(
+        &self,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<ShellExecutionResult> {
+        RemoteExecutor::execute_all_pipelines_with_realtime_output(self, output_callback, log_callback).await
+    }
+
+    async fn execute_pipelines_by_tag(
+        &self,
+        tag: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<ShellExecutionResult> {
+        RemoteExecutor::execute_pipelines_by_tag(self, tag, output_callback, log_callback).await
+    }
+}
+
+/// 录制的事件，timestamp 换成距执行开始的毫秒数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub offset_ms: u64, // 距执行开始的毫秒数，回放时按此间隔发送
+    pub pipeline_name: String,
+    pub server_name: String,
+    pub step: Step,
+    pub output_type: OutputType,
+    pub script_path: String,
+    pub content: String,
+    pub variables: HashMap<String, String>,
+    pub run_id: String,
+    pub pipeline_index: usize,
+    pub step_index: Option<usize>,
+    pub total_steps: usize,
+    pub elapsed_ms: u64,
+    pub line_count: u64,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub coalesced_line_count: u64,
+}
+
+impl RecordedEvent {
+    fn record(event: &OutputEvent, started: Instant) -> Self {
+        Self {
+            offset_ms: event.timestamp.saturating_duration_since(started).as_millis() as u64,
+            pipeline_name: event.pipeline_name.clone(),
+            server_name: event.server_name.clone(),
+            step: event.step.clone(),
+            output_type: event.output_type.clone(),
+            script_path: event.script_path.clone(),
+            content: event.content.clone(),
+            variables: event.variables.as_ref().clone(),
+            run_id: event.run_id.clone(),
+            pipeline_index: event.pipeline_index,
+            step_index: event.step_index,
+            total_steps: event.total_steps,
+            elapsed_ms: event.elapsed_ms,
+            line_count: event.line_count,
+            sequence: event.sequence,
+            coalesced_line_count: event.coalesced_line_count,
+        }
+    }
+
+    fn replay(self) -> OutputEvent {
+        OutputEvent {
+            pipeline_name: self.pipeline_name,
+            server_name: self.server_name,
+            step: self.step,
+            output_type: self.output_type,
+            script_path: self.script_path,
+            content: self.content,
+            timestamp: Instant::now(),
+            variables: Arc::new(self.variables),
+            run_id: self.run_id,
+            pipeline_index: self.pipeline_index,
+            step_index: self.step_index,
+            total_steps: self.total_steps,
+            elapsed_ms: self.elapsed_ms,
+            line_count: self.line_count,
+            sequence: self.sequence,
+            coalesced_line_count: self.coalesced_line_count,
+        }
+    }
+}
+
+/// 录制文件（JSON）：版本头、按发送顺序排列的事件和执行结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub format: String,
+    pub version: u32,
+    pub events: Vec<RecordedEvent>,
+    pub result: Option<ShellExecutionResult>, // 执行出错时为 None
+    pub error: Option<String>, // 执行返回的错误
+}
+
+impl Recording {
+    /// 读取录制文件，格式标识或版本不匹配时返回错误
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recording '{}'", path.display()))?;
+        let header: serde_json::Value = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse recording '{}'", path.display()))?;
+        if header.get("format").and_then(|f| f.as_str()) != Some(RECORDING_FORMAT) {
+            bail!("'{}' is not a net-shell recording", path.display());
+        }
+        let version = header.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+        if version != RECORDING_VERSION as u64 {
+            bail!("Unsupported recording version {} in '{}' (expected {})", version, path.display(), RECORDING_VERSION);
+        }
+        serde_json::from_value(header).with_context(|| format!("Failed to parse recording '{}'", path.display()))
+    }
+
+    /// 写入录制文件
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, content).with_context(|| format!("Failed to write recording '{}'", path.display()))
+    }
+}
+
+/// 录制执行的执行器：把每次执行的全部事件与结果写入录制文件，事件照常发送给回调
+/// 每次执行覆盖上一次的录制
+#[derive(Clone)]
+pub struct RecordingExecutor {
+    executor: RemoteExecutor,
+    path: PathBuf,
+}
+
+impl RecordingExecutor {
+    /// 被包装的执行器
+    pub fn inner(&self) -> &RemoteExecutor {
+        &self.executor
+    }
+
+    /// 录制文件的路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 执行 run 并录制回调收到的事件，结束后写入录制文件；写入失败时返回错误
+    async fn record<T, F, Fut>(
+        &self,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+        run: F,
+        to_result: fn(&T) -> ShellExecutionResult,
+    ) -> Result<T>
+    where
+        F: FnOnce(Option<OutputCallback>, Option<OutputCallback>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let started = Instant::now();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |forward: Option<OutputCallback>| -> Option<OutputCallback> {
+            let events = events.clone();
+            Some(Arc::new(move |event: OutputEvent| {
+                events.lock().unwrap().push(RecordedEvent::record(&event, started));
+                if let Some(callback) = &forward {
+                    callback(event);
+                }
+            }))
+        };
+        let result = run(recorder(output_callback), recorder(log_callback)).await;
+
+        let recording = Recording {
+            format: RECORDING_FORMAT.to_string(),
+            version: RECORDING_VERSION,
+            events: std::mem::take(&mut *events.lock().unwrap()),
+            result: result.as_ref().ok().map(to_result),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        };
+        recording.save(&self.path)?;
+        result
+    }
+}
+
+/// 单个流水线的结果包装为录制文件中的执行结果
+fn pipeline_as_shell_result(result: &PipelineExecutionResult) -> ShellExecutionResult {
+    ShellExecutionResult {
+        success: result.overall_success,
+        reason: if result.overall_success { "ok" } else { "failed" }.to_string(),
+        run_id: result.run_id.clone(),
+        pipeline_results: vec![result.clone()],
+        outcome: ExecutionOutcome::Completed,
+    }
+}
+
+impl PipelineExecutor for RecordingExecutor {
+    async fn execute_pipeline_with_realtime_output(
+        &self,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<PipelineExecutionResult> {
+        self.record(output_callback, log_callback, |output, log| {
+            self.executor.execute_pipeline_with_realtime_output(pipeline_name, output, log)
+        }, pipeline_as_shell_result).await
+    }
+
+    async fn execute_all_pipelines_with_realtime_output(
+        &self,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<ShellExecutionResult> {
+        self.record(output_callback, log_callback, |output, log| {
+            self.executor.execute_all_pipelines_with_realtime_output(output, log)
+        }, Clone::clone).await
+    }
+
+    async fn execute_pipelines_by_tag(
+        &self,
+        tag: &str,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> Result<ShellExecutionResult> {
+        self.record(output_callback, log_callback, |output, log| {
+            self.executor.execute_pipelines_by_tag(tag, output, log)
+        }, Clone::clone).await
+    }
+}
+
+impl RemoteExecutor {
+    /// 包装为录制执行的执行器，执行的事件与结果写入 path（JSON）
+    pub fn record_execution(&self, path: &Path) -> RecordingExecutor {
+        RecordingExecutor {
+            executor: self.clone(),
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// 读取录制文件，按录制的顺序把事件重新发送给 callback，返回录制的执行结果
+    /// fast 为 false 时按录制时的间隔发送事件，为 true 时不等待。
+    /// 回放事件的 timestamp 为发送时间；录制时执行出错的，回放完事件后返回该错误
+    pub async fn replay_from_recording(path: &Path, callback: OutputCallback, fast: bool) -> Result<ShellExecutionResult> {
+        let recording = Recording::load(path)?;
+        let started = tokio::time::Instant::now();
+        for event in recording.events {
+            if !fast {
+                tokio::time::sleep_until(started + Duration::from_millis(event.offset_ms)).await;
+            }
+            callback(event.replay());
+        }
+        match (recording.result, recording.error) {
+            (Some(result), _) => Ok(result),
+            (None, error) => Err(anyhow!("Recorded execution failed: {}", error.unwrap_or_default())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_matches_recorded_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("step.sh");
+        std::fs::write(&script, "echo one\nsleep 0.2\necho two >&2\necho \"version=1.2.3\"\n").unwrap();
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "release"
+    steps:
+      - name: "build"
+        script: "{}"
+        extract:
+          - name: "version"
+            patterns: ["version=(\\S+)"]
+            source: "stdout"
+"#, script.display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+        let path = dir.path().join("run.json");
+
+        let live = Arc::new(Mutex::new(Vec::new()));
+        let sink = live.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| sink.lock().unwrap().push(event));
+        let recorder = executor.record_execution(&path);
+        let result = recorder
+            .execute_all_pipelines_with_realtime_output(Some(callback.clone()), Some(callback))
+            .await
+            .unwrap();
+        assert!(result.success);
+
+        let key = |event: &OutputEvent| (
+            event.output_type.clone(), event.pipeline_name.clone(), event.server_name.clone(), event.step.name.clone(),
+            event.content.clone(), event.step_index, event.line_count, event.variables.as_ref().clone(),
+        );
+        let live: Vec<_> = live.lock().unwrap().iter().map(key).collect();
+        assert!(live.iter().any(|e| e.0 == OutputType::Stdout && e.4 == "one"));
+
+        for fast in [true, false] {
+            let replayed = Arc::new(Mutex::new(Vec::new()));
+            let sink = replayed.clone();
+            let start = Instant::now();
+            let replay = RemoteExecutor::replay_from_recording(
+                &path, Arc::new(move |event: OutputEvent| sink.lock().unwrap().push(event)), fast,
+            ).await.unwrap();
+            assert_eq!(replayed.lock().unwrap().iter().map(key).collect::<Vec<_>>(), live);
+            assert_eq!(replay.run_id, result.run_id);
+            assert_eq!(replay.all_extracted_variables()["version"], "1.2.3");
+            // 按原始时间回放时保留脚本中的 sleep 间隔
+            assert_eq!(start.elapsed() >= Duration::from_millis(200), !fast);
+        }
+
+        // 版本不匹配的录制文件
+        let content = std::fs::read_to_string(&path).unwrap().replacen("\"version\": 1", "\"version\": 99", 1);
+        std::fs::write(&path, content).unwrap();
+        let err = RemoteExecutor::replay_from_recording(&path, Arc::new(|_| {}), true).await.unwrap_err();
+        assert!(err.to_string().contains("Unsupported recording version 99"), "{}", err);
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use std::collections::BTreeSet;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::models::{OutputCallback, OutputEvent, OutputType, PipelineExecutionResult, Step};
+use super::RemoteExecutor;
+
+/// 下次触发时间的最大搜索范围（年），超出时认为表达式永远不会触发（如 2月30日）
+const MAX_SEARCH_YEARS: i32 = 5;
+
+/// cron 表达式
+/// 支持标准5字段（分 时 日 月 周）和带秒的6字段（秒 分 时 日 月 周）格式，
+/// 每个字段支持 `*`、数字、范围 `a-b`、步长 `*/n` / `a-b/n` 以及逗号分隔的列表；周日可写作 0 或 7
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    seconds: BTreeSet<u32>,
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days_of_month: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    days_of_week: BTreeSet<u32>,
+    day_of_month_any: bool, // 日字段为 *，仅按周匹配
+    day_of_week_any: bool,  // 周字段为 *，仅按日匹配
+}
+
+impl CronSchedule {
+    /// 解析 cron 表达式
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let (second, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => return Err(anyhow!("Invalid cron expression '{}': expected 5 or 6 fields, got {}", expression, n)),
+        };
+
+        let field = |value: &str, min: u32, max: u32, name: &str| {
+            parse_field(value, min, max)
+                .map_err(|e| anyhow!("Invalid cron expression '{}': {} field: {}", expression, name, e))
+        };
+
+        let mut days_of_week = field(rest[4], 0, 7, "day-of-week")?;
+        if days_of_week.remove(&7) {
+            days_of_week.insert(0);
+        }
+
+        Ok(Self {
+            seconds: field(second, 0, 59, "second")?,
+            minutes: field(rest[0], 0, 59, "minute")?,
+            hours: field(rest[1], 0, 23, "hour")?,
+            days_of_month: field(rest[2], 1, 31, "day-of-month")?,
+            months: field(rest[3], 1, 12, "month")?,
+            days_of_week,
+            day_of_month_any: rest[2] == "*",
+            day_of_week_any: rest[4] == "*",
+        })
+    }
+
+    /// 计算严格晚于 after 的下一个触发时间
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = after.with_nanosecond(0)? + Duration::seconds(1);
+        let limit = after.year() + MAX_SEARCH_YEARS;
+
+        while time.year() <= limit {
+            if !self.months.contains(&time.month()) {
+                time = first_day_of_next_month(time.date())?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.matches_day(time.date()) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.hours.contains(&time.hour()) {
+                time = time.with_minute(0)?.with_second(0)? + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes.contains(&time.minute()) {
+                time = time.with_second(0)? + Duration::minutes(1);
+                continue;
+            }
+            if !self.seconds.contains(&time.second()) {
+                time += Duration::seconds(1);
+                continue;
+            }
+            return Some(time);
+        }
+        None
+    }
+
+    /// 日与周都指定时满足任一即可（与标准 cron 一致），否则按指定的字段匹配
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let by_month = self.days_of_month.contains(&date.day());
+        let by_week = self.days_of_week.contains(&date.weekday().num_days_from_sunday());
+        match (self.day_of_month_any, self.day_of_week_any) {
+            (false, false) => by_month || by_week,
+            _ => by_month && by_week,
+        }
+    }
+}
+
+/// 解析单个字段，返回取值集合
+fn parse_field(value: &str, min: u32, max: u32) -> Result<BTreeSet<u32>> {
+    let mut values = BTreeSet::new();
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| anyhow!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err(anyhow!("step must be greater than 0"));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_number(start, min, max)?, parse_number(end, min, max)?)
+        } else {
+            let start = parse_number(range, min, max)?;
+            // `5/10` 表示从5开始到最大值，每10个取一个
+            (start, if part.contains('/') { max } else { start })
+        };
+        if start > end {
+            return Err(anyhow!("invalid range '{}'", range));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+fn parse_number(value: &str, min: u32, max: u32) -> Result<u32> {
+    let number: u32 = value.parse().map_err(|_| anyhow!("invalid value '{}'", value))?;
+    if number < min || number > max {
+        return Err(anyhow!("value {} out of range {}-{}", number, min, max));
+    }
+    Ok(number)
+}
+
+fn first_day_of_next_month(date: NaiveDate) -> Option<NaiveDate> {
+    match date.month() {
+        12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1),
+    }
+}
+
+/// 计算本地时间下的下一个触发时刻距现在的时长
+/// 夏令时跳过的本地时间不存在，继续向后查找
+fn delay_until_next(schedule: &CronSchedule) -> Option<std::time::Duration> {
+    let now = Local::now();
+    let mut after = now.naive_local();
+    loop {
+        let next = schedule.next_after(after)?;
+        if let Some(fire_at) = Local.from_local_datetime(&next).earliest() {
+            return Some((fire_at - now).to_std().unwrap_or_default());
+        }
+        after = next;
+    }
+}
+
+/// 一次调度执行的结果
+pub struct ScheduledRun {
+    pub pipeline_name: String,
+    pub result: Result<PipelineExecutionResult>,
+}
+
+/// 调度器句柄
+/// 通过 next_result 接收每次执行的结果，shutdown 停止调度并等待正在执行的流水线结束
+pub struct SchedulerHandle {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+    results: mpsc::UnboundedReceiver<ScheduledRun>,
+}
+
+impl SchedulerHandle {
+    /// 等待下一次执行结果；调度器停止且结果取完后返回 None
+    pub async fn next_result(&mut self) -> Option<ScheduledRun> {
+        self.results.recv().await
+    }
+
+    /// 取出已完成但尚未接收的执行结果，不等待
+    pub fn try_next_result(&mut self) -> Option<ScheduledRun> {
+        self.results.try_recv().ok()
+    }
+
+    /// 停止调度，不再开始新的执行；正在执行的流水线执行完后返回
+    /// 返回停止前尚未接收的执行结果
+    pub async fn shutdown(mut self) -> Vec<ScheduledRun> {
+        let _ = self.shutdown.send(true);
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+        let mut remaining = Vec::new();
+        while let Ok(run) = self.results.try_recv() {
+            remaining.push(run);
+        }
+        remaining
+    }
+}
+
+impl RemoteExecutor {
+    /// 启动调度器，按各流水线的 schedule 重复执行
+    /// 未配置 schedule 的流水线不参与调度，仍可手动执行；同一流水线上一次执行尚未结束时跳过本次触发并发送日志事件。
+    /// 每次执行使用独立的变量上下文，结果通过返回的句柄接收
+    pub fn run_scheduler(
+        &self,
+        output_callback: Option<OutputCallback>,
+        log_callback: Option<OutputCallback>,
+    ) -> SchedulerHandle {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (results_tx, results_rx) = mpsc::unbounded_channel();
+
+        let mut tasks = Vec::new();
+        for pipeline in &self.config.pipelines {
+            let Some(expression) = &pipeline.schedule else { continue };
+            // 配置加载时已校验，这里仅防御手动构造的配置
+            let schedule = match CronSchedule::parse(expression) {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    warn!("Pipeline '{}' is not scheduled: {}", pipeline.name, e);
+                    continue;
+                }
+            };
+            tasks.push(tokio::spawn(schedule_pipeline(
+                self.clone(),
+                pipeline.name.clone(),
+                schedule,
+                output_callback.clone(),
+                log_callback.clone(),
+                shutdown_rx.clone(),
+                results_tx.clone(),
+            )));
+        }
+
+        SchedulerHandle {
+            shutdown: shutdown_tx,
+            tasks,
+            results: results_rx,
+        }
+    }
+}
+
+/// 单个流水线的调度循环
+async fn schedule_pipeline(
+    executor: RemoteExecutor,
+    pipeline_name: String,
+    schedule: CronSchedule,
+    output_callback: Option<OutputCallback>,
+    log_callback: Option<OutputCallback>,
+    mut shutdown: watch::Receiver<bool>,
+    results: mpsc::UnboundedSender<ScheduledRun>,
+) {
+    let mut running: Option<JoinHandle<()>> = None;
+
+    while let Some(delay) = delay_until_next(&schedule) {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.wait_for(|stopped| *stopped) => break,
+        }
+
+        if running.as_ref().is_some_and(|run| !run.is_finished()) {
+            if let Some(callback) = executor.dispatcher("", super::legacy_subscriptions(None, log_callback.clone())) {
+                callback(OutputEvent::new(
+                    pipeline_name.clone(),
+                    "system",
+                    Step::default(),
+                    OutputType::Log,
+                    format!("上一次执行尚未结束，跳过本次调度: {}", pipeline_name),
+                    executor.variable_manager.snapshot(),
+                ));
+            }
+            continue;
+        }
+
+        let executor = executor.clone();
+        let pipeline_name = pipeline_name.clone();
+        let (output_callback, log_callback) = (output_callback.clone(), log_callback.clone());
+        let results = results.clone();
+        running = Some(tokio::spawn(async move {
+            let result = executor
+                .execute_pipeline_with_realtime_output(&pipeline_name, output_callback, log_callback)
+                .await;
+            let _ = results.send(ScheduledRun { pipeline_name, result });
+        }));
+    }
+
+    if let Some(run) = running {
+        let _ = run.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn next(expression: &str, after: &str) -> String {
+        CronSchedule::parse(expression)
+            .unwrap()
+            .next_after(at(after))
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
+
+    #[test]
+    fn test_next_fire_times() {
+        assert_eq!(next("*/15 * * * *", "2024-03-10 10:07:30"), "2024-03-10 10:15:00");
+        assert_eq!(next("*/15 * * * *", "2024-03-10 10:15:00"), "2024-03-10 10:30:00");
+        assert_eq!(next("0 2 * * *", "2024-12-31 03:00:00"), "2025-01-01 02:00:00");
+        assert_eq!(next("30 9 * * 1-5", "2024-03-08 10:00:00"), "2024-03-11 09:30:00"); // 周五之后是周一
+        assert_eq!(next("0 0 29 2 *", "2024-03-01 00:00:00"), "2028-02-29 00:00:00");
+        assert_eq!(next("0 12 1 * 7", "2024-03-02 00:00:00"), "2024-03-03 12:00:00"); // 日或周任一满足
+        assert_eq!(next("* * * * * *", "2024-03-10 10:07:30"), "2024-03-10 10:07:31");
+        assert_eq!(next("10,40 * * * * *", "2024-03-10 10:07:30"), "2024-03-10 10:07:40");
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expression in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(CronSchedule::parse(expression).is_err(), "{}", expression);
+        }
+        assert!(CronSchedule::parse("0 0 30 2 *").unwrap().next_after(at("2024-01-01 00:00:00")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_runs_pipelines_and_skips_overlapping_ticks() {
+        use std::sync::{Arc, Mutex};
+
+        let mut fast = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut fast, b"echo fast\n").unwrap();
+        let mut slow = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut slow, b"sleep 1.5\n").unwrap();
+        let yaml = format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "fast"
+    schedule: "* * * * * *"
+    steps:
+      - name: "echo"
+        script: "{}"
+        servers: []
+  - name: "slow"
+    schedule: "* * * * * *"
+    steps:
+      - name: "sleep"
+        script: "{}"
+        servers: []
+  - name: "manual"
+    steps:
+      - name: "echo"
+        script: "{}"
+        servers: []
+"#, fast.path().display(), slow.path().display(), fast.path().display());
+        let executor = RemoteExecutor::from_yaml_str(&yaml, None).unwrap();
+
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        let log: OutputCallback = {
+            let skipped = skipped.clone();
+            Arc::new(move |event: OutputEvent| {
+                if event.content.contains("跳过本次调度") {
+                    skipped.lock().unwrap().push(event.pipeline_name);
+                }
+            })
+        };
+
+        let mut handle = executor.run_scheduler(None, Some(log));
+        let mut runs: Vec<String> = Vec::new();
+        while runs.iter().filter(|name| *name == "slow").count() < 2 {
+            let run = tokio::time::timeout(std::time::Duration::from_secs(10), handle.next_result())
+                .await
+                .expect("scheduled run")
+                .unwrap();
+            assert!(run.result.unwrap().overall_success);
+            runs.push(run.pipeline_name);
+        }
+        runs.extend(handle.shutdown().await.into_iter().map(|run| run.pipeline_name));
+
+        assert!(runs.iter().filter(|name| *name == "fast").count() >= 2, "{:?}", runs);
+        assert!(!runs.iter().any(|name| name == "manual"));
+        let skipped = skipped.lock().unwrap();
+        assert!(skipped.iter().any(|name| name == "slow"), "{:?}", skipped);
+        assert!(!skipped.iter().any(|name| name == "fast"), "{:?}", skipped);
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{anyhow, Context, Result};
+use futures::stream::{self, Stream, StreamExt};
+use std::future::{ready, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::models::{OutputEvent, OutputType, PipelineExecutionResult, Step};
+use super::{AbortOnDrop, RemoteExecutor};
+
+/// 流式执行时事件通道的容量，消费方处理较慢、通道已满时执行等待（背压）
+const STREAM_BUFFER: usize = 64;
+
+/// 流式执行返回的事件流，被丢弃时中止执行
+struct EventStream {
+    events: ReceiverStream<Result<OutputEvent>>,
+    _task: AbortOnDrop<()>,
+}
+
+impl Stream for EventStream {
+    type Item = Result<OutputEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl RemoteExecutor {
+    /// 以事件流的形式执行指定的流水线
+    /// 事件实时产生，最后一项为 PipelineResult 事件（content 为 JSON 格式的执行结果），执行出错时最后一项为错误。
+    /// 消费方处理较慢时执行等待（背压）；流被丢弃时中止执行
+    pub fn execute_pipeline_as_stream(&self, pipeline_name: &str) -> impl Stream<Item = Result<OutputEvent>> + Unpin {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let (run, mut events) = self.execute_pipeline_with_progress_channel(pipeline_name, STREAM_BUFFER);
+        let position = self.config.pipelines.iter().position(|p| p.name == pipeline_name);
+        let total_steps = position.map_or(0, |index| self.config.pipelines[index].steps.len());
+        let task = tokio::spawn(async move {
+            let forward = async {
+                while let Some(event) = events.recv().await {
+                    if sender.send(Ok(event)).await.is_err() {
+                        break;
+                    }
+                }
+            };
+            let (result, ()) = tokio::join!(run, forward);
+            let event = result.and_then(|result| {
+                let content = serde_json::to_string(&result)?;
+                Ok(OutputEvent {
+                    run_id: result.run_id,
+                    pipeline_index: position.unwrap_or_default(),
+                    total_steps,
+                    ..OutputEvent::new(
+                        result.pipeline_name,
+                        "system",
+                        Step::default(),
+                        OutputType::PipelineResult,
+                        content,
+                        Arc::new(result.final_variables),
+                    )
+                })
+            });
+            let _ = sender.send(event).await;
+        });
+        EventStream {
+            events: ReceiverStream::new(receiver),
+            _task: AbortOnDrop(task),
+        }
+    }
+}
+
+/// 执行事件流的辅助方法
+pub trait OutputStreamExt: Stream<Item = Result<OutputEvent>> + Send + Sized {
+    /// 只保留指定类型的事件，错误照常传递
+    fn filter_output_type(self, output_type: OutputType) -> impl Stream<Item = Result<OutputEvent>> + Send {
+        self.filter(move |event| ready(event.as_ref().map_or(true, |event| event.output_type == output_type)))
+    }
+
+    /// 逐行返回 stdout 输出（合并发送的多行事件拆分为单行），遇到错误时结束
+    fn stdout_lines(self) -> impl Stream<Item = String> + Send {
+        self.take_while(|event| ready(event.is_ok()))
+            .filter_map(|event| ready(event.ok().filter(|event| event.output_type == OutputType::Stdout)))
+            .flat_map(|event| stream::iter(event.content.split('\n').map(str::to_string).collect::<Vec<_>>()))
+    }
+
+    /// 消费整个事件流，返回最后的执行结果；执行出错时返回该错误
+    fn into_pipeline_result(self) -> impl Future<Output = Result<PipelineExecutionResult>> + Send {
+        async move {
+            let mut events = std::pin::pin!(self);
+            while let Some(event) = events.next().await {
+                let event = event?;
+                if event.output_type == OutputType::PipelineResult {
+                    return serde_json::from_str(&event.content).context("Failed to parse pipeline result event");
+                }
+            }
+            Err(anyhow!("Event stream ended without a pipeline result"))
+        }
+    }
+}
+
+impl<S: Stream<Item = Result<OutputEvent>> + Send> OutputStreamExt for S {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio_test::{assert_ok, assert_pending, task};
+
+    fn pipeline_yaml(scripts: &[&std::path::Path]) -> String {
+        let steps: String = scripts.iter().enumerate()
+            .map(|(i, script)| format!("      - name: \"step{}\"\n        script: \"{}\"\n", i + 1, script.display()))
+            .collect();
+        format!(r#"
+clients:
+  web1:
+    name: "web1"
+    execution_method: ssh
+pipelines:
+  - name: "stream"
+    steps:
+{}"#, steps)
+    }
+
+    #[tokio::test]
+    async fn test_stream_yields_events_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("first.sh"), dir.path().join("second.sh"));
+        std::fs::write(&first, "echo one\necho two\n").unwrap();
+        std::fs::write(&second, "echo three\n").unwrap();
+        let executor = RemoteExecutor::from_yaml_str(&pipeline_yaml(&[&first, &second]), None).unwrap();
+
+        let events: Vec<_> = executor.execute_pipeline_as_stream("stream")
+            .filter_output_type(OutputType::Log)
+            .collect()
+            .await;
+        assert!(events.iter().all(|event| event.as_ref().unwrap().output_type == OutputType::Log));
+
+        let events: Vec<OutputEvent> = executor.execute_pipeline_as_stream("stream")
+            .map(|event| event.unwrap())
+            .filter(|event| ready(event.output_type != OutputType::Log))
+            .collect()
+            .await;
+        let order: Vec<_> = events.iter()
+            .map(|event| (event.output_type.clone(), event.step.name.as_str()))
+            .collect();
+        assert_eq!(order, [
+            (OutputType::StepStarted, "step1"),
+            (OutputType::Stdout, "step1"),
+            (OutputType::Stdout, "step1"),
+            (OutputType::StepCompleted, "step1"),
+            (OutputType::StepStarted, "step2"),
+            (OutputType::Stdout, "step2"),
+            (OutputType::StepCompleted, "step2"),
+            (OutputType::PipelineResult, ""),
+        ]);
+        let last = events.last().unwrap();
+        assert_eq!(last.output_type, OutputType::PipelineResult);
+        let result: PipelineExecutionResult = serde_json::from_str(&last.content).unwrap();
+        assert!(result.overall_success);
+        assert_eq!(last.run_id, result.run_id);
+
+        let lines: Vec<String> = executor.execute_pipeline_as_stream("stream").stdout_lines().collect().await;
+        assert_eq!(lines, ["one", "two", "three"]);
+
+        let result = assert_ok!(executor.execute_pipeline_as_stream("stream").into_pipeline_result().await);
+        assert_eq!(result.step_results.len(), 2);
+        let err = executor.execute_pipeline_as_stream("missing").into_pipeline_result().await.unwrap_err();
+        assert!(err.to_string().contains("missing"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_slow_consumer_holds_back_execution() {
+        let dir = tempfile::tempdir().unwrap();
+        let (script, marker) = (dir.path().join("flood.sh"), dir.path().join("done"));
+        // 输出远超通道容量与管道缓冲区，之后才写入标记文件
+        std::fs::write(&script, format!("seq 1 50000\ntouch {}\n", marker.display())).unwrap();
+        let executor = RemoteExecutor::from_yaml_str(&pipeline_yaml(&[&script]), None).unwrap();
+
+        let mut result = task::spawn(executor.execute_pipeline_as_stream("stream").stdout_lines().collect::<Vec<_>>());
+        assert_pending!(result.poll());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!marker.exists(), "execution ran ahead of the consumer");
+
+        let lines = result.await;
+        assert_eq!(lines.len(), 50000);
+        assert_eq!(lines.last().map(String::as_str), Some("50000"));
+        assert!(marker.exists());
+    }
+}
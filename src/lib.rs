@@ -7,6 +7,19 @@ pub mod template;
 
 // 重新导出主要类型，方便外部使用
 pub use executor::RemoteExecutor;
+pub use executor::adhoc::{AdhocOptions, ScriptSource, ADHOC_PIPELINE_NAME};
+pub use executor::builder::{ExecutorOptions, RemoteExecutorBuilder};
+pub use executor::control::{CancellationHandle, StepController};
+pub use executor::events::{
+    file_logging_callback, AsyncSinkOptions, AsyncSubscription, EventFilter, EventThrottle, OutputBatching, QueuePolicy, SubscriptionId,
+};
+pub use executor::journal::EventBuffering;
+pub use executor::recording::{PipelineExecutor, RecordingExecutor};
+pub use executor::schedule::{CronSchedule, ScheduledRun, SchedulerHandle};
+pub use executor::stream::OutputStreamExt;
 pub use models::*;
-pub use template::TemplateEngine;
+pub use ssh::auth::{AuthProvider, SshCredentials, StaticAuthProvider};
+pub use template::{AutoEscapeMode, CustomDirective, EscapeMode, TemplateEngine, TemplateRenderer};
+#[cfg(feature = "remote-templates")]
+pub use template::VaultSecretDirective;
 
@@ -3,59 +3,18 @@ pub mod config;
 pub mod executor;
 pub mod models;
 pub mod ssh;
+pub mod template;
 pub mod vars;
 
 // 重新导出主要类型，方便外部使用
 pub use executor::RemoteExecutor;
+use executor::adhoc::{AdhocOptions, ScriptSource};
+use executor::control::CancellationHandle;
 pub use models::*;
-use net_shell::TemplateEngine;
 
 use std::{env, fs};
 use std::{collections::HashMap, sync::Arc};
-use tracing_subscriber;
-
-#[cfg(test)]
-mod tests {
-
-    use super::*;
-
-    #[test]
-    fn test_parse_yaml() {
-        // 这个测试需要有效的YAML文件，跳过以避免解析TEMPLATE_ENGINE.md
-        // 如果需要测试Tera功能，应该创建专门的测试模板文件
-    }
-
-    #[test]
-    fn test_config_parsing() {
-        let yaml_content = r#"
-global_scripts: []
-clients:
-  server1:
-    name: "server1"
-    execution_method: ssh
-    ssh_config:
-      host: "192.168.1.100"
-      port: 22
-      username: "user"
-      password: "password"
-      timeout_seconds: 30
-pipelines:
-  - name: "test_pipeline"
-    steps:
-      - name: "test_step"
-        script: "echo 'test'"
-        servers:
-          - server1
-default_timeout: 60
-"#;
-
-        let executor = RemoteExecutor::from_yaml_str(yaml_content, None).unwrap();
-        assert_eq!(executor.get_available_clients().len(), 1);
-        assert!(executor.client_exists("server1"));
-        assert_eq!(executor.get_available_pipelines().len(), 1);
-        assert!(executor.pipeline_exists("test_pipeline"));
-    }
-}
+use tokio::io::{AsyncBufReadExt, BufReader};
 
 // 主函数用于演示实时输出功能
 #[tokio::main]
@@ -63,33 +22,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 初始化日志
     tracing_subscriber::fmt::init();
 
-    // 解析命令行参数，支持指定配置文件路径
-    let args: Vec<String> = env::args().collect();
-    let config_path = if args.len() > 1 {
-        &args[1]
-    } else {
-        "config.yaml"
+    // 解析命令行参数，支持指定配置文件路径，--step-by-step 开启单步执行，
+    // --outputs-file <路径> 读取并保存流水线导出的变量，--dry-run 只输出执行计划，
+    // --strict 在配置模板引用未定义的变量时报错
+    let args: Vec<String> = env::args().skip(1).collect();
+    // exec 子命令：net-shell exec [--config <路径>] --server <名称>... -- <命令>
+    let exec_args = match args.first().map(String::as_str) {
+        Some("exec") => Some(parse_exec_args(&args[1..])?),
+        _ => None,
+    };
+    let step_by_step = args.iter().any(|a| a == "--step-by-step");
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let strict = args.iter().any(|a| a == "--strict");
+    let outputs_file = args.iter()
+        .position(|a| a == "--outputs-file")
+        .and_then(|index| args.get(index + 1));
+    let config_path = match &exec_args {
+        Some(exec) => exec.config_path.as_str(),
+        None => args.iter()
+            .enumerate()
+            .find(|(index, a)| !a.starts_with("--") && (*index == 0 || args[index - 1] != "--outputs-file"))
+            .map(|(_, a)| a.as_str())
+            .unwrap_or("config.yaml"),
     };
 
     let mut variables = HashMap::new();
     variables.insert("new_master_ip".to_string(), "192.168.1.100".to_string());
     variables.insert("script_dir".to_string(), "./scripts".to_string());
 
-    let mut t = TemplateEngine::with_all_delimiters("#{", "}", "#{%", "%}");
-
-
     let template_content = fs::read_to_string(config_path)?;
 
-    let data:RemoteExecutionConfig = serde_yaml::from_str(&template_content)?;
-
-    data.variables.unwrap_or_default().iter().for_each(|(k,v)|{
-        t.set_variable(k, v.as_str());
-    });
-
-    let parsed_content = t.set_preserve_loop_newlines(false).render_string(template_content.as_str())?;
+    // 默认未定义的变量渲染为空字符串；--strict 时直接报错，不带着空值执行
+    let parsed_content = if strict {
+        config::ConfigManager::render_template_strict(&template_content, HashMap::new(), None)?
+    } else {
+        config::ConfigManager::render_template(&template_content, HashMap::new(), None)?
+    };
 
     // 创建执行器
-    let mut executor = RemoteExecutor::from_yaml_str(&parsed_content, Some(variables))?;
+    let mut builder = RemoteExecutor::builder().yaml_str(&parsed_content).variables(variables);
+    if let Some(path) = outputs_file {
+        builder = builder.with_outputs_file(path);
+    }
+    let executor = builder.build()?;
+
+    // 打印配置检查发现的问题（如脚本中未定义的变量）
+    for warning in executor.lint()? {
+        eprintln!("[WARN] {}", warning);
+    }
 
     // 定义实时输出回调函数
     let output_callback = Arc::new(|event: models::OutputEvent| {
@@ -131,6 +111,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 //         event.server_name,
                 //         event.content);
             }
+            models::OutputType::PipelineResult | models::OutputType::EventsDropped => {}
         }
 
         // 显示当前变量状态
@@ -139,17 +120,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    if let Some(exec) = exec_args {
+        return run_adhoc(&executor, exec, output_callback).await;
+    }
+
+    if dry_run {
+        executor.print_execution_plan(&mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
     // 执行所有流水线
-    let res = executor
-        .execute_all_pipelines_with_realtime_output(
-            Some(output_callback.clone()),
-            Some(output_callback),
-        )
-        .await?;
+    let res = if step_by_step {
+        run_step_by_step(&executor, output_callback).await?
+    } else {
+        let cancel = CancellationHandle::new();
+        tokio::spawn(handle_signals(cancel.clone()));
+        executor
+            .execute_all_pipelines_with_cancellation(
+                &cancel,
+                Some(output_callback.clone()),
+                Some(output_callback),
+            )
+            .await?
+    };
     let results = res.pipeline_results;
 
     // 打印执行结果摘要
-    println!("\n=== 执行结果摘要 ===");
+    let interrupted = res.outcome == ExecutionOutcome::Interrupted;
+    if interrupted {
+        println!("\n=== 执行结果摘要 (INTERRUPTED) ===");
+    } else {
+        println!("\n=== 执行结果摘要 ===");
+    }
     for result in &results {
         println!(
             "\n流水线: {} ({})",
@@ -164,10 +166,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("步骤结果:");
 
         for step_result in &result.step_results {
-            let status = if step_result.execution_result.success {
-                "✅"
-            } else {
-                "❌"
+            let status = match step_result.status {
+                StepStatus::Succeeded => "✅",
+                StepStatus::Failed => "❌",
+                StepStatus::Skipped => "⏭️",
+                StepStatus::NotRun => "⏸️",
             };
             println!(
                 "  {} [{}:{}] {} - {}ms, {} {}",
@@ -195,10 +198,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .flat_map(|r| &r.step_results)
         .filter(|r| r.execution_result.success)
         .count();
+    let skipped_steps = results
+        .iter()
+        .flat_map(|r| &r.step_results)
+        .filter(|r| r.status == StepStatus::Skipped)
+        .count();
+    let not_run_steps = results
+        .iter()
+        .flat_map(|r| &r.step_results)
+        .filter(|r| r.status == StepStatus::NotRun)
+        .count();
 
     println!("\n=== 总体统计 ===");
     println!("流水线: {}/{} 成功", successful_pipelines, total_pipelines);
-    println!("步骤: {}/{} 成功", successful_steps, total_steps);
+    println!(
+        "步骤: {}/{} 成功（其中 {} 个跳过），{} 个未执行",
+        successful_steps, total_steps, skipped_steps, not_run_steps
+    );
+
+    if interrupted {
+        println!("执行被中断，中断前没有执行完的步骤记为未执行");
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
 
     if !res.success {
         println!("执行失败: {}", res.reason);
@@ -207,3 +228,239 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// exec 子命令的参数
+#[derive(Debug, PartialEq)]
+struct ExecArgs {
+    config_path: String,
+    servers: Vec<String>,
+    command: String,
+}
+
+/// 解析 exec 子命令的参数：--config <路径>（默认 config.yaml）、可重复的 --server <名称>，
+/// -- 之后的参数以空格连接作为要执行的命令
+fn parse_exec_args(args: &[String]) -> Result<ExecArgs, Box<dyn std::error::Error>> {
+    let mut exec = ExecArgs { config_path: "config.yaml".to_string(), servers: Vec::new(), command: String::new() };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--" => {
+                exec.command = iter.by_ref().cloned().collect::<Vec<_>>().join(" ");
+                break;
+            }
+            "--server" | "--config" => {
+                let value = iter.next().ok_or_else(|| format!("{} 缺少参数值", arg))?.clone();
+                if arg == "--server" {
+                    exec.servers.push(value);
+                } else {
+                    exec.config_path = value;
+                }
+            }
+            other => return Err(format!("exec 不支持的参数: {}", other).into()),
+        }
+    }
+    if exec.servers.is_empty() || exec.command.trim().is_empty() {
+        return Err("用法: net-shell exec [--config <路径>] --server <名称>... -- <命令>".into());
+    }
+    Ok(exec)
+}
+
+/// 在指定服务器上执行临时命令并打印每台服务器的结果，有服务器失败时以退出码1退出
+async fn run_adhoc(
+    executor: &RemoteExecutor,
+    exec: ExecArgs,
+    output_callback: OutputCallback,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let results = executor
+        .execute_adhoc(
+            &exec.servers,
+            ScriptSource::Inline(exec.command),
+            AdhocOptions::default(),
+            Some(output_callback.clone()),
+            Some(output_callback),
+        )
+        .await?;
+
+    println!("\n=== 执行结果摘要 ===");
+    for result in &results {
+        let status = if result.execution_result.success { "✅" } else { "❌" };
+        println!(
+            "  {} {} - 退出码 {}, {}ms {}",
+            status,
+            result.server_name,
+            result.execution_result.exit_code,
+            result.execution_result.execution_time_ms,
+            result.execution_result.error_message.clone().unwrap_or_default()
+        );
+    }
+    if !results.iter().all(|r| r.execution_result.success) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// 被 SIGINT/SIGTERM 中断时的退出码
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// 等待 SIGINT/SIGTERM：第一次取消执行（停止新步骤、中止正在执行的步骤并输出部分结果），
+/// 第二次立即退出
+async fn handle_signals(cancel: CancellationHandle) {
+    #[cfg(unix)]
+    let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            eprintln!("[WARN] 无法监听 SIGTERM: {}", e);
+            None
+        }
+    };
+
+    loop {
+        #[cfg(unix)]
+        let received = tokio::select! {
+            result = tokio::signal::ctrl_c() => result.is_ok(),
+            Some(()) = async { terminate.as_mut()?.recv().await } => true,
+        };
+        #[cfg(not(unix))]
+        let received = tokio::signal::ctrl_c().await.is_ok();
+        if !received {
+            return;
+        }
+
+        if cancel.is_cancelled() {
+            eprintln!("\n再次收到中断信号，立即退出");
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("\n收到中断信号，停止执行（再次按 Ctrl-C 立即退出）");
+        cancel.cancel();
+    }
+}
+
+/// 单步执行所有流水线：每个步骤开始前暂停，从标准输入读取命令
+/// 回车执行下一步，c 执行剩余步骤，q 中止，vars 查看变量，set k=v 修改变量
+async fn run_step_by_step(
+    executor: &RemoteExecutor,
+    output_callback: OutputCallback,
+) -> Result<ShellExecutionResult, Box<dyn std::error::Error>> {
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let mut results = Vec::new();
+
+    for pipeline_name in executor.get_available_pipelines() {
+        let (mut controller, handle) = executor.execute_pipeline_controlled(
+            &pipeline_name,
+            Some(output_callback.clone()),
+            Some(output_callback.clone()),
+        );
+
+        while let Some(step_name) = controller.paused().await {
+            loop {
+                println!("⏸️  {}:{} [回车] 下一步 | c 继续 | q 中止 | vars | set k=v", pipeline_name, step_name);
+                // 标准输入关闭时执行剩余步骤
+                let line = stdin.next_line().await?.unwrap_or_else(|| "c".to_string());
+                match line.trim() {
+                    "" => controller.next(),
+                    "c" => controller.run_to_end(),
+                    "q" => controller.abort(),
+                    "vars" => {
+                        let mut variables: Vec<_> = controller.current_variables().into_iter().collect();
+                        variables.sort();
+                        for (k, v) in variables {
+                            println!("  {} = {}", k, v);
+                        }
+                        continue;
+                    }
+                    command => {
+                        match command.strip_prefix("set ").and_then(|kv| kv.split_once('=')) {
+                            Some((k, v)) => controller.set_variable(k.trim(), v.trim()),
+                            None => println!("未知命令: {}", command),
+                        }
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+
+        let result = match handle.await? {
+            Ok(result) => result,
+            Err(e) => {
+                return Ok(ShellExecutionResult {
+                    pipeline_results: results,
+                    success: false,
+                    reason: e.to_string(),
+                    run_id: String::new(),
+                    outcome: ExecutionOutcome::Completed,
+                });
+            }
+        };
+        let success = result.overall_success;
+        results.push(result);
+        if !success {
+            break;
+        }
+    }
+
+    // 单步模式下每个流水线单独执行，run_id 见各流水线的结果
+    Ok(ShellExecutionResult {
+        pipeline_results: results,
+        success: true,
+        reason: "ok".to_string(),
+        run_id: String::new(),
+        outcome: ExecutionOutcome::Completed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml() {
+        // 这个测试需要有效的YAML文件，跳过以避免解析TEMPLATE_ENGINE.md
+        // 如果需要测试Tera功能，应该创建专门的测试模板文件
+    }
+
+    #[test]
+    fn test_config_parsing() {
+        let yaml_content = r#"
+global_scripts: []
+clients:
+  server1:
+    name: "server1"
+    execution_method: ssh
+    ssh_config:
+      host: "192.168.1.100"
+      port: 22
+      username: "user"
+      password: "password"
+      timeout_seconds: 30
+pipelines:
+  - name: "test_pipeline"
+    steps:
+      - name: "test_step"
+        script: "echo 'test'"
+        servers:
+          - server1
+default_timeout: 60
+"#;
+
+        let executor = RemoteExecutor::from_yaml_str(yaml_content, None).unwrap();
+        assert_eq!(executor.get_available_clients().len(), 1);
+        assert!(executor.client_exists("server1"));
+        assert_eq!(executor.get_available_pipelines().len(), 1);
+        assert!(executor.pipeline_exists("test_pipeline"));
+    }
+
+    #[test]
+    fn test_parse_exec_args() {
+        let args: Vec<String> = ["--server", "web1", "--server", "web2", "--", "systemctl", "status", "nginx"]
+            .iter().map(|a| a.to_string()).collect();
+        let exec = parse_exec_args(&args).unwrap();
+        assert_eq!(exec, ExecArgs {
+            config_path: "config.yaml".to_string(),
+            servers: vec!["web1".to_string(), "web2".to_string()],
+            command: "systemctl status nginx".to_string(),
+        });
+        assert!(parse_exec_args(&args[..4]).is_err());
+    }
+}
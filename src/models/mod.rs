@@ -8,18 +8,62 @@ pub enum ExecutionMethod {
     SSH,
     #[serde(rename = "websocket")]
     WebSocket,
+    #[serde(rename = "local")]
+    Local, // 在执行器所在机器上通过 LocalExecutor 执行，不需要 ssh_config
+}
+
+/// SSH实现后端
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum SshBackend {
+    #[serde(rename = "libssh2")]
+    Libssh2,
+    #[serde(rename = "russh")]
+    Russh,
+}
+
+impl Default for SshBackend {
+    /// 默认使用 libssh2；仅启用 russh 特性时使用 russh
+    fn default() -> Self {
+        if cfg!(feature = "russh") && !cfg!(feature = "ssh") {
+            SshBackend::Russh
+        } else {
+            SshBackend::Libssh2
+        }
+    }
 }
 
 /// SSH连接配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SshConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
     pub password: Option<String>,
     pub private_key_path: Option<String>,
+    pub private_key_passphrase: Option<String>, // 加密私钥的口令；写在配置文件中不安全，建议使用口令回调或 AuthProvider
     pub session_timeout_seconds: Option<u64>,
     pub timeout_seconds: Option<u64>,
+    pub keepalive_seconds: Option<u64>, // 命令长时间没有输出时发送SSH keepalive的间隔，防止中间防火墙断开空闲连接
+    pub backend: Option<SshBackend>, // SSH实现后端，未设置时按启用的特性选择
+    pub proxy: Option<ProxyConfig>, // 通过SOCKS5或HTTP代理建立连接
+}
+
+/// 代理类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+/// 代理配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
 }
 
 /// WebSocket配置（预留，后续实现）
@@ -36,16 +80,121 @@ pub struct ClientConfig {
     pub execution_method: ExecutionMethod,
     pub ssh_config: Option<SshConfig>,
     pub websocket_config: Option<WebSocketConfig>,
+    pub labels: Option<HashMap<String, String>>, // 标签（如 role: web），供步骤的 server_selector 选择服务器
+    pub variables: Option<HashMap<String, String>>, // 只在该客户端上执行时生效的变量，覆盖同名的其他变量
+    pub local_fallback: Option<bool>, // SSH连接失败时改为在本地执行步骤，用于开发环境
+}
+
+impl ClientConfig {
+    /// 是否具有 selector 中的全部标签
+    pub fn matches_labels(&self, selector: &HashMap<String, String>) -> bool {
+        let labels = self.labels.as_ref();
+        selector.iter().all(|(key, value)| labels.and_then(|l| l.get(key)) == Some(value))
+    }
 }
 
 /// 变量提取规则
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExtractRule {
     pub name: String,
-    pub patterns: Vec<String>, // 支持多个正则表达式，按顺序尝试直到匹配成功
+    #[serde(default)]
+    pub patterns: Vec<String>, // 支持多个正则表达式，按顺序尝试直到匹配成功；exit_code 规则可省略，直接保存退出码；可引用变量，变量值不做正则转义
     pub source: String, // "stdout", "stderr", "exit_code"
     #[serde(default = "default_cascade")]
     pub cascade: bool, // 是否启用级联模式：前一个正则的匹配结果作为下一个正则的输入，默认为true
+    pub capture_intermediates: Option<bool>, // 级联模式下是否保存中间阶段的匹配结果（默认变量名为 name_stage_N）
+    pub named_stages: Option<Vec<String>>, // 中间阶段的变量名，按阶段顺序对应，未提供的阶段使用 name_stage_N
+    #[serde(alias = "multiline")]
+    pub dotall: Option<bool>, // 是否让 . 匹配换行符（相当于在正则前加 (?s)），用于提取跨行的内容；也可以写作 multiline
+    #[serde(default, deserialize_with = "deserialize_transforms")]
+    pub transform: Option<Vec<ValueTransform>>, // 按顺序对提取到的最终值进行的转换，可写作单个转换名或列表
+    pub find_all: Option<bool>, // 是否收集（最后一个）正则的所有匹配的第一个捕获组，以逗号连接保存
+}
+
+/// 提取值的转换，配置中写作转换名，如 trim、default:unknown
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ValueTransform {
+    TrimNewlines,    // 去除首尾的换行符（\r、\n），保留中间的换行
+    Trim,            // 去除首尾的空白字符
+    Base64Decode,    // 按标准 base64 解码，结果须为UTF-8
+    UrlDecode,       // 解码 %XX 百分号编码，结果须为UTF-8
+    StripAnsi,       // 去除ANSI颜色/控制序列
+    Lower,           // 转为小写
+    Upper,           // 转为大写
+    Default(String), // 值为空时替换为给定的默认值（default:<val>）
+}
+
+/// 解析 transform：单个转换名（transform: trim）或转换名列表（transform: [trim, lower]）
+fn deserialize_transforms<'de, D>(deserializer: D) -> Result<Option<Vec<ValueTransform>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let names = match Option::<OneOrMany>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(OneOrMany::One(name)) => vec![name],
+        Some(OneOrMany::Many(names)) => names,
+    };
+    names.into_iter()
+        .map(|name| ValueTransform::try_from(name).map_err(serde::de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+impl TryFrom<String> for ValueTransform {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        if let Some(value) = name.strip_prefix("default:") {
+            return Ok(ValueTransform::Default(value.to_string()));
+        }
+        match name.as_str() {
+            "trim_newlines" => Ok(ValueTransform::TrimNewlines),
+            "trim" => Ok(ValueTransform::Trim),
+            "base64_decode" => Ok(ValueTransform::Base64Decode),
+            "url_decode" => Ok(ValueTransform::UrlDecode),
+            "strip_ansi" => Ok(ValueTransform::StripAnsi),
+            "lower" => Ok(ValueTransform::Lower),
+            "upper" => Ok(ValueTransform::Upper),
+            _ => Err(format!(
+                "unknown transform '{}', expected trim_newlines, trim, base64_decode, url_decode, strip_ansi, lower, upper or default:<value>",
+                name
+            )),
+        }
+    }
+}
+
+impl From<ValueTransform> for String {
+    fn from(transform: ValueTransform) -> Self {
+        match transform {
+            ValueTransform::TrimNewlines => "trim_newlines".to_string(),
+            ValueTransform::Trim => "trim".to_string(),
+            ValueTransform::Base64Decode => "base64_decode".to_string(),
+            ValueTransform::UrlDecode => "url_decode".to_string(),
+            ValueTransform::StripAnsi => "strip_ansi".to_string(),
+            ValueTransform::Lower => "lower".to_string(),
+            ValueTransform::Upper => "upper".to_string(),
+            ValueTransform::Default(value) => format!("default:{}", value),
+        }
+    }
+}
+
+impl ExtractRule {
+    /// 按规则的匹配选项修饰正则表达式文本
+    pub fn pattern_text(&self, pattern: &str) -> String {
+        if self.dotall == Some(true) {
+            format!("(?s){}", pattern)
+        } else {
+            pattern.to_string()
+        }
+    }
 }
 
 /// 默认级联模式为true
@@ -53,26 +202,200 @@ fn default_cascade() -> bool {
     true
 }
 
+/// 退出码分类
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitClass {
+    #[default]
+    Success,
+    Failure,
+    Skipped,   // 无需执行（如已部署），视为成功但在结果中标记为跳过
+    Retryable, // 临时失败，配置了 retries 时重新执行
+}
+
+/// 步骤执行状态
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    #[default]
+    Succeeded,
+    Failed,
+    Skipped, // 条件为假、幂等守卫命中或退出码分类为 skipped
+    NotRun,  // 流水线提前结束（前面的步骤失败或执行被取消），步骤没有执行完
+}
+
+impl StepStatus {
+    /// 根据执行是否成功与退出码分类得到步骤状态
+    pub fn of(success: bool, exit_class: ExitClass) -> Self {
+        match (success, exit_class) {
+            (false, _) => StepStatus::Failed,
+            (true, ExitClass::Skipped) => StepStatus::Skipped,
+            (true, _) => StepStatus::Succeeded,
+        }
+    }
+}
+
+impl ExitClass {
+    /// 根据退出码映射解析分类，未映射的退出码沿用 0 成功 / 非0 失败的规则
+    pub fn resolve(exit_code: i32, meanings: &HashMap<i32, ExitClass>) -> Self {
+        match meanings.get(&exit_code) {
+            Some(class) => *class,
+            None if exit_code == 0 => ExitClass::Success,
+            None => ExitClass::Failure,
+        }
+    }
+
+    /// 该分类是否视为步骤成功
+    pub fn is_success(&self) -> bool {
+        matches!(self, ExitClass::Success | ExitClass::Skipped)
+    }
+
+    /// 分类名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitClass::Success => "success",
+            ExitClass::Failure => "failure",
+            ExitClass::Skipped => "skipped",
+            ExitClass::Retryable => "retryable",
+        }
+    }
+}
+
 /// 步骤配置
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Step {
     pub title: Option<String>,
     pub name: String,
+    pub description: Option<String>, // 步骤说明，仅用于文档
     pub script: String,
+    pub before_script: Option<String>, // 在 script 之前执行的脚本，远程执行时与 script 共用同一个SSH会话
+    pub after_script: Option<String>,  // 在 script 成功之后执行的脚本
+    #[serde(default)]
+    pub local_scripts: Vec<String>, // 未指定服务器时与 script 在本地并发执行的脚本，每个脚本单独产生结果
+    pub local_parallelism: Option<usize>, // local_scripts 的最大并发数，默认4
+    pub max_parallel: Option<usize>, // 同时执行的服务器数上限，默认不限制
     #[serde(default)]
     pub servers: Vec<String>,
+    pub server_selector: Option<HashMap<String, String>>, // 按标签选择服务器（全部匹配），与 servers 合并
+    pub allow_empty_selection: Option<bool>, // server_selector 没有匹配的服务器时跳过步骤而不是报错
     pub timeout_seconds: Option<u64>,
+    pub graceful_shutdown_seconds: Option<u64>, // 本地执行超时后发送 SIGTERM，等待该秒数仍未退出再发送 SIGKILL，默认5
     pub extract: Option<Vec<ExtractRule>>,
     #[serde(default)]
     pub variables: Option<HashMap<String, String>>,
+    pub encoding: Option<String>, // 输出编码（如 "gbk"），未设置时按UTF-8有损解码
+    pub exit_code_meanings: Option<HashMap<i32, ExitClass>>, // 退出码分类，覆盖全局配置中的同名退出码
+    pub retries: Option<u32>, // 退出码分类为 retryable 时的最大重试次数
+    pub depends_on: Option<Vec<String>>, // 依赖的步骤名称，未设置时依赖上一个步骤；无共同依赖的步骤并发执行
+    pub condition: Option<String>, // 执行条件（如 env == "production"），为假时跳过步骤
+    pub creates: Option<String>, // 幂等守卫：目标服务器上该路径已存在时跳过步骤
+    pub removes: Option<String>, // 幂等守卫：目标服务器上该路径不存在时跳过步骤
+    pub unless: Option<String>, // 幂等守卫：在目标服务器上执行的检查命令，退出码为0时跳过步骤
+    pub artifacts: Option<Vec<ArtifactSpec>>, // 步骤结束后从服务器（本地步骤为本机）收集的文件
+    #[serde(default)]
+    pub collect_on_failure: bool, // 步骤失败时是否仍然收集产物
+    pub strip_ansi: Option<bool>, // 是否去除输出中的ANSI颜色/控制序列，未设置时沿用全局配置
+    pub quote_variables: Option<bool>, // 是否对脚本中的所有变量值按POSIX shell规则加引号，覆盖构建器的 auto_escape 设置
+    pub export_variables: Option<bool>, // 远程执行时是否在脚本开头 export 所有变量（本地执行始终通过环境变量传入）
+    pub aggregate_output: Option<AggregateConfig>, // 所有服务器执行完成后把各服务器的输出汇总到一个变量
+    pub fail_fast: Option<bool>, // 某台服务器执行出错时是否中止步骤，默认true；为false时等待所有服务器完成并把错误记为失败结果
+    pub sudo: Option<SudoConfig>, // 远程执行时通过 sudo 切换用户执行脚本
+    pub run_once: Option<bool>, // 为true时只在一台服务器上执行：按顺序选第一台能连接执行的服务器，用于数据库迁移等只应执行一次的操作
+    pub servers_failover: Option<Vec<String>>, // 与解析后的 servers 按下标对应的备用服务器，SSH连接失败时改在备用服务器上执行；脚本失败不切换
+    pub include_rendered_script_in_events: Option<bool>, // 每台服务器执行每个阶段前发送内容为渲染后脚本的 StepStarted 事件，未设置时沿用全局配置
+    pub compress: Option<bool>, // 远程执行时用gzip压缩脚本内容，在服务器上经 gzip -dc 解压后执行，适用于很大的脚本；默认不压缩
+    pub responses: Option<Vec<PromptResponse>>, // 脚本等待输入时按输出中的提示写入的应答；设置后远程执行使用PTY
+    pub responses_any_order: Option<bool>, // 为true时提示可以按任意顺序出现，默认按列表顺序依次等待
+    pub group: Option<String>, // 步骤分组，用于组织步骤、按组汇总结果和只执行某一组，不改变执行顺序；未设置时属于 "default"
+    pub assert: Option<StepAssert>, // 对脚本输出和退出码的断言，不满足时步骤失败，错误信息中给出期望值与实际值
 }
 
-/// 流水线配置
+/// 未设置 group 的步骤所属的分组
+pub const DEFAULT_STEP_GROUP: &str = "default";
+
+impl Step {
+    /// 步骤所属的分组名
+    pub fn group_name(&self) -> &str {
+        self.group.as_deref().unwrap_or(DEFAULT_STEP_GROUP)
+    }
+}
+
+/// 交互提示的应答
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptResponse {
+    pub expect_regex: String,         // 匹配提示的正则表达式，在脚本的输出（含未换行的部分）中查找
+    pub send: String,                 // 匹配后写入脚本输入的内容，自动追加换行
+    pub timeout_seconds: Option<u64>, // 等待该提示的最长时间（从上一个提示应答或开始执行时算起），未设置时使用步骤超时
+}
+
+/// 步骤断言：设置的各项期望同时满足时步骤才成功
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct StepAssert {
+    pub stdout_equals: Option<String>,   // stdout 与之完全相同
+    pub stdout_contains: Option<String>, // stdout 包含该文本
+    pub stdout_matches: Option<String>,  // stdout 匹配该正则（多行模式，^ $ 匹配每行的开头和结尾）
+    pub exit_code: Option<i32>,          // 期望的退出码，设置后代替退出码分类决定成败
+    #[serde(default)]
+    pub trim: bool, // 比较前去除 stdout 与期望值首尾的空白
+    #[serde(default)]
+    pub ignore_case: bool, // 比较时忽略大小写
+}
+
+/// sudo 配置
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SudoConfig {
+    pub user: Option<String>,     // 目标用户，默认 root
+    pub password: Option<String>, // sudo 密码，通过 sudo -S 从stdin传入；未设置时使用 sudo -n（免密）
+}
+
+/// 输出汇总配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregateConfig {
+    pub variable_name: String,
+    pub strategy: AggregateStrategy,
+}
+
+/// 输出汇总方式
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateStrategy {
+    Concat(String), // 按服务器顺序用分隔符拼接各服务器的stdout
+    CountSuccess,   // 成功的服务器数量
+    CountFailure,   // 失败的服务器数量
+    FirstSuccess,   // 第一个成功的服务器的stdout，没有成功的服务器时为空
+    Json,           // 各服务器结果的JSON数组：[{"server", "stdout", "exit_code"}]
+}
+
+/// 产物收集规则
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactSpec {
+    pub remote_path: String, // 文件路径，文件名部分支持 * ? [..] 通配符
+    pub local_dir: String,   // 本地保存目录，文件保存到 local_dir/{server_name}/ 下
+    #[serde(default)]
+    pub required: bool,      // 必需的产物缺失时步骤失败，否则只记录日志
+}
+
+/// 已收集的产物
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CollectedArtifact {
+    pub remote_path: String,
+    pub local_path: String,
+    pub size: u64,
+}
+
+/// 流水线配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Pipeline {
     pub name: String,
     pub title: Option<String>,
+    pub description: Option<String>, // 流水线说明，仅用于文档
+    pub tags: Option<Vec<String>>,   // 标签，可按标签筛选和执行流水线
     pub script: Option<String>,
+    pub schedule: Option<String>, // cron表达式，配置后由 run_scheduler 按计划重复执行
+    pub gather_facts: Option<bool>, // 执行步骤前在引用的每台服务器上收集主机信息，写入 facts.<服务器>.<key> 变量
+    pub require_facts: Option<bool>, // 收集主机信息失败时是否中止流水线，默认false（只记录日志）
+    pub outputs: Option<Vec<String>>, // 流水线结束时导出的变量名，写入结果的 outputs（和输出文件）
+    pub timeout_seconds: Option<u64>, // 流水线的总时间预算，用完后不再开始新的步骤，正在执行的步骤被中止
     pub steps: Vec<Step>,
 }
 
@@ -83,17 +406,51 @@ pub struct RemoteExecutionConfig {
     pub clients: HashMap<String, ClientConfig>,
     pub pipelines: Vec<Pipeline>,
     pub default_timeout: Option<u64>,
-    pub global_scripts:Vec<String>
+    pub global_scripts: Option<Vec<String>>, // 公共脚本文件（如辅助函数），按顺序拼接在每个步骤脚本之前
+    pub exit_code_meanings: Option<HashMap<i32, ExitClass>>, // 全局退出码分类
+    pub strip_ansi: Option<bool>, // 是否去除输出中的ANSI颜色/控制序列，默认不去除
+    pub facts_script: Option<String>, // 替换内置主机信息探测脚本的本地脚本文件，输出 key=value 行
+    pub before_all: Option<Vec<Step>>, // 执行多个流水线前运行的步骤（如加锁、发送开始通知）
+    pub after_all: Option<Vec<Step>>, // 执行多个流水线后运行的步骤，前面的流水线失败时也会运行
+    pub include_rendered_script_in_events: Option<bool>, // 是否在事件中附带渲染后的脚本，默认不附带（脚本可能很大）
+    pub rendered_script_max_bytes: Option<usize>, // 结果中 rendered_script 保留的最大字节数，默认65536，超出部分截断
+    pub ssh_worker_threads: Option<usize>, // 同时占用阻塞线程执行 libssh2 会话的上限，默认64，超出的服务器排队等待
+    pub log_dir: Option<String>, // 按 {流水线}/{步骤}/{服务器}.log 保存每台服务器的步骤输出的目录
+    pub step_templates: Option<HashMap<String, serde_yaml::Value>>, // 可复用的步骤定义，步骤通过 template 引用，加载配置时展开
+    pub total_timeout_seconds: Option<u64>, // 一次执行（含 before_all / after_all）的总时间预算，用完后与流水线预算用完的处理相同
+}
+
+impl RemoteExecutionConfig {
+    /// 把 before_all 包装为名为 before_all 的流水线，未配置或为空时返回 None
+    pub fn before_all_pipeline(&self) -> Option<Pipeline> {
+        hook_pipeline("before_all", self.before_all.as_ref())
+    }
+
+    /// 把 after_all 包装为名为 after_all 的流水线，未配置或为空时返回 None
+    pub fn after_all_pipeline(&self) -> Option<Pipeline> {
+        hook_pipeline("after_all", self.after_all.as_ref())
+    }
+}
+
+fn hook_pipeline(name: &str, steps: Option<&Vec<Step>>) -> Option<Pipeline> {
+    let steps = steps.filter(|steps| !steps.is_empty())?;
+    Some(Pipeline {
+        name: name.to_string(),
+        steps: steps.clone(),
+        ..Pipeline::default()
+    })
 }
 
 /// 实时输出类型
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OutputType {
     Stdout,
     Stderr,
     Log,
     StepStarted,    // 步骤开始执行
     StepCompleted,  // 步骤执行完成
+    PipelineResult, // 流式执行的最后一个事件，content 为 JSON 格式的 PipelineExecutionResult
+    EventsDropped,  // 回放缓冲的事件时，缓冲区超出上限而丢弃了最早的事件，content 为丢弃的事件数
 }
 
 /// 实时输出事件
@@ -106,12 +463,96 @@ pub struct OutputEvent {
     pub script_path:String,
     pub content: String,
     pub timestamp: std::time::Instant,
-    pub variables: HashMap<String, String>, // 添加当前变量上下文
+    pub variables: std::sync::Arc<HashMap<String, String>>, // 当前变量上下文，同一步骤的输出事件共用一份
+    pub run_id: String, // 所属执行的ID，同一次 execute_* 调用产生的事件相同，由执行器在分发事件时填入
+    pub pipeline_index: usize, // 流水线在配置中的位置（从0开始），由执行器在分发事件时填入
+    pub step_index: Option<usize>, // 步骤在流水线中的位置（从0开始），流水线级事件为None
+    pub total_steps: usize, // 流水线的步骤总数
+    pub elapsed_ms: u64, // 输出事件距步骤（阶段）开始的毫秒数，其他事件为0
+    pub line_count: u64, // 输出事件为止累计的 stdout + stderr 行数，其他事件为0
+    pub sequence: u64, // 本次执行内的事件序号（从1开始，按分发顺序递增），由执行器在分发事件时填入
+    pub coalesced_line_count: u64, // 合并了多行输出的事件包含的行数（output_batching 或 event_throttle），未合并的事件为0
+}
+
+impl OutputEvent {
+    /// 创建输出事件，script_path 取步骤的脚本路径，由执行器在分发事件时填入的字段及计数字段置为默认值
+    pub fn new(
+        pipeline_name: impl Into<String>,
+        server_name: impl Into<String>,
+        step: Step,
+        output_type: OutputType,
+        content: impl Into<String>,
+        variables: std::sync::Arc<HashMap<String, String>>,
+    ) -> Self {
+        Self {
+            pipeline_name: pipeline_name.into(),
+            server_name: server_name.into(),
+            script_path: step.script.clone(),
+            step,
+            output_type,
+            content: content.into(),
+            timestamp: std::time::Instant::now(),
+            variables,
+            run_id: String::new(),
+            pipeline_index: 0,
+            step_index: None,
+            total_steps: 0,
+            elapsed_ms: 0,
+            line_count: 0,
+            sequence: 0,
+            coalesced_line_count: 0,
+        }
+    }
 }
 
 /// 输出回调函数类型
 pub type OutputCallback = std::sync::Arc<dyn Fn(OutputEvent) + Send + Sync>;
 
+/// 异步输出回调函数类型，通过 RemoteExecutor::subscribe_async 注册，执行器等待返回的 future 完成
+pub type AsyncOutputCallback = std::sync::Arc<dyn Fn(OutputEvent) -> futures::future::BoxFuture<'static, ()> + Send + Sync>;
+
+/// 输出行过滤器：在行写入执行结果和输出事件之前改写该行，返回 None 丢弃该行
+pub type LineFilter = std::sync::Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// 私钥口令回调：使用私钥认证且没有配置口令时，在建立连接前调用（libssh2 后端在阻塞线程中调用）
+pub type PassphraseCallback = std::sync::Arc<dyn Fn() -> String + Send + Sync>;
+
+/// 连接方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionKind {
+    Ssh,
+    Local,
+}
+
+/// 执行所用连接的审计信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub kind: ConnectionKind,
+    pub resolved_ip: Option<String>, // 实际连接的IP（主机名可能轮询解析），经代理连接或本地执行时为 None
+    pub host_key_fingerprint_sha256: Option<String>, // 服务器主机密钥指纹，格式同 ssh-keygen -l：SHA256:<base64>
+    pub server_banner: Option<String>, // 服务器标识（如 SSH-2.0-OpenSSH_9.6），russh 后端不提供
+    pub username: String, // 认证使用的用户，本地执行为当前用户
+    pub port: Option<u16>,
+}
+
+impl ConnectionInfo {
+    /// 本地执行的连接信息，用户取自 USER / LOGNAME 环境变量
+    pub fn local() -> Self {
+        let username = std::env::var("USER")
+            .or_else(|_| std::env::var("LOGNAME"))
+            .unwrap_or_else(|_| "unknown".to_string());
+        Self {
+            kind: ConnectionKind::Local,
+            resolved_ip: None,
+            host_key_fingerprint_sha256: None,
+            server_banner: None,
+            username,
+            port: None,
+        }
+    }
+}
+
 /// 执行结果
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExecutionResult {
@@ -122,6 +563,46 @@ pub struct ExecutionResult {
     pub exit_code: i32,
     pub execution_time_ms: u64,
     pub error_message: Option<String>,
+    #[serde(default)]
+    pub had_invalid_utf8: bool, // 输出中是否包含无法解码的字节（已替换为U+FFFD）
+    #[serde(default)]
+    pub artifacts: Vec<CollectedArtifact>, // 执行后收集到的产物
+    #[serde(default)]
+    pub connect_time_ms: u64, // 建立连接（TCP、握手、认证）耗时，本地执行为0
+    #[serde(default)]
+    pub command_time_ms: u64, // 脚本执行耗时，不含建立连接
+    #[serde(default)]
+    pub fallback_reason: Option<String>, // SSH连接失败后改为本地执行时的连接错误
+    #[serde(default)]
+    pub rendered_script: Option<String>, // 实际发送给shell的脚本（变量替换并拼接全局脚本后），多个阶段按执行顺序拼接；已脱敏，超出上限时截断
+    #[serde(default)]
+    pub script_sha256: Option<String>, // rendered_script 脱敏和截断之前的完整内容的SHA-256（十六进制）
+    #[serde(default)]
+    pub connection_info: Option<ConnectionInfo>, // 执行所用连接的审计信息，未连接（如跳过、连接失败）时为 None
+    #[serde(default)]
+    pub answered_prompts: Vec<String>, // 按应答顺序记录已应答的交互提示（步骤 responses 的 expect_regex）
+    #[serde(default)]
+    pub ssh_trace: Option<SshConnectionTrace>, // 建立SSH连接各阶段的耗时，仅 ssh 后端提供
+    #[serde(default)]
+    pub skip_reason: Option<String>, // 跳过执行的原因（condition 为假、幂等守卫命中），执行了脚本时为 None
+}
+
+/// SSH认证方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SshAuthMethod {
+    Password,
+    PublicKey,
+}
+
+/// 建立SSH连接各阶段的耗时，用于区分慢在网络（TCP）、SSH握手还是认证（如 LDAP/PAM）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SshConnectionTrace {
+    pub tcp_connect_ms: u64,   // TCP连接（配置了代理时包含代理协商）
+    pub handshake_ms: u64,     // SSH握手
+    pub auth_ms: u64,          // 认证
+    pub total_connect_ms: u64, // 从开始连接到认证完成的总耗时
+    pub method: SshAuthMethod, // 使用的认证方式
 }
 
 /// 步骤执行结果
@@ -132,8 +613,73 @@ pub struct StepExecutionResult {
     pub server_name: String,
     pub execution_result: ExecutionResult,
     pub overall_success: bool,
+    #[serde(default)]
+    pub status: StepStatus, // 步骤状态，比 overall_success 多区分跳过与未执行
     pub scritp_path:String,
     pub execution_time_ms: u64,
+    pub exit_code: i32, // 原始退出码
+    #[serde(default)]
+    pub exit_class: ExitClass, // 按 exit_code_meanings 解析后的分类
+    #[serde(default)]
+    pub attempts: u32, // 实际执行次数（含重试）
+    #[serde(default)]
+    pub skipped: bool, // 是否因 condition 为假而跳过
+    #[serde(default)]
+    pub artifacts: Vec<CollectedArtifact>, // 步骤收集到的产物及其大小
+    #[serde(default)]
+    pub run_id: String, // 所属执行的ID
+    #[serde(default)]
+    pub extracted_variables: HashMap<String, String>, // 本步骤新设置或修改的变量（提取、汇总输出）
+    #[serde(default)]
+    pub variable_diff: Option<VariableDiff>, // 本步骤前后的变量差异，含 step 级 variables；未执行的步骤为 None
+    #[serde(default)]
+    pub executed_locally: bool, // 是否因SSH连接失败而改为本地执行（客户端 local_fallback）
+    #[serde(default)]
+    pub stdout_line_count: usize, // stdout 行数
+    #[serde(default)]
+    pub stderr_line_count: usize, // stderr 行数
+    #[serde(default)]
+    pub output_rate_lps: f64, // 执行期间平均每秒输出的行数（stdout + stderr）
+    #[serde(default)]
+    pub ssh_trace: Option<SshConnectionTrace>, // 建立SSH连接各阶段的耗时，本地执行、russh 后端为 None
+    #[serde(default)]
+    pub failover_used: bool, // 是否因主服务器连接失败而在 servers_failover 中的备用服务器上执行，此时 server_name 为备用服务器
+    #[serde(default)]
+    pub primary_server: String, // servers 中计划执行的服务器
+}
+
+/// 一段执行（如一个步骤）前后的变量差异
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariableDiff {
+    pub added: HashMap<String, String>,
+    pub modified: HashMap<String, (String, String)>, // 变量名 -> (原值, 新值)
+    pub removed: Vec<String>,
+}
+
+impl VariableDiff {
+    /// 比较前后两份变量，removed 按变量名排序
+    pub fn between(before: &HashMap<String, String>, after: &HashMap<String, String>) -> Self {
+        let mut diff = Self::default();
+        for (name, value) in after {
+            match before.get(name) {
+                None => {
+                    diff.added.insert(name.clone(), value.clone());
+                }
+                Some(old) if old != value => {
+                    diff.modified.insert(name.clone(), (old.clone(), value.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = before.keys().filter(|name| !after.contains_key(*name)).cloned().collect();
+        diff.removed.sort();
+        diff
+    }
+
+    /// 没有任何变量变化（如步骤没有提取或修改变量）
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
 }
 
 /// 流水线执行结果
@@ -141,10 +687,44 @@ pub struct StepExecutionResult {
 pub struct PipelineExecutionResult {
     pub pipeline_name: String,
     pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
     pub step_results: Vec<StepExecutionResult>,
     pub overall_success: bool,
     pub total_execution_time_ms: u64,
-} 
+    #[serde(default)]
+    pub run_id: String, // 所属执行的ID
+    #[serde(default)]
+    pub final_variables: HashMap<String, String>, // 流水线结束时的全部变量
+    #[serde(default)]
+    pub outputs: HashMap<String, String>, // 按流水线 outputs 导出的变量
+    #[serde(default)]
+    pub group_summaries: HashMap<String, GroupSummary>, // 按步骤分组汇总的结果，键为分组名
+    #[serde(default)]
+    pub failure_reason: Option<String>, // 流水线不是因为步骤失败而失败时的原因（如 pipeline timeout exceeded）
+}
+
+/// 一个步骤分组的执行汇总
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupSummary {
+    pub success: bool,                // 组内所有步骤都成功
+    pub total_steps: usize,           // 组内步骤数
+    pub successful_steps: usize,      // 成功的步骤数（跳过的步骤算成功）
+    pub total_execution_time_ms: u64, // 组内各步骤耗时之和，步骤在多台服务器上执行时取最长的一台
+}
+
+/// 整次执行的结束方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionOutcome {
+    /// 正常执行结束（成功或失败见 success）
+    #[default]
+    Completed,
+    /// 被取消（如收到 SIGINT/SIGTERM），结果只包含取消前完成的步骤
+    Interrupted,
+}
 
 /// 流水线执行结果
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -152,4 +732,19 @@ pub struct ShellExecutionResult {
     pub pipeline_results: Vec<PipelineExecutionResult>,
     pub success: bool,
     pub reason: String,
-} 
\ No newline at end of file
+    #[serde(default)]
+    pub run_id: String, // 所属执行的ID
+    #[serde(default)]
+    pub outcome: ExecutionOutcome, // 正常结束还是被取消
+} 
+impl ShellExecutionResult {
+    /// 按执行顺序合并所有流水线各步骤提取的变量，后设置的值覆盖先设置的
+    pub fn all_extracted_variables(&self) -> HashMap<String, String> {
+        self.pipeline_results
+            .iter()
+            .flat_map(|pipeline| &pipeline.step_results)
+            .flat_map(|step| &step.extracted_variables)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::models::{ArtifactSpec, CollectedArtifact, ExecutionResult, OutputCallback, OutputEvent, OutputType, Step};
+use crate::vars::VariableManager;
+
+/// 产物路径：目录部分为字面路径，文件名部分可以包含通配符
+pub(crate) struct ArtifactPattern {
+    pub dir: String,
+    name: Regex,
+}
+
+impl ArtifactPattern {
+    /// 解析产物路径，通配符只允许出现在最后一段文件名中
+    pub fn parse(path: &str) -> Result<Self> {
+        let (dir, name) = match path.rfind('/') {
+            Some(0) => ("/", &path[1..]),
+            Some(pos) => (&path[..pos], &path[pos + 1..]),
+            None => (".", path),
+        };
+        if dir.contains(['*', '?', '[']) {
+            return Err(anyhow::anyhow!("Artifact path '{}': wildcards are only supported in the file name", path));
+        }
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Artifact path '{}' has no file name", path));
+        }
+        Ok(Self { dir: dir.to_string(), name: glob_to_regex(name)? })
+    }
+
+    /// 文件名是否匹配
+    pub fn is_match(&self, file_name: &str) -> bool {
+        self.name.is_match(file_name)
+    }
+
+    /// 目录下某个文件的完整路径
+    pub fn path_of(&self, file_name: &str) -> String {
+        match self.dir.as_str() {
+            "/" => format!("/{}", file_name),
+            dir => format!("{}/{}", dir, file_name),
+        }
+    }
+}
+
+/// 把 shell 通配符（* ? [..]）转换为匹配整个文件名的正则
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => {
+                pattern.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    pattern.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '^' {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                pattern.push(']');
+            }
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).context(format!("Invalid artifact pattern: {}", glob))
+}
+
+/// 步骤结束后是否需要收集产物
+pub(crate) fn should_collect(step: &Step, success: bool) -> bool {
+    step.artifacts.as_ref().is_some_and(|a| !a.is_empty()) && (success || step.collect_on_failure)
+}
+
+/// 收集到的文件在本地的保存路径：local_dir/{server_name}/{file_name}，目录不存在时创建
+pub(crate) fn local_destination(local_dir: &str, server_name: &str, file_name: &str) -> Result<PathBuf> {
+    let dir = Path::new(local_dir).join(server_name);
+    std::fs::create_dir_all(&dir).context(format!("Failed to create artifact directory: {}", dir.display()))?;
+    Ok(dir.join(file_name))
+}
+
+/// 规则没有匹配到文件或收集失败：必需的产物返回错误，可选的只记录日志
+pub(crate) fn handle_missing(spec: &ArtifactSpec, server_name: &str, reason: String) -> Result<()> {
+    if spec.required {
+        return Err(anyhow::anyhow!("Required artifact '{}' on '{}': {}", spec.remote_path, server_name, reason));
+    }
+    warn!("Optional artifact '{}' on '{}': {}", spec.remote_path, server_name, reason);
+    Ok(())
+}
+
+/// 发送收集到一个产物的日志事件
+pub(crate) fn emit_collected(
+    output_callback: Option<&OutputCallback>,
+    step: &Step,
+    pipeline_name: &str,
+    server_name: &str,
+    variable_manager: &VariableManager,
+    artifact: &CollectedArtifact,
+) {
+    info!("Collected artifact {} -> {} ({} bytes)", artifact.remote_path, artifact.local_path, artifact.size);
+    if let Some(callback) = output_callback {
+        callback(OutputEvent {
+            script_path: artifact.remote_path.clone(),
+            ..OutputEvent::new(
+                pipeline_name.to_string(),
+                server_name.to_string(),
+                step.clone(),
+                OutputType::Log,
+                format!("已收集产物: {} -> {} ({} 字节)", artifact.remote_path, artifact.local_path, artifact.size),
+                variable_manager.snapshot(),
+            )
+        });
+    }
+}
+
+/// 按步骤的产物规则收集文件（同步实现，本地执行与 libssh2 后端共用）
+/// `list` 返回目录下的普通文件名，`fetch` 把文件保存到本地路径并返回大小
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn collect_with(
+    step: &Step,
+    pipeline_name: &str,
+    server_name: &str,
+    variable_manager: &VariableManager,
+    output_callback: Option<&OutputCallback>,
+    mut list: impl FnMut(&str) -> Result<Vec<String>>,
+    mut fetch: impl FnMut(&str, &Path) -> Result<u64>,
+) -> Result<Vec<CollectedArtifact>> {
+    let mut collected = Vec::new();
+    for spec in step.artifacts.iter().flatten() {
+        let remote_path = variable_manager.replace_variables(&spec.remote_path);
+        let local_dir = variable_manager.replace_variables(&spec.local_dir);
+        let pattern = ArtifactPattern::parse(&remote_path)?;
+
+        let names = match list(&pattern.dir) {
+            Ok(names) => names,
+            Err(e) => {
+                handle_missing(spec, server_name, format!("failed to list {}: {}", pattern.dir, e))?;
+                continue;
+            }
+        };
+        let mut matched: Vec<&String> = names.iter().filter(|n| pattern.is_match(n)).collect();
+        matched.sort();
+        if matched.is_empty() {
+            handle_missing(spec, server_name, "no matching files".to_string())?;
+            continue;
+        }
+
+        for name in matched {
+            let source = pattern.path_of(name);
+            let result = local_destination(&local_dir, server_name, name)
+                .and_then(|destination| Ok((fetch(&source, &destination)?, destination)));
+            match result {
+                Ok((size, destination)) => {
+                    let artifact = CollectedArtifact {
+                        remote_path: source,
+                        local_path: destination.to_string_lossy().to_string(),
+                        size,
+                    };
+                    emit_collected(output_callback, step, pipeline_name, server_name, variable_manager, &artifact);
+                    collected.push(artifact);
+                }
+                Err(e) => handle_missing(spec, server_name, format!("failed to fetch {}: {}", source, e))?,
+            }
+        }
+    }
+    Ok(collected)
+}
+
+/// 记录收集结果：成功时保存产物列表，必需产物缺失时把步骤标记为失败
+pub(crate) fn apply_collection(result: &mut ExecutionResult, collection: Result<Vec<CollectedArtifact>>) {
+    match collection {
+        Ok(artifacts) => result.artifacts = artifacts,
+        Err(e) => {
+            result.success = false;
+            result.stderr.push_str(&format!("{}\n", e));
+            result.error_message = Some(e.to_string());
+        }
+    }
+}
+
+/// 从本地文件系统收集产物（未指定服务器的步骤），保存到 local_dir/localhost/
+pub(crate) fn collect_local(
+    step: &Step,
+    pipeline_name: &str,
+    variable_manager: &VariableManager,
+    output_callback: Option<&OutputCallback>,
+) -> Result<Vec<CollectedArtifact>> {
+    collect_with(
+        step,
+        pipeline_name,
+        "localhost",
+        variable_manager,
+        output_callback,
+        |dir| {
+            let mut names = Vec::new();
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+            Ok(names)
+        },
+        |source, destination| Ok(std::fs::copy(source, destination)?),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_artifact_pattern() {
+        let pattern = ArtifactPattern::parse("/tmp/diag-*.tar.gz").unwrap();
+        assert_eq!(pattern.dir, "/tmp");
+        assert!(pattern.is_match("diag-web1.tar.gz"));
+        assert!(!pattern.is_match("diag-web1.tar.gzip"));
+        assert!(!pattern.is_match("xdiag-1.tar.gz"));
+        assert_eq!(pattern.path_of("diag-1.tar.gz"), "/tmp/diag-1.tar.gz");
+
+        let pattern = ArtifactPattern::parse("log-[0-9]?.txt").unwrap();
+        assert_eq!(pattern.dir, ".");
+        assert!(pattern.is_match("log-12.txt"));
+        assert!(!pattern.is_match("log-a1.txt"));
+
+        assert!(ArtifactPattern::parse("/tmp/*/out.log").is_err());
+        assert!(ArtifactPattern::parse("/tmp/").is_err());
+    }
+
+    #[test]
+    fn test_collect_local_copies_matching_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("diag-1.tar.gz"), b"12345").unwrap();
+        std::fs::write(source.path().join("diag-2.tar.gz"), b"12").unwrap();
+        std::fs::write(source.path().join("other.txt"), b"x").unwrap();
+
+        let step = Step {
+            name: "diag".to_string(),
+            artifacts: Some(vec![
+                ArtifactSpec {
+                    remote_path: format!("{}/diag-*.tar.gz", source.path().display()),
+                    local_dir: "{{ out }}".to_string(),
+                    required: true,
+                },
+                ArtifactSpec {
+                    remote_path: format!("{}/missing-*.log", source.path().display()),
+                    local_dir: "{{ out }}".to_string(),
+                    required: false,
+                },
+            ]),
+            ..Default::default()
+        };
+        let variables = VariableManager::new(Some([("out".to_string(), target.path().display().to_string())].into()));
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let callback: OutputCallback = Arc::new(move |e: OutputEvent| sink.lock().unwrap().push(e.content));
+
+        let collected = collect_local(&step, "p", &variables, Some(&callback)).unwrap();
+        assert_eq!(collected.len(), 2);
+        assert_eq!(collected[0].size, 5);
+        assert_eq!(collected[1].size, 2);
+        let copied = target.path().join("localhost").join("diag-1.tar.gz");
+        assert_eq!(collected[0].local_path, copied.to_string_lossy());
+        assert_eq!(std::fs::read(copied).unwrap(), b"12345");
+        assert_eq!(logs.lock().unwrap().len(), 2);
+
+        // 必需的产物缺失时返回错误
+        let mut step = step;
+        step.artifacts.as_mut().unwrap()[1].required = true;
+        let err = collect_local(&step, "p", &variables, None).unwrap_err().to_string();
+        assert!(err.contains("missing-*.log") && err.contains("no matching files"), "{}", err);
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+
+use crate::models::{ClientConfig, PassphraseCallback, SshAuthMethod, SshConfig};
+
+/// SSH登录凭据，连接时覆盖客户端 ssh_config 中的 username / password / private_key_path / private_key_passphrase
+#[derive(Clone, Default)]
+pub struct SshCredentials {
+    pub username: String,
+    pub password: Option<String>,
+    pub private_key_path: Option<String>, // 设置了 password 时优先使用密码认证
+    pub private_key_passphrase: Option<String>, // 加密私钥的口令
+}
+
+impl std::fmt::Debug for SshCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SshCredentials")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "******"))
+            .field("private_key_path", &self.private_key_path)
+            .field("private_key_passphrase", &self.private_key_passphrase.as_ref().map(|_| "******"))
+            .finish()
+    }
+}
+
+impl SshCredentials {
+    /// 把凭据写入SSH配置
+    #[cfg_attr(not(any(feature = "ssh", feature = "russh")), allow(dead_code))]
+    pub(crate) fn apply(self, ssh_config: SshConfig) -> SshConfig {
+        SshConfig {
+            username: self.username,
+            password: self.password,
+            private_key_path: self.private_key_path,
+            private_key_passphrase: self.private_key_passphrase,
+            ..ssh_config
+        }
+    }
+}
+
+/// 按SSH配置选择的认证方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuthMethod<'a> {
+    Password(&'a str),
+    PublicKey { path: &'a str, passphrase: Option<&'a str> },
+}
+
+impl AuthMethod<'_> {
+    /// 不含凭据的认证方式，用于记录连接耗时
+    #[cfg_attr(not(feature = "ssh"), allow(dead_code))]
+    pub(crate) fn kind(&self) -> SshAuthMethod {
+        match self {
+            AuthMethod::Password(_) => SshAuthMethod::Password,
+            AuthMethod::PublicKey { .. } => SshAuthMethod::PublicKey,
+        }
+    }
+}
+
+/// 选择认证方式：配置了 password 时优先使用密码认证，其次是 private_key_path
+#[cfg_attr(not(any(feature = "ssh", feature = "russh")), allow(dead_code))]
+pub(crate) fn auth_method(ssh_config: &SshConfig) -> Result<AuthMethod<'_>> {
+    match (&ssh_config.password, &ssh_config.private_key_path) {
+        (Some(password), _) => Ok(AuthMethod::Password(password)),
+        (None, Some(path)) => Ok(AuthMethod::PublicKey { path, passphrase: ssh_config.private_key_passphrase.as_deref() }),
+        (None, None) => Err(anyhow!("No authentication method provided")),
+    }
+}
+
+/// 使用私钥认证（没有密码）且没有口令时，调用口令回调获取口令
+#[cfg_attr(not(any(feature = "ssh", feature = "russh")), allow(dead_code))]
+pub(crate) fn resolve_passphrase(ssh_config: SshConfig, callback: Option<&PassphraseCallback>) -> SshConfig {
+    match callback {
+        Some(callback) if ssh_config.password.is_none()
+            && ssh_config.private_key_path.is_some()
+            && ssh_config.private_key_passphrase.is_none() =>
+        {
+            SshConfig { private_key_passphrase: Some(callback()), ..ssh_config }
+        }
+        _ => ssh_config,
+    }
+}
+
+/// 认证提供者：执行器在每次建立SSH连接前调用，可在运行时从 Vault 或云密钥管理服务获取凭据，
+/// 避免把密码写在YAML中。返回错误时按连接失败处理（run_once 换下一台服务器、local_fallback 改为本地执行）
+pub trait AuthProvider: Send + Sync {
+    fn credentials(&self, client: &ClientConfig) -> Result<SshCredentials>;
+}
+
+/// 默认的认证提供者：直接使用客户端 ssh_config 中的凭据
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StaticAuthProvider;
+
+impl AuthProvider for StaticAuthProvider {
+    fn credentials(&self, client: &ClientConfig) -> Result<SshCredentials> {
+        let ssh_config = client.ssh_config.as_ref()
+            .ok_or_else(|| anyhow!("SSH configuration not found for client '{}'", client.name))?;
+        Ok(SshCredentials {
+            username: ssh_config.username.clone(),
+            password: ssh_config.password.clone(),
+            private_key_path: ssh_config.private_key_path.clone(),
+            private_key_passphrase: ssh_config.private_key_passphrase.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_provider_reads_ssh_config() {
+        let client: ClientConfig = serde_yaml::from_str(r#"
+name: "web1"
+execution_method: ssh
+ssh_config:
+  host: "10.0.0.1"
+  port: 22
+  username: "deploy"
+  private_key_path: "/keys/deploy"
+"#).unwrap();
+        let credentials = StaticAuthProvider.credentials(&client).unwrap();
+        assert_eq!(credentials.username, "deploy");
+        assert_eq!(credentials.password, None);
+        assert_eq!(credentials.private_key_path.as_deref(), Some("/keys/deploy"));
+
+        let vaulted = SshCredentials { password: Some("s3cr3t".to_string()), ..credentials };
+        assert!(!format!("{:?}", vaulted).contains("s3cr3t"));
+        let ssh_config = vaulted.apply(client.ssh_config.clone().unwrap());
+        assert_eq!((ssh_config.host.as_str(), ssh_config.password.as_deref()), ("10.0.0.1", Some("s3cr3t")));
+    }
+
+    #[test]
+    fn test_auth_method_prefers_password() {
+        let mut ssh_config: SshConfig = serde_yaml::from_str(r#"
+host: "10.0.0.1"
+port: 22
+username: "deploy"
+password: "s3cr3t"
+private_key_path: "/keys/deploy"
+private_key_passphrase: "open sesame"
+"#).unwrap();
+        assert_eq!(auth_method(&ssh_config).unwrap(), AuthMethod::Password("s3cr3t"));
+        ssh_config.password = None;
+        assert_eq!(
+            auth_method(&ssh_config).unwrap(),
+            AuthMethod::PublicKey { path: "/keys/deploy", passphrase: Some("open sesame") }
+        );
+        ssh_config.private_key_path = None;
+        assert_eq!(auth_method(&ssh_config).unwrap_err().to_string(), "No authentication method provided");
+    }
+
+    #[test]
+    fn test_passphrase_round_trip_and_callback() {
+        let client: ClientConfig = serde_yaml::from_str(r#"
+name: "web1"
+execution_method: ssh
+ssh_config:
+  host: "10.0.0.1"
+  port: 22
+  username: "deploy"
+  private_key_path: "/keys/deploy"
+  private_key_passphrase: "open sesame"
+"#).unwrap();
+        let yaml = serde_yaml::to_string(&client).unwrap();
+        let parsed: ClientConfig = serde_yaml::from_str(&yaml).unwrap();
+        let ssh_config = parsed.ssh_config.unwrap();
+        assert_eq!(ssh_config.private_key_passphrase.as_deref(), Some("open sesame"));
+
+        let credentials = StaticAuthProvider.credentials(&client).unwrap();
+        assert!(!format!("{:?}", credentials).contains("open sesame"));
+
+        // 配置了口令时不调用回调，没有口令时使用回调返回的口令
+        let callback: PassphraseCallback = std::sync::Arc::new(|| "from prompt".to_string());
+        let resolved = resolve_passphrase(ssh_config.clone(), Some(&callback));
+        assert_eq!(resolved.private_key_passphrase.as_deref(), Some("open sesame"));
+        let without = SshConfig { private_key_passphrase: None, ..ssh_config };
+        let resolved = resolve_passphrase(without, Some(&callback));
+        assert_eq!(resolved.private_key_passphrase.as_deref(), Some("from prompt"));
+    }
+}
@@ -1,20 +1,102 @@
-use anyhow::{Context, Error, Result};
-use std::fs;
+use anyhow::{Context, Result};
 use std::process::{Command, Stdio};
-use std::time::Instant;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command as TokioCommand;
 use tracing::{error, info};
 use tempfile;
 use std::io::Write;
 
-use crate::models::{ExecutionResult, OutputCallback, OutputEvent, OutputType, Step};
+use crate::models::{ConnectionInfo, ExecutionResult, LineFilter, OutputCallback, OutputEvent, OutputType, Step};
+use crate::ssh::artifacts::{apply_collection, collect_local, should_collect};
+use crate::ssh::prompts::{prompt_answered_event, PromptResponder, PROMPT_POLL_INTERVAL};
+use crate::ssh::{build_script_preamble, check_script_file, decode_output, emit_rendered_script, filter_output_line, guard_callback, merge_phase_result, render_phase_script, resolve_encoding, script_error_result, step_phases};
+use crate::vars::VariableManager;
+
+/// 超时后发送 SIGTERM 到 SIGKILL 之间默认等待的秒数
+const DEFAULT_GRACEFUL_SHUTDOWN_SECONDS: u64 = 5;
+
+/// 超时终止进程后等待读取剩余输出的时间，脚本的后台子进程可能仍占用输出管道
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 读取任务已收集的输出，读取任务被中止时仍能取到已读到的部分
+#[derive(Default)]
+struct CapturedOutput {
+    content: String,
+    had_invalid_utf8: bool,
+}
+
+/// 本地执行时的提示应答：读取任务把输出片段交给 responder，应答经通道交给写入脚本stdin的任务，
+/// 全部提示应答后发送 None 关闭stdin
+struct PromptWatch {
+    responder: Mutex<PromptResponder>,
+    replies: tokio::sync::mpsc::UnboundedSender<Option<String>>,
+    encoding: Option<&'static encoding_rs::Encoding>,
+    on_answer: Box<dyn Fn(&str) + Send + Sync>,
+}
+
+impl PromptWatch {
+    fn observe(&self, chunk: &[u8]) {
+        let (text, _) = decode_output(chunk, self.encoding);
+        let mut responder = self.responder.lock().unwrap_or_else(|e| e.into_inner());
+        for (expect, send) in responder.feed(&text) {
+            (self.on_answer)(&expect);
+            let _ = self.replies.send(Some(send));
+        }
+        if responder.is_done() {
+            let _ = self.replies.send(None);
+        }
+    }
+
+    /// 等待中的提示超时后返回错误信息
+    async fn expired(&self) -> String {
+        loop {
+            tokio::time::sleep(PROMPT_POLL_INTERVAL).await;
+            if let Some(message) = self.responder.lock().unwrap_or_else(|e| e.into_inner()).expired() {
+                return message;
+            }
+        }
+    }
+}
+
+/// 读取一行追加到 buf（含换行符），返回本次读取的字节数，0 表示输出结束
+/// 配置了提示应答时，读到的每段输出（包括还没有换行、等待输入的提示）先交给 watch
+async fn read_line_watching<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    watch: Option<&PromptWatch>,
+) -> std::io::Result<usize> {
+    let Some(watch) = watch else {
+        return reader.read_until(b'\n', buf).await;
+    };
+    let start = buf.len();
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(buf.len() - start);
+        }
+        let (used, line_end) = match available.iter().position(|b| *b == b'\n') {
+            Some(pos) => (pos + 1, true),
+            None => (available.len(), false),
+        };
+        watch.observe(&available[..used]);
+        buf.extend_from_slice(&available[..used]);
+        reader.consume(used);
+        if line_end {
+            return Ok(buf.len() - start);
+        }
+    }
+}
 
 /// 本地脚本执行器
 pub struct LocalExecutor;
 
 impl LocalExecutor {
     /// 在本地执行shell脚本（支持实时输出）
+    /// 依次执行步骤的 before_script / script / after_script，任一阶段失败即停止
+    #[allow(clippy::too_many_arguments)]
     pub async fn execute_script_with_realtime_output(
         script: Option<String>,
         global_scripts:Vec<String>,
@@ -22,56 +104,68 @@ impl LocalExecutor {
         pipeline_name: &str,
         _step_name: &str,
         output_callback: Option<OutputCallback>,
-        variables: std::collections::HashMap<String, String>,
+        variable_manager: VariableManager,
+        line_filter: Option<LineFilter>,
     ) -> Result<ExecutionResult> {
-        let start_time = Instant::now();
-        let pipeline_name = pipeline_name.to_string();
-        
-        let script_path_str = step.script.clone();
-        let script_path = std::path::Path::new(&script_path_str);
-        if !script_path.exists() {
-            return Err(anyhow::anyhow!("Script '{}' not found", script_path_str));
-        }
-
-        // 读取脚本内容并进行变量替换
-        let mut script_content = std::fs::read_to_string(&script_path)
-            .map_err(|e| anyhow::anyhow!("Failed to read script file '{}': {}", script_path_str, e))?;
-        for (key, value) in &variables {
-            let placeholder = format!("{{{{ {} }}}}", key);
-            script_content = script_content.replace(&placeholder, value);
-        }
-
-        let mut gloabl_script_content = global_scripts.iter()
-        .map(|v|std::fs::read_to_string(v).context(format!("read file:[{}]", v)))
-        .fold(Ok("".to_string()), |p:Result<String>,v|{
-            if p.is_err(){
-                return p;
+        let phases = step_phases(step);
+        let output_callback = output_callback.map(guard_callback);
+        for script_path in &phases {
+            if let Err(e) = check_script_file(script_path) {
+                return Ok(script_error_result(script_path, &e));
             }
+        }
 
-            if v.is_err(){
-                return Err(Error::msg(format!("{:?}", v.err())));
+        // 公共前置内容：全局脚本 + 流水线脚本
+        let preamble = build_script_preamble(&global_scripts, script.as_deref())?;
+
+        let mut execution_result: Option<ExecutionResult> = None;
+        for script_path in phases {
+            let phase_result = Self::execute_phase(
+                &preamble,
+                script_path,
+                step,
+                pipeline_name,
+                output_callback.clone(),
+                &variable_manager,
+                line_filter.clone(),
+            ).await?;
+            let success = phase_result.success;
+            execution_result = Some(merge_phase_result(execution_result, phase_result));
+            if !success {
+                break;
             }
-            let content = v.unwrap();
-
-            let mut s = p.unwrap_or_default();
-
-            s.push_str("\n");
-            s.push_str(&content);
+        }
 
-            return  Ok(s.clone());
-        })?;
+        let mut execution_result = execution_result
+            .ok_or_else(|| anyhow::anyhow!("Step '{}' has no script to execute", step.name))?;
 
-        if let Some(script_header) = script {
-            let cont =  fs::read_to_string(&script_header)
-                .map_err(|e| anyhow::anyhow!("Failed to read script header file '{}': {}", script_header, e))?;
-            gloabl_script_content.push_str("\n");
-            gloabl_script_content.push_str(&cont);
+        // 从本地文件系统收集产物
+        if should_collect(step, execution_result.success) {
+            let collection = collect_local(step, pipeline_name, &variable_manager, output_callback.as_ref());
+            apply_collection(&mut execution_result, collection);
         }
 
-        gloabl_script_content.push_str("\n");
-        gloabl_script_content.push_str(&script_content);
+        Ok(execution_result)
+    }
 
-        let script_content = gloabl_script_content.clone();
+    /// 执行单个阶段的脚本文件
+    async fn execute_phase(
+        preamble: &str,
+        script_path: &str,
+        step: &Step,
+        pipeline_name: &str,
+        output_callback: Option<OutputCallback>,
+        variable_manager: &VariableManager,
+        line_filter: Option<LineFilter>,
+    ) -> Result<ExecutionResult> {
+        let start_time = Instant::now();
+        let pipeline_name = pipeline_name.to_string();
+        let variables = variable_manager.snapshot();
+        let script_path_str = script_path.to_string();
+
+        // 读取脚本内容并进行变量替换
+        let script_content = render_phase_script(preamble, script_path, variable_manager)?;
+        emit_rendered_script(output_callback.as_ref(), step, &pipeline_name, "localhost", &script_path_str, &script_content, variable_manager);
 
         // 写入临时文件
         let mut temp_file = tempfile::NamedTempFile::new()
@@ -85,20 +179,22 @@ impl LocalExecutor {
         // 发送开始执行的日志
         if let Some(callback) = &output_callback {
             let event = OutputEvent {
-                pipeline_name: pipeline_name.clone(),
-                server_name: "localhost".to_string(),
-                step: step.clone(),
-                script_path:script_path_str.clone(),
-                output_type: OutputType::Log,
-                content: format!("开始执行本地脚本: {} (内容已变量替换)", script_path_str),
-                timestamp: Instant::now(),
-                variables: variables.clone(),
+                script_path: script_path_str.clone(),
+                ..OutputEvent::new(
+                    pipeline_name.clone(),
+                    "localhost",
+                    step.clone(),
+                    OutputType::Log,
+                    format!("开始执行本地脚本: {} (内容已变量替换)", script_path_str),
+                    variables.clone(),
+                )
             };
             callback(event);
         }
 
         // 设置超时
         let timeout_seconds = step.timeout_seconds.unwrap_or(60);
+        let responder = PromptResponder::new(step, Duration::from_secs(timeout_seconds))?;
         
         // 创建异步命令
         let mut command = TokioCommand::new("bash");
@@ -106,13 +202,19 @@ impl LocalExecutor {
         command.current_dir(std::env::current_dir()?);
         
         // 设置环境变量
-        for (key, value) in &variables {
+        for (key, value) in variables.iter() {
             command.env(key, value);
         }
 
         // 设置标准输出和错误输出
         command.stdout(Stdio::piped());
         command.stderr(Stdio::piped());
+        // 配置了提示应答时通过stdin写入应答
+        if responder.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        // 执行被取消（任务被中止）时结束脚本进程
+        command.kill_on_drop(true);
 
         // 执行命令
         let mut child = command.spawn()
@@ -121,6 +223,31 @@ impl LocalExecutor {
         let stdout = child.stdout.take().expect("Failed to capture stdout");
         let stderr = child.stderr.take().expect("Failed to capture stderr");
 
+        let watch = match (responder, child.stdin.take()) {
+            (Some(responder), Some(mut stdin)) => {
+                let (replies, mut pending) = tokio::sync::mpsc::unbounded_channel::<Option<String>>();
+                tokio::spawn(async move {
+                    while let Some(Some(reply)) = pending.recv().await {
+                        if stdin.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                let encoding = resolve_encoding(step.encoding.as_deref());
+                let (callback, step, pipeline_name, script_path, variables) =
+                    (output_callback.clone(), step.clone(), pipeline_name.clone(), script_path_str.clone(), variables.clone());
+                let on_answer = move |expect: &str| {
+                    info!("Answered prompt '{}' in local script '{}'", expect, script_path);
+                    if let Some(callback) = &callback {
+                        callback(prompt_answered_event(&step, &pipeline_name, "localhost", &script_path, expect, variables.clone()));
+                    }
+                };
+                Some(Arc::new(PromptWatch { responder: Mutex::new(responder), replies, encoding, on_answer: Box::new(on_answer) }))
+            }
+            _ => None,
+        };
+        let (stdout_watch, stderr_watch) = (watch.clone(), watch.clone());
+
         // 克隆必要的数据用于异步任务
         let step_clone = step.clone();
         let pipeline_name1 = pipeline_name.clone();
@@ -130,90 +257,159 @@ impl LocalExecutor {
         let output_callback_clone = output_callback.clone();
         let output_callback_clone2 = output_callback.clone();
         let script_path = script_path_str.clone();
+        let encoding = resolve_encoding(step.encoding.as_deref());
+        let line_filter2 = line_filter.clone();
+
+        let stdout_captured = Arc::new(Mutex::new(CapturedOutput::default()));
+        let stderr_captured = Arc::new(Mutex::new(CapturedOutput::default()));
+        let (stdout_sink, stderr_sink) = (stdout_captured.clone(), stderr_captured.clone());
+        // stdout 与 stderr 共同累计的行数
+        let line_count = Arc::new(AtomicU64::new(0));
+        let line_count2 = line_count.clone();
 
         // 创建输出读取任务
-        let stdout_task = tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-            let mut content = String::new();
+        let mut stdout_task = tokio::spawn(async move {
+            let mut reader = BufReader::new(stdout);
+            let mut buf = Vec::new();
             
-            while let Ok(Some(line)) = lines.next_line().await {
-                content.push_str(&line);
-                content.push('\n');
+            // 按原始字节读取每一行，非UTF-8内容有损转换而不是中断读取
+            while let Ok(n) = read_line_watching(&mut reader, &mut buf, stdout_watch.as_deref()).await {
+                if n == 0 {
+                    break;
+                }
+                let (line, invalid) = decode_output(trim_line_ending(&buf), encoding);
+                buf.clear();
+                let filtered = filter_output_line(line_filter.as_ref(), line);
+                let mut captured = stdout_sink.lock().unwrap_or_else(|e| e.into_inner());
+                captured.had_invalid_utf8 |= invalid;
+                let Some(line) = filtered else {
+                    continue;
+                };
+                captured.content.push_str(&line);
+                captured.content.push('\n');
+                drop(captured);
+                let line_count = line_count.fetch_add(1, Ordering::SeqCst) + 1;
                 
                 // 发送实时输出
                 if let Some(callback) = &output_callback_clone {
                     let event = OutputEvent {
-                        pipeline_name: pipeline_name1.to_string(),
-                        server_name: "localhost".to_string(),
-                        step: step_clone.clone(),
-                        script_path:script_path.clone(),
-                        output_type: OutputType::Stdout,
-                        content: line,
-                        timestamp: Instant::now(),
-                        variables: variables_clone.clone(),
+                        script_path: script_path.clone(),
+                        elapsed_ms: start_time.elapsed().as_millis() as u64,
+                        line_count,
+                        ..OutputEvent::new(
+                            pipeline_name1.to_string(),
+                            "localhost",
+                            step_clone.clone(),
+                            OutputType::Stdout,
+                            line,
+                            variables_clone.clone(),
+                        )
                     };
                     callback(event);
                 }
             }
-            content
         });
 
         let script_path = script_path_str.clone();
         let step_clone2 = step.clone();
-        let stderr_task = tokio::spawn(async move {
-            let reader = BufReader::new(stderr);
-            let mut lines = reader.lines();
-            let mut content = String::new();
+        let mut stderr_task = tokio::spawn(async move {
+            let mut reader = BufReader::new(stderr);
+            let mut buf = Vec::new();
             
-            while let Ok(Some(line)) = lines.next_line().await {
-                content.push_str(&line);
-                content.push('\n');
+            // 按原始字节读取每一行，非UTF-8内容有损转换而不是中断读取
+            while let Ok(n) = read_line_watching(&mut reader, &mut buf, stderr_watch.as_deref()).await {
+                if n == 0 {
+                    break;
+                }
+                let (line, invalid) = decode_output(trim_line_ending(&buf), encoding);
+                buf.clear();
+                let filtered = filter_output_line(line_filter2.as_ref(), line);
+                let mut captured = stderr_sink.lock().unwrap_or_else(|e| e.into_inner());
+                captured.had_invalid_utf8 |= invalid;
+                let Some(line) = filtered else {
+                    continue;
+                };
+                captured.content.push_str(&line);
+                captured.content.push('\n');
+                drop(captured);
+                let line_count = line_count2.fetch_add(1, Ordering::SeqCst) + 1;
                 
                 // 发送实时输出
                 if let Some(callback) = &output_callback_clone2 {
                     let event = OutputEvent {
-                        pipeline_name: pipeline_name2.to_string(),
-                        server_name: "localhost".to_string(),
-                        step: step_clone2.clone(),
-                        script_path:script_path.clone(),
-                        output_type: OutputType::Stderr,
-                        content: line,
-                        timestamp: Instant::now(),
-                        variables: variables_clone2.clone(),
+                        script_path: script_path.clone(),
+                        elapsed_ms: start_time.elapsed().as_millis() as u64,
+                        line_count,
+                        ..OutputEvent::new(
+                            pipeline_name2.to_string(),
+                            "localhost",
+                            step_clone2.clone(),
+                            OutputType::Stderr,
+                            line,
+                            variables_clone2.clone(),
+                        )
                     };
                     callback(event);
                 }
             }
-            content
         });
 
-        // 等待命令完成（带超时）
-        let status = tokio::time::timeout(
-            std::time::Duration::from_secs(timeout_seconds),
-            child.wait()
-        ).await;
+        // 等待命令完成（带超时）；等待中的提示超时与步骤超时一样结束脚本
+        let wait = tokio::time::timeout(Duration::from_secs(timeout_seconds), child.wait());
+        let mut prompt_expired = None;
+        let status = match &watch {
+            Some(watch) => tokio::select! {
+                status = wait => status.map_err(|_| ()),
+                message = watch.expired() => {
+                    prompt_expired = Some(message);
+                    Err(())
+                }
+            },
+            None => wait.await.map_err(|_| ()),
+        };
 
-        let exit_code = match status {
+        let (exit_code, timed_out) = match status {
             Ok(Ok(exit_status)) => {
-                exit_status.code().unwrap_or(-1)
+                (exit_status.code().unwrap_or(-1), false)
             }
             Ok(Err(e)) => {
                 error!("Local script execution failed: {}", e);
                 return Err(anyhow::anyhow!("Local script execution failed: {}", e));
             }
-            Err(_) => {
-                // 超时，强制终止进程
-                let _ = child.kill().await;
-                return Err(anyhow::anyhow!("Local script execution timed out after {} seconds", timeout_seconds));
+            Err(()) => {
+                // 超时：先发送 SIGTERM 让脚本清理，等待 graceful_shutdown_seconds 后仍未退出再强制终止
+                let grace = Duration::from_secs(step.graceful_shutdown_seconds.unwrap_or(DEFAULT_GRACEFUL_SHUTDOWN_SECONDS));
+                match &prompt_expired {
+                    Some(message) => info!("Local script '{}': {}, sending SIGTERM", script_path_str, message),
+                    None => info!("Local script '{}' timed out after {}s, sending SIGTERM", script_path_str, timeout_seconds),
+                }
+                if let Some(pid) = child.id() {
+                    let _ = TokioCommand::new("kill").arg("-TERM").arg(pid.to_string()).status().await;
+                }
+                if !matches!(tokio::time::timeout(grace, child.wait()).await, Ok(Ok(_))) {
+                    info!("Local script '{}' did not exit within {}s, sending SIGKILL", script_path_str, grace.as_secs());
+                    let _ = child.kill().await;
+                }
+                (-1, true)
             }
         };
 
-        // 等待输出读取完成
-        let (stdout_result, stderr_result) = tokio::join!(stdout_task, stderr_task);
-        
-        let stdout_content = stdout_result.unwrap_or_default();
-        let stderr_content = stderr_result.unwrap_or_default();
+        // 等待输出读取完成；超时终止时脚本的后台进程可能仍占用输出管道，只等待一小段时间
+        if timed_out {
+            let drained = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, async {
+                let _ = tokio::join!(&mut stdout_task, &mut stderr_task);
+            }).await;
+            if drained.is_err() {
+                stdout_task.abort();
+                stderr_task.abort();
+            }
+        } else {
+            let _ = tokio::join!(stdout_task, stderr_task);
+        }
+
+        let take = |captured: &Mutex<CapturedOutput>| std::mem::take(&mut *captured.lock().unwrap_or_else(|e| e.into_inner()));
+        let CapturedOutput { content: stdout_content, had_invalid_utf8: stdout_invalid } = take(&stdout_captured);
+        let CapturedOutput { content: stderr_content, had_invalid_utf8: stderr_invalid } = take(&stderr_captured);
 
         let execution_time = start_time.elapsed().as_millis() as u64;
         let success = exit_code == 0;
@@ -224,14 +420,15 @@ impl LocalExecutor {
         if let Some(callback) = &output_callback {
             let status = if success { "成功" } else { "失败" };
             let event = OutputEvent {
-                pipeline_name: pipeline_name.to_string(),
-                server_name: "localhost".to_string(),
-                step: step.clone(),
-                script_path:script_path_str.clone(),
-                output_type: OutputType::Log,
-                content: format!("本地脚本执行完成: {} ({}) - 耗时: {}ms", script_path_str, status, execution_time),
-                timestamp: Instant::now(),
-                variables: variables.clone(),
+                script_path: script_path_str.clone(),
+                ..OutputEvent::new(
+                    pipeline_name.to_string(),
+                    "localhost",
+                    step.clone(),
+                    OutputType::Log,
+                    format!("本地脚本执行完成: {} ({}) - 耗时: {}ms", script_path_str, status, execution_time),
+                    variables.clone(),
+                )
             };
             callback(event);
         }
@@ -239,25 +436,45 @@ impl LocalExecutor {
         // 清理临时文件（drop后自动删除）
         drop(temp_file);
 
-        Ok(ExecutionResult {
+        let mut result = ExecutionResult {
             success,
             stdout: stdout_content,
             stderr: stderr_content,
-            script: script_path_str.clone(),
+            script: step.script.clone(),
             exit_code,
             execution_time_ms: execution_time,
-            error_message: if success { None } else { Some(format!("Script exited with code {}", exit_code)) },
-        })
+            error_message: if timed_out {
+                Some(format!("Timed out after {}s", timeout_seconds))
+            } else if success {
+                None
+            } else {
+                Some(format!("Script exited with code {}", exit_code))
+            },
+            had_invalid_utf8: stdout_invalid || stderr_invalid,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: execution_time,
+            fallback_reason: None,
+            rendered_script: Some(script_content),
+            script_sha256: None,
+            connection_info: Some(ConnectionInfo::local()),
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        };
+        if let Some(watch) = &watch {
+            watch.responder.lock().unwrap_or_else(|e| e.into_inner()).finish(&mut result, prompt_expired);
+        }
+        Ok(result)
     }
 
     /// 在本地执行shell脚本（同步版本，用于兼容性）
     pub fn execute_script(step: &Step) -> Result<ExecutionResult> {
         let start_time = Instant::now();
         
-        // 检查脚本文件是否存在
-        let script_path = std::path::Path::new(&step.script);
-        if !script_path.exists() {
-            return Err(anyhow::anyhow!("Script '{}' not found", step.script));
+        // 检查脚本文件：不存在、是目录或不可读时返回失败结果
+        if let Err(e) = check_script_file(&step.script) {
+            return Ok(script_error_result(&step.script, &e));
         }
 
         info!("Executing local script: {}", step.script);
@@ -276,8 +493,9 @@ impl LocalExecutor {
         let exit_code = output.status.code().unwrap_or(-1);
         let success = exit_code == 0;
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let encoding = resolve_encoding(step.encoding.as_deref());
+        let (stdout, stdout_invalid) = decode_output(&output.stdout, encoding);
+        let (stderr, stderr_invalid) = decode_output(&output.stderr, encoding);
 
         info!("Local script '{}' completed with exit code: {}", step.script, exit_code);
 
@@ -289,6 +507,224 @@ impl LocalExecutor {
             exit_code,
             execution_time_ms: execution_time,
             error_message: if success { None } else { Some(format!("Script exited with code {}", exit_code)) },
+            had_invalid_utf8: stdout_invalid || stderr_invalid,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: execution_time,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: Some(ConnectionInfo::local()),
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
         })
     }
-} 
\ No newline at end of file
+}
+
+/// 去掉行尾的 `\n` 或 `\r\n`
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_script(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn step_for(script: &tempfile::NamedTempFile) -> Step {
+        Step {
+            name: "test_step".to_string(),
+            script: script.path().to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_output_is_lossy() {
+        let script = write_script("printf 'before\\n\\xff\\xfe bad\\nafter\\n'\n");
+        let step = step_for(&script);
+
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+
+        assert!(result.success);
+        assert!(result.had_invalid_utf8);
+        assert!(result.stdout.contains("before"));
+        assert!(result.stdout.contains('\u{FFFD}'));
+        assert!(result.stdout.contains("after"));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_callback_does_not_fail_step() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let script = write_script("echo one\necho two\necho three\necho oops >&2\n");
+        let step = step_for(&script);
+        let stdout_events = Arc::new(AtomicUsize::new(0));
+        let counter = stdout_events.clone();
+        let callback: OutputCallback = Arc::new(move |event: OutputEvent| {
+            if event.output_type == OutputType::Stdout && counter.fetch_add(1, Ordering::SeqCst) == 1 {
+                panic!("callback failure");
+            }
+        });
+
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", Some(callback), VariableManager::new(None), None,
+        ).await.unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.stdout, "one\ntwo\nthree\n");
+        assert_eq!(result.stderr.trim(), "oops");
+        // panic 之后回调被停用，不再收到事件
+        assert_eq!(stdout_events.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_sends_sigterm_and_keeps_partial_output() {
+        let script = write_script("trap 'echo cleanup done; exit 1' TERM\necho started\nsleep 30 &\nwait\n");
+        let mut step = step_for(&script);
+        step.timeout_seconds = Some(1);
+        step.graceful_shutdown_seconds = Some(2);
+
+        let start = Instant::now();
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+
+        assert!(!result.success);
+        assert_eq!(result.exit_code, -1);
+        assert_eq!(result.error_message.as_deref(), Some("Timed out after 1s"));
+        assert_eq!(result.stdout, "started\ncleanup done\n");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // 忽略 SIGTERM 的脚本在宽限时间后被强制终止
+        let script = write_script("trap '' TERM\necho stubborn\nwhile true; do sleep 0.1; done\n");
+        let mut step = step_for(&script);
+        step.timeout_seconds = Some(1);
+        step.graceful_shutdown_seconds = Some(1);
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+        assert_eq!(result.exit_code, -1);
+        assert_eq!(result.stdout, "stubborn\n");
+    }
+
+    #[tokio::test]
+    async fn test_prompt_responses_are_written_to_stdin() {
+        let script = write_script("printf 'Continue? [y/N] '\nread answer\necho \"answer=$answer\"\n");
+        let mut step = step_for(&script);
+        step.responses = Some(vec![crate::models::PromptResponse {
+            expect_regex: r"Continue\? \[y/N\]".to_string(),
+            send: "y".to_string(),
+            timeout_seconds: None,
+        }]);
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+        assert!(result.success, "{:?}", result.error_message);
+        assert_eq!(result.stdout, "Continue? [y/N] answer=y\n");
+        assert_eq!(result.answered_prompts, [r"Continue\? \[y/N\]"]);
+
+        // 提示没有在等待时间内出现时步骤失败，不会等到步骤超时
+        let script = write_script("echo waiting\nsleep 30\n");
+        let mut step = step_for(&script);
+        step.responses = Some(vec![crate::models::PromptResponse {
+            expect_regex: "Password:".to_string(),
+            send: "secret".to_string(),
+            timeout_seconds: Some(1),
+        }]);
+        step.graceful_shutdown_seconds = Some(1);
+        let start = Instant::now();
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error_message.as_deref(), Some("Prompt 'Password:' was not seen within 1s"));
+        assert!(result.answered_prompts.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_output_encoding_transcodes_gbk() {
+        // “你好” 的 GBK 编码
+        let script = write_script("printf '\\xc4\\xe3\\xba\\xc3\\n'\n");
+        let mut step = step_for(&script);
+        step.encoding = Some("gbk".to_string());
+
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+
+        assert!(result.success);
+        assert!(!result.had_invalid_utf8);
+        assert_eq!(result.stdout, "你好\n");
+    }
+
+    #[tokio::test]
+    async fn test_before_and_after_phases() {
+        let before = write_script("echo before\n");
+        let main = write_script("echo main\n");
+        let after = write_script("echo after\n");
+        let mut step = step_for(&main);
+        step.before_script = Some(before.path().to_string_lossy().to_string());
+        step.after_script = Some(after.path().to_string_lossy().to_string());
+
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.stdout, "before\nmain\nafter\n");
+        // 本地执行没有连接阶段，各阶段的执行耗时累加
+        assert_eq!(result.connect_time_ms, 0);
+        assert_eq!(result.command_time_ms, result.execution_time_ms);
+        let connection = result.connection_info.unwrap();
+        assert_eq!(connection.kind, crate::models::ConnectionKind::Local);
+        assert_eq!(connection, ConnectionInfo::local());
+    }
+
+    #[tokio::test]
+    async fn test_unusable_script_is_failed_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write_script("echo main\n");
+        let mut step = step_for(&main);
+        step.before_script = Some(dir.path().to_string_lossy().to_string());
+
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.stdout, "");
+        assert_eq!(result.error_message, Some(format!("Script '{}' is a directory, not a file", dir.path().display())));
+
+        let missing = dir.path().join("missing.sh").to_string_lossy().to_string();
+        step.before_script = None;
+        step.script = missing.clone();
+        let result = LocalExecutor::execute_script(&step).unwrap();
+        assert_eq!(result.error_message, Some(format!("Script '{}' not found", missing)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_phase_stops_step() {
+        let before = write_script("echo before\nexit 3\n");
+        let main = write_script("echo main\n");
+        let mut step = step_for(&main);
+        step.before_script = Some(before.path().to_string_lossy().to_string());
+
+        let result = LocalExecutor::execute_script_with_realtime_output(
+            None, vec![], &step, "p", "s", None, VariableManager::new(None), None,
+        ).await.unwrap();
+
+        assert!(!result.success);
+        assert_eq!(result.exit_code, 3);
+        assert_eq!(result.stdout, "before\n");
+    }
+}
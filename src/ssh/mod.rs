@@ -1,272 +1,440 @@
 pub mod local;
-
-use anyhow::{Context, Error, Result};
-use ssh2::Session;
-use std::fs;
-use std::io::{BufRead, BufReader};
-use std::net::TcpStream;
-use std::path::Path;
-use std::sync::Arc;
-use std::sync::mpsc;
-use std::time::{Duration};
-use tokio::sync::mpsc as tokio_mpsc;
-use tracing::info;
-
-use crate::models::{ExecutionResult, SshConfig, OutputEvent, OutputType, OutputCallback};
-use crate::Step;
+pub mod auth;
+pub(crate) mod artifacts;
+pub(crate) mod prompts;
+#[cfg(feature = "ssh")]
+mod remote;
+
+#[cfg(feature = "russh")]
+mod russh_backend;
+
+#[cfg(any(feature = "ssh", feature = "russh"))]
+mod proxy;
+
+#[cfg(feature = "ssh")]
+pub use remote::SshExecutor;
+#[cfg(feature = "russh")]
+pub use russh_backend::RusshExecutor;
+
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use regex::Regex;
+use std::borrow::Cow;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use tracing::error;
+
+use crate::models::{ExecutionResult, LineFilter, OutputCallback, OutputEvent, OutputType, Step};
+#[cfg(any(feature = "ssh", feature = "russh"))]
+use crate::models::{ConnectionInfo, ConnectionKind, SshConfig};
 use crate::vars::VariableManager;
-use crate::ExtractRule;
-
-/// SSH执行器
-pub struct SshExecutor;
-
-impl SshExecutor {
-    /// 通过SSH执行脚本（支持实时输出）
-    pub fn execute_script_with_realtime_output(
-        script: Option<String>,
-        global_scripts:Arc<Vec<String>>,
-        server_name: &str,
-        ssh_config: &SshConfig, 
-        step: &Step,
-        pipeline_name: &str,
-        step_name: &str,
-        output_callback: Option<OutputCallback>,
-        mut variable_manager: VariableManager,
-        extract_rules: Option<Vec<ExtractRule>>
-    ) -> Result<ExecutionResult> {
-        info!("Connecting to {}:{} as {}", ssh_config.host, ssh_config.port, ssh_config.username);
-
-        // 只用step.script作为脚本路径，不做参数处理
-        let script_path = step.script.as_str(); 
-
-        // 读取本地脚本内容并替换变量
-        let script_content = std::fs::read_to_string(script_path)
-            .context(format!("Failed to read script file: {}", script_path))?;
-
-        let mut gloabl_script_content = global_scripts.iter()
-        .map(|v|std::fs::read_to_string(v).context(format!("read file:[{}]", v)))
-        .fold(Ok("".to_string()), |p:Result<String>,v|{
-            if p.is_err(){
-                return p;
-            }
 
-            if v.is_err(){
-                return Err(Error::msg(format!("{:?}", v.err())));
-            }
-            let content = v.unwrap();
+/// 调用回调并捕获panic，返回回调是否正常结束
+pub(crate) fn call_guarded(callback: &OutputCallback, event: OutputEvent) -> bool {
+    catch_unwind(AssertUnwindSafe(|| callback(event))).is_ok()
+}
 
-            let mut s = p.unwrap_or_default();
+/// 包装输出回调：回调panic时记录一次错误并在本次执行的剩余部分停用该回调，
+/// 读取输出的线程和任务照常继续，步骤结果不受影响
+pub(crate) fn guard_callback(callback: OutputCallback) -> OutputCallback {
+    let disabled = AtomicBool::new(false);
+    Arc::new(move |event: OutputEvent| {
+        if disabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let output_type = event.output_type.clone();
+        if !call_guarded(&callback, event) && !disabled.swap(true, Ordering::Relaxed) {
+            error!("Output callback panicked while handling {:?} event; disabling it for the rest of the run", output_type);
+        }
+    })
+}
 
-            s.push_str("\n");
-            s.push_str(&content);
+/// 对一行输出应用过滤器，返回 None 表示丢弃该行
+/// 行尾换行符不传给过滤器，保留的行按原换行符写回
+pub(crate) fn filter_output_line(filter: Option<&LineFilter>, line: String) -> Option<String> {
+    let Some(filter) = filter else {
+        return Some(line);
+    };
+    let body = line.trim_end_matches(['\r', '\n']);
+    let ending = &line[body.len()..];
+    filter(body).map(|filtered| format!("{}{}", filtered, ending))
+}
 
-            return  Ok(s.clone());
+/// 去除一行输出中的ANSI转义序列（CSI颜色/光标控制、OSC标题等）
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+    let ansi = ANSI.get_or_init(|| {
+        Regex::new(r"\x1b(?:\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(?:\x07|\x1b\\)|[@-_])")
+            .expect("valid ANSI regex")
+    });
+    ansi.replace_all(line, "")
+}
+
+/// 拼接脚本公共前置内容：全局脚本依次拼接，随后是流水线级脚本
+pub(crate) fn build_script_preamble(global_scripts: &[String], script_header: Option<&str>) -> Result<String> {
+    let mut preamble = global_scripts.iter()
+        .try_fold(String::new(), |mut s, v| -> Result<String> {
+            let content = std::fs::read_to_string(v).context(format!("read file:[{}]", v))?;
+            s.push('\n');
+            s.push_str(&content);
+            Ok(s)
         })?;
 
-        if let Some(script_header) = script {
-            let cont =  fs::read_to_string(&script_header)
-                .map_err(|e| anyhow::anyhow!("Failed to read script header file '{}': {}", script_header, e))?;
-            gloabl_script_content.push_str("\n");
-            gloabl_script_content.push_str(&cont);
-        }
+    if let Some(script_header) = script_header {
+        let cont = std::fs::read_to_string(script_header)
+            .map_err(|e| anyhow::anyhow!("Failed to read script header file '{}': {}", script_header, e))?;
+        preamble.push('\n');
+        preamble.push_str(&cont);
+    }
 
-        gloabl_script_content.push_str("\n");
-        gloabl_script_content.push_str(&script_content);
-
-        let script_content = gloabl_script_content.clone();
-
-        variable_manager.set_variable("ssh_server_name".to_string(), server_name.to_string());
-        variable_manager.set_variable("ssh_server_ip".to_string(), ssh_config.host.to_string());
-
-        let script_content = variable_manager.replace_variables(&script_content);
-
-        // 设置连接超时
-        let ssh_timeout_seconds = ssh_config.timeout_seconds.unwrap_or(3);
-        let ssh_timeout_duration = Duration::from_secs(ssh_timeout_seconds);
-
-        // 建立TCP连接（带严格超时）
-        let tcp = connect_with_timeout(&format!("{}:{}", ssh_config.host, ssh_config.port), ssh_timeout_duration)
-            .context("Failed to connect to SSH server")?;
-
-        let timeout_duration = Duration::from_secs(step.timeout_seconds.unwrap_or(30));
-        
-        // 设置TCP连接超时
-        tcp.set_read_timeout(Some(timeout_duration))
-            .context("Failed to set read timeout")?;
-        tcp.set_write_timeout(Some(timeout_duration))
-            .context("Failed to set write timeout")?;
-        tcp.set_nodelay(true)
-            .context("Failed to set TCP nodelay")?;
-
-        // 创建SSH会话
-        let mut sess = Session::new()
-            .context("Failed to create SSH session")?;
-        
-        sess.set_tcp_stream(tcp);
-        
-        // 设置SSH会话超时（使用步骤级别的超时，如果没有则使用默认值）
-        let session_timeout_seconds = step.timeout_seconds.unwrap_or(30);
-        let session_timeout_duration = Duration::from_secs(session_timeout_seconds);
-        sess.set_timeout(session_timeout_duration.as_millis() as u32);
-        
-        // SSH握手（带超时）
-        sess.handshake()
-            .context(format!("SSH handshake failed: timeout {} s", ssh_timeout_seconds))?;
-
-        info!("SSH handshake completed, starting authentication");
-
-        // 认证（带超时）
-        let auth_result = if let Some(ref password) = ssh_config.password {
-            sess.userauth_password(&ssh_config.username, password)
-                .context("SSH password authentication failed")
-        } else if let Some(ref key_path) = ssh_config.private_key_path {
-            sess.userauth_pubkey_file(&ssh_config.username, None, Path::new(key_path), None)
-                .context("SSH key authentication failed")
-        } else {
-            Err(anyhow::anyhow!("No authentication method provided"))
-        };
-
-        auth_result?;
-        info!("SSH authentication successful");
-
-        // 打开远程shell
-        let mut channel = sess.channel_session()
-            .context("Failed to create SSH channel")?;
-        channel.exec("bash")
-            .context("Failed to exec remote shell")?;
-
-        // 把脚本内容写入远程shell的stdin
-        use std::io::Write;
-        channel.write_all(script_content.as_bytes())
-            .context("Failed to write script to remote shell")?;
-        channel.send_eof()
-            .context("Failed to send EOF to remote shell")?;
-
-        // 创建通道用于实时输出
-        let (tx, mut rx) = tokio_mpsc::channel::<OutputEvent>(100);
-        let output_callback = output_callback.map(|cb| Arc::new(cb));
-
-        // 在单独的线程中处理实时输出
-        let server_name = server_name.to_string();
-        let _step_name = step_name.to_string();
-        let pipeline_name = pipeline_name.to_string();
-        let output_callback_clone = output_callback.clone();
-        
-        let output_handle = std::thread::spawn(move || {
-            while let Some(event) = rx.blocking_recv() {
-                if let Some(callback) = &output_callback_clone {
-                    callback(event);
-                }
-            }
-        });
-
-        // 读取stdout和stderr
-        let mut stdout = String::new();
-        let mut stderr = String::new();
-        let start_time = std::time::Instant::now();
-
-        // 实时读取stdout
-        let stdout_stream = channel.stream(0);
-        let mut stdout_reader = BufReader::new(stdout_stream);
-        let mut line = String::new();
-        
-        while stdout_reader.read_line(&mut line)? > 0 {
-            let content = line.clone();
-            stdout.push_str(&content);
-            
-            // 发送实时输出事件
-            let event = OutputEvent {
-                pipeline_name: pipeline_name.clone(),
-                server_name: server_name.clone(),
-                step: step.clone(), // 传递完整的Step对象
-                script_path:step.script.to_string(),
-                output_type: OutputType::Stdout,
-                content: content.trim().to_string(),
-                timestamp: std::time::Instant::now(),
-                variables: variable_manager.get_variables().clone(),
-            };
-            
-            if tx.blocking_send(event).is_err() {
-                break;
+    Ok(preamble)
+}
+
+/// 读取阶段脚本并与前置内容拼接，进行唯一一次变量替换
+/// 本地与远程执行共用，保证脚本内容只被替换一次
+pub(crate) fn render_phase_script(preamble: &str, script_path: &str, variable_manager: &VariableManager) -> Result<String> {
+    let script_content = read_script_file(script_path)?;
+    Ok(variable_manager.replace_script_variables(&format!("{}\n{}", preamble, script_content)))
+}
+
+/// 读取脚本文件，区分不存在、是目录、没有读权限等常见配置错误
+pub(crate) fn read_script_file(script_path: &str) -> Result<String> {
+    check_script_file(script_path)?;
+    std::fs::read_to_string(script_path).map_err(|e| script_file_error(script_path, e))
+}
+
+/// 执行前检查脚本文件：必须存在、是普通文件且可读
+pub(crate) fn check_script_file(script_path: &str) -> Result<()> {
+    let metadata = std::fs::metadata(script_path).map_err(|e| script_file_error(script_path, e))?;
+    if metadata.is_dir() {
+        return Err(anyhow::anyhow!("Script '{}' is a directory, not a file", script_path));
+    }
+    std::fs::File::open(script_path).map_err(|e| script_file_error(script_path, e))?;
+    Ok(())
+}
+
+fn script_file_error(script_path: &str, error: std::io::Error) -> anyhow::Error {
+    match error.kind() {
+        std::io::ErrorKind::NotFound => anyhow::anyhow!("Script '{}' not found", script_path),
+        std::io::ErrorKind::PermissionDenied => anyhow::anyhow!("Script '{}' is not readable: permission denied", script_path),
+        _ => anyhow::anyhow!("Failed to read script '{}': {}", script_path, error),
+    }
+}
+
+/// 脚本文件无法读取时的失败结果：脚本没有执行，退出码为0表示没有可信的退出码
+pub(crate) fn script_error_result(script_path: &str, error: &anyhow::Error) -> ExecutionResult {
+    ExecutionResult {
+        success: false,
+        stdout: String::new(),
+        stderr: format!("{}\n", error),
+        script: script_path.to_string(),
+        exit_code: 0,
+        execution_time_ms: 0,
+        error_message: Some(error.to_string()),
+        had_invalid_utf8: false,
+        artifacts: Vec::new(),
+        connect_time_ms: 0,
+        command_time_ms: 0,
+        fallback_reason: None,
+        rendered_script: None,
+        script_sha256: None,
+        connection_info: None,
+        answered_prompts: Vec::new(),
+        ssh_trace: None,
+        skip_reason: None,
+    }
+}
+
+/// 远程执行的阶段脚本：步骤开启 export_variables 时在脚本最前面导出变量
+/// 导出行在变量替换之后拼接，变量值中的占位符文本不会被展开
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) fn render_remote_phase_script(preamble: &str, script_path: &str, step: &Step, variable_manager: &VariableManager) -> Result<String> {
+    let script = render_phase_script(preamble, script_path, variable_manager)?;
+    if step.export_variables != Some(true) {
+        return Ok(script);
+    }
+    Ok(format!("{}{}", export_variables_script(variable_manager.get_variables()), script))
+}
+
+/// 生成导出变量的脚本行，值按shell规则加引号；名称不是合法shell标识符的变量跳过
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) fn export_variables_script(variables: &std::collections::HashMap<String, String>) -> String {
+    let mut names: Vec<&String> = variables.keys()
+        .filter(|name| {
+            let mut chars = name.chars();
+            chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .collect();
+    names.sort();
+    names.iter()
+        .map(|name| format!("export {}={}\n", name, crate::template::EscapeMode::Shell.escape(&variables[*name])))
+        .collect()
+}
+
+/// 建立SSH连接失败（脚本尚未开始执行），作为错误的上下文附加，run_once 步骤据此换下一台服务器
+#[cfg(any(feature = "ssh", feature = "russh"))]
+#[derive(Debug)]
+pub(crate) struct ConnectError;
+
+#[cfg(any(feature = "ssh", feature = "russh"))]
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SSH connection failed")
+    }
+}
+
+/// 已认证SSH连接的审计信息；经代理连接时对端地址是代理，不记录 resolved_ip
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) fn ssh_connection_info(
+    ssh_config: &SshConfig,
+    peer: Option<std::net::SocketAddr>,
+    host_key_fingerprint_sha256: Option<String>,
+    server_banner: Option<String>,
+) -> ConnectionInfo {
+    ConnectionInfo {
+        kind: ConnectionKind::Ssh,
+        resolved_ip: peer.filter(|_| ssh_config.proxy.is_none()).map(|peer| peer.ip().to_string()),
+        host_key_fingerprint_sha256,
+        server_banner,
+        username: ssh_config.username.clone(),
+        port: Some(ssh_config.port),
+    }
+}
+
+/// 建立连接后发送包含对端地址与主机密钥指纹的日志事件
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) fn emit_connection_info(
+    output_callback: Option<&OutputCallback>,
+    step: &Step,
+    pipeline_name: &str,
+    server_name: &str,
+    info: &ConnectionInfo,
+    variable_manager: &VariableManager,
+) {
+    let Some(callback) = output_callback else {
+        return;
+    };
+    let address = info.resolved_ip.as_deref().unwrap_or("via proxy");
+    let fingerprint = info.host_key_fingerprint_sha256.as_deref().unwrap_or("unknown");
+    callback(OutputEvent::new(
+        pipeline_name.to_string(),
+        server_name.to_string(),
+        step.clone(),
+        OutputType::Log,
+        format!("已连接 {} ({})，主机密钥 {}，用户 {}", server_name, address, fingerprint, info.username),
+        variable_manager.snapshot(),
+    ));
+}
+
+/// 远程执行脚本时的shell命令与写入stdin的内容
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) struct RemoteShell {
+    pub command: String,
+    pub stdin: Vec<u8>,
+}
+
+/// 生成远程执行脚本的shell命令
+/// 未配置 sudo 时脚本通过stdin交给 bash；配置了 sudo 时通过 sudo 以目标用户执行：
+/// 无密码时使用 `sudo -n`，有密码时外层shell先从stdin读取密码行，密码经管道单独交给 `sudo -S`，
+/// 脚本作为 `bash -c` 的参数传入，密码不会出现在命令行中，密码错误时 sudo 也不会把脚本内容当作密码读取。
+/// 步骤开启 compress 时stdin中的脚本为gzip压缩后的内容，在服务器上先经 `gzip -dc` 解压
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) fn remote_shell(step: &Step, content: &str) -> RemoteShell {
+    if step.responses.is_some() {
+        return interactive_remote_shell(step, content);
+    }
+    let compress = step.compress.unwrap_or(false);
+    let (script, decompress) = if compress {
+        (gzip(content), "gzip -dc | ")
+    } else {
+        (content.as_bytes().to_vec(), "")
+    };
+    let Some(sudo) = &step.sudo else {
+        let command = if compress { "gzip -dc | bash".to_string() } else { "bash".to_string() };
+        return RemoteShell { command, stdin: script };
+    };
+    let user = crate::template::EscapeMode::Shell.escape(sudo.user.as_deref().unwrap_or("root"));
+    match &sudo.password {
+        None => RemoteShell {
+            command: format!("{}sudo -n -u {} bash", decompress, user),
+            stdin: script,
+        },
+        Some(password) => {
+            let read_script = if compress { "gzip -dc" } else { "cat" };
+            let wrapper = format!(
+                "IFS= read -r p; s=$({}); printf '%s\\n' \"$p\" | sudo -S -k -p '' -u {} bash -c \"$s\"",
+                read_script, user
+            );
+            let mut stdin = format!("{}\n", password).into_bytes();
+            stdin.extend_from_slice(&script);
+            RemoteShell {
+                command: format!("bash -c {}", crate::template::EscapeMode::Shell.escape(&wrapper)),
+                stdin,
             }
-            
-            line.clear();
         }
+    }
+}
 
-        // 实时读取stderr
-        let stderr_stream = channel.stderr();
-        let mut stderr_reader = BufReader::new(stderr_stream);
-        line.clear();
-        
-        while stderr_reader.read_line(&mut line)? > 0 {
-            let content = line.clone();
-            stderr.push_str(&content);
-            
-            // 发送实时输出事件
-            let event = OutputEvent {
-                pipeline_name: pipeline_name.clone(),
-                server_name: server_name.clone(),
-                step: step.clone(), // 传递完整的Step对象
-                script_path:step.script.to_string(),
-                output_type: OutputType::Stderr,
-                content: content.trim().to_string(),
-                timestamp: std::time::Instant::now(),
-                variables: variable_manager.get_variables().clone(),
-            };
-            
-            if tx.blocking_send(event).is_err() {
-                break;
+/// 配置了 responses 的步骤在PTY中执行：脚本作为 `bash -c` 的参数传入，stdin 只用于写入应答（与 sudo 密码）
+/// 先关闭PTY的回显与换行转换，写入的应答与密码不会出现在输出中，输出的行尾保持 `\n`；不使用 compress
+#[cfg(any(feature = "ssh", feature = "russh"))]
+fn interactive_remote_shell(step: &Step, content: &str) -> RemoteShell {
+    let script = crate::template::EscapeMode::Shell.escape(content);
+    let (run, stdin) = match &step.sudo {
+        None => (format!("bash -c {}", script), Vec::new()),
+        Some(sudo) => {
+            let user = crate::template::EscapeMode::Shell.escape(sudo.user.as_deref().unwrap_or("root"));
+            match &sudo.password {
+                None => (format!("sudo -n -u {} bash -c {}", user, script), Vec::new()),
+                Some(password) => (
+                    format!("sudo -S -k -p '' -u {} bash -c {}", user, script),
+                    format!("{}\n", password).into_bytes(),
+                ),
             }
-            
-            line.clear();
         }
+    };
+    RemoteShell { command: format!("stty -echo -onlcr 2>/dev/null; exec {}", run), stdin }
+}
 
-        // 等待通道关闭
-        drop(tx);
-        if let Err(e) = output_handle.join() {
-            eprintln!("Output handler thread error: {:?}", e);
+/// 将分块到达的输出切分为完整的行（保留换行符）
+#[cfg(any(feature = "ssh", feature = "russh"))]
+#[derive(Default)]
+pub(crate) struct LineSplitter {
+    pending: Vec<u8>,
+}
+
+#[cfg(any(feature = "ssh", feature = "russh"))]
+impl LineSplitter {
+    /// 追加数据，返回已完整的行
+    pub(crate) fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.pending.iter().position(|b| *b == b'\n') {
+            lines.push(self.pending.drain(..=pos).collect());
         }
+        lines
+    }
 
-        channel.wait_close()
-            .context("Failed to wait for channel close")?;
-
-        let exit_code = channel.exit_status()
-            .context("Failed to get exit status")?;
-
-        let execution_time = start_time.elapsed().as_millis() as u64;
-        info!("SSH command executed with exit code: {}", exit_code);
-
-        // 创建执行结果
-        let execution_result = ExecutionResult {
-            success: exit_code == 0,
-            stdout,
-            stderr,
-            script: step.script.to_string(),
-            exit_code,
-            execution_time_ms: execution_time,
-            error_message: None,
-        };
-
-        // 提取变量
-        if let Some(rules) = extract_rules {
-            if let Err(e) = variable_manager.extract_variables(&rules, &execution_result) {
-                info!("Failed to extract variables: {}", e);
-            }
+    /// 返回末尾没有换行符的剩余内容
+    pub(crate) fn finish(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.pending))
         }
+    }
+}
 
-        Ok(execution_result)
+/// gzip 压缩脚本内容
+#[cfg(any(feature = "ssh", feature = "russh"))]
+fn gzip(content: &str) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    // 写入内存缓冲区不会失败
+    encoder.write_all(content.as_bytes()).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+/// sudo 本身失败（退出码1且stderr中有 `sudo:` 开头的行）时填写易于理解的错误信息
+#[cfg(any(feature = "ssh", feature = "russh"))]
+pub(crate) fn check_sudo_failure(step: &Step, result: &mut ExecutionResult) {
+    let Some(sudo) = &step.sudo else { return };
+    if result.exit_code != 1 {
+        return;
+    }
+    if let Some(line) = result.stderr.lines().find(|line| line.starts_with("sudo: ")) {
+        result.error_message = Some(format!(
+            "sudo authentication failed for user '{}': {}",
+            sudo.user.as_deref().unwrap_or("root"),
+            line.trim()
+        ));
     }
+}
+
+/// 步骤按顺序执行的脚本路径：before_script、script、after_script
+pub(crate) fn step_phases(step: &Step) -> Vec<&str> {
+    step.before_script.as_deref()
+        .into_iter()
+        .chain(std::iter::once(step.script.as_str()))
+        .chain(step.after_script.as_deref())
+        .collect()
+}
 
+/// 合并多个阶段的执行结果，输出依次拼接，退出码与成功状态取最后执行的阶段
+pub(crate) fn merge_phase_result(previous: Option<ExecutionResult>, next: ExecutionResult) -> ExecutionResult {
+    let Some(mut merged) = previous else {
+        return next;
+    };
+    merged.stdout.push_str(&next.stdout);
+    merged.stderr.push_str(&next.stderr);
+    merged.success = next.success;
+    merged.exit_code = next.exit_code;
+    merged.execution_time_ms += next.execution_time_ms;
+    merged.error_message = next.error_message;
+    merged.had_invalid_utf8 |= next.had_invalid_utf8;
+    merged.connect_time_ms += next.connect_time_ms;
+    merged.command_time_ms += next.command_time_ms;
+    merged.connection_info = merged.connection_info.or(next.connection_info);
+    merged.answered_prompts.extend(next.answered_prompts);
+    merged.rendered_script = match (merged.rendered_script, next.rendered_script) {
+        (Some(previous), Some(next)) => Some(format!("{}\n{}", previous, next)),
+        (previous, next) => previous.or(next),
+    };
+    merged
 }
 
-/// 工具函数：带超时的TCP连接
-fn connect_with_timeout(addr: &str, timeout: Duration) -> std::io::Result<TcpStream> {
-    let (tx, rx) = mpsc::channel();
-    let addr = addr.to_string();
-    let error_message = format!("connect to {} timeout {} s", addr, timeout.as_secs());
-    std::thread::spawn(move || {
-        let res = TcpStream::connect(addr);
-        let _ = tx.send(res);
+/// 步骤开启 include_rendered_script_in_events 时，在执行阶段脚本前发送内容为渲染后脚本的 StepStarted 事件
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn emit_rendered_script(
+    output_callback: Option<&OutputCallback>,
+    step: &Step,
+    pipeline_name: &str,
+    server_name: &str,
+    script_path: &str,
+    content: &str,
+    variable_manager: &VariableManager,
+) {
+    let Some(callback) = output_callback else {
+        return;
+    };
+    if step.include_rendered_script_in_events != Some(true) {
+        return;
+    }
+    callback(OutputEvent {
+        script_path: script_path.to_string(),
+        ..OutputEvent::new(
+            pipeline_name.to_string(),
+            server_name.to_string(),
+            step.clone(),
+            OutputType::StepStarted,
+            content.to_string(),
+            variable_manager.snapshot(),
+        )
     });
-    rx.recv_timeout(timeout).unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, error_message)))
-} 
\ No newline at end of file
+}
+
+/// 根据步骤配置的编码名称查找编码，未知编码记录警告并回退到UTF-8
+pub fn resolve_encoding(label: Option<&str>) -> Option<&'static Encoding> {
+    let label = label?;
+    let encoding = Encoding::for_label(label.trim().as_bytes());
+    if encoding.is_none() {
+        tracing::warn!("Unknown output encoding '{}', falling back to UTF-8", label);
+    }
+    encoding
+}
+
+/// 将一段原始输出字节解码为字符串，返回 (内容, 是否包含无效字节)
+/// 无论编码是否匹配都不会失败，无法解码的字节替换为 U+FFFD
+pub fn decode_output(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, bool) {
+    if let Some(encoding) = encoding {
+        let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+        return (text.into_owned(), had_errors);
+    }
+
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(text) => (text.to_string(), false),
+        Cow::Owned(text) => (text, true),
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::models::{ExecutionResult, OutputEvent, OutputType, Step};
+
+/// 查找提示时最多保留的未匹配输出（字节），更早的输出不再参与匹配
+const MAX_PENDING_OUTPUT: usize = 8192;
+
+/// 等待输出期间检查提示是否超时的间隔
+pub(crate) const PROMPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 一个待应答的提示
+struct Prompt {
+    regex: Regex,
+    expect: String,
+    send: String,
+    timeout: Duration,
+    answered: bool,
+}
+
+/// 按步骤的 responses 在脚本输出中查找交互提示并给出应答
+/// 输出按到达的片段传入（可以是没有换行的提示），每个提示只应答一次
+pub(crate) struct PromptResponder {
+    prompts: Vec<Prompt>,
+    any_order: bool,
+    pending: String,
+    waiting_since: Instant,
+    answered: Vec<String>,
+}
+
+impl PromptResponder {
+    /// 根据步骤的 responses 创建，步骤没有配置时返回 None
+    /// 未设置 timeout_seconds 的提示最多等待 default_timeout
+    pub(crate) fn new(step: &Step, default_timeout: Duration) -> Result<Option<Self>> {
+        let Some(responses) = step.responses.as_ref().filter(|r| !r.is_empty()) else {
+            return Ok(None);
+        };
+        let prompts = responses.iter()
+            .map(|response| {
+                let regex = Regex::new(&response.expect_regex)
+                    .with_context(|| format!("Invalid expect_regex '{}' in step '{}'", response.expect_regex, step.name))?;
+                Ok(Prompt {
+                    regex,
+                    expect: response.expect_regex.clone(),
+                    send: format!("{}\n", response.send),
+                    timeout: response.timeout_seconds.map_or(default_timeout, Duration::from_secs),
+                    answered: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Self {
+            prompts,
+            any_order: step.responses_any_order.unwrap_or(false),
+            pending: String::new(),
+            waiting_since: Instant::now(),
+            answered: Vec::new(),
+        }))
+    }
+
+    /// 追加一段输出，返回 (提示, 需要写入脚本输入的应答) 列表
+    pub(crate) fn feed(&mut self, output: &str) -> Vec<(String, String)> {
+        self.pending.push_str(output);
+        let mut replies = Vec::new();
+        loop {
+            // 按顺序时只查找第一个未应答的提示，任意顺序时取最先出现的提示
+            let found = self.waiting()
+                .filter_map(|index| self.prompts[index].regex.find(&self.pending).map(|m| (m.start(), m.end(), index)))
+                .min();
+            let Some((_, end, index)) = found else { break };
+            self.pending.drain(..end);
+            let prompt = &mut self.prompts[index];
+            prompt.answered = true;
+            self.answered.push(prompt.expect.clone());
+            replies.push((prompt.expect.clone(), prompt.send.clone()));
+            self.waiting_since = Instant::now();
+        }
+        if self.pending.len() > MAX_PENDING_OUTPUT {
+            let mut cut = self.pending.len() - MAX_PENDING_OUTPUT;
+            while !self.pending.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.pending.drain(..cut);
+        }
+        replies
+    }
+
+    /// 所有提示都已应答
+    pub(crate) fn is_done(&self) -> bool {
+        self.prompts.iter().all(|prompt| prompt.answered)
+    }
+
+    /// 正在等待的提示超过等待时间时返回错误信息
+    pub(crate) fn expired(&self) -> Option<String> {
+        let elapsed = self.waiting_since.elapsed();
+        self.waiting()
+            .map(|index| &self.prompts[index])
+            .find(|prompt| elapsed >= prompt.timeout)
+            .map(|prompt| format!("Prompt '{}' was not seen within {}s", prompt.expect, prompt.timeout.as_secs()))
+    }
+
+    /// 把已应答的提示写入执行结果；提示等待超时（expired）或脚本结束时仍有提示没有出现，步骤失败
+    pub(crate) fn finish(&mut self, result: &mut ExecutionResult, expired: Option<String>) {
+        let missing: Vec<String> = self.waiting().map(|index| format!("'{}'", self.prompts[index].expect)).collect();
+        result.answered_prompts = std::mem::take(&mut self.answered);
+        let message = expired.or_else(|| {
+            (!missing.is_empty()).then(|| format!("Expected prompt(s) {} did not appear before the script exited", missing.join(", ")))
+        });
+        if let Some(message) = message {
+            result.success = false;
+            result.error_message = Some(message);
+        }
+    }
+
+    /// 当前等待中的提示下标
+    fn waiting(&self) -> impl Iterator<Item = usize> + '_ {
+        let unanswered = (0..self.prompts.len()).filter(|index| !self.prompts[*index].answered);
+        unanswered.take(if self.any_order { usize::MAX } else { 1 })
+    }
+}
+
+/// 应答提示后发送的日志事件，不包含应答内容
+pub(crate) fn prompt_answered_event(
+    step: &Step,
+    pipeline_name: &str,
+    server_name: &str,
+    script_path: &str,
+    expect: &str,
+    variables: Arc<HashMap<String, String>>,
+) -> OutputEvent {
+    OutputEvent {
+        script_path: script_path.to_string(),
+        ..OutputEvent::new(
+            pipeline_name.to_string(),
+            server_name.to_string(),
+            step.clone(),
+            OutputType::Log,
+            format!("已应答提示: {}", expect),
+            variables,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PromptResponse;
+
+    fn step(any_order: bool) -> Step {
+        let response = |expect: &str, send: &str| PromptResponse {
+            expect_regex: expect.to_string(),
+            send: send.to_string(),
+            timeout_seconds: None,
+        };
+        Step {
+            name: "install".to_string(),
+            responses: Some(vec![response(r"Continue\? \[y/N\]", "y"), response("Name:", "web")]),
+            responses_any_order: Some(any_order),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_prompts_split_across_chunks_in_order() {
+        let mut responder = PromptResponder::new(&step(false), Duration::from_secs(5)).unwrap().unwrap();
+        // 按顺序时第二个提示先出现不会被应答
+        assert!(responder.feed("Name: ").is_empty());
+        assert!(responder.feed("Contin").is_empty());
+        assert_eq!(responder.feed("ue? [y/N] "), [(r"Continue\? \[y/N\]".to_string(), "y\n".to_string())]);
+        assert!(!responder.is_done());
+        assert_eq!(responder.feed("Name: ")[0].1, "web\n");
+        assert!(responder.is_done());
+
+        let mut any = PromptResponder::new(&step(true), Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(any.feed("Name: ")[0].1, "web\n");
+        let mut result = ExecutionResult {
+            success: true,
+            stdout: String::new(),
+            stderr: String::new(),
+            script: String::new(),
+            exit_code: 0,
+            execution_time_ms: 0,
+            error_message: None,
+            had_invalid_utf8: false,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: 0,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        };
+        any.finish(&mut result, None);
+        assert!(!result.success);
+        assert_eq!(result.answered_prompts, ["Name:"]);
+        assert_eq!(result.error_message.unwrap(), r"Expected prompt(s) 'Continue\? \[y/N\]' did not appear before the script exited");
+    }
+}
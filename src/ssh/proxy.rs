@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::models::{ProxyConfig, ProxyKind};
+
+/// 带超时建立到目标地址的TCP连接，配置了代理时经代理连接
+/// 超时覆盖DNS解析、TCP连接以及代理协商的全过程
+pub fn connect_with_timeout(host: &str, port: u16, proxy: Option<&ProxyConfig>, timeout: Duration) -> Result<TcpStream> {
+    let (tx, rx) = mpsc::channel();
+    let (target_host, thread_proxy) = (host.to_string(), proxy.cloned());
+    std::thread::spawn(move || {
+        let res = match &thread_proxy {
+            Some(proxy) => connect_via_proxy(proxy, &target_host, port, timeout),
+            None => TcpStream::connect((target_host.as_str(), port)).map_err(anyhow::Error::from),
+        };
+        let _ = tx.send(res);
+    });
+
+    let via = proxy.map(|p| format!(" via {} proxy {}:{}", kind_name(p.kind), p.host, p.port)).unwrap_or_default();
+    rx.recv_timeout(timeout)
+        .map_err(|_| anyhow::anyhow!("connect to {}:{}{} timeout {} s", host, port, via, timeout.as_secs()))?
+}
+
+fn kind_name(kind: ProxyKind) -> &'static str {
+    match kind {
+        ProxyKind::Socks5 => "SOCKS5",
+        ProxyKind::Http => "HTTP",
+    }
+}
+
+/// 连接代理并完成协商，返回已连通目标的TCP连接
+fn connect_via_proxy(proxy: &ProxyConfig, host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .context(format!("Failed to connect to {} proxy {}:{}", kind_name(proxy.kind), proxy.host, proxy.port))?;
+    // 协商期间的读写超时，避免代理无响应时后台线程一直阻塞
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5_handshake(&mut stream, proxy, host, port)?,
+        ProxyKind::Http => http_connect(&mut stream, proxy, host, port)?,
+    }
+    Ok(stream)
+}
+
+/// SOCKS5 协商（RFC 1928），有用户名时使用用户名/密码认证（RFC 1929）
+fn socks5_handshake(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> Result<()> {
+    let io_err = |e: std::io::Error| anyhow::anyhow!("SOCKS5 proxy {}:{} negotiation failed: {}", proxy.host, proxy.port, e);
+
+    let methods: &[u8] = if proxy.username.is_some() { &[0x00, 0x02] } else { &[0x00] };
+    let mut greeting = vec![0x05, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).map_err(io_err)?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).map_err(io_err)?;
+    if reply[0] != 0x05 {
+        return Err(anyhow::anyhow!("{}:{} is not a SOCKS5 proxy", proxy.host, proxy.port));
+    }
+    match reply[1] {
+        0x00 => {}
+        0x02 => {
+            let username = proxy.username.as_deref().unwrap_or_default();
+            let password = proxy.password.as_deref().unwrap_or_default();
+            if username.len() > 255 || password.len() > 255 {
+                return Err(anyhow::anyhow!("SOCKS5 proxy username and password must be at most 255 bytes"));
+            }
+            let mut auth = vec![0x01, username.len() as u8];
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).map_err(io_err)?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).map_err(io_err)?;
+            if status[1] != 0x00 {
+                return Err(anyhow::anyhow!("SOCKS5 proxy {}:{} rejected the credentials", proxy.host, proxy.port));
+            }
+        }
+        _ => return Err(anyhow::anyhow!(
+            "SOCKS5 proxy {}:{} refused all offered authentication methods", proxy.host, proxy.port
+        )),
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(anyhow::anyhow!("Target host name '{}' is too long for SOCKS5", host));
+            }
+            request.push(0x03);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).map_err(io_err)?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).map_err(io_err)?;
+    if header[1] != 0x00 {
+        let reason = match header[1] {
+            0x01 => "general failure",
+            0x02 => "connection not allowed by ruleset",
+            0x03 => "network unreachable",
+            0x04 => "host unreachable",
+            0x05 => "connection refused",
+            0x06 => "TTL expired",
+            0x07 => "command not supported",
+            0x08 => "address type not supported",
+            _ => "unknown error",
+        };
+        return Err(anyhow::anyhow!(
+            "SOCKS5 proxy {}:{} could not reach target {}:{}: {}",
+            proxy.host, proxy.port, host, port, reason
+        ));
+    }
+
+    // 读取并丢弃代理返回的绑定地址
+    let address_len = match header[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).map_err(io_err)?;
+            len[0] as usize
+        }
+        other => return Err(anyhow::anyhow!("SOCKS5 proxy returned unknown address type {}", other)),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).map_err(io_err)?;
+    Ok(())
+}
+
+/// HTTP CONNECT 协商，有用户名时发送 Basic 认证
+fn http_connect(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> Result<()> {
+    let io_err = |e: std::io::Error| anyhow::anyhow!("HTTP proxy {}:{} negotiation failed: {}", proxy.host, proxy.port, e);
+
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(username) = &proxy.username {
+        let credentials = format!("{}:{}", username, proxy.password.as_deref().unwrap_or_default());
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", base64_encode(credentials.as_bytes())));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).map_err(io_err)?;
+
+    // 逐字节读取响应头，避免读走目标服务器随后发送的SSH握手数据
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).map_err(io_err)? == 0 {
+            return Err(anyhow::anyhow!("HTTP proxy {}:{} closed the connection during CONNECT", proxy.host, proxy.port));
+        }
+        response.push(byte[0]);
+        if response.len() > 16 * 1024 {
+            return Err(anyhow::anyhow!("HTTP proxy {}:{} sent an oversized response", proxy.host, proxy.port));
+        }
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(407) => Err(anyhow::anyhow!(
+            "HTTP proxy {}:{} requires authentication or rejected the credentials: {}",
+            proxy.host, proxy.port, status_line
+        )),
+        _ => Err(anyhow::anyhow!(
+            "HTTP proxy {}:{} refused CONNECT to target {}: {}",
+            proxy.host, proxy.port, authority, status_line
+        )),
+    }
+}
+
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    /// 进程内的SOCKS5代理桩：要求用户名/密码 user/secret，按 reply 回复CONNECT请求
+    /// 成功时把收到的目标地址写回客户端，便于验证请求编码
+    fn socks5_stub(reply: u8) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut greeting = [0u8; 2];
+                stream.read_exact(&mut greeting).unwrap();
+                let mut methods = vec![0u8; greeting[1] as usize];
+                stream.read_exact(&mut methods).unwrap();
+                stream.write_all(&[0x05, 0x02]).unwrap();
+
+                let mut version_len = [0u8; 2];
+                stream.read_exact(&mut version_len).unwrap();
+                let mut username = vec![0u8; version_len[1] as usize];
+                stream.read_exact(&mut username).unwrap();
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).unwrap();
+                let mut password = vec![0u8; len[0] as usize];
+                stream.read_exact(&mut password).unwrap();
+                if username != b"user" || password != b"secret" {
+                    stream.write_all(&[0x01, 0x01]).unwrap();
+                    continue;
+                }
+                stream.write_all(&[0x01, 0x00]).unwrap();
+
+                let mut header = [0u8; 5];
+                stream.read_exact(&mut header).unwrap();
+                let mut host = vec![0u8; header[4] as usize];
+                stream.read_exact(&mut host).unwrap();
+                let mut target_port = [0u8; 2];
+                stream.read_exact(&mut target_port).unwrap();
+                stream.write_all(&[0x05, reply, 0x00, 0x01, 127, 0, 0, 1, 0, 22]).unwrap();
+                if reply == 0x00 {
+                    let target = format!("{}:{}\n", String::from_utf8_lossy(&host), u16::from_be_bytes(target_port));
+                    stream.write_all(target.as_bytes()).unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    fn proxy(kind: ProxyKind, port: u16, password: &str) -> ProxyConfig {
+        ProxyConfig {
+            kind,
+            host: "127.0.0.1".to_string(),
+            port,
+            username: Some("user".to_string()),
+            password: Some(password.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_socks5_proxy_connects_to_target() {
+        let port = socks5_stub(0x00);
+        let stream = connect_with_timeout(
+            "db.internal", 2222, Some(&proxy(ProxyKind::Socks5, port, "secret")), Duration::from_secs(2),
+        ).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "db.internal:2222\n");
+    }
+
+    #[test]
+    fn test_socks5_proxy_errors_name_the_failing_side() {
+        let port = socks5_stub(0x00);
+        let err = connect_with_timeout(
+            "db.internal", 22, Some(&proxy(ProxyKind::Socks5, port, "wrong")), Duration::from_secs(2),
+        ).unwrap_err().to_string();
+        assert!(err.contains("rejected the credentials"), "{}", err);
+
+        let port = socks5_stub(0x05);
+        let err = connect_with_timeout(
+            "db.internal", 22, Some(&proxy(ProxyKind::Socks5, port, "secret")), Duration::from_secs(2),
+        ).unwrap_err().to_string();
+        assert!(err.contains("could not reach target db.internal:22: connection refused"), "{}", err);
+
+        // 代理本身不可达
+        let unused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let err = format!("{:#}", connect_with_timeout(
+            "db.internal", 22, Some(&proxy(ProxyKind::Socks5, unused, "secret")), Duration::from_secs(2),
+        ).unwrap_err());
+        assert!(err.contains("Failed to connect to SOCKS5 proxy"), "{}", err);
+    }
+
+    #[test]
+    fn test_http_connect_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let mut stream = reader.into_inner();
+                if head.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=") {
+                    stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\nSSH-2.0-stub\r\n").unwrap();
+                } else {
+                    stream.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").unwrap();
+                }
+            }
+        });
+
+        let stream = connect_with_timeout(
+            "db.internal", 22, Some(&proxy(ProxyKind::Http, port, "secret")), Duration::from_secs(2),
+        ).unwrap();
+        let mut banner = String::new();
+        BufReader::new(stream).read_line(&mut banner).unwrap();
+        assert_eq!(banner, "SSH-2.0-stub\r\n");
+
+        let err = connect_with_timeout(
+            "db.internal", 22, Some(&proxy(ProxyKind::Http, port, "wrong")), Duration::from_secs(2),
+        ).unwrap_err().to_string();
+        assert!(err.contains("rejected the credentials"), "{}", err);
+    }
+}
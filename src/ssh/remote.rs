@@ -0,0 +1,898 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use ssh2::{HashType, Session};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc as tokio_mpsc;
+use tracing::info;
+
+use super::artifacts::{apply_collection, collect_with, should_collect};
+use super::auth::{auth_method, AuthMethod};
+use super::prompts::{prompt_answered_event, PromptResponder, PROMPT_POLL_INTERVAL};
+use super::proxy::connect_with_timeout;
+use super::{
+    build_script_preamble, check_sudo_failure, ConnectError, decode_output, emit_connection_info, emit_rendered_script, filter_output_line, guard_callback, merge_phase_result, remote_shell,
+    render_remote_phase_script, LineSplitter, resolve_encoding, script_error_result, ssh_connection_info, step_phases,
+};
+use crate::models::{CollectedArtifact, ConnectionInfo, ExecutionResult, LineFilter, SshConfig, SshConnectionTrace, OutputEvent, OutputType, OutputCallback};
+use crate::Step;
+use crate::vars::VariableManager;
+use crate::ExtractRule;
+
+/// SSH执行器
+pub struct SshExecutor;
+
+impl SshExecutor {
+    /// 通过SSH执行脚本（支持实时输出）
+    /// 步骤的 before_script / script / after_script 共用同一个SSH会话，任一阶段失败即停止并关闭连接
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_script_with_realtime_output(
+        script: Option<String>,
+        global_scripts:Arc<Vec<String>>,
+        server_name: &str,
+        ssh_config: &SshConfig,
+        step: &Step,
+        pipeline_name: &str,
+        _step_name: &str,
+        output_callback: Option<OutputCallback>,
+        mut variable_manager: VariableManager,
+        extract_rules: Option<Vec<ExtractRule>>,
+        line_filter: Option<LineFilter>,
+    ) -> Result<ExecutionResult> {
+        info!("Connecting to {}:{} as {}", ssh_config.host, ssh_config.port, ssh_config.username);
+        let output_callback = output_callback.map(guard_callback);
+
+        // 公共前置内容：全局脚本 + 流水线脚本
+        let preamble = build_script_preamble(&global_scripts, script.as_deref())?;
+
+        variable_manager.set_variable("ssh_server_name".to_string(), server_name.to_string());
+        variable_manager.set_variable("ssh_server_ip".to_string(), ssh_config.host.to_string());
+
+        // 在建立连接前读取所有阶段的脚本，避免脚本缺失时白白建立连接
+        let mut phases = Vec::new();
+        for script_path in step_phases(step) {
+            match render_remote_phase_script(&preamble, script_path, step, &variable_manager) {
+                Ok(content) => phases.push((script_path, content)),
+                Err(e) => return Ok(script_error_result(script_path, &e)),
+            }
+        }
+
+        let timeout = Duration::from_secs(step.timeout_seconds.unwrap_or(30));
+        let (session, connect_time_ms, execution_result) = run_phases_on_one_session(
+            phases,
+            || SshSession::connect(ssh_config, timeout).context(ConnectError),
+            |session| emit_connection_info(output_callback.as_ref(), step, pipeline_name, server_name, &session.connection_info, &variable_manager),
+            |session, script_path, content| {
+                emit_rendered_script(output_callback.as_ref(), step, pipeline_name, server_name, script_path, &content, &variable_manager);
+                let mut phase_result = session.exec_script(
+                    &content,
+                    step,
+                    script_path,
+                    pipeline_name,
+                    server_name,
+                    output_callback.clone(),
+                    &variable_manager,
+                    line_filter.as_ref(),
+                )?;
+                phase_result.rendered_script = Some(content);
+                Ok(phase_result)
+            },
+        )?;
+
+        let mut execution_result = execution_result
+            .ok_or_else(|| anyhow::anyhow!("Step '{}' has no script to execute", step.name))?;
+        execution_result.connect_time_ms = connect_time_ms;
+        execution_result.connection_info = Some(session.connection_info.clone());
+        execution_result.ssh_trace = Some(session.trace);
+
+        // 在同一个会话上通过SFTP下载产物
+        if should_collect(step, execution_result.success) {
+            let collection = session.collect_artifacts(step, pipeline_name, server_name, &variable_manager, output_callback.as_ref());
+            apply_collection(&mut execution_result, collection);
+        }
+
+        // 所有阶段结束后立即释放连接
+        drop(session);
+
+        // 提取变量
+        if let Some(rules) = extract_rules {
+            if let Err(e) = variable_manager.extract_variables(&rules, &execution_result) {
+                info!("Failed to extract variables: {}", e);
+            }
+        }
+
+        Ok(execution_result)
+    }
+
+    /// 只建立TCP连接、完成SSH握手和认证，不执行脚本；返回连接信息，会话随即关闭
+    pub fn connect_and_auth(ssh_config: &SshConfig) -> Result<ConnectionInfo> {
+        let timeout = Duration::from_secs(ssh_config.timeout_seconds.unwrap_or(30));
+        connect_and_authenticate(ssh_config, timeout).map(|(_, connection_info, _)| connection_info)
+    }
+}
+
+/// 只建立一次连接，在同一个会话上依次执行各阶段脚本，任一阶段失败即停止
+/// 返回会话、建立连接（TCP、握手、认证）的耗时与合并后的阶段结果；on_connected 不计入连接耗时
+fn run_phases_on_one_session<S>(
+    phases: Vec<(&str, String)>,
+    connect: impl FnOnce() -> Result<S>,
+    on_connected: impl FnOnce(&S),
+    mut exec: impl FnMut(&S, &str, String) -> Result<ExecutionResult>,
+) -> Result<(S, u64, Option<ExecutionResult>)> {
+    let connect_start = Instant::now();
+    let session = connect()?;
+    let connect_time_ms = connect_start.elapsed().as_millis() as u64;
+    on_connected(&session);
+
+    let mut execution_result: Option<ExecutionResult> = None;
+    for (script_path, content) in phases {
+        let phase_result = exec(&session, script_path, content)?;
+        let success = phase_result.success;
+        execution_result = Some(merge_phase_result(execution_result, phase_result));
+        if !success {
+            break;
+        }
+    }
+    Ok((session, connect_time_ms, execution_result))
+}
+
+/// 按 ssh-keygen -l 的格式显示SHA256主机密钥指纹
+fn fingerprint_sha256(hash: &[u8]) -> String {
+    format!("SHA256:{}", base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash))
+}
+
+/// 已认证的SSH会话，离开作用域时断开连接
+pub struct SshSession {
+    session: Session,
+    command_timeout: Duration,
+    keepalive: Option<Duration>, // 配置了 keepalive_seconds 时读取输出按该间隔等待并发送keepalive
+    connection_info: ConnectionInfo, // 对端地址、主机密钥指纹、服务器标识与认证用户
+    trace: SshConnectionTrace,       // 建立连接各阶段的耗时
+}
+
+/// 按阶段计时，lap 返回距上一阶段结束的毫秒数
+struct PhaseTimer {
+    start: Instant,
+    last: Instant,
+}
+
+impl PhaseTimer {
+    fn start() -> Self {
+        let now = Instant::now();
+        Self { start: now, last: now }
+    }
+
+    fn lap(&mut self) -> u64 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_millis() as u64;
+        self.last = now;
+        elapsed
+    }
+
+    fn total(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// 建立TCP连接、完成SSH握手和认证，返回已认证的会话、连接信息与各阶段耗时
+/// 连接与握手使用 ssh_config.timeout_seconds（默认3秒），`timeout` 为之后读写套接字和会话操作的超时
+pub(crate) fn connect_and_authenticate(ssh_config: &SshConfig, timeout: Duration) -> Result<(Session, ConnectionInfo, SshConnectionTrace)> {
+    let mut timer = PhaseTimer::start();
+    let method = auth_method(ssh_config)?;
+
+    // 设置连接超时
+    let ssh_timeout_seconds = ssh_config.timeout_seconds.unwrap_or(3);
+    let ssh_timeout_duration = Duration::from_secs(ssh_timeout_seconds);
+
+    // 建立TCP连接（带严格超时，配置了代理时超时包含代理协商）
+    let tcp = connect_with_timeout(&ssh_config.host, ssh_config.port, ssh_config.proxy.as_ref(), ssh_timeout_duration)
+        .context("Failed to connect to SSH server")?;
+    let tcp_connect_ms = timer.lap();
+
+    // 设置TCP连接超时
+    tcp.set_read_timeout(Some(timeout))
+        .context("Failed to set read timeout")?;
+    tcp.set_write_timeout(Some(timeout))
+        .context("Failed to set write timeout")?;
+    tcp.set_nodelay(true)
+        .context("Failed to set TCP nodelay")?;
+    let peer = tcp.peer_addr().ok();
+
+    // 创建SSH会话
+    let mut sess = Session::new()
+        .context("Failed to create SSH session")?;
+
+    sess.set_tcp_stream(tcp);
+
+    // 设置SSH会话超时（使用步骤级别的超时）
+    sess.set_timeout(timeout.as_millis() as u32);
+
+    // SSH握手（带超时）
+    sess.handshake()
+        .context(format!("SSH handshake failed: timeout {} s", ssh_timeout_seconds))?;
+
+    let handshake_ms = timer.lap();
+    info!("SSH handshake completed, starting authentication");
+    let fingerprint = sess.host_key_hash(HashType::Sha256).map(fingerprint_sha256);
+    let banner = sess.banner().map(str::to_string);
+
+    // 认证（带超时）
+    match method {
+        AuthMethod::Password(password) => sess.userauth_password(&ssh_config.username, password)
+            .context("SSH password authentication failed")?,
+        AuthMethod::PublicKey { path, passphrase } => sess.userauth_pubkey_file(&ssh_config.username, None, Path::new(path), passphrase)
+            .context("SSH key authentication failed")?,
+    }
+    let trace = SshConnectionTrace {
+        tcp_connect_ms,
+        handshake_ms,
+        auth_ms: timer.lap(),
+        total_connect_ms: timer.total(),
+        method: method.kind(),
+    };
+    info!(
+        host = %ssh_config.host,
+        tcp_connect_ms = trace.tcp_connect_ms,
+        handshake_ms = trace.handshake_ms,
+        auth_ms = trace.auth_ms,
+        total_connect_ms = trace.total_connect_ms,
+        method = ?trace.method,
+        "SSH authentication successful"
+    );
+
+    Ok((sess, ssh_connection_info(ssh_config, peer, fingerprint, banner), trace))
+}
+
+impl SshSession {
+    /// 建立TCP连接、完成SSH握手和认证
+    /// `command_timeout` 用于后续命令执行期间的读写超时
+    pub fn connect(ssh_config: &SshConfig, command_timeout: Duration) -> Result<Self> {
+        let (sess, connection_info, trace) = connect_and_authenticate(ssh_config, command_timeout)?;
+
+        // 安静期间由读取循环调用 keepalive_send 发送，服务器回复的是全局请求而不是通道数据，不会混入输出
+        let keepalive = ssh_config.keepalive_seconds.filter(|s| *s > 0).map(Duration::from_secs);
+        if let Some(interval) = keepalive {
+            sess.set_keepalive(true, interval.as_secs() as u32);
+        }
+
+        Ok(Self { session: sess, command_timeout, keepalive, connection_info, trace })
+    }
+
+    /// 在会话上打开新通道执行脚本内容（支持实时输出）
+    #[allow(clippy::too_many_arguments)]
+    pub fn exec_script(
+        &self,
+        content: &str,
+        step: &Step,
+        script_path: &str,
+        pipeline_name: &str,
+        server_name: &str,
+        output_callback: Option<OutputCallback>,
+        variable_manager: &VariableManager,
+        line_filter: Option<&LineFilter>,
+    ) -> Result<ExecutionResult> {
+        // 打开远程shell（配置了 sudo 时通过 sudo 执行；配置了 responses 时在PTY中执行）
+        let shell = remote_shell(step, content);
+        let mut responder = PromptResponder::new(step, self.command_timeout)?;
+        let mut channel = self.session.channel_session()
+            .context("Failed to create SSH channel")?;
+        if responder.is_some() {
+            channel.request_pty("xterm", None, None)
+                .context("Failed to request PTY")?;
+        }
+        channel.exec(&shell.command)
+            .context("Failed to exec remote shell")?;
+
+        // 把脚本内容写入远程shell的stdin，需要应答提示时等应答全部写入后再关闭stdin
+        use std::io::Write;
+        channel.write_all(&shell.stdin)
+            .context("Failed to write script to remote shell")?;
+        if responder.is_none() {
+            channel.send_eof()
+                .context("Failed to send EOF to remote shell")?;
+        }
+
+        // 创建通道用于实时输出
+        let (tx, mut rx) = tokio_mpsc::channel::<OutputEvent>(100);
+
+        // 在单独的线程中处理实时输出
+        let output_handle = std::thread::spawn(move || {
+            while let Some(event) = rx.blocking_recv() {
+                if let Some(callback) = &output_callback {
+                    callback(event);
+                }
+            }
+        });
+
+        // 读取stdout和stderr（按原始字节读取，避免非UTF-8输出导致步骤失败）
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut had_invalid_utf8 = false;
+        let encoding = resolve_encoding(step.encoding.as_deref());
+        let start_time = std::time::Instant::now();
+        // 输出事件共用同一份变量副本
+        let variables = variable_manager.snapshot();
+        let mut line_count = 0u64;
+
+        // 实时读取stdout
+        let mut line = Vec::new();
+        let mut prompt_expired = None;
+
+        // 配置了 keepalive 时读取按间隔超时返回，以便在命令没有输出期间发送keepalive
+        if let Some(interval) = self.keepalive {
+            self.session.set_timeout(interval.as_millis() as u32);
+        }
+
+        let mut emit_stdout = |line: &[u8]| -> bool {
+            let (content, invalid) = decode_output(line, encoding);
+            had_invalid_utf8 |= invalid;
+            let Some(content) = filter_output_line(line_filter, content) else {
+                return true;
+            };
+            stdout.push_str(&content);
+            line_count += 1;
+
+            // 发送实时输出事件
+            let event = OutputEvent {
+                script_path: script_path.to_string(),
+                elapsed_ms: start_time.elapsed().as_millis() as u64,
+                line_count,
+                ..OutputEvent::new(
+                    pipeline_name.to_string(),
+                    server_name.to_string(),
+                    step.clone(),
+                    OutputType::Stdout,
+                    content.trim().to_string(),
+                    variables.clone(),
+                )
+            };
+
+            tx.blocking_send(event).is_ok()
+        };
+
+        match responder.as_mut() {
+            Some(responder) => {
+                let answered = |expect: &str| {
+                    let _ = tx.blocking_send(prompt_answered_event(step, pipeline_name, server_name, script_path, expect, variables.clone()));
+                };
+                prompt_expired = self.read_answering_prompts(&mut channel, responder, &mut emit_stdout, &answered)?;
+            }
+            None => {
+                let mut stdout_reader = BufReader::new(channel.stream(0));
+                while self.read_line(&mut stdout_reader, &mut line)? > 0 {
+                    let keep_going = emit_stdout(&line);
+                    line.clear();
+                    if !keep_going {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // 实时读取stderr
+        let stderr_stream = channel.stderr();
+        let mut stderr_reader = BufReader::new(stderr_stream);
+        line.clear();
+
+        while self.read_line(&mut stderr_reader, &mut line)? > 0 {
+            let (content, invalid) = decode_output(&line, encoding);
+            had_invalid_utf8 |= invalid;
+            line.clear();
+            let Some(content) = filter_output_line(line_filter, content) else {
+                continue;
+            };
+            stderr.push_str(&content);
+            line_count += 1;
+
+            // 发送实时输出事件
+            let event = OutputEvent {
+                script_path: script_path.to_string(),
+                elapsed_ms: start_time.elapsed().as_millis() as u64,
+                line_count,
+                ..OutputEvent::new(
+                    pipeline_name.to_string(),
+                    server_name.to_string(),
+                    step.clone(),
+                    OutputType::Stderr,
+                    content.trim().to_string(),
+                    variables.clone(),
+                )
+            };
+
+            if tx.blocking_send(event).is_err() {
+                break;
+            }
+        }
+
+        if self.keepalive.is_some() {
+            self.session.set_timeout(self.command_timeout.as_millis() as u32);
+        }
+
+        // 等待通道关闭
+        drop(tx);
+        if let Err(e) = output_handle.join() {
+            eprintln!("Output handler thread error: {:?}", e);
+        }
+
+        channel.wait_close()
+            .context("Failed to wait for channel close")?;
+
+        // 提示等待超时时通道已被关闭，没有退出码
+        let exit_code = match prompt_expired {
+            Some(_) => -1,
+            None => channel.exit_status().context("Failed to get exit status")?,
+        };
+
+        let execution_time = start_time.elapsed().as_millis() as u64;
+        info!("SSH command '{}' executed with exit code: {}", script_path, exit_code);
+
+        let mut result = ExecutionResult {
+            success: exit_code == 0,
+            stdout,
+            stderr,
+            script: step.script.to_string(),
+            exit_code,
+            execution_time_ms: execution_time,
+            error_message: None,
+            had_invalid_utf8,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: execution_time,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        };
+        check_sudo_failure(step, &mut result);
+        if let Some(responder) = responder.as_mut() {
+            responder.finish(&mut result, prompt_expired);
+        }
+        Ok(result)
+    }
+
+    /// 按到达的片段读取PTY输出并应答其中的提示，完整的行交给 emit；应答全部写入后关闭stdin
+    /// 读取按 PROMPT_POLL_INTERVAL 超时返回以检查提示是否超时，超时时关闭通道并返回错误信息
+    fn read_answering_prompts(
+        &self,
+        channel: &mut ssh2::Channel,
+        responder: &mut PromptResponder,
+        emit: &mut dyn FnMut(&[u8]) -> bool,
+        answered: &dyn Fn(&str),
+    ) -> Result<Option<String>> {
+        use std::io::{Read, Write};
+        self.session.set_timeout(PROMPT_POLL_INTERVAL.as_millis() as u32);
+        let mut lines = LineSplitter::default();
+        let mut buf = [0u8; 8192];
+        let mut last_output = std::time::Instant::now();
+        let expired = loop {
+            let n = match channel.read(&mut buf) {
+                Ok(0) => break None,
+                Ok(n) => n,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock) => {
+                    if let Some(message) = responder.expired() {
+                        let _ = channel.close();
+                        break Some(message);
+                    }
+                    if last_output.elapsed() >= self.command_timeout {
+                        anyhow::bail!("No output from remote shell within {}s", self.command_timeout.as_secs());
+                    }
+                    if self.keepalive.is_some() {
+                        self.session.keepalive_send().context("Failed to send SSH keepalive")?;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            last_output = std::time::Instant::now();
+            if !responder.is_done() {
+                for (expect, send) in responder.feed(&String::from_utf8_lossy(&buf[..n])) {
+                    channel.write_all(send.as_bytes()).context("Failed to answer prompt")?;
+                    answered(&expect);
+                }
+                if responder.is_done() {
+                    channel.send_eof().context("Failed to send EOF to remote shell")?;
+                }
+            }
+            if !lines.push(&buf[..n]).iter().all(|line| emit(line)) {
+                break None;
+            }
+        };
+        if let Some(line) = lines.finish() {
+            emit(&line);
+        }
+        let timeout = self.keepalive.unwrap_or(self.command_timeout);
+        self.session.set_timeout(timeout.as_millis() as u32);
+        Ok(expired)
+    }
+
+    /// 读取一行输出，配置了 keepalive 时在没有输出的间隔内发送keepalive
+    fn read_line(&self, reader: &mut impl BufRead, line: &mut Vec<u8>) -> Result<usize> {
+        let keepalive = || self.session.keepalive_send().map(|_| ()).context("Failed to send SSH keepalive");
+        read_line_with_keepalive(reader, line, self.keepalive.is_some().then_some(&keepalive))
+    }
+
+    /// 通过SFTP把步骤的产物下载到 local_dir/{server_name}/
+    pub fn collect_artifacts(
+        &self,
+        step: &Step,
+        pipeline_name: &str,
+        server_name: &str,
+        variable_manager: &VariableManager,
+        output_callback: Option<&OutputCallback>,
+    ) -> Result<Vec<CollectedArtifact>> {
+        let sftp = self.session.sftp().map_err(|e| anyhow::anyhow!("Failed to open SFTP session: {}", e));
+        collect_with(
+            step,
+            pipeline_name,
+            server_name,
+            variable_manager,
+            output_callback,
+            |dir| {
+                let entries = sftp.as_ref().map_err(|e| anyhow::anyhow!("{}", e))?.readdir(Path::new(dir))?;
+                Ok(entries
+                    .into_iter()
+                    .filter(|(_, stat)| stat.is_file())
+                    .filter_map(|(path, _)| path.file_name().map(|n| n.to_string_lossy().to_string()))
+                    .collect())
+            },
+            |source, destination| {
+                let mut remote = sftp.as_ref().map_err(|e| anyhow::anyhow!("{}", e))?.open(Path::new(source))?;
+                let mut local = std::fs::File::create(destination)?;
+                Ok(std::io::copy(&mut remote, &mut local)?)
+            },
+        )
+    }
+}
+
+/// 读取一行输出追加到 line，返回 line 的长度（0 表示输出结束）
+/// 传入 keepalive 时读取超时表示该间隔内没有输出：调用 keepalive 后继续等待，已读取的部分保留在 line 中
+fn read_line_with_keepalive(
+    reader: &mut impl BufRead,
+    line: &mut Vec<u8>,
+    keepalive: Option<&dyn Fn() -> Result<()>>,
+) -> Result<usize> {
+    loop {
+        match reader.read_until(b'\n', line) {
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock) => {
+                match keepalive {
+                    Some(keepalive) => keepalive()?,
+                    None => return Err(e.into()),
+                }
+            }
+            result => return Ok(result.map(|_| line.len())?),
+        }
+    }
+}
+
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        let _ = self.session.disconnect(None, "net-shell step finished", None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    /// 在本机执行阶段脚本，返回桩会话使用的执行结果
+    fn run_phase_locally(script_path: &str, content: &str) -> Result<ExecutionResult> {
+        let start = Instant::now();
+        let output = std::process::Command::new("bash").arg("-c").arg(content).output()?;
+        let command_time_ms = start.elapsed().as_millis() as u64;
+        Ok(ExecutionResult {
+            success: output.status.success(),
+            stdout: String::from_utf8(output.stdout)?,
+            stderr: String::from_utf8(output.stderr)?,
+            script: script_path.to_string(),
+            exit_code: output.status.code().unwrap_or(-1),
+            execution_time_ms: command_time_ms,
+            error_message: None,
+            had_invalid_utf8: false,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        })
+    }
+
+    #[test]
+    fn test_step_phases_share_one_connection() {
+        // 桩会话持有一条到本地监听端口的TCP连接，阶段脚本在本机执行
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let phases = vec![
+            ("before.sh", "echo before\n".to_string()),
+            ("main.sh", "echo main\n".to_string()),
+            ("after.sh", "echo after\n".to_string()),
+        ];
+        let mut used_by = Vec::new();
+        let (session, _, result) = run_phases_on_one_session(
+            phases,
+            || Ok(std::net::TcpStream::connect(addr)?),
+            |_| {},
+            |session, script_path, content| {
+                used_by.push((script_path.to_string(), session.local_addr().unwrap()));
+                run_phase_locally(script_path, &content)
+            },
+        ).unwrap();
+
+        let result = result.unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout, "before\nmain\nafter\n");
+        let paths: Vec<&str> = used_by.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["before.sh", "main.sh", "after.sh"]);
+        let local_addr = session.local_addr().unwrap();
+        assert!(used_by.iter().all(|(_, addr)| *addr == local_addr));
+
+        // 已完成的连接都在监听队列中，逐个取出计数
+        listener.set_nonblocking(true).unwrap();
+        let connections = std::iter::from_fn(|| listener.accept().ok()).count();
+        assert_eq!(connections, 1);
+    }
+
+    #[test]
+    fn test_connect_time_is_measured_apart_from_command_time() {
+        // 桩连接耗时约500ms，两个阶段各执行约100ms
+        let phases = vec![
+            ("one.sh", "sleep 0.1\n".to_string()),
+            ("two.sh", "sleep 0.1\n".to_string()),
+        ];
+        let (_, connect_time_ms, result) = run_phases_on_one_session(
+            phases,
+            || {
+                std::thread::sleep(Duration::from_millis(500));
+                Ok(())
+            },
+            |_| {},
+            |_, script_path, content| run_phase_locally(script_path, &content),
+        ).unwrap();
+
+        let result = result.unwrap();
+        assert!(result.success);
+        assert!(connect_time_ms >= 500, "connect {}ms", connect_time_ms);
+        // 连接耗时不计入命令耗时
+        assert!((200..500).contains(&result.command_time_ms), "command {}ms", result.command_time_ms);
+        assert_eq!(result.connect_time_ms, 0, "phase results carry no connect time");
+    }
+
+    #[test]
+    fn test_phase_timer_splits_connect_time() {
+        let mut timer = PhaseTimer::start();
+        std::thread::sleep(Duration::from_millis(20));
+        let tcp_connect_ms = timer.lap();
+        std::thread::sleep(Duration::from_millis(30));
+        let handshake_ms = timer.lap();
+        let trace = SshConnectionTrace {
+            tcp_connect_ms,
+            handshake_ms,
+            auth_ms: timer.lap(),
+            total_connect_ms: timer.total(),
+            method: auth_method(&SshConfig { password: Some("secret".to_string()), ..Default::default() }).unwrap().kind(),
+        };
+        assert!(trace.tcp_connect_ms >= 20 && trace.handshake_ms >= 30, "{:?}", trace);
+        assert!(trace.total_connect_ms >= trace.tcp_connect_ms + trace.handshake_ms + trace.auth_ms);
+        assert_eq!(trace.method, crate::models::SshAuthMethod::Password);
+        assert_eq!(serde_json::to_value(trace.method).unwrap(), "password");
+    }
+
+    #[test]
+    fn test_connection_info_from_session_data() {
+        use sha2::{Digest, Sha256};
+
+        // 与 ssh-keygen -l 相同：SHA256摘要的 base64（无填充）
+        let fingerprint = fingerprint_sha256(&Sha256::digest(b""));
+        assert_eq!(fingerprint, "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU");
+
+        let ssh_config = SshConfig {
+            host: "web.example.com".to_string(),
+            port: 2222,
+            username: "deploy".to_string(),
+            ..Default::default()
+        };
+        let peer = "10.0.0.7:2222".parse().ok();
+        let info = ssh_connection_info(&ssh_config, peer, Some(fingerprint.clone()), Some("SSH-2.0-OpenSSH_9.6".to_string()));
+        assert_eq!(info.kind, crate::models::ConnectionKind::Ssh);
+        assert_eq!(info.resolved_ip.as_deref(), Some("10.0.0.7"));
+        assert_eq!(info.host_key_fingerprint_sha256.as_deref(), Some(fingerprint.as_str()));
+        assert_eq!(info.server_banner.as_deref(), Some("SSH-2.0-OpenSSH_9.6"));
+        assert_eq!((info.username.as_str(), info.port), ("deploy", Some(2222)));
+
+        // 经代理连接时对端地址是代理本身
+        let proxied = SshConfig {
+            proxy: Some(crate::models::ProxyConfig {
+                kind: crate::models::ProxyKind::Socks5,
+                host: "127.0.0.1".to_string(),
+                port: 1080,
+                username: None,
+                password: None,
+            }),
+            ..ssh_config
+        };
+        assert_eq!(ssh_connection_info(&proxied, peer, None, None).resolved_ip, None);
+    }
+
+    #[test]
+    fn test_exported_variables_round_trip_through_shell() {
+        let message = "fix: don't \"break\" `id` $(whoami)\nsecond line";
+        let variables = HashMap::from([
+            ("commit_message".to_string(), message.to_string()),
+            ("not-exported".to_string(), "x".to_string()),
+        ]);
+        let script = format!("{}printf %s \"$commit_message\"", crate::ssh::export_variables_script(&variables));
+        assert!(!script.contains("not-exported"));
+        let output = std::process::Command::new("bash").arg("-c").arg(&script).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), message);
+    }
+
+    /// 在本机用假的 sudo 运行远程shell命令：-S 时从stdin读取一行密码，密码为 secret 才执行命令
+    fn run_remote_shell(step: &Step, content: &str) -> ExecutionResult {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = tempfile::tempdir().unwrap();
+        let sudo = bin.path().join("sudo");
+        std::fs::write(&sudo, concat!(
+            "#!/bin/sh\n",
+            "stdin=0\n",
+            "while [ $# -gt 0 ]; do case \"$1\" in -S) stdin=1; shift;; -k|-n) shift;; -p|-u) shift 2;; *) break;; esac; done\n",
+            "if [ $stdin = 1 ]; then IFS= read -r pw; [ \"$pw\" = secret ] || { echo 'sudo: 1 incorrect password attempt' >&2; exit 1; }; fi\n",
+            "exec \"$@\"\n",
+        )).unwrap();
+        std::fs::set_permissions(&sudo, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let shell = crate::ssh::remote_shell(step, content);
+        let mut child = std::process::Command::new("bash")
+            .arg("-c")
+            .arg(&shell.command)
+            .env("PATH", format!("{}:{}", bin.path().display(), std::env::var("PATH").unwrap()))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(&shell.stdin).unwrap();
+        let output = child.wait_with_output().unwrap();
+        let mut result = ExecutionResult {
+            success: output.status.success(),
+            stdout: String::from_utf8(output.stdout).unwrap(),
+            stderr: String::from_utf8(output.stderr).unwrap(),
+            script: String::new(),
+            exit_code: output.status.code().unwrap(),
+            execution_time_ms: 0,
+            error_message: None,
+            had_invalid_utf8: false,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: 0,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        };
+        crate::ssh::check_sudo_failure(step, &mut result);
+        result
+    }
+
+    #[test]
+    fn test_interactive_remote_shell_passes_script_as_argument() {
+        let step = Step {
+            responses: Some(vec![crate::models::PromptResponse {
+                expect_regex: "Name:".to_string(),
+                send: "web".to_string(),
+                timeout_seconds: None,
+            }]),
+            compress: Some(true),
+            ..Default::default()
+        };
+        let script = "echo 'it''s' \"$0\"\nread -r name || true\necho \"name=${name:-none}\"\n";
+        let shell = crate::ssh::remote_shell(&step, script);
+        assert!(shell.command.starts_with("stty -echo -onlcr 2>/dev/null; exec bash -c "), "{}", shell.command);
+        // stdin 只留给应答，脚本已作为参数传入
+        assert!(shell.stdin.is_empty());
+        assert_eq!(run_remote_shell(&step, script).stdout, "its bash\nname=none\n");
+    }
+
+    #[test]
+    fn test_sudo_remote_shell() {
+        let script = "read -r line || true\necho \"ran: ${line:-no stdin}\"\n";
+
+        // 未配置 sudo 时仍直接交给 bash
+        let step = Step::default();
+        let shell = crate::ssh::remote_shell(&step, script);
+        assert_eq!((shell.command.as_str(), shell.stdin.as_slice()), ("bash", script.as_bytes()));
+
+        let with_password = |password: &str| Step {
+            sudo: Some(crate::models::SudoConfig { user: None, password: Some(password.to_string()) }),
+            ..Default::default()
+        };
+        let step = with_password("secret");
+        assert!(!crate::ssh::remote_shell(&step, script).command.contains("secret"));
+        let result = run_remote_shell(&step, script);
+        assert_eq!(result.stdout, "ran: no stdin\n");
+        assert!(result.error_message.is_none());
+
+        // 密码错误时脚本不会被执行，也不会被当作密码读取
+        let result = run_remote_shell(&with_password("wrong"), script);
+        assert_eq!(result.exit_code, 1);
+        assert_eq!(result.stdout, "");
+        let message = result.error_message.unwrap();
+        assert!(message.starts_with("sudo authentication failed for user 'root'"), "{}", message);
+    }
+
+    #[test]
+    fn test_compressed_remote_shell() {
+        // 生成约4MB的配置脚本
+        let mut script = String::from("cat <<'EOF' | wc -l\n");
+        for i in 0..100_000 {
+            script.push_str(&format!("upstream_{i} = server-{i}.example.com:8080 weight=1\n"));
+        }
+        script.push_str("EOF\n");
+
+        let step = Step { compress: Some(true), ..Default::default() };
+        let shell = crate::ssh::remote_shell(&step, &script);
+        assert_eq!(shell.command, "gzip -dc | bash");
+        assert!(shell.stdin.len() * 10 < script.len(), "{} -> {}", script.len(), shell.stdin.len());
+        assert_eq!(run_remote_shell(&step, &script).stdout.trim(), "100000");
+
+        // 通过 sudo 执行时同样先解压
+        let step = Step {
+            compress: Some(true),
+            sudo: Some(crate::models::SudoConfig { user: None, password: Some("secret".to_string()) }),
+            ..Default::default()
+        };
+        assert_eq!(run_remote_shell(&step, "echo \"ran as sudo\"\n").stdout, "ran as sudo\n");
+    }
+
+    #[test]
+    fn test_quiet_periods_send_keepalives_without_output() {
+        /// 按顺序返回数据块，None 表示一次读取超时
+        struct Quiet(std::collections::VecDeque<Option<&'static [u8]>>);
+        impl std::io::Read for Quiet {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                match self.0.pop_front() {
+                    Some(Some(chunk)) => {
+                        buf[..chunk.len()].copy_from_slice(chunk);
+                        Ok(chunk.len())
+                    }
+                    Some(None) => Err(std::io::ErrorKind::TimedOut.into()),
+                    None => Ok(0),
+                }
+            }
+        }
+        let chunks = || Quiet([None, Some(&b"build"[..]), None, None, Some(&b" done\nlast"[..])].into());
+
+        let sent = std::cell::Cell::new(0);
+        let keepalive = || {
+            sent.set(sent.get() + 1);
+            Ok(())
+        };
+        let mut reader = BufReader::new(chunks());
+        let mut line = Vec::new();
+        let mut lines = Vec::new();
+        while read_line_with_keepalive(&mut reader, &mut line, Some(&keepalive)).unwrap() > 0 {
+            lines.push(String::from_utf8(std::mem::take(&mut line)).unwrap());
+        }
+        assert_eq!(lines, ["build done\n", "last"]);
+        assert_eq!(sent.get(), 3);
+
+        // 未配置 keepalive 时读取超时仍是错误
+        let mut reader = BufReader::new(chunks());
+        assert!(read_line_with_keepalive(&mut reader, &mut Vec::new(), None).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use anyhow::{Context, Result};
+use russh::client;
+use russh::keys::{load_secret_key, HashAlg, PrivateKeyWithHashAlg, PublicKeyOrCertificate};
+use russh::ChannelMsg;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+use super::artifacts::{apply_collection, emit_collected, handle_missing, local_destination, should_collect, ArtifactPattern};
+use super::auth::{auth_method, AuthMethod};
+use super::prompts::{prompt_answered_event, PromptResponder, PROMPT_POLL_INTERVAL};
+use super::proxy::connect_with_timeout;
+use super::{
+    build_script_preamble, check_sudo_failure, ConnectError, decode_output, emit_connection_info, emit_rendered_script, filter_output_line, guard_callback, merge_phase_result, remote_shell,
+    render_remote_phase_script, LineSplitter, resolve_encoding, script_error_result, ssh_connection_info, step_phases,
+};
+use crate::models::{CollectedArtifact, ConnectionInfo, ExecutionResult, LineFilter, OutputCallback, OutputEvent, OutputType, SshConfig, Step};
+use crate::template::EscapeMode;
+use crate::vars::VariableManager;
+use crate::ExtractRule;
+
+/// 基于 russh 的纯Rust SSH执行器
+/// 与 SshExecutor 的执行约定一致，便于在无法编译 libssh2 的环境（如 musl 静态构建）中使用
+pub struct RusshExecutor;
+
+impl RusshExecutor {
+    /// 通过SSH执行脚本（支持实时输出）
+    /// 步骤的 before_script / script / after_script 共用同一个SSH会话，任一阶段失败即停止并关闭连接
+    #[allow(clippy::too_many_arguments)]
+    pub async fn execute_script_with_realtime_output(
+        script: Option<String>,
+        global_scripts: Arc<Vec<String>>,
+        server_name: &str,
+        ssh_config: &SshConfig,
+        step: &Step,
+        pipeline_name: &str,
+        _step_name: &str,
+        output_callback: Option<OutputCallback>,
+        mut variable_manager: VariableManager,
+        extract_rules: Option<Vec<ExtractRule>>,
+        line_filter: Option<LineFilter>,
+    ) -> Result<ExecutionResult> {
+        info!("Connecting to {}:{} as {} (russh)", ssh_config.host, ssh_config.port, ssh_config.username);
+        let output_callback = output_callback.map(guard_callback);
+
+        // 公共前置内容：全局脚本 + 流水线脚本
+        let preamble = build_script_preamble(&global_scripts, script.as_deref())?;
+
+        variable_manager.set_variable("ssh_server_name".to_string(), server_name.to_string());
+        variable_manager.set_variable("ssh_server_ip".to_string(), ssh_config.host.to_string());
+
+        // 在建立连接前读取所有阶段的脚本
+        let mut phases = Vec::new();
+        for script_path in step_phases(step) {
+            match render_remote_phase_script(&preamble, script_path, step, &variable_manager) {
+                Ok(content) => phases.push((script_path, content)),
+                Err(e) => return Ok(script_error_result(script_path, &e)),
+            }
+        }
+
+        let command_timeout = Duration::from_secs(step.timeout_seconds.unwrap_or(30));
+        // 分别记录建立连接与执行脚本的耗时
+        let connect_start = std::time::Instant::now();
+        let (session, connection_info) = connect(ssh_config, command_timeout).await.context(ConnectError)?;
+        let connect_time_ms = connect_start.elapsed().as_millis() as u64;
+        emit_connection_info(output_callback.as_ref(), step, pipeline_name, server_name, &connection_info, &variable_manager);
+
+        let mut execution_result: Option<ExecutionResult> = None;
+        for (script_path, content) in phases {
+            emit_rendered_script(output_callback.as_ref(), step, pipeline_name, server_name, script_path, &content, &variable_manager);
+            let phase = exec_script(
+                &session,
+                &content,
+                step,
+                script_path,
+                pipeline_name,
+                server_name,
+                output_callback.as_ref(),
+                &variable_manager,
+                line_filter.as_ref(),
+            );
+            let mut phase_result = tokio::time::timeout(command_timeout, phase)
+                .await
+                .map_err(|_| anyhow::anyhow!("Script '{}' timed out after {} s", script_path, command_timeout.as_secs()))??;
+            phase_result.rendered_script = Some(content);
+            let success = phase_result.success;
+            execution_result = Some(merge_phase_result(execution_result, phase_result));
+            if !success {
+                break;
+            }
+        }
+
+        let mut execution_result = execution_result
+            .ok_or_else(|| anyhow::anyhow!("Step '{}' has no script to execute", step.name))?;
+        execution_result.connect_time_ms = connect_time_ms;
+        execution_result.connection_info = Some(connection_info);
+
+        // 在同一个会话上下载产物
+        if should_collect(step, execution_result.success) {
+            let collection = collect_artifacts(&session, step, pipeline_name, server_name, &variable_manager, output_callback.as_ref()).await;
+            apply_collection(&mut execution_result, collection);
+        }
+
+        // 所有阶段结束后立即释放连接
+        let _ = session
+            .disconnect(russh::Disconnect::ByApplication, "net-shell step finished", "en")
+            .await;
+
+        // 提取变量
+        if let Some(rules) = extract_rules {
+            if let Err(e) = variable_manager.extract_variables(&rules, &execution_result) {
+                info!("Failed to extract variables: {}", e);
+            }
+        }
+
+        Ok(execution_result)
+    }
+
+    /// 只建立TCP连接、完成SSH握手和认证，不执行脚本；返回连接信息，会话随即关闭
+    pub async fn connect_and_auth(ssh_config: &SshConfig) -> Result<ConnectionInfo> {
+        let timeout = Duration::from_secs(ssh_config.timeout_seconds.unwrap_or(30));
+        let (session, connection_info) = connect(ssh_config, timeout).await?;
+        let _ = session
+            .disconnect(russh::Disconnect::ByApplication, "net-shell healthcheck", "en")
+            .await;
+        Ok(connection_info)
+    }
+}
+
+/// 客户端事件处理：与 libssh2 后端一致，不校验服务器主机密钥，只记录其SHA256指纹
+#[derive(Default)]
+struct ClientHandler {
+    host_key_fingerprint: Arc<Mutex<Option<String>>>,
+}
+
+impl client::Handler for ClientHandler {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKeyOrCertificate,
+    ) -> Result<bool, Self::Error> {
+        let fingerprint = match server_public_key {
+            PublicKeyOrCertificate::PublicKey { key, .. } => key.fingerprint(HashAlg::Sha256),
+            PublicKeyOrCertificate::Certificate(cert) => cert.public_key().fingerprint(HashAlg::Sha256),
+        };
+        *self.host_key_fingerprint.lock().unwrap() = Some(fingerprint.to_string());
+        Ok(true)
+    }
+}
+
+/// 建立连接、完成握手和认证，返回会话与连接的审计信息
+async fn connect(ssh_config: &SshConfig, command_timeout: Duration) -> Result<(client::Handle<ClientHandler>, ConnectionInfo)> {
+    let connect_timeout_seconds = ssh_config.timeout_seconds.unwrap_or(3);
+    // 配置了 keepalive_seconds 时没有收到数据的间隔内发送keepalive，服务器的回复同样重置 inactivity_timeout
+    let config = Arc::new(client::Config {
+        inactivity_timeout: Some(command_timeout),
+        keepalive_interval: ssh_config.keepalive_seconds.filter(|s| *s > 0).map(Duration::from_secs),
+        ..Default::default()
+    });
+
+    let connect_timeout = Duration::from_secs(connect_timeout_seconds);
+    let handler = ClientHandler::default();
+    let host_key_fingerprint = handler.host_key_fingerprint.clone();
+    let connecting = async {
+        let stream = match &ssh_config.proxy {
+            // 经代理建立TCP连接（超时包含代理协商），再在该连接上完成SSH握手
+            Some(proxy) => {
+                let (host, port, proxy) = (ssh_config.host.clone(), ssh_config.port, proxy.clone());
+                let stream = tokio::task::spawn_blocking(move || connect_with_timeout(&host, port, Some(&proxy), connect_timeout))
+                    .await??;
+                stream.set_nonblocking(true)?;
+                tokio::net::TcpStream::from_std(stream)?
+            }
+            // 与 client::connect 相同，自行建立连接以记录实际连接的地址
+            None => {
+                let stream = tokio::net::TcpStream::connect((ssh_config.host.as_str(), ssh_config.port)).await?;
+                if config.nodelay {
+                    let _ = stream.set_nodelay(true);
+                }
+                stream
+            }
+        };
+        let peer = stream.peer_addr().ok();
+        Ok::<_, anyhow::Error>((client::connect_stream(config, stream, handler).await?, peer))
+    };
+    let (mut session, peer) = tokio::time::timeout(connect_timeout, connecting)
+        .await
+        .map_err(|_| anyhow::anyhow!("connect to {}:{} timeout {} s", ssh_config.host, ssh_config.port, connect_timeout_seconds))?
+        .context("Failed to connect to SSH server")?;
+
+    info!("SSH handshake completed, starting authentication");
+
+    let authenticated = match auth_method(ssh_config)? {
+        AuthMethod::Password(password) => session
+            .authenticate_password(&ssh_config.username, password)
+            .await
+            .context("SSH password authentication failed")?
+            .success(),
+        AuthMethod::PublicKey { path, passphrase } => {
+            let key = load_secret_key(path, passphrase)
+                .context(format!("Failed to load private key: {}", path))?;
+            let hash_alg = session.best_supported_rsa_hash().await?.flatten();
+            session
+                .authenticate_publickey(&ssh_config.username, PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+                .await
+                .context("SSH key authentication failed")?
+                .success()
+        }
+    };
+
+    if !authenticated {
+        return Err(anyhow::anyhow!("SSH authentication failed for user '{}'", ssh_config.username));
+    }
+    info!("SSH authentication successful");
+
+    // russh 不提供服务器标识（banner）
+    let fingerprint = host_key_fingerprint.lock().unwrap().take();
+    Ok((session, ssh_connection_info(ssh_config, peer, fingerprint, None)))
+}
+
+/// 在会话上打开新通道执行脚本内容（支持实时输出）
+#[allow(clippy::too_many_arguments)]
+async fn exec_script(
+    session: &client::Handle<ClientHandler>,
+    content: &str,
+    step: &Step,
+    script_path: &str,
+    pipeline_name: &str,
+    server_name: &str,
+    output_callback: Option<&OutputCallback>,
+    variable_manager: &VariableManager,
+    line_filter: Option<&LineFilter>,
+) -> Result<ExecutionResult> {
+    let start_time = std::time::Instant::now();
+
+    // 配置了 sudo 时通过 sudo 执行；配置了 responses 时在PTY中执行，应答全部写入后才关闭stdin
+    let shell = remote_shell(step, content);
+    let mut responder = PromptResponder::new(step, Duration::from_secs(step.timeout_seconds.unwrap_or(30)))?;
+    let mut channel = session.channel_open_session().await
+        .context("Failed to create SSH channel")?;
+    if responder.is_some() {
+        channel.request_pty(false, "xterm", 80, 24, 0, 0, &[]).await
+            .context("Failed to request PTY")?;
+    }
+    channel.exec(true, shell.command.as_str()).await
+        .context("Failed to exec remote shell")?;
+    channel.data(shell.stdin.as_slice()).await
+        .context("Failed to write script to remote shell")?;
+    if responder.is_none() {
+        channel.eof().await
+            .context("Failed to send EOF to remote shell")?;
+    }
+
+    let encoding = resolve_encoding(step.encoding.as_deref());
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let mut had_invalid_utf8 = false;
+    let mut stdout_lines = LineSplitter::default();
+    let mut stderr_lines = LineSplitter::default();
+    let mut exit_code = None;
+    // 输出事件共用同一份变量副本
+    let variables = variable_manager.snapshot();
+    let mut line_count = 0u64;
+
+    let mut emit = |line: &[u8], output_type: OutputType, target: &mut String| {
+        let (content, invalid) = decode_output(line, encoding);
+        had_invalid_utf8 |= invalid;
+        let Some(content) = filter_output_line(line_filter, content) else {
+            return;
+        };
+        target.push_str(&content);
+        line_count += 1;
+
+        if let Some(callback) = output_callback {
+            callback(OutputEvent {
+                script_path: script_path.to_string(),
+                elapsed_ms: start_time.elapsed().as_millis() as u64,
+                line_count,
+                ..OutputEvent::new(
+                    pipeline_name.to_string(),
+                    server_name.to_string(),
+                    step.clone(),
+                    output_type,
+                    content.trim().to_string(),
+                    variables.clone(),
+                )
+            });
+        }
+    };
+
+    let mut prompt_expired = None;
+    loop {
+        let msg = match &responder {
+            // 等待输出期间定期检查提示是否超时
+            Some(waiting) if !waiting.is_done() => {
+                match tokio::time::timeout(PROMPT_POLL_INTERVAL, channel.wait()).await {
+                    Ok(msg) => msg,
+                    Err(_) => match waiting.expired() {
+                        Some(message) => {
+                            prompt_expired = Some(message);
+                            let _ = channel.close().await;
+                            break;
+                        }
+                        None => continue,
+                    },
+                }
+            }
+            _ => channel.wait().await,
+        };
+        let Some(msg) = msg else { break };
+        match msg {
+            ChannelMsg::Data { ref data } => {
+                if let Some(responder) = responder.as_mut().filter(|r| !r.is_done()) {
+                    for (expect, send) in responder.feed(&String::from_utf8_lossy(data)) {
+                        channel.data(send.as_bytes()).await
+                            .context("Failed to answer prompt")?;
+                        if let Some(callback) = output_callback {
+                            callback(prompt_answered_event(step, pipeline_name, server_name, script_path, &expect, variables.clone()));
+                        }
+                    }
+                    if responder.is_done() {
+                        channel.eof().await
+                            .context("Failed to send EOF to remote shell")?;
+                    }
+                }
+                for line in stdout_lines.push(data) {
+                    emit(&line, OutputType::Stdout, &mut stdout);
+                }
+            }
+            ChannelMsg::ExtendedData { ref data, ext: 1 } => {
+                for line in stderr_lines.push(data) {
+                    emit(&line, OutputType::Stderr, &mut stderr);
+                }
+            }
+            ChannelMsg::ExitStatus { exit_status } => {
+                exit_code = Some(exit_status as i32);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(line) = stdout_lines.finish() {
+        emit(&line, OutputType::Stdout, &mut stdout);
+    }
+    if let Some(line) = stderr_lines.finish() {
+        emit(&line, OutputType::Stderr, &mut stderr);
+    }
+
+    // 提示等待超时时通道已关闭，没有退出码
+    let exit_code = match (exit_code, &prompt_expired) {
+        (Some(exit_code), _) => exit_code,
+        (None, Some(_)) => -1,
+        (None, None) => anyhow::bail!("Remote shell closed without an exit status"),
+    };
+    info!("SSH command '{}' executed with exit code: {}", script_path, exit_code);
+
+    let execution_time = start_time.elapsed().as_millis() as u64;
+    let mut result = ExecutionResult {
+        success: exit_code == 0,
+        stdout,
+        stderr,
+        script: step.script.to_string(),
+        exit_code,
+        execution_time_ms: execution_time,
+        error_message: None,
+        had_invalid_utf8,
+        artifacts: Vec::new(),
+        connect_time_ms: 0,
+        command_time_ms: execution_time,
+        fallback_reason: None,
+        rendered_script: None,
+        script_sha256: None,
+        connection_info: None,
+        answered_prompts: Vec::new(),
+        ssh_trace: None,
+        skip_reason: None,
+    };
+    check_sudo_failure(step, &mut result);
+    if let Some(responder) = responder.as_mut() {
+        responder.finish(&mut result, prompt_expired);
+    }
+    Ok(result)
+}
+
+/// 把步骤的产物下载到 local_dir/{server_name}/
+/// russh 没有内置SFTP，通过 exec 通道用 ls 列出目录、用 cat 读取文件
+async fn collect_artifacts(
+    session: &client::Handle<ClientHandler>,
+    step: &Step,
+    pipeline_name: &str,
+    server_name: &str,
+    variable_manager: &VariableManager,
+    output_callback: Option<&OutputCallback>,
+) -> Result<Vec<CollectedArtifact>> {
+    let mut collected = Vec::new();
+    for spec in step.artifacts.iter().flatten() {
+        let remote_path = variable_manager.replace_variables(&spec.remote_path);
+        let local_dir = variable_manager.replace_variables(&spec.local_dir);
+        let pattern = ArtifactPattern::parse(&remote_path)?;
+
+        let mut listing = Vec::new();
+        let command = format!("ls -1Ap -- {}", EscapeMode::Shell.escape(&pattern.dir));
+        let mut matched: Vec<String> = match exec_to_writer(session, &command, &mut listing).await {
+            Ok(0) => String::from_utf8_lossy(&listing)
+                .lines()
+                .filter(|name| !name.ends_with('/') && pattern.is_match(name))
+                .map(str::to_string)
+                .collect(),
+            Ok(code) => {
+                handle_missing(spec, server_name, format!("failed to list {}: exit code {}", pattern.dir, code))?;
+                continue;
+            }
+            Err(e) => {
+                handle_missing(spec, server_name, format!("failed to list {}: {}", pattern.dir, e))?;
+                continue;
+            }
+        };
+        matched.sort();
+        if matched.is_empty() {
+            handle_missing(spec, server_name, "no matching files".to_string())?;
+            continue;
+        }
+
+        for name in matched {
+            let source = pattern.path_of(&name);
+            let destination = local_destination(&local_dir, server_name, &name)?;
+            let mut file = std::fs::File::create(&destination)
+                .context(format!("Failed to create artifact file: {}", destination.display()))?;
+            let command = format!("cat -- {}", EscapeMode::Shell.escape(&source));
+            match exec_to_writer(session, &command, &mut file).await {
+                Ok(0) => {
+                    let artifact = CollectedArtifact {
+                        remote_path: source,
+                        local_path: destination.to_string_lossy().to_string(),
+                        size: file.metadata()?.len(),
+                    };
+                    emit_collected(output_callback, step, pipeline_name, server_name, variable_manager, &artifact);
+                    collected.push(artifact);
+                }
+                Ok(code) => handle_missing(spec, server_name, format!("failed to fetch {}: exit code {}", source, code))?,
+                Err(e) => handle_missing(spec, server_name, format!("failed to fetch {}: {}", source, e))?,
+            }
+        }
+    }
+    Ok(collected)
+}
+
+/// 在新通道上执行命令，把标准输出写入 `out`，返回退出码
+async fn exec_to_writer(
+    session: &client::Handle<ClientHandler>,
+    command: &str,
+    out: &mut impl std::io::Write,
+) -> Result<i32> {
+    let mut channel = session.channel_open_session().await
+        .context("Failed to create SSH channel")?;
+    channel.exec(true, command).await
+        .context("Failed to exec remote command")?;
+    let mut exit_code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { ref data } => out.write_all(data)?,
+            ChannelMsg::ExitStatus { exit_status } => exit_code = Some(exit_status as i32),
+            _ => {}
+        }
+    }
+    exit_code.ok_or_else(|| anyhow::anyhow!("Remote command closed without an exit status"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_splitter_handles_chunked_output() {
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"hel").is_empty());
+        assert_eq!(splitter.push(b"lo\nwor"), vec![b"hello\n".to_vec()]);
+        assert_eq!(splitter.push(b"ld\n\n"), vec![b"world\n".to_vec(), b"\n".to_vec()]);
+        assert!(splitter.push(b"tail").is_empty());
+        assert_eq!(splitter.finish(), Some(b"tail".to_vec()));
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused_reports_error() {
+        let ssh_config = SshConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1,
+            username: "user".to_string(),
+            password: Some("password".to_string()),
+            timeout_seconds: Some(1),
+            ..Default::default()
+        };
+        let err = connect(&ssh_config, Duration::from_secs(1)).await.err().unwrap();
+        assert!(err.to_string().contains("Failed to connect to SSH server"));
+    }
+}
@@ -1,8 +1,141 @@
 use anyhow::{anyhow, Result};
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "remote-templates")]
+use std::time::Duration;
+
+#[cfg(feature = "remote-templates")]
+mod remote;
+#[cfg(feature = "remote-templates")]
+mod vault;
+
+#[cfg(feature = "remote-templates")]
+pub use vault::VaultSecretDirective;
+
+/// include的最大嵌套层数
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// 严格模式的错误信息中，出错占位符前后各显示的模板字符数
+const ERROR_CONTEXT_CHARS: usize = 30;
+
+/// 用于循环检测的include键：尽量使用规范化后的绝对路径
+fn include_key(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// 拆分指令参数末尾的 `as 变量`，返回 (其余参数, 变量名)
+fn split_binding(args: &str) -> (&str, Option<&str>) {
+    static BINDING: OnceLock<Regex> = OnceLock::new();
+    let binding = BINDING.get_or_init(|| Regex::new(r"(?s)^(?:(.*?)\s+)?as\s+([a-zA-Z_][a-zA-Z0-9_]*)$").unwrap());
+    match binding.captures(args) {
+        Some(captures) => (captures.get(1).map_or("", |m| m.as_str()), captures.get(2).map(|m| m.as_str())),
+        None => (args, None),
+    }
+}
+
+/// 变量转义方式，通过 `{{ var | escape_shell }}` 等过滤器指定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeMode {
+    /// 按 shlex::quote 规则用单引号包裹，内部单引号转为 '\''
+    Shell,
+    /// 转义 < > & " '
+    Html,
+    /// 单引号加倍并用单引号包裹
+    Sql,
+}
+
+impl EscapeMode {
+    /// 按过滤器名称解析转义方式，sh_quote 与 escape_shell 相同
+    pub(crate) fn from_filter(name: &str) -> Option<Self> {
+        match name {
+            "escape_shell" | "sh_quote" => Some(EscapeMode::Shell),
+            "escape_html" => Some(EscapeMode::Html),
+            "escape_sql" => Some(EscapeMode::Sql),
+            _ => None,
+        }
+    }
+
+    /// 对值进行转义
+    pub fn escape(&self, value: &str) -> String {
+        match self {
+            EscapeMode::Shell => {
+                if !value.is_empty()
+                    && value
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c))
+                {
+                    value.to_string()
+                } else {
+                    format!("'{}'", value.replace('\'', "'\\''"))
+                }
+            }
+            EscapeMode::Html => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    match c {
+                        '<' => escaped.push_str("&lt;"),
+                        '>' => escaped.push_str("&gt;"),
+                        '&' => escaped.push_str("&amp;"),
+                        '"' => escaped.push_str("&quot;"),
+                        '\'' => escaped.push_str("&#x27;"),
+                        c => escaped.push(c),
+                    }
+                }
+                escaped
+            }
+            EscapeMode::Sql => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+/// 自动转义方式：对所有未显式指定过滤器的变量替换生效，`| raw` 可跳过
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AutoEscapeMode {
+    /// 不自动转义（默认）
+    #[default]
+    None,
+    Shell,
+    Html,
+    Sql,
+}
+
+impl AutoEscapeMode {
+    /// 对应的转义方式，None 表示不转义
+    pub fn escape_mode(&self) -> Option<EscapeMode> {
+        match self {
+            AutoEscapeMode::None => None,
+            AutoEscapeMode::Shell => Some(EscapeMode::Shell),
+            AutoEscapeMode::Html => Some(EscapeMode::Html),
+            AutoEscapeMode::Sql => Some(EscapeMode::Sql),
+        }
+    }
+}
+
+/// 把模板文本渲染为字符串，TemplateEngine 与 VariableManager 都实现了该接口
+pub trait TemplateRenderer {
+    /// 渲染模板
+    fn render(&self, template: &str) -> Result<String>;
+}
+
+/// 自定义模板指令，通过 TemplateEngine::register_directive 注册
+/// 模板中的 `{% 名称 参数 %}` 在展开 include 之后替换为 process 的返回值；
+/// `{% 名称 参数 as 变量 %}` 不输出内容，返回值绑定到变量，供模板中的 `{{ 变量 }}` 和for循环使用
+pub trait CustomDirective {
+    /// 指令名称，不能是 for、endfor、include
+    fn name(&self) -> &str;
+
+    /// 处理一个指令，args 为名称之后的文本（去掉首尾空白），返回替换指令的内容
+    fn process(&self, args: &str, engine: &TemplateEngine) -> Result<String>;
+}
+
+/// 内置指令的名称，不会按自定义指令处理
+const BUILTIN_DIRECTIVES: [&str; 3] = ["for", "endfor", "include"];
 
 /// 模板引擎结构体
 pub struct TemplateEngine {
@@ -20,12 +153,23 @@ pub struct TemplateEngine {
     for_right_delimiter: String,
     /// 是否保留循环中的换行符
     preserve_loop_newlines: bool,
+    /// 自动转义方式
+    auto_escape: AutoEscapeMode,
+    /// 严格模式：引用未定义的变量、属性或数组时报错，否则替换为空
+    strict_mode: bool,
     /// 变量正则表达式
     var_regex: Regex,
     /// for循环正则表达式
     for_regex: Regex,
     /// include正则表达式
     include_regex: Regex,
+    /// 自定义指令正则表达式：{% 名称 参数 %}
+    directive_regex: Regex,
+    /// 注册的自定义指令，键为指令名称
+    directives: HashMap<String, Arc<dyn CustomDirective + Send + Sync>>,
+    /// 通过URL include 的模板（remote-templates 特性）
+    #[cfg(feature = "remote-templates")]
+    remote: remote::RemoteIncludes,
 }
 
 impl TemplateEngine {
@@ -51,18 +195,19 @@ impl TemplateEngine {
         let for_left_escaped = regex::escape(for_left);
         let for_right_escaped = regex::escape(for_right);
 
-        // 变量匹配正则：{{ variable }}
+        // 变量匹配正则：{{ variable }}，支持过滤器：{{ variable | escape_shell }}
         let var_pattern = format!(
-            r"{}\s*([a-zA-Z_][a-zA-Z0-9_]*(?:\.[a-zA-Z_][a-zA-Z0-9_]*)*)\s*{}",
+            r"{}\s*([a-zA-Z_][a-zA-Z0-9_]*(?:\.[a-zA-Z_][a-zA-Z0-9_]*)*)\s*(?:\|\s*([a-zA-Z_][a-zA-Z0-9_]*)\s*)?{}",
             var_left_escaped, var_right_escaped
         );
         let var_regex = Regex::new(&var_pattern).unwrap();
 
         // for循环匹配正则：{% for item in items %}   ... {% endfor %}
+        // 支持键值对语法：{% for key, value in object %}   ... {% endfor %}
         // 支持split语法：{% for item in items split "," %}   ... {% endfor %}
         // 支持jsonparse语法：{% for item in items jsonparse %}   ... {% endfor %}
         let for_pattern = format!(
-            "(?s){}\\s*for\\s+(\\w+)\\s+in\\s+(\\w+)(?:\\s+(split|jsonparse)(?:\\s+\"([^\"]+)\")?)?\\s*{}(.*?){}\\s*endfor\\s*{}",
+            "(?s){}\\s*for\\s+(\\w+)(?:\\s*,\\s*(\\w+))?\\s+in\\s+(\\w+)(?:\\s+(split|jsonparse)(?:\\s+\"([^\"]+)\")?)?\\s*{}(.*?){}\\s*endfor\\s*{}",
             for_left_escaped, for_right_escaped, for_left_escaped, for_right_escaped
         );
         let for_regex = Regex::new(&for_pattern).unwrap();
@@ -74,6 +219,13 @@ impl TemplateEngine {
         );
         let include_regex = Regex::new(&include_pattern).unwrap();
 
+        // 自定义指令匹配正则：{% name args %}，for、endfor、include 之外的名称按自定义指令处理
+        let directive_pattern = format!(
+            "{}\\s*([a-zA-Z_][a-zA-Z0-9_]*)(?:\\s+(.*?))?\\s*{}",
+            for_left_escaped, for_right_escaped
+        );
+        let directive_regex = Regex::new(&directive_pattern).unwrap();
+
         Self {
             variables: HashMap::new(),
             template_dir: None,
@@ -82,9 +234,15 @@ impl TemplateEngine {
             for_left_delimiter: for_left.to_string(),
             for_right_delimiter: for_right.to_string(),
             preserve_loop_newlines: true, // 默认保留换行符，保持向后兼容
+            auto_escape: AutoEscapeMode::None,
+            strict_mode: false,
             var_regex,
             for_regex,
             include_regex,
+            directive_regex,
+            directives: HashMap::new(),
+            #[cfg(feature = "remote-templates")]
+            remote: remote::RemoteIncludes::new(),
         }
     }
 
@@ -112,91 +270,428 @@ impl TemplateEngine {
         self
     }
 
+    /// 获取变量值，支持点号路径访问嵌套对象，不存在时返回 None
+    pub fn get_variable(&self, path: &str) -> Option<serde_json::Value> {
+        self.get_variable_value(path).ok()
+    }
+
+    /// 注册自定义指令，同名的指令被替换；名称为内置指令时返回错误
+    pub fn register_directive(&mut self, directive: Box<dyn CustomDirective + Send + Sync>) -> Result<&mut Self> {
+        let name = directive.name().to_string();
+        if BUILTIN_DIRECTIVES.contains(&name.as_str()) {
+            return Err(anyhow!("Cannot register directive '{}': it is a built-in directive", name));
+        }
+        self.directives.insert(name, Arc::from(directive));
+        Ok(self)
+    }
+
     /// 设置是否保留循环中的换行符
     pub fn set_preserve_loop_newlines(&mut self, preserve: bool) -> &mut Self {
         self.preserve_loop_newlines = preserve;
         self
     }
 
-    /// 渲染模板字符串
-    pub fn render_string(&self, template: &str) -> Result<String> {
-        let mut result = template.to_string();
+    /// 设置自动转义方式
+    /// 生成shell脚本时，未转义的用户变量可以注入任意命令，建议使用 AutoEscapeMode::Shell
+    pub fn set_auto_escape(&mut self, mode: AutoEscapeMode) -> &mut Self {
+        self.auto_escape = mode;
+        self
+    }
 
-        // 1. 处理include指令
-        result = self.process_includes(&result)?;
+    /// 设置严格模式（默认关闭）
+    /// 关闭时未定义的变量、不存在的属性、对非对象取属性都替换为空字符串，遍历未定义的数组不输出内容；
+    /// 开启时这些情况都返回错误，错误中包含变量路径和占位符前后各30个字符的模板内容，适合在CI中发现拼写错误
+    pub fn set_strict_mode(&mut self, strict: bool) -> &mut Self {
+        self.strict_mode = strict;
+        self
+    }
 
-        // 2. 处理for循环
-        result = self.process_for_loops(&result)?;
+    /// 设置获取URL模板使用的HTTP客户端，用于自定义TLS、代理或认证
+    #[cfg(feature = "remote-templates")]
+    pub fn set_http_client(&mut self, client: reqwest::Client) -> &mut Self {
+        self.remote.set_client(client);
+        self
+    }
 
-        // 3. 处理变量替换
-        result = self.process_variables(&result)?;
+    /// 设置URL模板的缓存有效期，None（默认）表示获取一次后一直使用缓存
+    #[cfg(feature = "remote-templates")]
+    pub fn set_cache_ttl(&mut self, ttl: Option<Duration>) -> &mut Self {
+        self.remote.cache_ttl = ttl;
+        self
+    }
 
-        Ok(result)
+    /// 设置获取URL模板的超时时间，默认30秒
+    #[cfg(feature = "remote-templates")]
+    pub fn set_fetch_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.remote.fetch_timeout = timeout;
+        self
+    }
+
+    /// 限制可以 include 的URL前缀，为空（默认）时不限制
+    #[cfg(feature = "remote-templates")]
+    pub fn set_allowed_url_prefixes<I, S>(&mut self, prefixes: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.remote.allowed_url_prefixes = prefixes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 渲染模板字符串
+    pub fn render_string(&self, template: &str) -> Result<String> {
+        self.render_with_origin(template, None, self.strict_mode)
+    }
+
+    /// 以严格模式渲染模板字符串，只对本次渲染生效
+    pub fn render_string_strict(&self, template: &str) -> Result<String> {
+        self.render_with_origin(template, None, true)
     }
 
     /// 渲染模板文件
+    /// 文件中的 include 在模板目录中找不到时，相对该文件所在目录查找
     pub fn render_file<P: AsRef<Path>>(&self, template_path: P) -> Result<String> {
-        let template_content = fs::read_to_string(template_path)?;
-        self.render_string(&template_content)
+        let template_content = fs::read_to_string(template_path.as_ref())?;
+        self.render_with_origin(&template_content, Some(template_path.as_ref()), self.strict_mode)
     }
 
-    /// 处理include指令
-    fn process_includes(&self, template: &str) -> Result<String> {
-        let mut result = template.to_string();
+    /// 使用给定变量和默认设置渲染一次模板字符串
+    pub fn render_with_context(template: &str, context: &HashMap<String, serde_json::Value>) -> Result<String> {
+        Self::with_context(context).render_string(template)
+    }
 
-        while let Some(captures) = self.include_regex.captures(&result) {
-            let full_match = captures.get(0).unwrap().as_str();
+    /// 使用给定变量和默认设置渲染一次模板文件
+    pub fn render_file_with_context(path: &Path, context: &HashMap<String, serde_json::Value>) -> Result<String> {
+        Self::with_context(context).render_file(path)
+    }
+
+    /// 使用字符串变量和默认设置渲染一次模板字符串
+    pub fn render_string_simple(template: &str, vars: &HashMap<String, &str>) -> Result<String> {
+        let context = vars.iter()
+            .map(|(key, value)| (key.clone(), serde_json::Value::String(value.to_string())))
+            .collect();
+        Self::render_with_context(template, &context)
+    }
+
+    fn with_context(context: &HashMap<String, serde_json::Value>) -> Self {
+        let mut engine = Self::new();
+        engine.set_variables(context.clone());
+        engine
+    }
+
+    /// 渲染模板，origin 为模板所在文件（用于相对路径 include 和循环检测）
+    /// 错误信息中的行号指向原模板（或被 include 的模板）中的行
+    fn render_with_origin(&self, template: &str, origin: Option<&Path>, strict: bool) -> Result<String> {
+        // 1. 处理include指令（递归展开，被包含的内容中的for循环和变量在后续步骤统一处理）
+        let mut stack = Vec::new();
+        if let Some(path) = origin {
+            let name = path.file_name().map_or_else(|| path.to_string_lossy(), |n| n.to_string_lossy());
+            stack.push((include_key(path), name.to_string()));
+        }
+        let (result, lines) = self.process_includes(template, origin.and_then(Path::parent), &mut stack)?;
+
+        // 2. 处理自定义指令，指令的输出与模板其余部分一样处理for循环和变量
+        let (result, directive_expansions, bindings) = self.process_directives(&result, &|line| lines.locate(line))?;
+        let before_directives = |line| lines.locate(line_before_expansions(&directive_expansions, line));
+
+        // `as 变量` 绑定的变量在之后的for循环和变量替换中可用
+        let bound;
+        let engine = if bindings.is_empty() {
+            self
+        } else {
+            let mut variables = self.variables.clone();
+            variables.extend(bindings);
+            bound = Self {
+                variables,
+                template_dir: self.template_dir.clone(),
+                left_delimiter: self.left_delimiter.clone(),
+                right_delimiter: self.right_delimiter.clone(),
+                for_left_delimiter: self.for_left_delimiter.clone(),
+                for_right_delimiter: self.for_right_delimiter.clone(),
+                preserve_loop_newlines: self.preserve_loop_newlines,
+                auto_escape: self.auto_escape,
+                strict_mode: self.strict_mode,
+                var_regex: self.var_regex.clone(),
+                for_regex: self.for_regex.clone(),
+                include_regex: self.include_regex.clone(),
+                directive_regex: self.directive_regex.clone(),
+                directives: self.directives.clone(),
+                #[cfg(feature = "remote-templates")]
+                remote: self.remote.clone(),
+            };
+            &bound
+        };
+
+        // 3. 处理for循环
+        let (result, loop_expansions) = engine.process_for_loops(&result, strict, &before_directives)?;
+
+        // 4. 处理变量替换
+        let locate = |line| before_directives(line_before_expansions(&loop_expansions, line));
+        engine.process_variables(&result, strict, &locate)
+    }
+
+    /// 列出模板引用的变量（不渲染），结果去重并排序
+    /// 点号路径如 `user.name` 同时列出 `user.name` 和顶层变量 `user`；
+    /// for循环的数组名计入，循环项变量（`item`、`item.name`）不计入。
+    /// include 的内容不会被读取
+    pub fn list_required_variables(&self, template: &str) -> Result<Vec<String>> {
+        let mut names = BTreeSet::new();
+        let mut add = |path: &str| {
+            if let Some((top, _)) = path.split_once('.') {
+                names.insert(top.to_string());
+            }
+            names.insert(path.to_string());
+        };
+
+        let outside = self.scan_for_loops(template, |loop_vars, array_name, loop_content| {
+            add(array_name);
+            for captures in self.var_regex.captures_iter(loop_content) {
+                let path = captures.get(1).unwrap().as_str();
+                if !loop_vars.contains(&path.split('.').next().unwrap_or_default()) {
+                    add(path);
+                }
+            }
+        })?;
+        for captures in self.var_regex.captures_iter(&outside) {
+            add(captures.get(1).unwrap().as_str());
+        }
+
+        Ok(names.into_iter().collect())
+    }
+
+    /// 列出模板中for循环遍历的数组名（不渲染），结果去重并排序
+    pub fn list_declared_arrays(&self, template: &str) -> Result<Vec<String>> {
+        let mut names = BTreeSet::new();
+        self.scan_for_loops(template, |_, array_name, _| {
+            names.insert(array_name.to_string());
+        })?;
+        Ok(names.into_iter().collect())
+    }
+
+    /// 依次访问模板中的for循环 (循环变量名, 数组名, 循环体)，返回去掉循环后的其余文本
+    /// 存在没有匹配 endfor 的for标签时返回错误
+    fn scan_for_loops<F>(&self, template: &str, mut visit: F) -> Result<String>
+    where
+        F: FnMut(&[&str], &str, &str),
+    {
+        let mut outside = String::with_capacity(template.len());
+        let mut last = 0;
+        for captures in self.for_regex.captures_iter(template) {
+            let full_match = captures.get(0).unwrap();
+            let loop_vars: Vec<&str> = [captures.get(1), captures.get(2)].into_iter().flatten().map(|m| m.as_str()).collect();
+            visit(
+                &loop_vars,
+                captures.get(3).unwrap().as_str(),
+                captures.get(6).unwrap().as_str(),
+            );
+            outside.push_str(&template[last..full_match.start()]);
+            last = full_match.end();
+        }
+        outside.push_str(&template[last..]);
+
+        let unclosed = Regex::new(&format!(r"{}\s*for\s", regex::escape(&self.for_left_delimiter)))?;
+        if unclosed.is_match(&outside) {
+            return Err(anyhow!("Template contains a for loop without a matching endfor"));
+        }
+        Ok(outside)
+    }
+
+    /// 处理include指令
+    /// http:// 或 https:// 开头的路径按URL获取（需要 remote-templates 特性），其余路径先相对模板目录、
+    /// 再相对包含它的文件所在目录（base_dir）读取。被包含的内容递归展开，stack 为当前的包含链 (键, 名称)
+    /// 同时返回展开后各行对应的来源行
+    fn process_includes(
+        &self,
+        template: &str,
+        base_dir: Option<&Path>,
+        stack: &mut Vec<(String, String)>,
+    ) -> Result<(String, LineMap)> {
+        let source = stack.last().map(|(_, name)| name.clone());
+        let mut lines = LineMap { segments: vec![(1, source.clone(), 1)] };
+        let mut result = String::with_capacity(template.len());
+        let mut last = 0;
+
+        for captures in self.include_regex.captures_iter(template) {
+            let full_match = captures.get(0).unwrap();
             let template_name = captures.get(1).unwrap().as_str();
+            let here = SourceLine { source: source.clone(), line: line_at(template, full_match.start()) };
 
-            let included_content = if let Some(ref dir) = self.template_dir {
-                let full_path = Path::new(dir).join(template_name);
-                fs::read_to_string(full_path)
-                    .map_err(|e| anyhow!("Failed to include template '{}': {}", template_name, e))?
+            let included = if template_name.starts_with("http://") || template_name.starts_with("https://") {
+                self.fetch_url(template_name).map(|content| (template_name.to_string(), content, None))
             } else {
+                self.resolve_include(template_name, base_dir).and_then(|path| {
+                    let content = fs::read_to_string(&path)
+                        .map_err(|e| anyhow!("Failed to include template '{}': {}", template_name, e))?;
+                    let dir = path.parent().map(Path::to_path_buf);
+                    Ok((include_key(&path), content, dir))
+                })
+            };
+            let (key, included_content, included_dir) = included.map_err(|e| anyhow!("{} at {}", e, here))?;
+
+            let chain = || {
+                stack
+                    .iter()
+                    .map(|(_, name)| name.as_str())
+                    .chain(std::iter::once(template_name))
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            };
+            if stack.iter().any(|(k, _)| *k == key) {
+                return Err(anyhow!("Include cycle detected: {}", chain()));
+            }
+            if stack.len() >= MAX_INCLUDE_DEPTH {
                 return Err(anyhow!(
-                    "Template directory not set for include: {}",
-                    template_name
+                    "Include depth exceeds {}: {}",
+                    MAX_INCLUDE_DEPTH,
+                    chain()
                 ));
-            };
+            }
 
-            result = result.replace(full_match, &included_content);
+            stack.push((key, template_name.to_string()));
+            let (expanded, included_lines) = self.process_includes(&included_content, included_dir.as_deref(), stack)?;
+            stack.pop();
+
+            result.push_str(&template[last..full_match.start()]);
+            // 被包含的内容从当前行开始，其后的文本接着 include 标签结束处的行
+            let start_line = line_at(&result, result.len());
+            lines.segments.extend(included_lines.segments.into_iter()
+                .map(|(line, source, source_line)| (start_line + line - 1, source, source_line)));
+            result.push_str(&expanded);
+            lines.segments.push((line_at(&result, result.len()), source.clone(), line_at(template, full_match.end())));
+            last = full_match.end();
         }
+        result.push_str(&template[last..]);
 
-        Ok(result)
+        Ok((result, lines))
+    }
+
+    /// 处理自定义指令，指令替换为处理器的返回值，返回值中的指令不再处理
+    /// 没有注册的指令名称返回错误；locate 把 template 中的行号换算为来源行。
+    /// 带 `as 变量` 的指令不输出内容，同时返回这些绑定的变量
+    fn process_directives(
+        &self,
+        template: &str,
+        locate: &dyn Fn(usize) -> SourceLine,
+    ) -> Result<(String, Vec<Expansion>, HashMap<String, serde_json::Value>)> {
+        let mut result = String::with_capacity(template.len());
+        let mut expansions = Vec::new();
+        let mut bindings = HashMap::new();
+        let mut last = 0;
+
+        for captures in self.directive_regex.captures_iter(template) {
+            let full_match = captures.get(0).unwrap();
+            let name = captures.get(1).unwrap().as_str();
+            if BUILTIN_DIRECTIVES.contains(&name) {
+                continue;
+            }
+            let args = captures.get(2).map_or("", |m| m.as_str()).trim();
+            let here = locate(line_at(template, full_match.start()));
+            let directive = self.directives.get(name)
+                .ok_or_else(|| anyhow!("Unknown template directive '{}' at {}", name, here))?;
+            let (args, binding) = split_binding(args);
+            let mut output = directive.process(args, self)
+                .map_err(|e| anyhow!("Directive '{}' failed at {}: {}", name, here, e))?;
+            if let Some(variable) = binding {
+                bindings.insert(variable.to_string(), serde_json::Value::String(std::mem::take(&mut output)));
+            }
+
+            result.push_str(&template[last..full_match.start()]);
+            expansions.push(Expansion {
+                line: line_at(&result, result.len()),
+                input_newlines: full_match.as_str().matches('\n').count(),
+                output_newlines: output.matches('\n').count(),
+            });
+            result.push_str(&output);
+            last = full_match.end();
+        }
+        result.push_str(&template[last..]);
+
+        Ok((result, expansions, bindings))
+    }
+
+    /// 查找include的文件：先相对模板目录，找不到时再相对包含它的文件所在目录
+    fn resolve_include(&self, template_name: &str, base_dir: Option<&Path>) -> Result<PathBuf> {
+        let candidates: Vec<PathBuf> = self
+            .template_dir
+            .as_deref()
+            .map(Path::new)
+            .into_iter()
+            .chain(base_dir)
+            .map(|dir| dir.join(template_name))
+            .collect();
+
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            // 都不存在时返回第一个候选路径，由读取时报告错误
+            None => candidates.into_iter().next().ok_or_else(|| {
+                anyhow!("Template directory not set for include: {}", template_name)
+            }),
+        }
+    }
+
+    /// 获取URL模板的内容
+    #[cfg(feature = "remote-templates")]
+    fn fetch_url(&self, url: &str) -> Result<String> {
+        self.remote.fetch(url)
+    }
+
+    #[cfg(not(feature = "remote-templates"))]
+    fn fetch_url(&self, url: &str) -> Result<String> {
+        Err(anyhow!(
+            "Failed to include template '{}': URL includes require the `remote-templates` feature",
+            url
+        ))
     }
 
     /// 处理for循环
-    fn process_for_loops(&self, template: &str) -> Result<String> {
+    /// 单变量形式只遍历数组；键值对形式 `for key, value in x` 遍历对象时按键排序，
+    /// 遍历数组时 key 为下标
+    /// locate 把 template 中的行号换算为来源行，同时返回每个循环的展开记录，用于把展开后的行号换算回来
+    fn process_for_loops(&self, template: &str, strict: bool, locate: &dyn Fn(usize) -> SourceLine) -> Result<(String, Vec<Expansion>)> {
         let mut result = template.to_string();
+        let mut expansions = Vec::new();
 
         while let Some(captures) = self.for_regex.captures(&result) {
-            let full_match = captures.get(0).unwrap().as_str();
+            let whole = captures.get(0).unwrap();
+            let line = line_at(&result, whole.start());
+            let here = locate(line_before_expansions(&expansions, line));
+            let full_match = whole.as_str();
             let item_name = captures.get(1).unwrap().as_str();
-            let array_name = captures.get(2).unwrap().as_str();
-            let operation = captures.get(3).map(|m| m.as_str());
-            let operation_param = captures.get(4).map(|m| m.as_str());
-            let loop_content = captures.get(5).unwrap().as_str();
-
-            let array_value = self
-                .variables
-                .get(array_name)
-                .ok_or_else(|| anyhow!("Array '{}' not found in variables", array_name))?;
-
-            // 根据操作类型处理不同的数据类型
-            let items: Vec<serde_json::Value> = match operation {
+            let value_name = captures.get(2).map(|m| m.as_str());
+            let array_name = captures.get(3).unwrap().as_str();
+            let operation = captures.get(4).map(|m| m.as_str());
+            let operation_param = captures.get(5).map(|m| m.as_str());
+            let loop_content = captures.get(6).unwrap().as_str();
+
+            // 非严格模式下遍历未定义的数组不输出内容
+            let Some(array_value) = self.variables.get(array_name) else {
+                if strict {
+                    return Err(anyhow!(
+                        "Array '{}' not found in variables at {}, near `{}`",
+                        array_name, here, error_context(&result, whole.start(), whole.end())
+                    ));
+                }
+                let full_match = full_match.to_string();
+                expansions.push(Expansion { line, input_newlines: full_match.matches('\n').count(), output_newlines: 0 });
+                result = result.replace(&full_match, "");
+                continue;
+            };
+
+            // 根据操作类型得到要遍历的集合
+            let collection = match operation {
                 Some("split") => {
                     // 处理split操作
                     let delimiter = operation_param.ok_or_else(|| anyhow!("Split operation requires a delimiter"))?;
                     match array_value {
-                        serde_json::Value::String(s) => {
+                        serde_json::Value::String(s) => serde_json::Value::Array(
                             s.split(delimiter)
                                 .map(|part| serde_json::Value::String(part.to_string()))
-                                .collect()
-                        }
+                                .collect(),
+                        ),
                         _ => {
                             return Err(anyhow!(
-                                "Cannot split non-string variable '{}'",
-                                array_name
+                                "Cannot split non-string variable '{}' at {}",
+                                array_name, here
                             ))
                         }
                     }
@@ -206,55 +701,71 @@ impl TemplateEngine {
                     match array_value {
                         serde_json::Value::String(s) => {
                             let parsed: serde_json::Value = serde_json::from_str(s)
-                                .map_err(|e| anyhow!("Failed to parse JSON from variable '{}': {}", array_name, e))?;
-                            
-                            match parsed {
-                                serde_json::Value::Array(arr) => arr,
-                                serde_json::Value::Object(obj) => {
-                                    // 如果是对象，转换为键值对数组
-                                    obj.into_iter()
-                                        .map(|(k, v)| {
-                                            serde_json::json!({
-                                                "key": k,
-                                                "value": v
-                                            })
-                                        })
-                                        .collect()
-                                }
-                                _ => {
-                                    return Err(anyhow!(
-                                        "JSON must be an array or object for iteration, got: {}",
-                                        parsed
-                                    ))
-                                }
+                                .map_err(|e| anyhow!("Failed to parse JSON from variable '{}' at {}: {}", array_name, here, e))?;
+                            if !parsed.is_array() && !parsed.is_object() {
+                                return Err(anyhow!(
+                                    "JSON must be an array or object for iteration at {}, got: {}",
+                                    here, parsed
+                                ));
                             }
+                            parsed
                         }
                         _ => {
                             return Err(anyhow!(
-                                "Cannot jsonparse non-string variable '{}'",
-                                array_name
+                                "Cannot jsonparse non-string variable '{}' at {}",
+                                array_name, here
                             ))
                         }
                     }
                 }
-                None => {
-                    // 处理普通数组
-                    if let serde_json::Value::Array(items) = array_value {
-                        items.clone()
-                    } else {
-                        return Err(anyhow!("'{}' is not an array", array_name));
-                    }
-                }
+                None => array_value.clone(),
                 _ => {
-                    return Err(anyhow!("Unknown operation: {}", operation.unwrap()));
+                    return Err(anyhow!("Unknown operation: {} at {}", operation.unwrap(), here));
                 }
             };
 
+            // 每个循环项为 (循环变量的值, 键值对形式中 value 的值)
+            let items: Vec<(serde_json::Value, Option<serde_json::Value>)> = match (collection, value_name) {
+                (serde_json::Value::Array(items), Some(_)) => items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| (serde_json::Value::from(index), Some(item)))
+                    .collect(),
+                (serde_json::Value::Object(obj), Some(_)) => {
+                    let mut entries: Vec<_> = obj.into_iter().collect();
+                    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    entries
+                        .into_iter()
+                        .map(|(k, v)| (serde_json::Value::String(k), Some(v)))
+                        .collect()
+                }
+                (_, Some(_)) => return Err(anyhow!(
+                    "'{}' is not an array or object at {}, near `{}`",
+                    array_name, here, error_context(&result, whole.start(), whole.end())
+                )),
+                (serde_json::Value::Array(items), None) => items.into_iter().map(|item| (item, None)).collect(),
+                // jsonparse 得到的对象转换为键值对数组
+                (serde_json::Value::Object(obj), None) if operation == Some("jsonparse") => obj
+                    .into_iter()
+                    .map(|(k, v)| (serde_json::json!({ "key": k, "value": v }), None))
+                    .collect(),
+                (_, None) => return Err(anyhow!(
+                    "'{}' is not an array at {}, near `{}`",
+                    array_name, here, error_context(&result, whole.start(), whole.end())
+                )),
+            };
+            // 循环体中的第 n 行对应展开前的行
+            let body_line = line_at(&result, captures.get(6).unwrap().start());
+            let locate_body = |n: usize| locate(line_before_expansions(&expansions, body_line + n - 1));
+
             let mut loop_result = String::new();
 
-            for item in items {
+            for (item, value) in items {
                 let mut temp_vars = self.variables.clone();
-                temp_vars.insert(item_name.to_string(), item.clone());
+                temp_vars.insert(item_name.to_string(), item);
+                if let (Some(value_name), Some(value)) = (value_name, value) {
+                    temp_vars.insert(value_name.to_string(), value);
+                }
 
                 let temp_engine = Self {
                     variables: temp_vars,
@@ -264,12 +775,18 @@ impl TemplateEngine {
                     for_left_delimiter: self.for_left_delimiter.clone(),
                     for_right_delimiter: self.for_right_delimiter.clone(),
                     preserve_loop_newlines: self.preserve_loop_newlines,
+                    auto_escape: self.auto_escape,
+                    strict_mode: self.strict_mode,
                     var_regex: self.var_regex.clone(),
                     for_regex: self.for_regex.clone(),
                     include_regex: self.include_regex.clone(),
+                    directive_regex: self.directive_regex.clone(),
+                    directives: self.directives.clone(),
+                    #[cfg(feature = "remote-templates")]
+                    remote: self.remote.clone(),
                 };
 
-                let mut rendered = temp_engine.process_variables(loop_content)?;
+                let mut rendered = temp_engine.process_variables(loop_content, strict, &locate_body)?;
 
                 // 如果不保留换行符，则去除循环产生的空行，但保留内容内的换行符和缩进
                 if !self.preserve_loop_newlines {
@@ -285,7 +802,7 @@ impl TemplateEngine {
 
                         // 如果不是第一个循环项，在前面添加换行符
                         if !loop_result.is_empty() {
-                            loop_result.push_str("\n");
+                            loop_result.push('\n');
                         }
                     } else {
                         rendered = String::new();
@@ -295,28 +812,64 @@ impl TemplateEngine {
                 loop_result.push_str(&rendered);
             }
 
+            expansions.push(Expansion {
+                line,
+                input_newlines: full_match.matches('\n').count(),
+                output_newlines: loop_result.matches('\n').count(),
+            });
             result = result.replace(full_match, &loop_result);
         }
 
-        Ok(result)
+        Ok((result, expansions))
     }
 
     /// 处理变量替换
-    fn process_variables(&self, template: &str) -> Result<String> {
-        let mut result = template.to_string();
-
-        while let Some(captures) = self.var_regex.captures(&result) {
-            let full_match = captures.get(0).unwrap().as_str();
+    /// 单次扫描替换，变量值中的定界符文本不会被再次展开；非严格模式下无法取值的变量替换为空
+    /// locate 把 template 中的行号换算为来源行，用于错误信息
+    fn process_variables(&self, template: &str, strict: bool, locate: &dyn Fn(usize) -> SourceLine) -> Result<String> {
+        let mut result = String::with_capacity(template.len());
+        let mut last = 0;
+
+        for captures in self.var_regex.captures_iter(template) {
+            let full_match = captures.get(0).unwrap();
             let variable_path = captures.get(1).unwrap().as_str();
+            let filter = captures.get(2).map(|m| m.as_str());
 
-            let value = self.get_variable_value(variable_path)?;
+            let value = match self.get_variable_value(variable_path) {
+                Ok(value) => value,
+                Err(e) if strict => {
+                    return Err(anyhow!(
+                        "{} at {}, near `{}`",
+                        e, locate(line_at(template, full_match.start())), error_context(template, full_match.start(), full_match.end())
+                    ));
+                }
+                Err(_) => serde_json::Value::String(String::new()),
+            };
             let value_str = match value {
-                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::String(s) => s,
                 v => v.to_string(),
             };
 
-            result = result.replace(full_match, &value_str);
+            let escape = match filter {
+                Some("raw") => None,
+                Some(name) => Some(
+                    EscapeMode::from_filter(name)
+                        .ok_or_else(|| anyhow!(
+                            "Unknown filter '{}' for variable '{}' at {}",
+                            name, variable_path, locate(line_at(template, full_match.start()))
+                        ))?,
+                ),
+                None => self.auto_escape.escape_mode(),
+            };
+
+            result.push_str(&template[last..full_match.start()]);
+            match escape {
+                Some(mode) => result.push_str(&mode.escape(&value_str)),
+                None => result.push_str(&value_str),
+            }
+            last = full_match.end();
         }
+        result.push_str(&template[last..]);
 
         Ok(result)
     }
@@ -332,7 +885,7 @@ impl TemplateEngine {
         let current = self
             .variables
             .get(parts[0])
-            .ok_or_else(|| anyhow!("Variable '{}' not found", parts[0]))?;
+            .ok_or_else(|| anyhow!("Variable '{}' not found", path))?;
 
         if parts.len() == 1 {
             return Ok(current.clone());
@@ -344,12 +897,12 @@ impl TemplateEngine {
                 serde_json::Value::Object(map) => {
                     result = map
                         .get(*part)
-                        .ok_or_else(|| anyhow!("Property '{}' not found in variable", part))?;
+                        .ok_or_else(|| anyhow!("Property '{}' not found in variable '{}'", part, path))?;
                 }
                 _ => {
                     return Err(anyhow!(
-                        "Cannot access property '{}' on non-object value",
-                        part
+                        "Cannot access property '{}' on non-object value in variable '{}'",
+                        part, path
                     ))
                 }
             }
@@ -359,17 +912,89 @@ impl TemplateEngine {
     }
 }
 
+/// 模板中的一行：来源（include 的模板名或渲染的文件名，模板字符串为 None）和行号（从1开始）
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SourceLine {
+    source: Option<String>,
+    line: usize,
+}
+
+impl std::fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "line {} of '{}'", self.line, source),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// 展开 include 后的文本各行对应的来源行
+/// 每段 (起始行, 来源, 来源中的起始行) 到下一段之前逐行对应
+#[derive(Debug, Clone, Default)]
+struct LineMap {
+    segments: Vec<(usize, Option<String>, usize)>,
+}
+
+impl LineMap {
+    fn locate(&self, line: usize) -> SourceLine {
+        match self.segments.iter().rev().find(|(start, _, _)| *start <= line) {
+            Some((start, source, source_line)) => SourceLine { source: source.clone(), line: source_line + line - start },
+            None => SourceLine { source: None, line },
+        }
+    }
+}
+
+/// 一个for循环或自定义指令的展开记录：在展开后文本中的起始行，展开前后各占的换行数
+#[derive(Debug, Clone, Copy)]
+struct Expansion {
+    line: usize,
+    input_newlines: usize,
+    output_newlines: usize,
+}
+
+/// 展开后的行号换算为展开前的行号，展开结果中的行都对应循环或指令开始的行
+fn line_before_expansions(expansions: &[Expansion], line: usize) -> usize {
+    let mut shift = 0isize;
+    for expansion in expansions {
+        if line < expansion.line {
+            break;
+        }
+        if line <= expansion.line + expansion.output_newlines {
+            return (expansion.line as isize - shift) as usize;
+        }
+        shift += expansion.output_newlines as isize - expansion.input_newlines as isize;
+    }
+    (line as isize - shift) as usize
+}
+
+/// 文本中字节偏移 offset 处所在的行号（从1开始）
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+/// 模板中 start..end 处的内容及其前后各 ERROR_CONTEXT_CHARS 个字符，用于错误信息
+fn error_context(template: &str, start: usize, end: usize) -> String {
+    let before: Vec<char> = template[..start].chars().rev().take(ERROR_CONTEXT_CHARS).collect();
+    let after: String = template[end..].chars().take(ERROR_CONTEXT_CHARS).collect();
+    format!("{}{}{}", before.into_iter().rev().collect::<String>(), &template[start..end], after)
+}
+
 impl Default for TemplateEngine {
     fn default() -> Self {
         Self::new()
     }
 }
 
+impl TemplateRenderer for TemplateEngine {
+    fn render(&self, template: &str) -> Result<String> {
+        self.render_string(template)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
-    use tracing::instrument::WithSubscriber;
 
     #[test]
     fn test_variable_replacement() {
@@ -383,6 +1008,34 @@ mod tests {
         assert_eq!(result, "Hello, World! You are 25 years old.");
     }
 
+    #[test]
+    fn test_render_with_context_matches_long_form() {
+        let template = "{% for host in hosts %}{{ host }}:{{ port }} {% endfor %}{{ user.name }}";
+        let context = HashMap::from([
+            ("hosts".to_string(), json!(["a", "b"])),
+            ("port".to_string(), json!(22)),
+            ("user".to_string(), json!({"name": "ops"})),
+        ]);
+        let mut engine = TemplateEngine::new();
+        engine.set_variables(context.clone());
+        let expected = engine.render_string(template).unwrap();
+        assert_eq!(expected, "a:22 b:22 ops");
+        assert_eq!(TemplateEngine::render_with_context(template, &context).unwrap(), expected);
+        assert_eq!(engine.render(template).unwrap(), expected);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, template.as_bytes()).unwrap();
+        assert_eq!(TemplateEngine::render_file_with_context(file.path(), &context).unwrap(), expected);
+
+        let vars = HashMap::from([("name".to_string(), "World")]);
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("name", "World");
+        assert_eq!(
+            TemplateEngine::render_string_simple("Hello, {{ name }}!", &vars).unwrap(),
+            engine.render_string("Hello, {{ name }}!").unwrap()
+        );
+    }
+
     #[test]
     fn test_nested_variable_access() {
         let mut engine = TemplateEngine::new();
@@ -424,6 +1077,28 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_for_loop_key_value_pairs() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("config", json!({"b": "2", "a": "1"}));
+        engine.set_variable("items", json!(["x", "y"]));
+        engine.set_preserve_loop_newlines(false);
+
+        let template = "{% for k, v in config %}{{ k }}={{ v }}{% endfor %}";
+        assert_eq!(engine.render_string(template).unwrap(), "a=1\nb=2");
+        // 数组的 key 为下标
+        let template = "{% for i, item in items %}{{ i }}:{{ item }}{% endfor %}";
+        assert_eq!(engine.render_string(template).unwrap(), "0:x\n1:y");
+        // 单变量形式不变，仍然只接受数组
+        assert_eq!(engine.render_string("{% for item in items %}{{ item }}{% endfor %}").unwrap(), "x\ny");
+        assert!(engine.render_string("{% for item in config %}{{ item }}{% endfor %}").is_err());
+
+        assert_eq!(
+            engine.list_required_variables("{% for k, v in config %}{{ k }}={{ v }} {{ sep }}{% endfor %}").unwrap(),
+            vec!["config", "sep"]
+        );
+    }
+
     #[test]
     fn test_custom_delimiters() {
         let mut engine = TemplateEngine::with_delimiters("${", "}");
@@ -662,4 +1337,233 @@ Key: {{ item.key }}, Value: {{ item.value }}
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("Cannot jsonparse non-string variable"));
     }
+
+    #[test]
+    fn test_escape_filters_with_adversarial_input() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("evil", "x'; rm -rf / #$(id)`id`<b>&\"");
+
+        let shell = engine.render_string("echo {{ evil | escape_shell }}").unwrap();
+        assert_eq!(shell, r#"echo 'x'\''; rm -rf / #$(id)`id`<b>&"'"#);
+
+        let html = engine.render_string("<p>{{ evil | escape_html }}</p>").unwrap();
+        assert_eq!(html, "<p>x&#x27;; rm -rf / #$(id)`id`&lt;b&gt;&amp;&quot;</p>");
+
+        let sql = engine.render_string("WHERE name = {{ evil|escape_sql }}").unwrap();
+        assert_eq!(sql, r#"WHERE name = 'x''; rm -rf / #$(id)`id`<b>&"'"#);
+
+        assert!(engine.render_string("{{ evil | escape_nothing }}").is_err());
+    }
+
+    #[test]
+    fn test_escape_shell_round_trips_through_sh() {
+        let mut engine = TemplateEngine::new();
+        let values = ["plain", "", "a b", "it's", "$(touch /tmp/pwned)", "`id`; echo; '\"\\"];
+        for value in values {
+            engine.set_variable("v", value);
+            let script = engine.render_string("printf %s {{ v | escape_shell }}").unwrap();
+            let output = std::process::Command::new("sh").arg("-c").arg(&script).output().unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_auto_escape_shell_and_raw_filter() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("arg", "a; reboot");
+        engine.set_variable("cmd", "ls -l");
+        engine.set_auto_escape(AutoEscapeMode::Shell);
+
+        let result = engine.render_string("{{ cmd | raw }} {{ arg }}").unwrap();
+        assert_eq!(result, "ls -l 'a; reboot'");
+
+        // 循环内的变量同样自动转义
+        engine.set_variable("files", serde_json::json!(["a b", "c"]));
+        let result = engine.render_string("{% for f in files %}rm {{ f }};{% endfor %}").unwrap();
+        assert_eq!(result, "rm 'a b';rm c;");
+    }
+
+    #[test]
+    fn test_strict_mode_reports_undefined_variables() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("user", json!({ "name": "deploy" }));
+        engine.set_variable("port", 22);
+        let template = "ssh {{ user.name }}@host -p {{ port }} && echo {{ typo_var }} done";
+
+        // 默认替换为空
+        assert_eq!(engine.render_string(template).unwrap(), "ssh deploy@host -p 22 && echo  done");
+        assert_eq!(engine.render_string("{% for h in hosts %}{{ h }}{% endfor %}ok").unwrap(), "ok");
+
+        let err = engine.render_string_strict(template).unwrap_err().to_string();
+        assert_eq!(err, "Variable 'typo_var' not found at line 1, near `}}@host -p {{ port }} && echo {{ typo_var }} done`");
+        let err = engine.render_string_strict("{{ user.name.first }}").unwrap_err().to_string();
+        assert!(err.starts_with("Cannot access property 'first' on non-object value in variable 'user.name.first'"), "{}", err);
+        assert!(engine.render_string_strict("{% for h in hosts %}{{ h }}{% endfor %}").is_err());
+        assert!(engine.render_string_strict("{% for c in user %}{{ c }}{% endfor %}").is_err());
+
+        // render_string_strict 只对本次渲染生效，set_strict_mode 对之后的渲染都生效
+        assert!(engine.render_string(template).is_ok());
+        engine.set_strict_mode(true);
+        assert!(engine.render_string(template).is_err());
+    }
+
+    #[test]
+    fn test_value_with_delimiters_is_not_expanded() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("a", "{{ b }}");
+        engine.set_variable("b", "secret");
+        assert_eq!(engine.render_string("{{ a }}").unwrap(), "{{ b }}");
+    }
+
+    #[test]
+    fn test_list_required_variables_without_rendering() {
+        let engine = TemplateEngine::new();
+        let template = r#"
+#!/bin/bash
+echo "deploying {{ app.name }} to {{ env | escape_shell }}"
+{% for host in hosts %}
+ssh {{ user }}@{{ host.addr }} "mkdir -p {{ app.paths.root }}"
+{% endfor %}
+{% for port in ports split "," %}
+open {{ host }}:{{ port }}
+{% endfor %}
+{% for svc in services jsonparse %}systemctl restart {{ svc.name }} {{ user }}{% endfor %}
+"#;
+
+        assert_eq!(
+            engine.list_required_variables(template).unwrap(),
+            vec!["app", "app.name", "app.paths.root", "env", "host", "hosts", "ports", "services", "user"]
+        );
+        assert_eq!(
+            engine.list_declared_arrays(template).unwrap(),
+            vec!["hosts", "ports", "services"]
+        );
+
+        let err = engine.list_required_variables("{% for x in xs %}{{ x }}").unwrap_err();
+        assert!(err.to_string().contains("without a matching endfor"), "{}", err);
+    }
+
+    #[test]
+    fn test_strict_errors_report_source_line() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("hosts", json!(["a", "b", "c"]));
+
+        // 循环展开后的行号仍对应原模板
+        let template = "#!/bin/sh\n{% for h in hosts %}\necho {{ h }}\n{% endfor %}\necho {{ missing }}\n";
+        let err = engine.render_string_strict(template).unwrap_err().to_string();
+        assert!(err.starts_with("Variable 'missing' not found at line 5, near"), "{}", err);
+        let err = engine.render_string_strict("one\n{% for h in hosts %}\n{{ h.name }}\n{% endfor %}").unwrap_err().to_string();
+        assert!(err.starts_with("Cannot access property 'name' on non-object value in variable 'h.name' at line 3"), "{}", err);
+
+        // include 的内容按被包含的文件报告
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.tmpl"), "first\n{% include \"part.tmpl\" %}\necho {{ after }}\n").unwrap();
+        std::fs::write(dir.path().join("part.tmpl"), "p1\necho {{ inner }}\n").unwrap();
+        engine.set_template_dir(dir.path());
+        let err = engine.render_string_strict("{% include \"main.tmpl\" %}").unwrap_err().to_string();
+        assert!(err.starts_with("Variable 'inner' not found at line 2 of 'part.tmpl'"), "{}", err);
+        engine.set_variable("inner", "x");
+        let err = engine.render_string_strict("{% include \"main.tmpl\" %}").unwrap_err().to_string();
+        assert!(err.starts_with("Variable 'after' not found at line 3 of 'main.tmpl'"), "{}", err);
+    }
+
+    struct Upper;
+
+    impl CustomDirective for Upper {
+        fn name(&self) -> &str {
+            "upper"
+        }
+
+        fn process(&self, args: &str, engine: &TemplateEngine) -> Result<String> {
+            let text = engine.get_variable(args).and_then(|v| v.as_str().map(str::to_string)).unwrap_or(args.to_string());
+            Ok(text.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn test_custom_directive_output_replaces_tag() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("env", "prod").set_variable("hosts", json!(["a", "b"]));
+        engine.register_directive(Box::new(Upper)).unwrap();
+
+        let template = "{% upper hello world %} {% upper env %}\n{% for h in hosts %}{{ h }}{% endfor %} {{ env }}";
+        assert_eq!(engine.render_string(template).unwrap(), "HELLO WORLD PROD\nab prod");
+
+        let err = engine.render_string("line 1\n{% lower x %}").unwrap_err().to_string();
+        assert_eq!(err, "Unknown template directive 'lower' at line 2");
+        assert!(engine.register_directive(Box::new(BuiltinName)).is_err());
+    }
+
+    #[test]
+    fn test_custom_directive_binds_result_with_as() {
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("env", "prod").set_variable("hosts", json!(["a", "b"]));
+        engine.register_directive(Box::new(Upper)).unwrap();
+
+        // `as 变量` 只绑定变量，指令本身不输出内容
+        let template = "{% upper env as loud %}{% upper a b as pair %}{% for h in hosts %}{{ h }}={{ loud }} {% endfor %}{{ pair }}";
+        assert_eq!(engine.render_string(template).unwrap(), "a=PROD b=PROD A B");
+        // 绑定只对本次渲染有效
+        assert_eq!(engine.get_variable("loud"), None);
+    }
+
+    struct BuiltinName;
+
+    impl CustomDirective for BuiltinName {
+        fn name(&self) -> &str {
+            "include"
+        }
+
+        fn process(&self, _args: &str, _engine: &TemplateEngine) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn test_nested_and_relative_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("parts")).unwrap();
+        std::fs::write(dir.path().join("main.tmpl"), "{% include \"parts/body.tmpl\" %}").unwrap();
+        // 两层include，第二层的 footer.tmpl 不在模板目录下，按 body.tmpl 所在目录查找
+        std::fs::write(
+            dir.path().join("parts/body.tmpl"),
+            "{% for h in hosts %}{{ h }};{% endfor %}{% include \"footer.tmpl\" %}",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("parts/footer.tmpl"), "by {{ user }}").unwrap();
+
+        let mut engine = TemplateEngine::new();
+        engine.set_template_dir(dir.path());
+        engine.set_variable("hosts", json!(["a", "b"]));
+        engine.set_variable("user", "ops");
+        assert_eq!(
+            engine.render_string("{% include \"main.tmpl\" %}").unwrap(),
+            "a;b;by ops"
+        );
+
+        // 不设置模板目录时，render_file 相对文件所在目录查找
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("hosts", json!(["c"]));
+        engine.set_variable("user", "dev");
+        assert_eq!(
+            engine.render_file(dir.path().join("parts/body.tmpl")).unwrap(),
+            "c;by dev"
+        );
+    }
+
+    #[test]
+    fn test_include_cycle_is_reported_with_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("self.tmpl"), "x {% include \"self.tmpl\" %}").unwrap();
+        std::fs::write(dir.path().join("a.tmpl"), "{% include \"b.tmpl\" %}").unwrap();
+        std::fs::write(dir.path().join("b.tmpl"), "{% include \"a.tmpl\" %}").unwrap();
+
+        let mut engine = TemplateEngine::new();
+        engine.set_template_dir(dir.path());
+        let err = engine.render_string("{% include \"self.tmpl\" %}").unwrap_err();
+        assert_eq!(err.to_string(), "Include cycle detected: self.tmpl -> self.tmpl");
+
+        let err = engine.render_file(dir.path().join("a.tmpl")).unwrap_err();
+        assert_eq!(err.to_string(), "Include cycle detected: a.tmpl -> b.tmpl -> a.tmpl");
+    }
 }
@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 默认的URL获取超时时间
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 通过URL include 的模板：获取、缓存与白名单检查
+#[derive(Clone)]
+pub(crate) struct RemoteIncludes {
+    client: reqwest::Client,
+    /// 已获取的内容，键为URL，值为 (获取时间, 内容)
+    cache: Arc<Mutex<HashMap<String, (Instant, String)>>>,
+    /// 缓存有效期，None 表示一直有效
+    pub cache_ttl: Option<Duration>,
+    /// 单次获取的超时时间（包括读取响应内容）
+    pub fetch_timeout: Duration,
+    /// 允许 include 的URL前缀，为空时不限制
+    pub allowed_url_prefixes: Vec<String>,
+}
+
+impl RemoteIncludes {
+    pub fn new() -> Self {
+        Self {
+            // 获取在临时运行时中完成，不保留空闲连接，避免连接绑定到已结束的运行时
+            client: reqwest::Client::builder()
+                .pool_max_idle_per_host(0)
+                .build()
+                .expect("default HTTP client"),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            cache_ttl: None,
+            fetch_timeout: DEFAULT_FETCH_TIMEOUT,
+            allowed_url_prefixes: Vec::new(),
+        }
+    }
+
+    /// 设置自定义HTTP客户端（TLS、认证头等）
+    pub fn set_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    /// 获取URL内容，优先使用未过期的缓存
+    pub fn fetch(&self, url: &str) -> Result<String> {
+        if !self.allowed_url_prefixes.is_empty()
+            && !self.allowed_url_prefixes.iter().any(|prefix| url.starts_with(prefix.as_str()))
+        {
+            return Err(anyhow!("Template URL '{}' is not allowed by allowed_url_prefixes", url));
+        }
+
+        if let Some((fetched_at, content)) = self.cache.lock().unwrap().get(url) {
+            if self.cache_ttl.is_none_or(|ttl| fetched_at.elapsed() < ttl) {
+                return Ok(content.clone());
+            }
+        }
+
+        let content = self.download(url)?;
+        self.cache.lock().unwrap().insert(url.to_string(), (Instant::now(), content.clone()));
+        Ok(content)
+    }
+
+    /// 下载URL内容
+    fn download(&self, url: &str) -> Result<String> {
+        send_blocking(self.client.get(url).timeout(self.fetch_timeout))
+            .map_err(|e| anyhow!("Failed to include template '{}': {}", url, e))
+    }
+}
+
+/// 发送请求并返回响应内容，非2xx状态码返回错误
+/// 模板渲染是同步调用，可能发生在 tokio 运行时内部，因此在独立线程的临时运行时中执行请求
+pub(crate) fn send_blocking(request: reqwest::RequestBuilder) -> Result<String> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| -> Result<String> {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .context("Failed to create runtime for template fetch")?;
+                let text = runtime.block_on(async {
+                    let response = request.send().await?.error_for_status()?;
+                    response.text().await
+                })?;
+                Ok(text)
+            })
+            .join()
+            .map_err(|_| anyhow!("Template fetch thread panicked"))?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::template::TemplateEngine;
+    use std::time::Duration;
+
+    #[test]
+    fn test_include_url_is_rendered_and_cached() {
+        let mut server = mockito::Server::new();
+        let mock = server.mock("GET", "/templates/greet.sh")
+            .with_body("echo \"hello {{ name }}\"\n")
+            .expect(1)
+            .create();
+
+        let mut engine = TemplateEngine::new();
+        engine.set_variable("name", "World");
+        let template = format!("{{% include \"{}/templates/greet.sh\" %}}", server.url());
+        assert_eq!(engine.render_string(&template).unwrap(), "echo \"hello World\"\n");
+        // 第二次渲染使用缓存
+        assert_eq!(engine.render_string(&template).unwrap(), "echo \"hello World\"\n");
+        mock.assert();
+
+        // 缓存过期后重新获取
+        let mock = server.mock("GET", "/templates/greet.sh").with_body("v2").expect(2).create();
+        let mut engine = TemplateEngine::new();
+        engine.set_cache_ttl(Some(Duration::ZERO));
+        engine.render_string(&template).unwrap();
+        assert_eq!(engine.render_string(&template).unwrap(), "v2");
+        mock.assert();
+    }
+
+    #[test]
+    fn test_include_url_prefixes_and_errors() {
+        let mut server = mockito::Server::new();
+        server.mock("GET", "/missing.sh").with_status(404).create();
+
+        let mut engine = TemplateEngine::new();
+        let template = format!("{{% include \"{}/missing.sh\" %}}", server.url());
+        let err = engine.render_string(&template).unwrap_err().to_string();
+        assert!(err.contains("missing.sh") && err.contains("404"), "{}", err);
+
+        engine.set_allowed_url_prefixes(["https://templates.internal/"]);
+        let err = engine.render_string(&template).unwrap_err().to_string();
+        assert!(err.contains("not allowed by allowed_url_prefixes"), "{}", err);
+    }
+}
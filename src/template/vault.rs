@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+use super::remote::send_blocking;
+use super::{CustomDirective, TemplateEngine};
+
+/// 读取 Vault 的超时时间
+const VAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 从 HashiCorp Vault 读取密钥的指令：`{% vault_secret "secret/data/app" password %}`，
+/// 或用 `{% vault_secret "secret/data/app" password as db_password %}` 绑定到变量
+/// 请求 {vault_url}/v1/{路径}，字段先按 KV v2（data.data.字段）、再按 KV v1（data.字段）查找，未指定字段时为 value。
+/// 未设置令牌时使用环境变量 VAULT_TOKEN
+pub struct VaultSecretDirective {
+    vault_url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl VaultSecretDirective {
+    pub fn new(vault_url: &str) -> Self {
+        Self {
+            vault_url: vault_url.trim_end_matches('/').to_string(),
+            token: None,
+            // 请求在临时运行时中完成，不保留空闲连接
+            client: reqwest::Client::builder()
+                .pool_max_idle_per_host(0)
+                .build()
+                .expect("default HTTP client"),
+        }
+    }
+
+    /// 设置访问 Vault 的令牌
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+}
+
+impl CustomDirective for VaultSecretDirective {
+    fn name(&self) -> &str {
+        "vault_secret"
+    }
+
+    fn process(&self, args: &str, _engine: &TemplateEngine) -> Result<String> {
+        let (path, field) = parse_args(args)?;
+        let token = match &self.token {
+            Some(token) => token.clone(),
+            None => std::env::var("VAULT_TOKEN").map_err(|_| anyhow!("VAULT_TOKEN is not set"))?,
+        };
+        let url = format!("{}/v1/{}", self.vault_url, path.trim_start_matches('/'));
+        let body = send_blocking(self.client.get(&url).header("X-Vault-Token", token).timeout(VAULT_TIMEOUT))
+            .map_err(|e| anyhow!("Failed to read secret '{}': {}", path, e))?;
+        let response: serde_json::Value = serde_json::from_str(&body)
+            .map_err(|e| anyhow!("Invalid response for secret '{}': {}", path, e))?;
+
+        let value = response.pointer(&format!("/data/data/{}", field))
+            .or_else(|| response.pointer(&format!("/data/{}", field)))
+            .ok_or_else(|| anyhow!("Field '{}' not found in secret '{}'", field, path))?;
+        Ok(match value {
+            serde_json::Value::String(s) => s.clone(),
+            v => v.to_string(),
+        })
+    }
+}
+
+/// 解析参数 `"路径" [字段]`，路径可以不加引号
+fn parse_args(args: &str) -> Result<(&str, &str)> {
+    let (path, rest) = match args.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').ok_or_else(|| anyhow!("Unclosed quote in '{}'", args))?,
+        None => args.split_once(char::is_whitespace).unwrap_or((args, "")),
+    };
+    if path.is_empty() {
+        return Err(anyhow!("vault_secret requires a secret path"));
+    }
+    let field = rest.trim();
+    Ok((path, if field.is_empty() { "value" } else { field }))
+}
+
+impl TemplateEngine {
+    /// 创建注册了 vault_secret 指令的模板引擎，令牌取自环境变量 VAULT_TOKEN
+    pub fn with_vault_directive(vault_url: &str) -> Self {
+        let mut engine = Self::new();
+        engine.directives.insert("vault_secret".to_string(), std::sync::Arc::new(VaultSecretDirective::new(vault_url)));
+        engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_secret_reads_kv_fields() {
+        let mut server = mockito::Server::new();
+        server.mock("GET", "/v1/secret/data/app")
+            .match_header("X-Vault-Token", "s.test")
+            .with_body(r#"{"data": {"data": {"password": "hunter2", "port": 5432}}}"#)
+            .create();
+
+        let mut engine = TemplateEngine::new();
+        engine.register_directive(Box::new(VaultSecretDirective::new(&server.url()).with_token("s.test"))).unwrap();
+        let template = "PGPASSWORD={% vault_secret \"secret/data/app\" password %} psql -p {% vault_secret secret/data/app port %}";
+        assert_eq!(engine.render_string(template).unwrap(), "PGPASSWORD=hunter2 psql -p 5432");
+
+        let err = engine.render_string("{% vault_secret \"secret/data/app\" user %}").unwrap_err().to_string();
+        assert!(err.contains("Field 'user' not found in secret 'secret/data/app'"), "{}", err);
+
+        let template = "{% vault_secret \"secret/data/app\" password as db_password %}PGPASSWORD={{ db_password }}";
+        assert_eq!(engine.render_string(template).unwrap(), "PGPASSWORD=hunter2");
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// 算术表达式的词法单元
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(i64),
+    Op(char),
+    LParen,
+    RParen,
+}
+
+/// 占位符内容是否是算术表达式：包含运算符或括号，并且能按语法解析
+/// 运算符两侧都直接连着名称时（如 db-host）视为变量名而不是减法，运算数之间需要空白或其中一个是数字；
+/// 名称中带 `-` 的变量（如 eu-west）已定义时按变量处理，由调用方先查找变量
+pub fn is_expression(inner: &str) -> bool {
+    let chars: Vec<char> = inner.chars().collect();
+    if (0..chars.len()).any(|i| joins_names(&chars, i)) {
+        return false;
+    }
+    match tokenize(inner) {
+        Ok(tokens) => tokens.iter().any(|token| matches!(token, Token::Op(_) | Token::LParen)) && is_well_formed(&tokens),
+        Err(_) => false,
+    }
+}
+
+/// 第 i 个字符是否是直接连接两个名称的运算符，如 `db-host` 中的 `-`
+fn joins_names(chars: &[char], i: usize) -> bool {
+    let is_name_char = |c: &char| c.is_alphanumeric() || *c == '_' || *c == '.';
+    if !matches!(chars[i], '+' | '-' | '*' | '/' | '%') {
+        return false;
+    }
+    // 前面连续的名称字符以字母或下划线开头时是变量名，以数字开头时是数字
+    let before = chars[..i].iter().rev().take_while(|c| is_name_char(c)).last();
+    let after = chars.get(i + 1);
+    matches!(before, Some(c) if !c.is_ascii_digit()) && matches!(after, Some(c) if c.is_alphabetic() || *c == '_')
+}
+
+/// 词法单元是否构成语法正确的表达式（不求值）
+fn is_well_formed(tokens: &[Token]) -> bool {
+    let mut depth = 0usize;
+    let mut expect_operand = true;
+    for token in tokens {
+        match (token, expect_operand) {
+            (Token::Num(_) | Token::Ident(_), true) => expect_operand = false,
+            (Token::LParen, true) => depth += 1,
+            (Token::Op('-'), true) => {}
+            (Token::Op(_), false) => expect_operand = true,
+            (Token::RParen, false) if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    !expect_operand && depth == 0
+}
+
+/// 表达式引用的变量名，按出现顺序；无法解析时为空
+pub fn variable_names(expr: &str) -> Vec<String> {
+    tokenize(expr).unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Ident(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 按变量的当前值计算整数算术表达式
+/// 支持 `+` `-` `*` `/`（整数除法，向零取整）`%`、一元负号和括号；变量值按 i64 解析
+/// 变量未定义、不是整数、除数为0或溢出时返回错误
+pub fn evaluate(expr: &str, variables: &HashMap<String, String>) -> Result<i64> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Err(anyhow::anyhow!("Expression is empty"));
+    }
+    let mut parser = Parser { tokens, pos: 0, variables };
+    let value = parser.parse_sum().map_err(|e| anyhow::anyhow!("Failed to evaluate expression '{}': {}", expr, e))?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(anyhow::anyhow!("Unexpected token {:?} in expression '{}'", token, expr));
+    }
+    Ok(value)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => {}
+            '+' | '-' | '*' | '/' | '%' => tokens.push(Token::Op(c)),
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let digits: String = chars[start..i].iter().collect();
+                let value = digits.parse().map_err(|_| anyhow::anyhow!("Number {} is out of range in expression '{}'", digits, input))?;
+                tokens.push(Token::Num(value));
+                continue;
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+                continue;
+            }
+            _ => return Err(anyhow::anyhow!("Unexpected character '{}' in expression '{}'", c, input)),
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+/// 递归下降解析器，解析的同时求值，优先级从低到高：加减、乘除取余、一元负号
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    variables: &'a HashMap<String, String>,
+}
+
+impl Parser<'_> {
+    fn eat_op(&mut self, ops: &[char]) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<i64> {
+        let mut value = self.parse_product()?;
+        while let Some(op) = self.eat_op(&['+', '-']) {
+            let right = self.parse_product()?;
+            value = match op {
+                '+' => value.checked_add(right),
+                _ => value.checked_sub(right),
+            }
+            .ok_or_else(|| anyhow::anyhow!("integer overflow"))?;
+        }
+        Ok(value)
+    }
+
+    fn parse_product(&mut self) -> Result<i64> {
+        let mut value = self.parse_unary()?;
+        while let Some(op) = self.eat_op(&['*', '/', '%']) {
+            let right = self.parse_unary()?;
+            if op != '*' && right == 0 {
+                return Err(anyhow::anyhow!("division by zero"));
+            }
+            value = match op {
+                '*' => value.checked_mul(right),
+                '/' => value.checked_div(right),
+                _ => value.checked_rem(right),
+            }
+            .ok_or_else(|| anyhow::anyhow!("integer overflow"))?;
+        }
+        Ok(value)
+    }
+
+    fn parse_unary(&mut self) -> Result<i64> {
+        if self.eat_op(&['-']).is_some() {
+            return self.parse_unary()?.checked_neg().ok_or_else(|| anyhow::anyhow!("integer overflow"));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<i64> {
+        let token = self.tokens.get(self.pos).cloned().ok_or_else(|| anyhow::anyhow!("unexpected end of expression"))?;
+        self.pos += 1;
+        match token {
+            Token::Num(value) => Ok(value),
+            Token::Ident(name) => {
+                let value = self.variables.get(&name).ok_or_else(|| anyhow::anyhow!("variable '{}' is not defined", name))?;
+                value.trim().parse().map_err(|_| anyhow::anyhow!("variable '{}' is not an integer: '{}'", name, value))
+            }
+            Token::LParen => {
+                let value = self.parse_sum()?;
+                if self.tokens.get(self.pos) != Some(&Token::RParen) {
+                    return Err(anyhow::anyhow!("missing closing parenthesis"));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            other => Err(anyhow::anyhow!("unexpected token {:?}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        HashMap::from([
+            ("count".to_string(), "4".to_string()),
+            ("base_port".to_string(), "8080".to_string()),
+            ("attempt".to_string(), "5".to_string()),
+            ("name".to_string(), "web".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_precedence_and_negative_results() {
+        let vars = vars();
+        let cases = [
+            ("count + 1", 5),
+            ("base_port + 3", 8083),
+            ("2 + count * 3", 14),
+            ("(2 + count) * 3", 18),
+            ("count - attempt", -1),
+            ("-count + 1", -3),
+            ("17 / count", 4),
+            ("-7 / 2", -3),
+            ("17 % count", 1),
+            ("count - 2 - 1", 1),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(evaluate(expr, &vars).unwrap(), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let vars = vars();
+        let err = |expr: &str| evaluate(expr, &vars).unwrap_err().to_string();
+        assert_eq!(err("max_retries - attempt"), "Failed to evaluate expression 'max_retries - attempt': variable 'max_retries' is not defined");
+        assert_eq!(err("name + 1"), "Failed to evaluate expression 'name + 1': variable 'name' is not an integer: 'web'");
+        assert_eq!(err("count / 0"), "Failed to evaluate expression 'count / 0': division by zero");
+        assert!(err("(count + 1").contains("missing closing parenthesis"));
+        assert!(err("count 1").contains("Unexpected token"));
+    }
+
+    #[test]
+    fn test_is_expression_requires_a_parsable_expression() {
+        for expr in ["count + 1", "count+1", "count-1", "2*count", "-count", "(count)", "a - b", "(2 + count) * 3"] {
+            assert!(is_expression(expr), "{}", expr);
+        }
+        // 连字符连接的名称、不完整或无法解析的内容按普通变量名处理
+        for expr in ["db-host", "eu-west-1", "a1-b", "x*y", "count -", "(count", "count)", "a + + b", "host:port-1", ""] {
+            assert!(!is_expression(expr), "{}", expr);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// 条件表达式的词法单元
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+/// 条件表达式语法树
+#[derive(Debug, Clone)]
+enum Expr {
+    Var(String),
+    Literal(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// 检查条件表达式的语法
+pub fn validate(condition: &str) -> Result<()> {
+    parse(condition).map(|_| ())
+}
+
+/// 按变量的当前值计算条件表达式
+/// 支持 `==` `!=` `>` `<` `>=` `<=` `&&` `||` `!` 和括号；未定义的变量视为空字符串
+/// `>` `<` `>=` `<=` 要求两侧都是数字；`==` `!=` 在两侧都是数字时按数值比较，否则按字符串比较
+/// 单独的值非空且不为 "false"/"0" 时为真；`&&` 和 `||` 短路求值
+pub fn evaluate(condition: &str, variables: &HashMap<String, String>) -> Result<bool> {
+    let expr = parse(condition)?;
+    eval_bool(&expr, variables).map_err(|e| anyhow::anyhow!("Failed to evaluate condition '{}': {}", condition, e))
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        if let Some(op) = ["==", "!=", ">=", "<=", "&&", "||"].into_iter().find(|op| *op == two) {
+            tokens.push(Token::Op(op));
+            i += 2;
+            continue;
+        }
+        match c {
+            '>' => tokens.push(Token::Op(">")),
+            '<' => tokens.push(Token::Op("<")),
+            '!' => tokens.push(Token::Op("!")),
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '"' | '\'' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some(ch) if *ch == c => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(ch) => {
+                            value.push(*ch);
+                            i += 1;
+                        }
+                        None => return Err(anyhow::anyhow!("Unterminated string literal in condition '{}'", input)),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())) => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Num(chars[start..i].iter().collect()));
+                continue;
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.' || chars[i] == '-') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+                continue;
+            }
+            _ => return Err(anyhow::anyhow!("Unexpected character '{}' in condition '{}'", c, input)),
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+fn parse(condition: &str) -> Result<Expr> {
+    let tokens = tokenize(condition)?;
+    if tokens.is_empty() {
+        return Err(anyhow::anyhow!("Condition is empty"));
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(anyhow::anyhow!("Unexpected token {:?} in condition '{}'", token, condition));
+    }
+    Ok(expr)
+}
+
+/// 递归下降解析器，优先级从低到高：|| && ! 比较
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut left = self.parse_and()?;
+        while self.eat_op("||") {
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut left = self.parse_unary()?;
+        while self.eat_op("&&") {
+            left = Expr::And(Box::new(left), Box::new(self.parse_unary()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.eat_op("!") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let left = self.parse_primary()?;
+        if let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if ["==", "!=", ">", "<", ">=", "<="].contains(&op) {
+                self.pos += 1;
+                let right = self.parse_primary()?;
+                return Ok(Expr::Compare(op, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let token = self.peek().cloned().ok_or_else(|| anyhow::anyhow!("Unexpected end of condition"))?;
+        self.pos += 1;
+        match token {
+            Token::Ident(name) if name == "true" || name == "false" => Ok(Expr::Literal(name)),
+            Token::Ident(name) => Ok(Expr::Var(name)),
+            Token::Str(value) | Token::Num(value) => Ok(Expr::Literal(value)),
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(anyhow::anyhow!("Missing closing parenthesis in condition"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            other => Err(anyhow::anyhow!("Unexpected token {:?} in condition", other)),
+        }
+    }
+}
+
+fn eval_bool(expr: &Expr, variables: &HashMap<String, String>) -> Result<bool> {
+    match expr {
+        Expr::Not(inner) => Ok(!eval_bool(inner, variables)?),
+        Expr::And(left, right) => Ok(eval_bool(left, variables)? && eval_bool(right, variables)?),
+        Expr::Or(left, right) => Ok(eval_bool(left, variables)? || eval_bool(right, variables)?),
+        Expr::Compare(op, left, right) => {
+            let (left, right) = (eval_value(left, variables)?, eval_value(right, variables)?);
+            let numbers = (left.trim().parse::<f64>(), right.trim().parse::<f64>());
+            match (*op, numbers) {
+                ("==", (Ok(l), Ok(r))) => Ok(l == r),
+                ("!=", (Ok(l), Ok(r))) => Ok(l != r),
+                ("==", _) => Ok(left == right),
+                ("!=", _) => Ok(left != right),
+                (">", (Ok(l), Ok(r))) => Ok(l > r),
+                ("<", (Ok(l), Ok(r))) => Ok(l < r),
+                (">=", (Ok(l), Ok(r))) => Ok(l >= r),
+                ("<=", (Ok(l), Ok(r))) => Ok(l <= r),
+                _ => Err(anyhow::anyhow!("'{}' requires numbers, got '{}' and '{}'", op, left, right)),
+            }
+        }
+        Expr::Var(_) | Expr::Literal(_) => {
+            let value = eval_value(expr, variables)?;
+            Ok(!value.is_empty() && value != "false" && value != "0")
+        }
+    }
+}
+
+fn eval_value(expr: &Expr, variables: &HashMap<String, String>) -> Result<String> {
+    match expr {
+        Expr::Var(name) => Ok(variables.get(name).cloned().unwrap_or_default()),
+        Expr::Literal(value) => Ok(value.clone()),
+        _ => Ok(eval_bool(expr, variables)?.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        HashMap::from([
+            ("env".to_string(), "production".to_string()),
+            ("retry_count".to_string(), "2".to_string()),
+            ("feature_flag".to_string(), "".to_string()),
+            ("version".to_string(), "1.10".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        let vars = vars();
+        let cases = [
+            ("env == \"production\"", true),
+            ("env == 'staging'", false),
+            ("env != \"staging\"", true),
+            ("feature_flag != \"\"", false),
+            ("missing == \"\"", true),
+            ("retry_count > 0", true),
+            ("retry_count < 2", false),
+            ("retry_count >= 2", true),
+            ("retry_count <= 1", false),
+            ("retry_count == 2.0", true),
+            ("version > 1.9", false),
+            ("-1 < 0", true),
+        ];
+        for (condition, expected) in cases {
+            assert_eq!(evaluate(condition, &vars).unwrap(), expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn test_logical_operators_and_precedence() {
+        let vars = vars();
+        let cases = [
+            ("env == \"production\" && retry_count > 0", true),
+            ("env == \"staging\" || retry_count > 0", true),
+            ("!(env == \"production\")", false),
+            ("!feature_flag", true),
+            ("env", true),
+            ("true || false && false", true),
+            ("(true || false) && false", false),
+            ("!false && !0", true),
+        ];
+        for (condition, expected) in cases {
+            assert_eq!(evaluate(condition, &vars).unwrap(), expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn test_short_circuit_and_errors() {
+        let vars = vars();
+        // 右侧比较会出错，短路时不会求值
+        assert!(!evaluate("false && env > 1", &vars).unwrap());
+        assert!(evaluate("true || env > 1", &vars).unwrap());
+        assert!(evaluate("true && env > 1", &vars).is_err());
+        assert!(evaluate("false || env > 1", &vars).is_err());
+
+        for invalid in ["", "env ==", "(env == \"a\"", "env == \"a", "env = 1", "env == 1 1"] {
+            assert!(validate(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -1,12 +1,33 @@
 use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
 use regex::Regex;
 use anyhow::{Result, Context};
-use crate::models::{ExtractRule, ExecutionResult};
+use crate::models::{ExtractRule, ExecutionResult, ValueTransform, VariableDiff};
+use crate::template::{EscapeMode, TemplateRenderer};
+
+pub mod arithmetic;
+pub mod condition;
+
+/// 有提取规则的步骤执行后设置的变量：执行耗时（毫秒）
+pub const STEP_ELAPSED_VARIABLE: &str = "__step_elapsed_ms";
 
 /// 变量管理器
 #[derive(Debug, Clone)]
 pub struct VariableManager {
     variables: HashMap<String, String>,
+    script_escape: Option<EscapeMode>, // 替换脚本内容时对变量值的转义方式
+    tracking: Option<(String, HashMap<String, String>)>, // begin_tracking 时的名称与变量副本
+    history: Vec<(String, VariableDiff)>, // 每段跟踪的名称（如步骤名）与变量差异，按结束顺序
+    #[cfg(feature = "fast-replace")]
+    replacer: OnceLock<Option<Arc<KeyReplacer>>>, // 按变量名构建的自动机，变量名增删时清空，下次替换时重建
+}
+
+/// fast-replace 特性使用的自动机，keys 与自动机的模式一一对应
+#[cfg(feature = "fast-replace")]
+#[derive(Debug)]
+struct KeyReplacer {
+    automaton: aho_corasick::AhoCorasick,
+    keys: Vec<String>,
 }
 
 impl VariableManager {
@@ -14,20 +35,146 @@ impl VariableManager {
     pub fn new(initial_variables: Option<HashMap<String, String>>) -> Self {
         Self {
             variables: initial_variables.unwrap_or_default(),
+            script_escape: None,
+            tracking: None,
+            history: Vec::new(),
+            #[cfg(feature = "fast-replace")]
+            replacer: OnceLock::new(),
         }
     }
 
+    /// 按当前变量计算条件表达式，语法见 condition::evaluate
+    pub fn evaluate_condition(&self, condition: &str) -> Result<bool> {
+        condition::evaluate(condition, &self.variables)
+    }
+
+    /// 按当前变量计算整数算术表达式（如 `count + 1`），结果转为字符串，语法见 arithmetic::evaluate
+    pub fn evaluate_expression(&self, expr: &str) -> Result<String> {
+        arithmetic::evaluate(expr, &self.variables).map(|value| value.to_string())
+    }
+
+    /// 设置替换脚本内容时对变量值的转义方式
+    pub fn set_script_escape(&mut self, escape: Option<EscapeMode>) {
+        self.script_escape = escape;
+    }
+
     /// 替换字符串中的变量占位符
+    /// 单次扫描替换 {{ variable_name }}，变量值中的占位符文本不会被再次展开，未定义的变量保持原样
+    /// 占位符可以指定过滤器：{{ name | sh_quote }} 按shell规则加引号，{{ name | raw }} 跳过自动转义
+    /// 不是已定义变量且能解析为算术表达式的占位符按表达式计算（如 {{ base_port + 3 }}），计算失败时保持原样
+    /// 开启 fast-replace 特性时使用 batch_replace_all
     pub fn replace_variables(&self, content: &str) -> String {
-        let mut result = content.to_string();
-        
-        // 替换 {{ variable_name }} 格式的变量
-        for (key, value) in &self.variables {
-            let placeholder = format!("{{{{ {} }}}}", key);
-            result = result.replace(&placeholder, value);
+        #[cfg(feature = "fast-replace")]
+        return self.batch_replace_all(content);
+        #[cfg(not(feature = "fast-replace"))]
+        self.replace_with_escape(content, None)
+    }
+
+    /// 一次扫描替换所有变量占位符，结果与 replace_variables 完全相同
+    /// 开启 fast-replace 特性时用所有 `{{ key }}` 构建一个 Aho-Corasick 自动机（缓存到变量名增删为止），
+    /// 带过滤器或算术表达式的占位符仍在匹配之间的片段中按正则处理；未开启时与 replace_variables 相同
+    pub fn batch_replace_all(&self, content: &str) -> String {
+        self.replace_with_escape(content, None)
+    }
+
+    /// 替换脚本内容中的变量占位符，按 set_script_escape 设置的方式转义变量值
+    pub fn replace_script_variables(&self, content: &str) -> String {
+        self.replace_with_escape(content, self.script_escape)
+    }
+
+    #[cfg(not(feature = "fast-replace"))]
+    fn replace_with_escape(&self, content: &str, escape: Option<EscapeMode>) -> String {
+        self.replace_placeholders(content, escape)
+    }
+
+    /// 自动机只匹配不带过滤器的 `{{ key }}`，名称含 `{`、`}` 或 `|` 的变量不会被正则识别为占位符，不加入自动机
+    #[cfg(feature = "fast-replace")]
+    fn replace_with_escape(&self, content: &str, escape: Option<EscapeMode>) -> String {
+        let Some(replacer) = self.replacer.get_or_init(|| self.build_replacer()) else {
+            return self.replace_placeholders(content, escape);
+        };
+
+        let mut output = String::with_capacity(content.len());
+        let mut last = 0;
+        for found in replacer.automaton.find_iter(content) {
+            output.push_str(&self.replace_placeholders(&content[last..found.start()], escape));
+            let value = &self.variables[&replacer.keys[found.pattern().as_usize()]];
+            match escape {
+                Some(mode) => output.push_str(&mode.escape(value)),
+                None => output.push_str(value),
+            }
+            last = found.end();
         }
-        
-        result
+        output.push_str(&self.replace_placeholders(&content[last..], escape));
+        output
+    }
+
+    /// 用当前的变量名构建自动机，构建失败时返回 None，替换退回正则
+    #[cfg(feature = "fast-replace")]
+    fn build_replacer(&self) -> Option<Arc<KeyReplacer>> {
+        use aho_corasick::{AhoCorasick, MatchKind};
+
+        let keys: Vec<String> = self.variables.keys()
+            .filter(|key| !key.is_empty() && !key.contains(['{', '}', '|']))
+            .cloned()
+            .collect();
+        let patterns = keys.iter().map(|key| format!("{{{{ {} }}}}", key));
+        let automaton = AhoCorasick::builder().match_kind(MatchKind::LeftmostLongest).build(patterns).ok()?;
+        Some(Arc::new(KeyReplacer { automaton, keys }))
+    }
+
+    /// 按占位符正则单次扫描替换
+    fn replace_placeholders(&self, content: &str, escape: Option<EscapeMode>) -> String {
+        placeholder_regex()
+            .replace_all(content, |caps: &regex::Captures| {
+                let (name, filter) = split_placeholder(&caps[1]);
+                let escape = match filter {
+                    None => escape,
+                    Some("raw") => None,
+                    // 未知的过滤器与未定义的变量一样保持原样
+                    Some(filter) => match EscapeMode::from_filter(filter) {
+                        Some(mode) => Some(mode),
+                        None => return caps[0].to_string(),
+                    },
+                };
+                let value = match self.variables.get(name) {
+                    Some(value) => value.clone(),
+                    None if arithmetic::is_expression(name) => match self.evaluate_expression(name) {
+                        Ok(value) => value,
+                        Err(_) => return caps[0].to_string(),
+                    },
+                    None => return caps[0].to_string(),
+                };
+                match escape {
+                    Some(mode) => mode.escape(&value),
+                    None => value,
+                }
+            })
+            .into_owned()
+    }
+
+    /// 替换正则表达式中的变量占位符，变量值按原样作为正则文本插入
+    /// 引用了未定义的变量时返回错误，错误信息包含原始表达式和缺失的变量名
+    fn expand_pattern(&self, rule: &ExtractRule, pattern_index: usize, pattern: &str) -> Result<Regex> {
+        for caps in placeholder_regex().captures_iter(pattern) {
+            let name = split_placeholder(&caps[1]).0;
+            if self.variables.contains_key(name) {
+                continue;
+            }
+            // 与 replace_variables 一样，不是已定义变量的算术表达式按当前变量计算
+            if arithmetic::is_expression(name) {
+                self.evaluate_expression(name).map_err(|e| anyhow::anyhow!(
+                    "{} in regex pattern {} for rule '{}': {}", e, pattern_index + 1, rule.name, pattern
+                ))?;
+                continue;
+            }
+            return Err(anyhow::anyhow!(
+                "Undefined variable '{}' in regex pattern {} for rule '{}': {}",
+                name, pattern_index + 1, rule.name, pattern
+            ));
+        }
+        Regex::new(&rule.pattern_text(&self.replace_variables(pattern)))
+            .context(format!("Invalid regex pattern {} for rule '{}': {}", pattern_index + 1, rule.name, pattern))
     }
 
     /// 从执行结果中提取变量
@@ -42,6 +189,12 @@ impl VariableManager {
                 }
             };
 
+            // 没有正则的 exit_code 规则直接保存退出码
+            if rule.source == "exit_code" && rule.patterns.is_empty() {
+                self.set_variable(rule.name.clone(), source_content.to_string());
+                continue;
+            }
+
             // 检查是否启用级联模式
             if rule.cascade {
                 // 级联模式：前一个正则的匹配结果作为下一个正则的输入
@@ -62,9 +215,20 @@ impl VariableManager {
         let mut extracted_value = None;
 
         for (pattern_index, pattern) in rule.patterns.iter().enumerate() {
-            let regex = Regex::new(pattern)
-                .context(format!("Invalid regex pattern {} for rule '{}': {}", pattern_index + 1, rule.name, pattern))?;
-            
+            let regex = self.expand_pattern(rule, pattern_index, pattern)?;
+
+            // find_all 时最后一个正则收集所有匹配
+            if rule.find_all == Some(true) && pattern_index == rule.patterns.len() - 1 {
+                let values: Vec<String> = regex.captures_iter(&current_content)
+                    .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+                    .map(|value| value.as_str().to_string())
+                    .collect();
+                if !values.is_empty() {
+                    extracted_value = Some(join_transformed(rule, values)?);
+                }
+                break;
+            }
+
             if let Some(captures) = regex.captures(&current_content) {
                 // 约定：始终获取第一个捕获组（第一个括号）的内容
                 let matched_value = if let Some(value) = captures.get(1) {
@@ -85,7 +249,14 @@ impl VariableManager {
                     extracted_value = Some(matched_value);
                     break;
                 } else {
-                    // 不是最后一个正则，将匹配结果作为下一个正则的输入
+                    // 不是最后一个正则，按需保存中间结果，并将匹配结果作为下一个正则的输入
+                    if rule.capture_intermediates == Some(true) {
+                        let stage_name = rule.named_stages.as_ref()
+                            .and_then(|names| names.get(pattern_index))
+                            .cloned()
+                            .unwrap_or_else(|| format!("{}_stage_{}", rule.name, pattern_index + 1));
+                        self.set_variable(stage_name, matched_value.clone());
+                    }
                     current_content = matched_value;
                 }
             } else {
@@ -96,7 +267,8 @@ impl VariableManager {
         }
 
         if let Some(value) = extracted_value {
-            self.variables.insert(rule.name.clone(), value.clone());
+            let value = if rule.find_all == Some(true) { value } else { apply_transforms(rule, value)? };
+            self.set_variable(rule.name.clone(), value.clone());
             tracing::debug!("Cascade extraction successful for rule '{}': {}", rule.name, value);
         } else {
             tracing::debug!("Cascade extraction failed for rule '{}'", rule.name);
@@ -111,15 +283,30 @@ impl VariableManager {
         let mut extracted = false;
         
         for (pattern_index, pattern) in rule.patterns.iter().enumerate() {
-            let regex = Regex::new(pattern)
-                .context(format!("Invalid regex pattern {} for rule '{}': {}", pattern_index + 1, rule.name, pattern))?;
-            
+            let regex = self.expand_pattern(rule, pattern_index, pattern)?;
+
+            if rule.find_all == Some(true) {
+                let values: Vec<String> = regex.captures_iter(source_content)
+                    .filter_map(|captures| captures.get(1))
+                    .map(|value| value.as_str().to_string())
+                    .collect();
+                if !values.is_empty() {
+                    let value = join_transformed(rule, values)?;
+                    tracing::debug!("Fallback extraction found all matches for rule '{}' with pattern {}: {}", rule.name, pattern_index + 1, value);
+                    self.set_variable(rule.name.clone(), value);
+                    extracted = true;
+                    break;
+                }
+                continue;
+            }
+
             if let Some(captures) = regex.captures(source_content) {
                 // 约定：始终获取第一个捕获组（第一个括号）的内容
                 if let Some(value) = captures.get(1) {
-                    self.variables.insert(rule.name.clone(), value.as_str().to_string());
+                    let value = apply_transforms(rule, value.as_str().to_string())?;
+                    tracing::debug!("Fallback extraction successful for rule '{}' with pattern {}: {}", rule.name, pattern_index + 1, value);
+                    self.set_variable(rule.name.clone(), value);
                     extracted = true;
-                    tracing::debug!("Fallback extraction successful for rule '{}' with pattern {}: {}", rule.name, pattern_index + 1, value.as_str());
                     break; // 找到匹配就停止尝试其他模式
                 } else {
                     // 如果没有捕获组，记录警告
@@ -144,16 +331,480 @@ impl VariableManager {
 
     /// 移除变量
     pub fn remove_variable(&mut self, key: &str) {
-        self.variables.remove(key);
+        if self.variables.remove(key).is_some() {
+            self.keys_changed();
+        }
     }
 
     /// 设置变量
     pub fn set_variable(&mut self, key: String, value: String) {
-        self.variables.insert(key, value);
+        if self.variables.insert(key, value).is_none() {
+            self.keys_changed();
+        }
+    }
+
+    /// 变量名有增删时清空 fast-replace 的自动机；只改变值时自动机仍然可用
+    fn keys_changed(&mut self) {
+        #[cfg(feature = "fast-replace")]
+        {
+            self.replacer = OnceLock::new();
+        }
+    }
+
+    /// 当前变量的共享副本，用于输出事件；同一步骤的多个事件可共用一份
+    pub fn snapshot(&self) -> Arc<HashMap<String, String>> {
+        Arc::new(self.variables.clone())
     }
 
     /// 获取变量值
     pub fn get_variable(&self, key: &str) -> Option<&String> {
         self.variables.get(key)
     }
-} 
\ No newline at end of file
+
+    /// 开始跟踪变量变化：保存当前变量的副本，name（如步骤名）用于 history；
+    /// 已在跟踪时重新开始
+    pub fn begin_tracking(&mut self, name: &str) {
+        self.tracking = Some((name.to_string(), self.variables.clone()));
+    }
+
+    /// 结束跟踪，返回 begin_tracking 以来的变量差异并记入 history；没有在跟踪时返回空差异
+    pub fn end_tracking(&mut self) -> VariableDiff {
+        let Some((name, before)) = self.tracking.take() else {
+            return VariableDiff::default();
+        };
+        let diff = VariableDiff::between(&before, &self.variables);
+        self.record_history(name, diff.clone());
+        diff
+    }
+
+    /// 按结束顺序返回每段跟踪的名称与变量差异
+    pub fn history(&self) -> &[(String, VariableDiff)] {
+        &self.history
+    }
+
+    /// 记入在其他变量副本上跟踪到的差异（如并发执行的步骤）
+    pub(crate) fn record_history(&mut self, name: String, diff: VariableDiff) {
+        self.history.push((name, diff));
+    }
+}
+
+/// 按顺序对规则提取到的最终值应用 transform
+fn apply_transforms(rule: &ExtractRule, mut value: String) -> Result<String> {
+    for transform in rule.transform.iter().flatten() {
+        value = match transform {
+            ValueTransform::TrimNewlines => value.trim_matches(['\r', '\n']).to_string(),
+            ValueTransform::Trim => value.trim().to_string(),
+            ValueTransform::Base64Decode => {
+                use base64::Engine;
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(value.trim())
+                    .with_context(|| format!("Failed to base64-decode variable '{}'", rule.name))?;
+                String::from_utf8(bytes).with_context(|| format!("Decoded variable '{}' is not valid UTF-8", rule.name))?
+            }
+            ValueTransform::UrlDecode => url_decode(&value)
+                .with_context(|| format!("Decoded variable '{}' is not valid UTF-8", rule.name))?,
+            ValueTransform::StripAnsi => crate::ssh::strip_ansi(&value).into_owned(),
+            ValueTransform::Lower => value.to_lowercase(),
+            ValueTransform::Upper => value.to_uppercase(),
+            ValueTransform::Default(default) if value.is_empty() => default.clone(),
+            ValueTransform::Default(_) => value,
+        };
+    }
+    Ok(value)
+}
+
+/// find_all 收集到的各个值分别应用 transform 后以逗号连接
+fn join_transformed(rule: &ExtractRule, values: Vec<String>) -> Result<String> {
+    let values = values.into_iter()
+        .map(|value| apply_transforms(rule, value))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(values.join(","))
+}
+
+/// 解码 %XX 百分号编码，不是合法编码的 % 保持原样
+fn url_decode(value: &str) -> std::result::Result<String, std::string::FromUtf8Error> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded)
+}
+
+/// 按 replace_variables 的规则渲染，未定义的变量保持原样，不会返回错误
+impl TemplateRenderer for VariableManager {
+    fn render(&self, template: &str) -> Result<String> {
+        Ok(self.replace_variables(template))
+    }
+}
+
+/// 变量占位符 {{ name }}
+pub(crate) fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{ ([^{}]+?) \}\}").unwrap())
+}
+
+/// 拆分占位符内容为变量名与过滤器：`name | sh_quote` -> ("name", Some("sh_quote"))
+pub(crate) fn split_placeholder(inner: &str) -> (&str, Option<&str>) {
+    match inner.split_once('|') {
+        Some((name, filter)) => (name.trim(), Some(filter.trim())),
+        None => (inner, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdout_result(stdout: &str) -> ExecutionResult {
+        ExecutionResult {
+            success: true,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+            script: String::new(),
+            exit_code: 0,
+            execution_time_ms: 0,
+            error_message: None,
+            had_invalid_utf8: false,
+            artifacts: Vec::new(),
+            connect_time_ms: 0,
+            command_time_ms: 0,
+            fallback_reason: None,
+            rendered_script: None,
+            script_sha256: None,
+            connection_info: None,
+            answered_prompts: Vec::new(),
+            ssh_trace: None,
+            skip_reason: None,
+        }
+    }
+
+    fn rule(patterns: &[&str], cascade: bool) -> ExtractRule {
+        ExtractRule {
+            name: "value".to_string(),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            source: "stdout".to_string(),
+            cascade,
+            capture_intermediates: None,
+            named_stages: None,
+            dotall: None,
+            transform: None,
+            find_all: None,
+        }
+    }
+
+    #[test]
+    fn test_extract_patterns_expand_variables() {
+        let mut manager = VariableManager::new(Some(HashMap::from([("prefix".to_string(), "version".to_string())])));
+        for cascade in [false, true] {
+            manager.remove_variable("value");
+            manager.extract_variables(&[rule(&["{{ prefix }}_(\\d+)"], cascade)], &stdout_result("build_7\nversion_42\n")).unwrap();
+            assert_eq!(manager.get_variable("value").unwrap(), "42");
+        }
+    }
+
+    #[test]
+    fn test_extract_pattern_with_undefined_variable_fails() {
+        let mut manager = VariableManager::new(None);
+        for cascade in [false, true] {
+            let err = manager
+                .extract_variables(&[rule(&["{{ prefix }}_(\\d+)"], cascade)], &stdout_result("version_42"))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("Undefined variable 'prefix'"), "{}", err);
+            assert!(err.contains("{{ prefix }}_(\\d+)"), "{}", err);
+        }
+
+        // 连字符名称不按算术表达式处理
+        let err = manager
+            .extract_variables(&[rule(&["{{ db-host }}:(\\d+)"], false)], &stdout_result("db:5432"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Undefined variable 'db-host'"), "{}", err);
+    }
+
+    #[test]
+    fn test_extract_pattern_evaluates_arithmetic() {
+        let mut manager = VariableManager::new(Some(HashMap::from([("count".to_string(), "2".to_string())])));
+        for cascade in [false, true] {
+            manager.remove_variable("value");
+            manager.extract_variables(&[rule(&["step{{ count + 1 }}=(\\w+)"], cascade)], &stdout_result("step2=old\nstep3=new\n")).unwrap();
+            assert_eq!(manager.get_variable("value").unwrap(), "new");
+        }
+
+        let err = manager
+            .extract_variables(&[rule(&["step{{ retries - 1 }}=(\\w+)"], false)], &stdout_result("step3=new"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("variable 'retries' is not defined"), "{}", err);
+    }
+
+    #[test]
+    fn test_cascade_captures_intermediate_stages() {
+        let output = stdout_result("noise\nrelease: app-v1.2.3 (stable)\n");
+        let patterns = ["release: (.+)", "app-v(\\S+)", "^(\\d+\\.\\d+)"];
+
+        let mut manager = VariableManager::new(None);
+        let mut numbered = rule(&patterns, true);
+        numbered.capture_intermediates = Some(true);
+        manager.extract_variables(&[numbered], &stdout_result(&output.stdout)).unwrap();
+        assert_eq!(manager.get_variable("value_stage_1").unwrap(), "app-v1.2.3 (stable)");
+        assert_eq!(manager.get_variable("value_stage_2").unwrap(), "1.2.3");
+        assert_eq!(manager.get_variable("value").unwrap(), "1.2");
+        assert!(manager.get_variable("value_stage_3").is_none());
+
+        let mut manager = VariableManager::new(None);
+        let mut named = rule(&patterns, true);
+        named.capture_intermediates = Some(true);
+        named.named_stages = Some(vec!["release_line".to_string()]);
+        manager.extract_variables(&[named], &output).unwrap();
+        assert_eq!(manager.get_variable("release_line").unwrap(), "app-v1.2.3 (stable)");
+        assert_eq!(manager.get_variable("value_stage_2").unwrap(), "1.2.3");
+        assert!(manager.get_variable("value_stage_1").is_none());
+
+        // 未开启时只保存最终结果
+        let mut manager = VariableManager::new(None);
+        manager.extract_variables(&[rule(&patterns, true)], &output).unwrap();
+        assert_eq!(manager.get_variables().len(), 1);
+    }
+
+    #[test]
+    fn test_dotall_extracts_across_lines() {
+        let output = stdout_result("key:\n-----BEGIN CERTIFICATE-----\nMIIB\nAAAA\n-----END CERTIFICATE-----\ndone\n");
+        let pattern = ["(-----BEGIN CERTIFICATE-----.*?-----END CERTIFICATE-----)"];
+
+        let mut manager = VariableManager::new(None);
+        manager.extract_variables(&[rule(&pattern, false)], &output).unwrap();
+        assert!(manager.get_variable("value").is_none());
+
+        for cascade in [false, true] {
+            let mut dotall = rule(&pattern, cascade);
+            dotall.dotall = Some(true);
+            manager.extract_variables(&[dotall], &output).unwrap();
+            assert_eq!(
+                manager.get_variable("value").unwrap(),
+                "-----BEGIN CERTIFICATE-----\nMIIB\nAAAA\n-----END CERTIFICATE-----"
+            );
+        }
+    }
+
+    #[test]
+    fn test_multiline_block_with_transforms() {
+        let output = stdout_result("build log\nBEGIN\n\x1b[32mline1\x1b[0m\nline2\nEND\ntoken=aGVsbG8gd29ybGQ= path=a%2Fb%20c\n");
+        let rules: Vec<ExtractRule> = serde_yaml::from_str(r#"
+- name: "raw"
+  patterns: ["BEGIN(.*)END"]
+  source: "stdout"
+  multiline: true
+  transform: ["strip_ansi"]
+- name: "block"
+  patterns: ["BEGIN(.*)END"]
+  source: "stdout"
+  multiline: true
+  transform: ["strip_ansi", "trim_newlines"]
+- name: "token"
+  patterns: ["token=(\\S+)"]
+  source: "stdout"
+  transform: ["base64_decode"]
+- name: "path"
+  patterns: ["path=(\\S+)"]
+  source: "stdout"
+  transform: ["url_decode"]
+"#).unwrap();
+        let mut manager = VariableManager::new(None);
+        manager.extract_variables(&rules, &output).unwrap();
+        assert_eq!(manager.get_variable("raw").unwrap(), "\nline1\nline2\n");
+        assert_eq!(manager.get_variable("block").unwrap(), "line1\nline2");
+        assert_eq!(manager.get_variable("token").unwrap(), "hello world");
+        assert_eq!(manager.get_variable("path").unwrap(), "a/b c");
+
+        // 未知的转换在解析配置时报错
+        assert!(serde_yaml::from_str::<ExtractRule>("{name: x, source: stdout, transform: [rot13]}").is_err());
+    }
+
+    #[test]
+    fn test_case_and_default_transforms() {
+        let output = stdout_result("Status:  ACTIVE \nowner=\nregion=eu-west\n");
+        let rules: Vec<ExtractRule> = serde_yaml::from_str(r#"
+- name: "status"
+  patterns: ["Status:(.*)"]
+  source: "stdout"
+  transform: ["trim", "lower"]
+- name: "owner"
+  patterns: ["owner=(.*)"]
+  source: "stdout"
+  transform: ["default:nobody"]
+- name: "region"
+  patterns: ["region=(.*)"]
+  source: "stdout"
+  transform: ["default:us-east", "upper"]
+"#).unwrap();
+        let mut manager = VariableManager::new(None);
+        manager.extract_variables(&rules, &output).unwrap();
+        assert_eq!(manager.get_variable("status").unwrap(), "active");
+        assert_eq!(manager.get_variable("owner").unwrap(), "nobody");
+        assert_eq!(manager.get_variable("region").unwrap(), "EU-WEST");
+        assert_eq!(serde_yaml::to_string(&rules[1].transform).unwrap().trim(), "- default:nobody");
+
+        // 单个转换可以不写成列表
+        let rules: Vec<ExtractRule> = serde_yaml::from_str(r#"
+- name: "status"
+  patterns: ["Status:(.*)"]
+  source: "stdout"
+  transform: trim
+- name: "owner"
+  patterns: ["owner=(.*)"]
+  source: "stdout"
+  transform: "default:nobody"
+"#).unwrap();
+        assert_eq!(rules[0].transform, Some(vec![ValueTransform::Trim]));
+        assert_eq!(rules[1].transform, Some(vec![ValueTransform::Default("nobody".to_string())]));
+        let mut manager = VariableManager::new(None);
+        manager.extract_variables(&rules, &output).unwrap();
+        assert_eq!(manager.get_variable("status").unwrap(), "ACTIVE");
+        assert_eq!(manager.get_variable("owner").unwrap(), "nobody");
+        let err = serde_yaml::from_str::<ExtractRule>("{name: x, source: stdout, transform: rot13}").unwrap_err();
+        assert!(err.to_string().contains("unknown transform 'rot13'"), "{}", err);
+    }
+
+    #[test]
+    fn test_find_all_collects_every_match() {
+        let output = stdout_result("eth0 inet 10.0.0.5\neth1 inet 10.0.1.7\nlo inet 127.0.0.1\n");
+        let mut manager = VariableManager::new(None);
+        let mut all = rule(&["inet (\\S+)"], false);
+        all.find_all = Some(true);
+        manager.extract_variables(&[all], &output).unwrap();
+        assert_eq!(manager.get_variable("value").unwrap(), "10.0.0.5,10.0.1.7,127.0.0.1");
+
+        // 级联时收集最后一个正则在上一阶段结果中的所有匹配，transform 分别应用于每个值
+        let mut all = rule(&["(?s)eth0(.*)lo", "inet (\\S+)\\."], true);
+        all.find_all = Some(true);
+        all.transform = Some(vec![ValueTransform::Trim]);
+        manager.extract_variables(&[all], &output).unwrap();
+        assert_eq!(manager.get_variable("value").unwrap(), "10.0.0,10.0.1");
+    }
+
+    #[test]
+    fn test_exit_code_rule_without_patterns() {
+        let rules: Vec<ExtractRule> = serde_yaml::from_str("- name: \"status\"\n  source: \"exit_code\"\n").unwrap();
+        let mut output = stdout_result("exit 3\n");
+        output.exit_code = 3;
+        let mut manager = VariableManager::new(None);
+        manager.extract_variables(&rules, &output).unwrap();
+        assert_eq!(manager.get_variable("status").unwrap(), "3");
+
+        // 有正则时仍按正则提取
+        let mut with_pattern = rule(&["^(\\d)"], false);
+        with_pattern.source = "exit_code".to_string();
+        output.exit_code = 42;
+        manager.extract_variables(&[with_pattern], &output).unwrap();
+        assert_eq!(manager.get_variable("value").unwrap(), "4");
+    }
+
+    #[test]
+    fn test_variable_manager_as_template_renderer() {
+        let manager = VariableManager::new(Some([("host".to_string(), "web1".to_string())].into()));
+        let renderer: &dyn TemplateRenderer = &manager;
+        assert_eq!(renderer.render("ssh {{ host }} {{ missing }}").unwrap(), "ssh web1 {{ missing }}");
+    }
+
+    #[test]
+    fn test_tracking_records_variable_diff() {
+        let mut manager = VariableManager::new(Some([
+            ("env".to_string(), "dev".to_string()),
+            ("app".to_string(), "shop".to_string()),
+        ].into()));
+        assert!(manager.end_tracking().is_empty());
+
+        manager.begin_tracking("deploy");
+        manager.set_variable("env".to_string(), "prod".to_string());
+        manager.set_variable("version".to_string(), "1.0".to_string());
+        let diff = manager.end_tracking();
+        assert_eq!(diff.added, HashMap::from([("version".to_string(), "1.0".to_string())]));
+        assert_eq!(diff.modified, HashMap::from([("env".to_string(), ("dev".to_string(), "prod".to_string()))]));
+        assert!(diff.removed.is_empty());
+
+        manager.begin_tracking("noop");
+        assert!(manager.end_tracking().is_empty());
+        let names: Vec<&str> = manager.history().iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["deploy", "noop"]);
+    }
+
+    #[test]
+    fn test_placeholder_filters() {
+        let mut manager = VariableManager::new(Some([("msg".to_string(), "it's $(id)".to_string())].into()));
+        assert_eq!(manager.replace_variables("echo {{ msg | sh_quote }}"), "echo 'it'\\''s $(id)'");
+        assert_eq!(manager.replace_variables("echo {{ msg }}"), "echo it's $(id)");
+        // 未知的过滤器保持原样
+        assert_eq!(manager.replace_variables("{{ msg | nope }}"), "{{ msg | nope }}");
+
+        manager.set_script_escape(Some(EscapeMode::Shell));
+        assert_eq!(manager.replace_script_variables("{{ msg }}"), "'it'\\''s $(id)'");
+        assert_eq!(manager.replace_script_variables("{{ msg | raw }}"), "it's $(id)");
+    }
+
+    #[test]
+    fn test_arithmetic_placeholders() {
+        let manager = VariableManager::new(Some([
+            ("base_port".to_string(), "8080".to_string()),
+            ("count".to_string(), "2".to_string()),
+            ("eu-west".to_string(), "Ireland".to_string()),
+        ].into()));
+        assert_eq!(manager.evaluate_expression("(count + 1) * 10").unwrap(), "30");
+        assert_eq!(
+            manager.replace_variables("port={{ base_port + 3 }} left={{ count-5 }} region={{ eu-west }}"),
+            "port=8083 left=-3 region=Ireland"
+        );
+        // 计算失败（如引用未定义的变量）时与未定义的变量一样保持原样
+        assert_eq!(manager.replace_variables("{{ retries - 1 }}"), "{{ retries - 1 }}");
+        // 未定义的连字符名称按未定义的变量处理
+        assert_eq!(manager.replace_variables("{{ db-host }}"), "{{ db-host }}");
+        assert!(manager.evaluate_expression("retries - 1").is_err());
+    }
+
+    #[test]
+    fn test_batch_replace_all_matches_sequential_replacement() {
+        let mut manager = VariableManager::new(Some([
+            ("app".to_string(), "shop".to_string()),
+            ("app_name".to_string(), "{{ app }}".to_string()),
+            ("msg".to_string(), "it's $(id)".to_string()),
+            ("count".to_string(), "2".to_string()),
+            ("a|b".to_string(), "pipe".to_string()),
+            ("x }} y".to_string(), "brace".to_string()),
+            (" padded ".to_string(), "spaced".to_string()),
+        ].into()));
+        let content = concat!(
+            "deploy {{ app }} as {{ app_name }} {{{ app }}} {{ app }}}\n",
+            "{{ msg | sh_quote }} {{ msg | raw }} {{ msg | nope }} {{ count + 1 }}\n",
+            "{{ a|b }} {{ x }} y }} {{  padded  }} {{ missing }} {{ app\n",
+        );
+        let expected = manager.replace_placeholders(content, None);
+        assert_eq!(manager.batch_replace_all(content), expected);
+        assert_eq!(manager.replace_variables(content), expected);
+        assert!(expected.starts_with("deploy shop as {{ app }} {shop} shop}\n"), "{}", expected);
+
+        manager.set_script_escape(Some(EscapeMode::Shell));
+        assert_eq!(manager.replace_script_variables(content), manager.replace_placeholders(content, Some(EscapeMode::Shell)));
+
+        // 新增、删除变量和修改值之后，缓存的自动机不会使用过期的变量名
+        manager.set_variable("missing".to_string(), "found".to_string());
+        manager.remove_variable("app");
+        manager.set_variable("count".to_string(), "7".to_string());
+        let expected = manager.replace_placeholders(content, None);
+        assert_eq!(manager.batch_replace_all(content), expected);
+        assert!(expected.contains("found") && expected.contains("{{ app }}") && expected.contains('8'), "{}", expected);
+    }
+}